@@ -0,0 +1,207 @@
+use std::future::{ready, Ready};
+use std::sync::OnceLock;
+use actix_session::SessionExt;
+use actix_web::body::{EitherBody, MessageBody};
+use actix_web::dev::{Payload, ServiceRequest, ServiceResponse};
+use actix_web::error::ErrorUnauthorized;
+use actix_web::http::header;
+use actix_web::middleware::Next;
+use actix_web::{web, Error, FromRequest, HttpMessage, HttpRequest, HttpResponse};
+use sqlx::SqlitePool;
+use crate::models::{ApiResponse, ApiToken, User, hash_password, hash_token};
+
+mod role;
+mod throttle;
+mod token;
+
+pub use role::RequireRole;
+pub use throttle::{client_key, LoginThrottle};
+pub use token::bearer_token;
+use token::{token_access, TokenAccess};
+
+/// Session key holding the logged in user's id, also used by the rate limiter
+pub const SESSION_USER_ID: &str = "user_id";
+/// Session key tying the session to the password it was opened with
+pub const SESSION_CREDENTIALS: &str = "credentials";
+
+/// Paths reachable without logging in
+const PUBLIC_PATHS: [&str; 3] = ["/login", "/api/auth/login", "/favicon.ico"];
+const PUBLIC_PREFIXES: [&str; 1] = ["/static/"];
+
+
+/// Middleware rejecting requests without a logged in user
+///
+/// API requests get 401, page requests are redirected to the login page.
+/// API routes also accept an `Authorization: Bearer` API token in place of the session.
+/// The user is stored in the request extensions and extracted in handlers with `user: User`.
+pub async fn require_login(
+    req: ServiceRequest,
+    next: Next<impl MessageBody>,
+) -> Result<ServiceResponse<EitherBody<impl MessageBody>>, Error> {
+    let path = routed_path(&req);
+    if PUBLIC_PATHS.contains(&path) || PUBLIC_PREFIXES.iter().any(|prefix| path.starts_with(prefix)) {
+        return next.call(req).await.map(ServiceResponse::map_into_left_body);
+    }
+
+    if path.starts_with("/api/") && let Some(token) = bearer_token(req.headers()).map(str::to_string) {
+        return match token_user(&req, &token).await {
+            Ok(user) => {
+                req.extensions_mut().insert(user);
+                next.call(req).await.map(ServiceResponse::map_into_left_body)
+            },
+            Err(response) => Ok(req.into_response(response).map_into_right_body()),
+        };
+    }
+
+    match session_user(&req).await {
+        Some(user) => {
+            req.extensions_mut().insert(user);
+            next.call(req).await.map(ServiceResponse::map_into_left_body)
+        },
+        None => {
+            let response = match routed_path(&req).starts_with("/api/") {
+                true => HttpResponse::Unauthorized().json(ApiResponse::<()>::error("Login required".to_string())),
+                false => HttpResponse::SeeOther().insert_header((header::LOCATION, "/login")).finish(),
+            };
+            Ok(req.into_response(response).map_into_right_body())
+        },
+    }
+}
+
+/// Percent-decoded path the router matches routes against, `req.path()` is still encoded
+fn routed_path(req: &ServiceRequest) -> &str {
+    req.match_info().as_str()
+}
+
+async fn session_user(req: &ServiceRequest) -> Option<User> {
+    let session = req.get_session();
+    let user_id = session.get::<String>(SESSION_USER_ID).ok().flatten()?.parse::<i64>().ok()?;
+    let pool = req.app_data::<web::Data<SqlitePool>>()?;
+
+    match User::find_by_id(user_id, pool).await {
+        Ok(Some(user)) if session.get::<String>(SESSION_CREDENTIALS).ok().flatten() == Some(session_credentials(&user)) => Some(user),
+        Ok(Some(user)) => {
+            log::info!("Session of '{}' was opened before a password change", user.username);
+            session.purge();
+            None
+        },
+        Ok(None) => {
+            log::warn!("Session refers to deleted user {}", user_id);
+            session.purge();
+            None
+        },
+        Err(e) => {
+            log::error!("Failed to load session user {}: {}", user_id, e);
+            None
+        },
+    }
+}
+
+async fn token_user(req: &ServiceRequest, token: &str) -> Result<User, HttpResponse> {
+    let unauthorized = || HttpResponse::Unauthorized()
+        .json(ApiResponse::<()>::error("Invalid or expired API token".to_string()));
+    let pool = req.app_data::<web::Data<SqlitePool>>()
+        .ok_or_else(|| HttpResponse::InternalServerError().finish())?;
+
+    let mut api_token = match ApiToken::find_by_token(token, pool).await {
+        Ok(Some(api_token)) => api_token,
+        Ok(None) => {
+            log::warn!("Rejected unknown or expired API token for {}", req.path());
+            return Err(unauthorized());
+        },
+        Err(e) => {
+            log::error!("Failed to look up API token: {}", e);
+            return Err(HttpResponse::InternalServerError().finish());
+        },
+    };
+
+    let denied = match token_access(req.method(), routed_path(req)) {
+        TokenAccess::Any => None,
+        TokenAccess::Scope(scope) if api_token.has_scope(scope) => None,
+        TokenAccess::Scope(scope) => Some(format!("API token lacks the '{}' scope", scope)),
+        TokenAccess::SessionOnly => Some("Not available to API tokens, log in instead".to_string()),
+    };
+    if let Some(message) = denied {
+        return Err(HttpResponse::Forbidden().json(ApiResponse::<()>::error(message)));
+    }
+
+    let user = match User::find_by_id(api_token.user_id, pool).await {
+        Ok(Some(user)) => user,
+        Ok(None) => return Err(unauthorized()),
+        Err(e) => {
+            log::error!("Failed to load user {} of API token: {}", api_token.user_id, e);
+            return Err(HttpResponse::InternalServerError().finish());
+        },
+    };
+
+    if let Err(e) = api_token.touch(pool).await {
+        log::warn!("Failed to record use of API token {}: {}", api_token.id, e);
+    }
+
+    Ok(user)
+}
+
+impl FromRequest for User {
+    type Error = Error;
+    type Future = Ready<Result<Self, Self::Error>>;
+
+    fn from_request(req: &HttpRequest, _payload: &mut Payload) -> Self::Future {
+        ready(req.extensions().get::<User>().cloned().ok_or_else(|| ErrorUnauthorized("Login required")))
+    }
+}
+
+/// Value stored under [`SESSION_CREDENTIALS`], changes with the password so older sessions stop working
+pub fn session_credentials(user: &User) -> String {
+    hash_token(&user.password_hash)
+}
+
+/// Hash checked when the username does not exist, so both failures take the same time
+pub fn dummy_password_hash() -> &'static str {
+    static DUMMY_HASH: OnceLock<String> = OnceLock::new();
+    DUMMY_HASH.get_or_init(|| hash_password("dummy password").unwrap_or_default())
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use actix_web::http::StatusCode;
+    use actix_web::middleware::from_fn;
+    use actix_web::{test, App};
+    use crate::models::{Role, TokenScope};
+    use crate::test_utils::test_pool;
+
+    #[actix_web::test]
+    async fn test_token_scope_uses_routed_path() {
+        let pool = test_pool().await;
+        let user = User::create("admin", "admin password", Role::Admin, &pool).await.unwrap();
+        let created = ApiToken::create(user.id, "print only", &[TokenScope::Print], None, &pool).await.unwrap();
+
+        let app = test::init_service(
+            App::new()
+                .wrap(from_fn(require_login))
+                .app_data(web::Data::new(pool.clone()))
+                .route("/api/tokens", web::post().to(HttpResponse::Ok))
+                .route("/api/printers/{name}/disable", web::post().to(HttpResponse::Ok))
+                .route("/api/print/jobs", web::get().to(HttpResponse::Ok))
+        ).await;
+
+        let expected = [
+            ("/api/tokens", StatusCode::FORBIDDEN),
+            ("/api/%74okens", StatusCode::FORBIDDEN),
+            ("/api/%70rinters/epson/disable", StatusCode::FORBIDDEN),
+            ("/%61pi/printers/epson/disable", StatusCode::FORBIDDEN),
+        ];
+        for (uri, status) in expected {
+            let req = test::TestRequest::post().uri(uri)
+                .insert_header((header::AUTHORIZATION, format!("Bearer {}", created.token)))
+                .to_request();
+            assert_eq!(test::call_service(&app, req).await.status(), status, "{}", uri);
+        }
+
+        let req = test::TestRequest::get().uri("/api/%70rint/jobs")
+            .insert_header((header::AUTHORIZATION, format!("Bearer {}", created.token)))
+            .to_request();
+        assert_eq!(test::call_service(&app, req).await.status(), StatusCode::OK);
+    }
+}
@@ -0,0 +1,126 @@
+use std::future::{ready, Future, Ready};
+use std::pin::Pin;
+use std::rc::Rc;
+use actix_web::body::EitherBody;
+use actix_web::dev::{forward_ready, Service, ServiceRequest, ServiceResponse, Transform};
+use actix_web::{Error, HttpMessage, HttpResponse};
+use crate::models::{ApiResponse, Role, User};
+
+
+/// Route middleware answering 403 Forbidden unless the user has at least `role`
+///
+/// Relies on [`super::require_login`] having stored the user in the request extensions,
+/// so it is meant for `Route::wrap` and scopes inside the app.
+#[derive(Clone)]
+pub struct RequireRole {
+    role: Role,
+}
+
+impl RequireRole {
+    pub fn new(role: Role) -> Self {
+        Self { role }
+    }
+}
+
+impl<S, B> Transform<S, ServiceRequest> for RequireRole
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error> + 'static,
+    S::Future: 'static,
+    B: 'static,
+{
+    type Response = ServiceResponse<EitherBody<B>>;
+    type Error = Error;
+    type Transform = RequireRoleMiddleware<S>;
+    type InitError = ();
+    type Future = Ready<Result<Self::Transform, Self::InitError>>;
+
+    fn new_transform(&self, service: S) -> Self::Future {
+        ready(Ok(RequireRoleMiddleware {
+            service: Rc::new(service),
+            role: self.role,
+        }))
+    }
+}
+
+pub struct RequireRoleMiddleware<S> {
+    service: Rc<S>,
+    role: Role,
+}
+
+impl<S, B> Service<ServiceRequest> for RequireRoleMiddleware<S>
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error> + 'static,
+    S::Future: 'static,
+    B: 'static,
+{
+    type Response = ServiceResponse<EitherBody<B>>;
+    type Error = Error;
+    type Future = Pin<Box<dyn Future<Output = Result<Self::Response, Self::Error>>>>;
+
+    forward_ready!(service);
+
+    fn call(&self, req: ServiceRequest) -> Self::Future {
+        let service = Rc::clone(&self.service);
+        let required = self.role;
+
+        Box::pin(async move {
+            let denied = match req.extensions().get::<User>() {
+                None => Some(HttpResponse::Unauthorized().json(ApiResponse::<()>::error("Login required".to_string()))),
+                Some(user) if !user.role.at_least(required) => {
+                    log::warn!("User '{}' ({}) denied {} {}, needs {}", user.username, user.role, req.method(), req.path(), required);
+                    Some(HttpResponse::Forbidden().json(ApiResponse::<()>::error(format!("The {} role is required", required))))
+                },
+                Some(_) => None,
+            };
+
+            match denied {
+                Some(response) => Ok(req.into_response(response).map_into_right_body()),
+                None => service.call(req).await.map(ServiceResponse::map_into_left_body),
+            }
+        })
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use actix_web::http::StatusCode;
+    use actix_web::{test, web, App};
+    use chrono::Utc;
+
+    fn user_with_role(role: Role) -> User {
+        User {
+            id: 1,
+            username: "test".to_string(),
+            password_hash: String::new(),
+            role,
+            created_at: Utc::now(),
+            last_login_at: None,
+        }
+    }
+
+    #[actix_web::test]
+    async fn test_require_role() {
+        let app = test::init_service(
+            App::new().route(
+                "/maintenance",
+                web::post().to(HttpResponse::Ok).wrap(RequireRole::new(Role::Admin))
+            )
+        ).await;
+
+        let expected = [
+            (None, StatusCode::UNAUTHORIZED),
+            (Some(Role::Guest), StatusCode::FORBIDDEN),
+            (Some(Role::User), StatusCode::FORBIDDEN),
+            (Some(Role::Admin), StatusCode::OK),
+        ];
+        for (role, status) in expected {
+            let req = test::TestRequest::post().uri("/maintenance").to_request();
+            if let Some(role) = role {
+                req.extensions_mut().insert(user_with_role(role));
+            }
+            assert_eq!(test::call_service(&app, req).await.status(), status);
+        }
+    }
+}
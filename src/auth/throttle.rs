@@ -0,0 +1,125 @@
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+use actix_web::HttpRequest;
+
+
+struct FailedLogins {
+    count: u32,
+    window_start: Instant,
+    locked_until: Option<Instant>,
+}
+
+/// Counts failed logins per client and locks the client out once `max_failures` is reached
+pub struct LoginThrottle {
+    max_failures: u32,
+    lockout: Duration,
+    clients: Mutex<HashMap<String, FailedLogins>>,
+}
+
+impl LoginThrottle {
+    pub fn new(max_failures: u32, lockout: Duration) -> Self {
+        Self {
+            max_failures,
+            lockout,
+            clients: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Remaining lockout for `key`, `None` when a login attempt is allowed
+    pub fn locked_for(&self, key: &str) -> Option<Duration> {
+        self.locked_for_at(key, Instant::now())
+    }
+
+    pub fn record_failure(&self, key: &str) {
+        self.record_failure_at(key, Instant::now());
+    }
+
+    pub fn reset(&self, key: &str) {
+        self.clients.lock().unwrap_or_else(|e| e.into_inner()).remove(key);
+    }
+
+    fn locked_for_at(&self, key: &str, now: Instant) -> Option<Duration> {
+        let clients = self.clients.lock().unwrap_or_else(|e| e.into_inner());
+
+        clients.get(key)
+            .and_then(|failed| failed.locked_until)
+            .filter(|locked_until| *locked_until > now)
+            .map(|locked_until| locked_until - now)
+    }
+
+    fn record_failure_at(&self, key: &str, now: Instant) {
+        let mut clients = self.clients.lock().unwrap_or_else(|e| e.into_inner());
+
+        // Forget clients whose window and lockout are over
+        clients.retain(|_, failed| {
+            now.duration_since(failed.window_start) < self.lockout
+                || failed.locked_until.is_some_and(|locked_until| locked_until > now)
+        });
+
+        let failed = clients.entry(key.to_string()).or_insert(FailedLogins {
+            count: 0,
+            window_start: now,
+            locked_until: None,
+        });
+        failed.count += 1;
+
+        if failed.count >= self.max_failures {
+            log::warn!("Too many failed logins from {}, locked for {} minutes", key, self.lockout.as_secs() / 60);
+            failed.locked_until = Some(now + self.lockout);
+            failed.count = 0;
+            failed.window_start = now;
+        }
+    }
+}
+
+/// Key failed logins of a request are counted under, the address of the connection
+///
+/// `X-Forwarded-For` and `Forwarded` come from the client, a new value per attempt would dodge the lockout.
+pub fn client_key(req: &HttpRequest) -> String {
+    req.peer_addr().map_or_else(|| "unknown".to_string(), |addr| addr.ip().to_string())
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use actix_web::test::TestRequest;
+
+    #[test]
+    fn test_lockout_after_failures() {
+        let throttle = LoginThrottle::new(3, Duration::from_secs(60));
+        let start = Instant::now();
+
+        throttle.record_failure_at("10.0.0.2", start);
+        throttle.record_failure_at("10.0.0.2", start);
+        assert_eq!(throttle.locked_for_at("10.0.0.2", start), None);
+
+        throttle.record_failure_at("10.0.0.2", start);
+        assert_eq!(throttle.locked_for_at("10.0.0.2", start + Duration::from_secs(20)), Some(Duration::from_secs(40)));
+        assert_eq!(throttle.locked_for_at("10.0.0.3", start), None);
+        assert_eq!(throttle.locked_for_at("10.0.0.2", start + Duration::from_secs(61)), None);
+
+        throttle.reset("10.0.0.2");
+        assert_eq!(throttle.locked_for_at("10.0.0.2", start), None);
+    }
+
+    #[test]
+    fn test_client_key_ignores_forwarded_headers() {
+        let throttle = LoginThrottle::new(2, Duration::from_secs(60));
+        let peer = "192.168.1.20:51000".parse().unwrap();
+
+        for forwarded_for in ["10.0.0.1", "10.0.0.2"] {
+            let req = TestRequest::default()
+                .peer_addr(peer)
+                .insert_header(("X-Forwarded-For", forwarded_for))
+                .insert_header(("Forwarded", format!("for={}", forwarded_for)))
+                .to_http_request();
+            assert_eq!(client_key(&req), "192.168.1.20");
+            throttle.record_failure(&client_key(&req));
+        }
+
+        assert!(throttle.locked_for("192.168.1.20").is_some());
+        assert_eq!(client_key(&TestRequest::default().to_http_request()), "unknown");
+    }
+}
@@ -0,0 +1,92 @@
+use actix_web::http::header::{HeaderMap, AUTHORIZATION};
+use actix_web::http::Method;
+use crate::models::TokenScope;
+
+
+/// What an API token needs to call a route
+#[derive(Debug, PartialEq, Eq)]
+pub enum TokenAccess {
+    /// Any valid token
+    Any,
+    /// Token must carry the scope
+    Scope(TokenScope),
+    /// Account management stays behind the login session
+    SessionOnly,
+}
+
+/// Token from an `Authorization: Bearer` header
+pub fn bearer_token(headers: &HeaderMap) -> Option<&str> {
+    let value = headers.get(AUTHORIZATION)?.to_str().ok()?;
+    let (scheme, token) = value.split_once(' ')?;
+
+    match scheme.eq_ignore_ascii_case("bearer") && !token.trim().is_empty() {
+        true => Some(token.trim()),
+        false => None,
+    }
+}
+
+/// What a token needs for `path`, which has to be the percent-decoded path the router matches
+pub fn token_access(method: &Method, path: &str) -> TokenAccess {
+    let path = path.trim_end_matches('/');
+
+    if path.starts_with("/api/tokens") || path == "/api/auth/password" || path == "/api/auth/logout" {
+        return TokenAccess::SessionOnly;
+    }
+
+    if path.starts_with("/api/users")
+        || path.starts_with("/api/system/nozzle")
+        || (path == "/api/system/settings" && method != Method::GET)
+        || path == "/api/printers/discover"
+        || (path.starts_with("/api/printers") && method != Method::GET) {
+        return TokenAccess::Scope(TokenScope::Admin);
+    }
+
+    // Looks for printers and scanners alike, the user role is enforced on the route
+    if path == "/api/system/devices/refresh" {
+        return TokenAccess::Any;
+    }
+
+    // Also covers /api/printers and /api/scanners
+    if path.starts_with("/api/print") {
+        return TokenAccess::Scope(TokenScope::Print);
+    }
+    if path.starts_with("/api/scan") {
+        return TokenAccess::Scope(TokenScope::Scan);
+    }
+
+    TokenAccess::Any
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use actix_web::http::header::HeaderValue;
+
+    #[test]
+    fn test_token_access() {
+        assert_eq!(token_access(&Method::POST, "/api/print"), TokenAccess::Scope(TokenScope::Print));
+        assert_eq!(token_access(&Method::GET, "/api/printers"), TokenAccess::Scope(TokenScope::Print));
+        assert_eq!(token_access(&Method::POST, "/api/printers/epson/disable"), TokenAccess::Scope(TokenScope::Admin));
+        assert_eq!(token_access(&Method::GET, "/api/printers/discover"), TokenAccess::Scope(TokenScope::Admin));
+        assert_eq!(token_access(&Method::GET, "/api/scan/download/x"), TokenAccess::Scope(TokenScope::Scan));
+        assert_eq!(token_access(&Method::GET, "/api/system/settings"), TokenAccess::Any);
+        assert_eq!(token_access(&Method::POST, "/api/system/settings"), TokenAccess::Scope(TokenScope::Admin));
+        assert_eq!(token_access(&Method::POST, "/api/system/devices/refresh"), TokenAccess::Any);
+        assert_eq!(token_access(&Method::DELETE, "/api/users/2"), TokenAccess::Scope(TokenScope::Admin));
+        assert_eq!(token_access(&Method::POST, "/api/tokens"), TokenAccess::SessionOnly);
+        assert_eq!(token_access(&Method::GET, "/api/auth/me"), TokenAccess::Any);
+    }
+
+    #[test]
+    fn test_bearer_token() {
+        let mut headers = HeaderMap::new();
+        assert_eq!(bearer_token(&headers), None);
+
+        headers.insert(AUTHORIZATION, HeaderValue::from_static("Bearer wpt_abc"));
+        assert_eq!(bearer_token(&headers), Some("wpt_abc"));
+
+        headers.insert(AUTHORIZATION, HeaderValue::from_static("Basic YWRtaW46YWRtaW4="));
+        assert_eq!(bearer_token(&headers), None);
+    }
+}
@@ -56,6 +56,107 @@ pub async fn run_migrations(pool: &SqlitePool) -> Result<(), sqlx::Error> {
         ;"#
     ).execute(pool).await?;
 
+    sqlx::query(
+        r#"
+        CREATE TABLE IF NOT EXISTS users (
+            id INTEGER PRIMARY KEY AUTOINCREMENT,
+            username TEXT UNIQUE NOT NULL COLLATE NOCASE,
+            password_hash TEXT NOT NULL,
+            role TEXT NOT NULL DEFAULT 'user',
+            created_at DATETIME NOT NULL,
+            last_login_at DATETIME
+        )
+        ;"#
+    ).execute(pool).await?;
+
+    // System settings editable from the UI, values are JSON encoded
+    sqlx::query(
+        r#"
+        CREATE TABLE IF NOT EXISTS settings (
+            key TEXT PRIMARY KEY NOT NULL,
+            value TEXT NOT NULL,
+            updated_at DATETIME NOT NULL
+        )
+        ;"#
+    ).execute(pool).await?;
+
+    // Used by the embedded session store
+    sqlx::query(
+        r#"
+        CREATE TABLE IF NOT EXISTS sessions (
+            session_key TEXT PRIMARY KEY NOT NULL,
+            state TEXT NOT NULL,
+            expires_at INTEGER NOT NULL
+        )
+        ;"#
+    ).execute(pool).await?;
+
+    // Personal API tokens, only the SHA-256 of the token is stored
+    sqlx::query(
+        r#"
+        CREATE TABLE IF NOT EXISTS api_tokens (
+            id INTEGER PRIMARY KEY AUTOINCREMENT,
+            user_id INTEGER NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+            name TEXT NOT NULL,
+            token_hash TEXT UNIQUE NOT NULL,
+            scopes TEXT NOT NULL,
+            created_at DATETIME NOT NULL,
+            expires_at DATETIME,
+            last_used_at DATETIME
+        )
+        ;"#
+    ).execute(pool).await?;
+
+    // Ink and toner levels over time, a row is only added when a level changes
+    sqlx::query(
+        r#"
+        CREATE TABLE IF NOT EXISTS marker_levels (
+            id INTEGER PRIMARY KEY AUTOINCREMENT,
+            printer TEXT NOT NULL,
+            marker TEXT NOT NULL,
+            color TEXT,
+            level INTEGER NOT NULL,
+            recorded_at DATETIME NOT NULL
+        )
+        ;"#
+    ).execute(pool).await?;
+
+    // Job owners, added after the first release so older databases get the columns here
+    add_column_if_missing(pool, "print_jobs", "user_id", "INTEGER REFERENCES users(id) ON DELETE SET NULL").await?;
+    add_column_if_missing(pool, "scan_jobs", "user_id", "INTEGER REFERENCES users(id) ON DELETE SET NULL").await?;
+
+    // Print options beyond copies, pages, duplex, colour and page size
+    add_column_if_missing(pool, "print_jobs", "orientation", "TEXT NOT NULL DEFAULT 'portrait'").await?;
+    add_column_if_missing(pool, "print_jobs", "scaling", "TEXT NOT NULL DEFAULT 'auto'").await?;
+    add_column_if_missing(pool, "print_jobs", "pages_per_sheet", "INTEGER NOT NULL DEFAULT 1").await?;
+    add_column_if_missing(pool, "print_jobs", "quality", "TEXT NOT NULL DEFAULT 'normal'").await?;
+    add_column_if_missing(pool, "print_jobs", "media_type", "TEXT").await?;
+    add_column_if_missing(pool, "print_jobs", "input_tray", "TEXT").await?;
+    add_column_if_missing(pool, "print_jobs", "collate_copies", "BOOLEAN NOT NULL DEFAULT true").await?;
+
+    // Paper actually used, as counted by CUPS
+    add_column_if_missing(pool, "print_jobs", "pages_printed", "INTEGER").await?;
+    add_column_if_missing(pool, "print_jobs", "sheets_printed", "INTEGER").await?;
+
+    // mime_type used to hold the file extension
+    sqlx::query(
+        r#"
+        UPDATE print_jobs SET mime_type = CASE lower(mime_type)
+            WHEN 'pdf' THEN 'application/pdf'
+            WHEN 'ps' THEN 'application/postscript'
+            WHEN 'txt' THEN 'text/plain'
+            WHEN 'jpg' THEN 'image/jpeg'
+            WHEN 'jpeg' THEN 'image/jpeg'
+            WHEN 'png' THEN 'image/png'
+            WHEN 'tif' THEN 'image/tiff'
+            WHEN 'tiff' THEN 'image/tiff'
+            WHEN 'gif' THEN 'image/gif'
+            ELSE mime_type
+        END
+        WHERE mime_type NOT LIKE '%/%'
+        ;"#
+    ).execute(pool).await?;
+
     // Pi Zero 2W optimizations
     sqlx::query("CREATE INDEX IF NOT EXISTS idx_scan_jobs_status ON scan_jobs(status)")
         .execute(pool).await?;
@@ -73,5 +174,34 @@ pub async fn run_migrations(pool: &SqlitePool) -> Result<(), sqlx::Error> {
     sqlx::query("CREATE INDEX IF NOT EXISTS idx_print_jobs_cups ON print_jobs(cups_id_job)")
         .execute(pool).await?;
 
+    sqlx::query("CREATE INDEX IF NOT EXISTS idx_scan_jobs_user ON scan_jobs(user_id)")
+        .execute(pool).await?;
+    sqlx::query("CREATE INDEX IF NOT EXISTS idx_print_jobs_user ON print_jobs(user_id)")
+        .execute(pool).await?;
+
+    sqlx::query("CREATE INDEX IF NOT EXISTS idx_api_tokens_user ON api_tokens(user_id)")
+        .execute(pool).await?;
+
+    sqlx::query("CREATE INDEX IF NOT EXISTS idx_marker_levels_printer ON marker_levels(printer, recorded_at)")
+        .execute(pool).await?;
+
+    sqlx::query("CREATE INDEX IF NOT EXISTS idx_sessions_expires ON sessions(expires_at)")
+        .execute(pool).await?;
+
+    Ok(())
+}
+
+async fn add_column_if_missing(pool: &SqlitePool, table: &str, column: &str, definition: &str) -> Result<(), sqlx::Error> {
+    let exists = sqlx::query("SELECT 1 FROM pragma_table_info(?) WHERE name = ?;")
+        .bind(table)
+        .bind(column)
+        .fetch_optional(pool).await?
+        .is_some();
+
+    if !exists {
+        sqlx::query(&format!("ALTER TABLE {} ADD COLUMN {} {};", table, column, definition))
+            .execute(pool).await?;
+    }
+
     Ok(())
-}
\ No newline at end of file
+}
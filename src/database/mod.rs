@@ -7,13 +7,15 @@ use sqlx::sqlite::{SqliteJournalMode, SqliteSynchronous};
 pub mod migrations;
 
 
-pub async fn init_database() -> Result<SqlitePool, sqlx::Error> {
-    let db_path = Path::new("data/print_scan_manager.db");
+pub async fn init_database(db_path: &Path) -> Result<SqlitePool, sqlx::Error> {
     if let Some(parent) = db_path.parent() {
-        fs::create_dir_all(parent).map_err(|e| {sqlx::Error::Io(e)})?;
+        fs::create_dir_all(parent).map_err(sqlx::Error::Io)?;
     }
 
-    let options = SqliteConnectOptions::from_str(db_path.to_str().unwrap())?
+    let db_path = db_path.to_str()
+        .ok_or_else(|| sqlx::Error::Configuration("Database path is not valid UTF-8".into()))?;
+
+    let options = SqliteConnectOptions::from_str(db_path)?
         .create_if_missing(true)
         .journal_mode(SqliteJournalMode::Wal)
         .synchronous(SqliteSynchronous::Normal)
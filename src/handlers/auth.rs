@@ -0,0 +1,201 @@
+use actix_session::Session;
+use actix_web::error::ErrorInternalServerError;
+use actix_web::{web, HttpRequest, HttpResponse, Result};
+use sqlx::SqlitePool;
+use crate::auth::{client_key, dummy_password_hash, session_credentials, LoginThrottle, SESSION_CREDENTIALS, SESSION_USER_ID};
+use crate::handlers::{internal_error, json_error, json_success};
+use crate::models::{
+    validate_password, validate_username, verify_password, ApiResponse, ApiToken, ChangePasswordRequest, LoginRequest,
+    NewUserRequest, Role, RoleUpdateRequest, User,
+};
+
+
+/// POST /api/auth/login - Log in with username and password
+pub async fn login(
+    req: HttpRequest,
+    body: web::Json<LoginRequest>,
+    session: Session,
+    pool: web::Data<SqlitePool>,
+    throttle: web::Data<LoginThrottle>
+) -> Result<HttpResponse> {
+    let client = client_key(&req);
+
+    if let Some(remaining) = throttle.locked_for(&client) {
+        return Ok(HttpResponse::TooManyRequests()
+            .insert_header(("Retry-After", remaining.as_secs().max(1).to_string()))
+            .json(ApiResponse::<()>::error("Too many failed logins, try again later".to_string())));
+    }
+
+    let LoginRequest { username, password } = body.into_inner();
+    let user = User::find_by_username(username.trim(), &pool)
+        .await
+        .map_err(|e| ErrorInternalServerError(e.to_string()))?;
+
+    // Argon2 is slow on purpose, keep it off the worker thread
+    let password_hash = user.as_ref()
+        .map(|user| user.password_hash.clone())
+        .unwrap_or_else(|| dummy_password_hash().to_string());
+    let password_valid = web::block(move || verify_password(&password, &password_hash)).await?;
+
+    let mut user = match user {
+        Some(user) if password_valid => user,
+        _ => {
+            log::warn!("Failed login for '{}' from {}", username, client);
+            throttle.record_failure(&client);
+            return Ok(HttpResponse::Unauthorized()
+                .json(ApiResponse::<()>::error("Invalid username or password".to_string())));
+        }
+    };
+
+    throttle.reset(&client);
+
+    session.renew();
+    if let Err(e) = session.insert(SESSION_USER_ID, user.id.to_string())
+        .and_then(|_| session.insert(SESSION_CREDENTIALS, session_credentials(&user))) {
+        log::error!("Failed to store session: {}", e);
+        return internal_error("Failed to create session".to_string());
+    }
+
+    if let Err(e) = user.update_last_login(&pool).await {
+        log::warn!("Failed to update last login of '{}': {}", user.username, e);
+    }
+
+    log::info!("User '{}' logged in from {}", user.username, client);
+    json_success(user)
+}
+
+/// POST /api/auth/logout - End the current session
+pub async fn logout(session: Session, user: User) -> Result<HttpResponse> {
+    session.purge();
+    log::info!("User '{}' logged out", user.username);
+
+    json_success(serde_json::json!({"message": "Logged out"}))
+}
+
+/// GET /api/auth/me - Get the logged in user
+pub async fn me(user: User) -> Result<HttpResponse> {
+    json_success(user)
+}
+
+/// POST /api/auth/password - Change the logged in user's password
+///
+/// Ends the user's other sessions and revokes their API tokens, the current session stays logged in.
+pub async fn change_password(
+    body: web::Json<ChangePasswordRequest>,
+    session: Session,
+    mut user: User,
+    pool: web::Data<SqlitePool>
+) -> Result<HttpResponse> {
+    let ChangePasswordRequest { current_password, new_password } = body.into_inner();
+
+    let password_hash = user.password_hash.clone();
+    if !web::block(move || verify_password(&current_password, &password_hash)).await? {
+        return json_error("Current password is incorrect".to_string());
+    }
+    if let Err(e) = validate_password(&new_password) {
+        return json_error(e);
+    }
+
+    user.set_password(&new_password, &pool)
+        .await
+        .map_err(|e| ErrorInternalServerError(e.to_string()))?;
+    let revoked = ApiToken::remove_by_user(user.id, &pool)
+        .await
+        .map_err(|e| ErrorInternalServerError(e.to_string()))?;
+
+    session.renew();
+    if let Err(e) = session.insert(SESSION_CREDENTIALS, session_credentials(&user)) {
+        log::error!("Failed to store session: {}", e);
+        return internal_error("Failed to update session".to_string());
+    }
+
+    log::info!("User '{}' changed their password, {} API tokens revoked", user.username, revoked);
+    json_success(serde_json::json!({"message": "Password changed"}))
+}
+
+/// GET /api/users - List all users (admin)
+pub async fn list_users(pool: web::Data<SqlitePool>) -> Result<HttpResponse> {
+    let users = User::get_all(&pool)
+        .await
+        .map_err(|e| ErrorInternalServerError(e.to_string()))?;
+
+    json_success(users)
+}
+
+/// POST /api/users - Create a user (admin)
+pub async fn create_user(
+    body: web::Json<NewUserRequest>,
+    user: User,
+    pool: web::Data<SqlitePool>
+) -> Result<HttpResponse> {
+    let NewUserRequest { username, password, role } = body.into_inner();
+    let username = username.trim().to_string();
+    if let Err(e) = validate_username(&username).and_then(|_| validate_password(&password)) {
+        return json_error(e);
+    }
+
+    let exists = User::find_by_username(&username, &pool)
+        .await
+        .map_err(|e| ErrorInternalServerError(e.to_string()))?
+        .is_some();
+    if exists {
+        return json_error(format!("User '{}' already exists", username));
+    }
+
+    let new_user = User::create(&username, &password, role.unwrap_or(Role::User), &pool)
+        .await
+        .map_err(|e| ErrorInternalServerError(e.to_string()))?;
+
+    log::info!("User '{}' created account '{}' ({})", user.username, new_user.username, new_user.role);
+    json_success(new_user)
+}
+
+/// DELETE /api/users/{user_id} - Delete a user (admin)
+pub async fn delete_user(path: web::Path<i64>, user: User, pool: web::Data<SqlitePool>) -> Result<HttpResponse> {
+    let user_id = path.into_inner();
+    if user_id == user.id {
+        return json_error("You cannot delete your own account".to_string());
+    }
+
+    match User::remove_by_id(user_id, &pool).await {
+        Ok(0) => json_error("User not found".to_string()),
+        Ok(_) => {
+            log::info!("User '{}' deleted user {}", user.username, user_id);
+            json_success(format!("Successfully removed user {}", user_id))
+        },
+        Err(e) => internal_error(format!("Failed to remove user: {}", e)),
+    }
+}
+
+/// PUT /api/users/{user_id}/role - Change a user's role (admin)
+pub async fn update_role(
+    path: web::Path<i64>,
+    body: web::Json<RoleUpdateRequest>,
+    user: User,
+    pool: web::Data<SqlitePool>
+) -> Result<HttpResponse> {
+    let user_id = path.into_inner();
+    let role = body.into_inner().role;
+
+    let mut target = match User::find_by_id(user_id, &pool).await {
+        Ok(Some(target)) => target,
+        Ok(None) => return json_error("User not found".to_string()),
+        Err(e) => return internal_error(format!("Failed to load user: {}", e)),
+    };
+
+    if target.role == Role::Admin && role != Role::Admin {
+        let admins = User::count_admins(&pool)
+            .await
+            .map_err(|e| ErrorInternalServerError(e.to_string()))?;
+        if admins <= 1 {
+            return json_error("Cannot remove the admin role from the last admin".to_string());
+        }
+    }
+
+    target.set_role(role, &pool)
+        .await
+        .map_err(|e| ErrorInternalServerError(e.to_string()))?;
+
+    log::info!("User '{}' changed role of '{}' to {}", user.username, target.username, role);
+    json_success(target)
+}
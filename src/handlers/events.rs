@@ -1,4 +1,3 @@
-use std::cmp::Ordering;
 use actix_web::{web, Responder};
 use actix_web_lab::sse::{self, Sse, Data as SseData};
 use futures::stream::Stream;
@@ -12,7 +11,7 @@ use tokio_stream::wrappers::IntervalStream;
 use tokio_stream::StreamExt;
 use sqlx::SqlitePool;
 use serde_json::json;
-use crate::models::{PrintJob, ScanJob, ScanJobStatus, PrintJobStatus, JobQueue, Job};
+use crate::models::{PrintJob, ScanJob, ScanJobStatus, PrintJobStatus, JobQueue, Job, SystemSettings, SystemSettingsResponse, User, AppState, DeviceUpdate, LowInkWarning};
 use crate::utils::get_disk_space;
 
 #[derive(Clone)]
@@ -20,6 +19,13 @@ pub struct EventState {
     pub queue_version: Arc<RwLock<u64>>,
     pub status_version: Arc<RwLock<u64>>,
     pub recent_activity_version: Arc<RwLock<u64>>,
+    pub settings_version: Arc<RwLock<u64>>,
+    pub devices_version: Arc<RwLock<u64>>,
+    /// What the last device refresh found, sent along with the device lists
+    pub device_update: Arc<RwLock<DeviceUpdate>>,
+    pub ink_warning_version: Arc<RwLock<u64>>,
+    /// Markers that dropped below the low-ink threshold on the last check
+    pub ink_warnings: Arc<RwLock<Vec<LowInkWarning>>>,
 }
 
 impl EventState {
@@ -28,6 +34,11 @@ impl EventState {
             queue_version: Arc::new(RwLock::new(0)),
             status_version: Arc::new(RwLock::new(0)),
             recent_activity_version: Arc::new(RwLock::new(0)),
+            settings_version: Arc::new(RwLock::new(0)),
+            devices_version: Arc::new(RwLock::new(0)),
+            device_update: Arc::new(RwLock::new(DeviceUpdate::default())),
+            ink_warning_version: Arc::new(RwLock::new(0)),
+            ink_warnings: Arc::new(RwLock::new(Vec::new())),
         }
     }
 
@@ -35,11 +46,8 @@ impl EventState {
         let mut version = self.queue_version.write().await;
         *version += 1;
 
-        match version.cmp(&u64::MAX) {
-            Ordering::Equal => {
-                *version = 0;
-            }
-            _ => {}
+        if *version == u64::MAX {
+            *version = 0;
         }
         // self.increment_recent_activity_version().await;
     }
@@ -48,11 +56,8 @@ impl EventState {
         let mut version = self.status_version.write().await;
         *version += 1;
 
-        match version.cmp(&u64::MAX) {
-            Ordering::Equal => {
-                *version = 0;
-            }
-            _ => {}
+        if *version == u64::MAX {
+            *version = 0;
         }
     }
 
@@ -60,11 +65,39 @@ impl EventState {
         let mut version = self.recent_activity_version.write().await;
         *version += 1;
 
-        match version.cmp(&u64::MAX) {
-            Ordering::Equal => {
-                *version = 0;
-            }
-            _ => {}
+        if *version == u64::MAX {
+            *version = 0;
+        }
+    }
+
+    pub async fn increment_settings_version(&self) {
+        let mut version = self.settings_version.write().await;
+        *version += 1;
+
+        if *version == u64::MAX {
+            *version = 0;
+        }
+    }
+
+    pub async fn publish_device_update(&self, update: DeviceUpdate) {
+        *self.device_update.write().await = update;
+
+        let mut version = self.devices_version.write().await;
+        *version += 1;
+
+        if *version == u64::MAX {
+            *version = 0;
+        }
+    }
+
+    pub async fn publish_ink_warnings(&self, warnings: Vec<LowInkWarning>) {
+        *self.ink_warnings.write().await = warnings;
+
+        let mut version = self.ink_warning_version.write().await;
+        *version += 1;
+
+        if *version == u64::MAX {
+            *version = 0;
         }
     }
 }
@@ -74,11 +107,13 @@ pub async fn event_stream(
     job_queue: web::Data<JobQueue>,
     pool: web::Data<SqlitePool>,
     event_state: web::Data<EventState>,
+    app_state: web::Data<AppState>,
+    user: User,
 ) -> impl Responder {
     let pool = pool.clone();
     let event_state = event_state.clone();
 
-    let stream = create_event_stream(job_queue, pool, event_state);
+    let stream = create_event_stream(job_queue, pool, event_state, app_state, user);
 
     Sse::from_stream(stream)
         .with_keep_alive(Duration::from_secs(15))
@@ -88,6 +123,8 @@ fn create_event_stream(
     job_queue: web::Data<JobQueue>,
     pool: web::Data<SqlitePool>,
     event_state: web::Data<EventState>,
+    app_state: web::Data<AppState>,
+    user: User,
 ) -> Pin<Box<dyn Stream<Item = Result<sse::Event, std::io::Error>> + Send>> {
     let mut interval_stream = IntervalStream::new(interval(Duration::from_millis(250)));
 
@@ -96,30 +133,37 @@ fn create_event_stream(
     let mut last_recent_activity_version = 0u64;
 
     let stream = async_stream::stream! {
-        let data = job_queue.get_current_queue(&pool).await;
-        if let Ok(sse_data) = SseData::new_json(&json!({
+        let data = visible_queue(&job_queue, &pool, &user).await;
+        if let Ok(sse_data) = SseData::new_json(json!({
             "type": "queue_update",
             "queue": data
         })) {
             yield Ok(sse::Event::Data(sse_data));
         }
 
-        if let Ok(recent_activity) = get_recent_activity(&pool).await {
-            if let Ok(sse_data) = SseData::new_json(&json!({
+        if let Ok(recent_activity) = get_recent_activity(&pool, &user).await
+            && let Ok(sse_data) = SseData::new_json(json!({
                 "type": "recent_activity_update",
                 "recent_activity": recent_activity
             })) {
-                yield Ok(sse::Event::Data(sse_data));
-            }
+            yield Ok(sse::Event::Data(sse_data));
         };
 
-        while let Some(_) = interval_stream.next().await {
+        let mut last_settings_version = *event_state.settings_version.read().await;
+        if let Some(sse_data) = settings_event(&pool).await {
+            yield Ok(sse::Event::Data(sse_data));
+        }
+
+        let mut last_devices_version = *event_state.devices_version.read().await;
+        let mut last_ink_warning_version = *event_state.ink_warning_version.read().await;
+
+        while interval_stream.next().await.is_some() {
             let current_queue_version = *event_state.queue_version.read().await;
             if current_queue_version != last_queue_version {
                 last_queue_version = current_queue_version;
 
-                let data = job_queue.get_current_queue(&pool).await;
-                if let Ok(sse_data) = SseData::new_json(&json!({
+                let data = visible_queue(&job_queue, &pool, &user).await;
+                if let Ok(sse_data) = SseData::new_json(json!({
                     "type": "queue_update",
                     "queue": data
                 })) {
@@ -131,10 +175,10 @@ fn create_event_stream(
             if current_status_version != last_status_version {
                 last_status_version = current_status_version;
 
-                let status_result = get_system_status(&pool).await;
+                let status_result = get_system_status(&pool, &app_state).await;
                 match status_result {
                     Ok(status) => {
-                        if let Ok(sse_data) = SseData::new_json(&json!({
+                        if let Ok(sse_data) = SseData::new_json(json!({
                             "type": "status_update",
                             "status": status
                         })) {
@@ -152,24 +196,58 @@ fn create_event_stream(
             if current_recent_activity_version != last_recent_activity_version {
                 last_recent_activity_version = current_recent_activity_version;
 
-                if let Ok(recent_activity) = get_recent_activity(&pool).await {
-                    if let Ok(sse_data) = SseData::new_json(&json!({
+                if let Ok(recent_activity) = get_recent_activity(&pool, &user).await
+                    && let Ok(sse_data) = SseData::new_json(json!({
                         "type": "recent_activity_update",
                         "recent_activity": recent_activity
                     })) {
-                        yield Ok(sse::Event::Data(sse_data));
-                    }
+                    yield Ok(sse::Event::Data(sse_data));
                 };
             }
+
+            let current_settings_version = *event_state.settings_version.read().await;
+            if current_settings_version != last_settings_version {
+                last_settings_version = current_settings_version;
+
+                if let Some(sse_data) = settings_event(&pool).await {
+                    yield Ok(sse::Event::Data(sse_data));
+                }
+            }
+
+            let current_devices_version = *event_state.devices_version.read().await;
+            if current_devices_version != last_devices_version {
+                last_devices_version = current_devices_version;
+
+                if let Ok(sse_data) = SseData::new_json(json!({
+                    "type": "device_update",
+                    "changes": *event_state.device_update.read().await,
+                    "printers": app_state.get_printers().await,
+                    "scanners": app_state.get_scanners().await
+                })) {
+                    yield Ok(sse::Event::Data(sse_data));
+                }
+            }
+
+            let current_ink_warning_version = *event_state.ink_warning_version.read().await;
+            if current_ink_warning_version != last_ink_warning_version {
+                last_ink_warning_version = current_ink_warning_version;
+
+                if let Ok(sse_data) = SseData::new_json(json!({
+                    "type": "ink_warning",
+                    "warnings": *event_state.ink_warnings.read().await
+                })) {
+                    yield Ok(sse::Event::Data(sse_data));
+                }
+            }
         }
     };
 
     Box::pin(stream)
 }
 
-async fn get_system_status(pool: &SqlitePool) -> Result<serde_json::Value, sqlx::Error> {
+async fn get_system_status(pool: &SqlitePool, app_state: &AppState) -> Result<serde_json::Value, sqlx::Error> {
     let active_prints = PrintJob::find_by_statuses(
-        vec![PrintJobStatus::Printing, PrintJobStatus::Processing],
+        vec![PrintJobStatus::Converting, PrintJobStatus::Printing, PrintJobStatus::Processing],
         pool
     ).await?.len();
 
@@ -184,9 +262,23 @@ async fn get_system_status(pool: &SqlitePool) -> Result<serde_json::Value, sqlx:
         "active_prints": active_prints,
         "active_scans": active_scans,
         "disk_space_mb": disk_space_mb,
+        "ink_levels": app_state.get_ink_levels().await,
     }))
 }
 
+async fn settings_event(pool: &SqlitePool) -> Option<SseData> {
+    match SystemSettings::load(pool).await {
+        Ok(settings) => SseData::new_json(json!({
+            "type": "settings_update",
+            "settings": SystemSettingsResponse::from(settings)
+        })).ok(),
+        Err(e) => {
+            log::error!("Failed to load settings: {}", e);
+            None
+        }
+    }
+}
+
 fn was_within_last_hour(completed_at: Option<DateTime<Utc>>) -> bool {
     let completed_at = match completed_at {
         Some(com) => com,
@@ -199,12 +291,20 @@ fn was_within_last_hour(completed_at: Option<DateTime<Utc>>) -> bool {
     completed_at >= one_hour_ago && completed_at <= now
 }
 
-async fn get_recent_activity(pool: &web::Data<SqlitePool>) -> Result<Vec<Job>, sqlx::Error> {
+/// Queue as seen by `user`, other users' jobs are left out unless they are an admin
+async fn visible_queue(job_queue: &JobQueue, pool: &SqlitePool, user: &User) -> Vec<Job> {
+    job_queue.get_current_queue(pool).await
+        .into_iter()
+        .filter(|job| user.can_access(job.user_id()))
+        .collect()
+}
+
+async fn get_recent_activity(pool: &web::Data<SqlitePool>, user: &User) -> Result<Vec<Job>, sqlx::Error> {
     let limit = 4;
 
     let (scan_jobs_r, print_jobs_r) = tokio::try_join!(
-        ScanJob::get_recent(limit, &pool),
-        PrintJob::get_recent(limit, &pool)
+        ScanJob::get_recent(limit, user.job_owner_filter(), pool),
+        PrintJob::get_recent(limit, user.job_owner_filter(), pool)
     ).map_err(|e| {
         log::error!("Error getting recent activity: {}", e);
         e
@@ -2,6 +2,9 @@ pub mod print;
 pub mod scan;
 pub mod system;
 pub mod events;
+pub mod auth;
+pub mod tokens;
+pub mod upload;
 
 use actix_web::{HttpResponse, Result};
 use crate::models::ApiResponse;
@@ -3,12 +3,15 @@ use actix_multipart::Multipart;
 use futures_util::{TryFutureExt, TryStreamExt};
 use std::collections::HashMap;
 use uuid::Uuid;
-use actix_web::error::{ErrorBadRequest, ErrorInternalServerError};
+use actix_web::error::{ErrorBadRequest, ErrorForbidden, ErrorInternalServerError};
 use sqlx::SqlitePool;
 use crate::handlers::{json_success, json_error, internal_error};
 use crate::handlers::events::EventState;
-use crate::models::{PrintJob, PrintRequest, PrintJobStatus, PrintPageSize, AppState, add_to_job_queue, Job, notify_scan_queue, JobQueue};
-use crate::services::cups::CupsService;
+use crate::handlers::upload::{read_text_field, sanitize_filename, stream_to_temp_file, TempUpload, UploadError};
+use crate::models::{PrintJob, PrintRequest, PrintJobStatus, PrintPageSize, PrintOrientation, PrintScaling, PrintQuality, PAGES_PER_SHEET, AppState, PrinterStateRequest, NewPrinterRequest, DeviceUpdate, MarkerHistoryQuery, MarkerLevelRecord, add_to_job_queue, Job, notify_scan_queue, JobQueue, SystemSettings, ApiResponse, User};
+use crate::services::backend::PrintBackend;
+use crate::services::dnssd::DnsSdService;
+use crate::settings::Settings;
 
 
 
@@ -19,61 +22,268 @@ pub async fn list_printers(app_state: web::Data<AppState>) -> Result<HttpRespons
     json_success(printers)
 }
 
+/// GET /api/printers/{name}/capabilities - Media, sides, colour modes and other options of a printer
+pub async fn get_printer_capabilities(path: web::Path<String>, app_state: web::Data<AppState>) -> Result<HttpResponse> {
+    let printer_name = path.into_inner();
+
+    match app_state.backend().get_capabilities(&printer_name).await {
+        Ok(capabilities) => json_success(capabilities),
+        Err(e) => {
+            log::warn!("Failed to get capabilities of printer '{}': {}", printer_name, e);
+            json_error(format!("Printer '{}' not found", printer_name))
+        },
+    }
+}
+
+/// GET /api/printers/{name}/ink/history?days=30 - Recorded ink and toner levels of a printer, at most a year back
+pub async fn get_ink_history(
+    path: web::Path<String>,
+    query: web::Query<MarkerHistoryQuery>,
+    pool: web::Data<SqlitePool>
+) -> Result<HttpResponse> {
+    let printer_name = path.into_inner();
+    let days = query.days.unwrap_or(30).clamp(1, 365);
+    let since = chrono::Utc::now() - chrono::Duration::days(days as i64);
+
+    match MarkerLevelRecord::get_history(&printer_name, since, &pool).await {
+        Ok(history) => json_success(history),
+        Err(e) => internal_error(format!("Failed to load ink history: {}", e)),
+    }
+}
+
+/// GET /api/printers/discover - Devices found by the CUPS backends and DNS-SD browsing (admin)
+pub async fn discover_printers(app_state: web::Data<AppState>) -> Result<HttpResponse> {
+    let dnssd_service = DnsSdService::new();
+    let browse_dnssd = async {
+        match dnssd_service.is_available().await {
+            true => dnssd_service.browse_ipp_printers().await,
+            false => Ok(Vec::new()),
+        }
+    };
+    let (devices, dnssd_printers) = tokio::join!(app_state.backend().discover_devices(), browse_dnssd);
+
+    if let (Err(lpinfo_error), Err(dnssd_error)) = (&devices, &dnssd_printers) {
+        log::error!("Printer discovery failed: {}; {}", lpinfo_error, dnssd_error);
+        return internal_error("Printer discovery failed".to_string());
+    }
+
+    let mut devices = devices.unwrap_or_else(|e| {
+        log::warn!("{}", e);
+        Vec::new()
+    });
+    // The CUPS dnssd backend reports the same network printers under their service name
+    for printer in dnssd_printers.unwrap_or_else(|e| {
+        log::warn!("{}", e);
+        Vec::new()
+    }) {
+        if !devices.iter().any(|device| device.uri == printer.uri || (device.connection == "network" && device.info == printer.info)) {
+            devices.push(printer);
+        }
+    }
+
+    json_success(devices)
+}
+
+/// POST /api/printers - Create a queue for a discovered device (admin)
+pub async fn add_printer(
+    body: web::Json<NewPrinterRequest>,
+    app_state: web::Data<AppState>,
+    event_state: web::Data<EventState>,
+    user: User
+) -> Result<HttpResponse> {
+    let request = body.into_inner();
+    let result = app_state.backend().add_printer(&request).await;
+
+    printer_changed(result, request.name.trim(), "added", &app_state, &event_state, &user).await
+}
+
+/// DELETE /api/printers/{name} - Remove a printer queue (admin)
+pub async fn delete_printer(
+    path: web::Path<String>,
+    app_state: web::Data<AppState>,
+    event_state: web::Data<EventState>,
+    user: User
+) -> Result<HttpResponse> {
+    let printer_name = path.into_inner();
+    let result = app_state.backend().delete_printer(&printer_name).await;
+
+    printer_changed(result, &printer_name, "deleted", &app_state, &event_state, &user).await
+}
+
+/// POST /api/printers/{name}/enable - Resume a stopped printer (admin)
+pub async fn enable_printer(
+    path: web::Path<String>,
+    app_state: web::Data<AppState>,
+    event_state: web::Data<EventState>,
+    user: User
+) -> Result<HttpResponse> {
+    let printer_name = path.into_inner();
+    let result = app_state.backend().enable_printer(&printer_name).await;
+
+    printer_changed(result, &printer_name, "enabled", &app_state, &event_state, &user).await
+}
+
+/// POST /api/printers/{name}/disable - Stop a printer, with an optional `reason` (admin)
+pub async fn disable_printer(
+    path: web::Path<String>,
+    body: Option<web::Json<PrinterStateRequest>>,
+    app_state: web::Data<AppState>,
+    event_state: web::Data<EventState>,
+    user: User
+) -> Result<HttpResponse> {
+    let printer_name = path.into_inner();
+    let reason = state_reason(body);
+    let result = app_state.backend().disable_printer(&printer_name, reason.as_deref()).await;
+
+    printer_changed(result, &printer_name, "disabled", &app_state, &event_state, &user).await
+}
+
+/// POST /api/printers/{name}/accept - Let a printer take new jobs again (admin)
+pub async fn accept_printer_jobs(
+    path: web::Path<String>,
+    app_state: web::Data<AppState>,
+    event_state: web::Data<EventState>,
+    user: User
+) -> Result<HttpResponse> {
+    let printer_name = path.into_inner();
+    let result = app_state.backend().accept_jobs(&printer_name).await;
+
+    printer_changed(result, &printer_name, "set to accept jobs on", &app_state, &event_state, &user).await
+}
+
+/// POST /api/printers/{name}/reject - Refuse new jobs, with an optional `reason` (admin)
+pub async fn reject_printer_jobs(
+    path: web::Path<String>,
+    body: Option<web::Json<PrinterStateRequest>>,
+    app_state: web::Data<AppState>,
+    event_state: web::Data<EventState>,
+    user: User
+) -> Result<HttpResponse> {
+    let printer_name = path.into_inner();
+    let reason = state_reason(body);
+    let result = app_state.backend().reject_jobs(&printer_name, reason.as_deref()).await;
+
+    printer_changed(result, &printer_name, "set to reject jobs on", &app_state, &event_state, &user).await
+}
+
+/// POST /api/printers/{name}/purge - Cancel every job queued on a printer (admin)
+pub async fn purge_printer_jobs(
+    path: web::Path<String>,
+    app_state: web::Data<AppState>,
+    event_state: web::Data<EventState>,
+    user: User
+) -> Result<HttpResponse> {
+    let printer_name = path.into_inner();
+    let result = app_state.backend().purge_jobs(&printer_name).await;
+
+    // The job monitors pick up the cancelled CUPS jobs and update the queue
+    printer_changed(result, &printer_name, "purged the queue of", &app_state, &event_state, &user).await
+}
+
+/// POST /api/printers/{name}/default - Make a printer the system default destination (admin)
+pub async fn set_default_printer(
+    path: web::Path<String>,
+    app_state: web::Data<AppState>,
+    event_state: web::Data<EventState>,
+    user: User
+) -> Result<HttpResponse> {
+    let printer_name = path.into_inner();
+    let result = app_state.backend().set_default_printer(&printer_name).await;
+
+    printer_changed(result, &printer_name, "set as default", &app_state, &event_state, &user).await
+}
+
+fn state_reason(body: Option<web::Json<PrinterStateRequest>>) -> Option<String> {
+    body.and_then(|body| body.into_inner().reason)
+        .map(|reason| reason.trim().to_string())
+        .filter(|reason| !reason.is_empty())
+}
+
+/// Reload the printers from CUPS after an admin change and push them to the clients, returns the changed printer
+async fn printer_changed(
+    result: Result<(), String>,
+    printer_name: &str,
+    action: &str,
+    app_state: &AppState,
+    event_state: &EventState,
+    user: &User
+) -> Result<HttpResponse> {
+    if let Err(e) = result {
+        log::error!("{}", e);
+        return json_error(e);
+    }
+    log::info!("User '{}' {} printer '{}'", user.username, action, printer_name);
+
+    let changes = app_state.refresh_printers()
+        .await
+        .map_err(ErrorInternalServerError)?;
+    if !changes.is_empty() {
+        event_state.publish_device_update(DeviceUpdate { printers: changes, ..Default::default() }).await;
+    }
+
+    let printer = app_state.get_printers().await.into_iter().find(|printer| printer.name == printer_name);
+    json_success(printer)
+}
+
 /// POST /api/print - Submit a print job
 pub async fn submit_print_job(
     mut payload: Multipart, 
     pool: web::Data<SqlitePool>, 
     job_queue: web::Data<JobQueue>, 
     app_state: web::Data<AppState>,
-    event_state: web::Data<EventState>
+    event_state: web::Data<EventState>,
+    settings: web::Data<Settings>,
+    user: User
 ) -> Result<HttpResponse> {
-    let cups_service = CupsService::new();
+    if job_queue.is_shutting_down().await {
+        return Ok(HttpResponse::ServiceUnavailable()
+            .json(ApiResponse::<()>::error("Server is shutting down, try again shortly".to_string())));
+    }
 
-    if !cups_service.is_available().await {
+    if !app_state.backend().is_available().await {
         return json_error("CUPS service is not available".to_string());
     }
 
-    let mut file_data: Option<Vec<u8>> = None;
+    let system_settings = SystemSettings::load(&pool)
+        .await
+        .map_err(|e| ErrorInternalServerError(e.to_string()))?;
+    let max_file_size = system_settings.max_file_size_bytes();
+
+    let mut upload: Option<TempUpload> = None;
     let mut filename: Option<String> = None;
     let mut form_data: HashMap<String, String> = HashMap::new();
-    
-    while let Some(mut field) = payload.try_next().await.map_err(|e| {
-        log::error!("Error reading multipart field: {}", e);
-    }).unwrap_or(None) {
-        let content_disposition = field.content_disposition().unwrap();
-        let field_name = content_disposition.get_name().map(|s| s.to_string());
-        let file_name = content_disposition.get_filename().map(|s| s.to_string());
-
-        if let Some(field_name) = field_name {
-            if field_name == "file" {
-                if let Some(file_name) = file_name {
-                    filename = Some(file_name);
-                }
 
-                let mut bytes = Vec::new();
-                while let Some(chunk) = field.try_next().await.map_err(|e| {
-                    log::error!("Error reading file chunk: {}", e);
-                }).unwrap_or(None) {
-                    bytes.extend_from_slice(&chunk);
-                }
-                file_data = Some(bytes);
-            } else {
-                let mut field_data = Vec::new();
-                while let Some(chunk) = field.try_next().await.map_err(|e| {
-                    log::error!("Error reading form field: {}", e);
-                }).unwrap_or(None) {
-                    field_data.extend_from_slice(&chunk);
-                }
+    // The file goes straight to a temporary file, it is only moved into place once the job is valid
+    loop {
+        let mut field = match payload.try_next().await {
+            Ok(Some(field)) => field,
+            Ok(None) => break,
+            Err(e) => return UploadError::Multipart(e.to_string()).into_response(),
+        };
+        let Some(field_name) = field.content_disposition().and_then(|disposition| disposition.get_name()).map(|s| s.to_string()) else {
+            continue;
+        };
 
-                if let Ok(value) = String::from_utf8(field_data) {
-                    form_data.insert(field_name, value);
-                }
+        if field_name == "file" {
+            if let Some(file_name) = field.content_disposition().and_then(|disposition| disposition.get_filename()) {
+                filename = Some(file_name.to_string());
+            }
+
+            let too_large = || format!("File exceeds the maximum size of {} MB", system_settings.max_file_size_mb);
+            match stream_to_temp_file(&mut field, settings.uploads_dir(), max_file_size, too_large).await {
+                Ok(file) => upload = Some(file),
+                Err(e) => return e.into_response(),
+            }
+        } else {
+            match read_text_field(&mut field, &field_name).await {
+                Ok(value) => { form_data.insert(field_name, value); },
+                Err(e) => return e.into_response(),
             }
         }
     }
 
-    let file_data = match file_data {
-        Some(data) => data,
+    let upload = match upload {
+        Some(upload) => upload,
         None => {
             log::warn!("No file provided in print request");
             return json_error("No file provided".to_string());
@@ -87,6 +297,21 @@ pub async fn submit_print_job(
             return json_error("No filename provided".to_string());
         }
     };
+    let filename = match sanitize_filename(&filename) {
+        Ok(name) => name,
+        Err(e) => {
+            log::warn!("Rejected print upload named {:?}: {}", filename, e);
+            return json_error(e);
+        }
+    };
+
+    let mime_type = match system_settings.check_upload(&filename, upload.head()) {
+        Ok(mime_type) => mime_type,
+        Err(e) => {
+            log::warn!("Rejected print upload {}: {}", filename, e);
+            return json_error(e);
+        }
+    };
     
     let print_request = PrintRequest {
         printer: form_data.get("printer").cloned(),
@@ -100,9 +325,28 @@ pub async fn submit_print_job(
             .map(|s| s == "true" || s == "on")
             .unwrap_or(true)),
         page_size: form_data.get("page_size")
-            .cloned().map(|s| { PrintPageSize::from(s)} )
+            .cloned().map(|s| { PrintPageSize::from(s)} ),
+        orientation: form_data.get("orientation")
+            .cloned().map(PrintOrientation::from),
+        scaling: form_data.get("scaling")
+            .cloned().map(PrintScaling::from),
+        pages_per_sheet: form_data.get("pages_per_sheet")
+            .and_then(|s| s.parse().ok()),
+        quality: form_data.get("quality")
+            .cloned().map(PrintQuality::from),
+        media_type: form_data.get("media_type")
+            .map(|s| s.trim().to_string()),
+        input_tray: form_data.get("input_tray")
+            .map(|s| s.trim().to_string()),
+        collate: form_data.get("collate")
+            .map(|s| s == "true" || s == "on"),
     };
 
+    if let Some(pages_per_sheet) = print_request.pages_per_sheet
+        && !PAGES_PER_SHEET.contains(&pages_per_sheet) {
+        return json_error(format!("Pages per sheet must be one of {:?}", PAGES_PER_SHEET));
+    }
+
 
 
     let available_printers = app_state.get_printers().await;
@@ -153,27 +397,37 @@ pub async fn submit_print_job(
             })
     };
     log::info!("Using printer: {}", printer_name);
-    let (vendor, model) = app_state.get_printers().await
-        .iter()
-        .find(|&x| {x.name == printer_name})
-        .map(|x| (x.vendor.clone(), x.model.clone()))
-        .ok_or_else(|| {
-            log::error!("Printer '{}' not found in all printers list", printer_name);
+    // The list above is cached at startup, make sure the queue still exists in CUPS
+    let (vendor, model) = app_state.backend().get_printer(&printer_name)
+        .await
+        .map(|printer| (printer.vendor, printer.model))
+        .map_err(|e| {
+            log::error!("Printer '{}' not found in CUPS: {}", printer_name, e);
             ErrorBadRequest("Printer not found".to_string())
         })?;
 
-    let print_job = PrintJob::new(filename.clone(), printer_name, vendor, model, print_request);
+    let mut print_job = PrintJob::new(filename, printer_name, vendor, model, print_request, user.id);
+    print_job.mime_type = Some(mime_type.to_string());
+
+    let capabilities = app_state.backend().get_capabilities(&print_job.printer)
+        .await
+        .map_err(|e| ErrorInternalServerError(e.to_string()))?;
+    if let Err(e) = capabilities.check(&print_job) {
+        log::warn!("Rejected print job for '{}': {}", print_job.printer, e);
+        return json_error(e);
+    }
     let job_id = print_job.id;
     let printer = print_job.printer.clone();
 
-    let file_path = format!("uploads/{}", filename);
-    if let Err(e) = std::fs::write(&file_path, &file_data) {
-        log::error!("Failed to save uploaded file: {}", e);
-        return internal_error("Failed to save uploaded file".to_string());
+    let file_path = settings.uploads_dir().join(&print_job.filename);
+    log::info!("Received {} ({} bytes) for printing as {}", print_job.original_filename, upload.size(), print_job.filename);
+    if let Err(e) = upload.persist(&file_path).await {
+        return UploadError::Io(e).into_response();
     };
 
-    print_job.save_to_db(&pool).await.map_err(|e| {
+    print_job.save_to_db(&pool, settings.uploads_dir()).await.map_err(|e| {
         log::error!("Failed to save print job: {}", e);
+        let _ = std::fs::remove_file(&file_path);
         ErrorInternalServerError(e.to_string())
     })?;
 
@@ -185,8 +439,9 @@ pub async fn submit_print_job(
         })?;
     
     let event_state_clone = event_state.clone();
+    let backend = app_state.backend().clone();
     tokio::spawn(async move {
-        if let Err(e) = notify_scan_queue(&job_queue, &pool, &event_state_clone).await {
+        if let Err(e) = notify_scan_queue(&job_queue, &pool, &event_state_clone, &settings, &backend).await {
             let _ = std::fs::remove_file(&file_path);
             log::error!("Failed to notify scan queue: {}", e);
         };
@@ -201,11 +456,11 @@ pub async fn submit_print_job(
     }))
 }
 
-/// GET /api/print/jobs - List all print jobs
-pub async fn list_print_jobs(pool: web::Data<SqlitePool>) -> Result<HttpResponse> {
+/// GET /api/print/jobs - List the caller's print jobs, all of them for admins
+pub async fn list_print_jobs(pool: web::Data<SqlitePool>, user: User) -> Result<HttpResponse> {
     let pool = pool.as_ref();
 
-    let jobs = PrintJob::get_all(pool).map_err(|e| {
+    let jobs = PrintJob::get_all(user.job_owner_filter(), pool).map_err(|e| {
         log::error!("Failed to get print jobs: {}", e);
         ErrorInternalServerError(e.to_string())
     }).await?;
@@ -214,26 +469,30 @@ pub async fn list_print_jobs(pool: web::Data<SqlitePool>) -> Result<HttpResponse
 }
 
 /// GET /api/print/jobs/{job_id} - Get specific print job
-pub async fn get_print_job(path: web::Path<Uuid>, pool: web::Data<SqlitePool>) -> Result<HttpResponse> {
+pub async fn get_print_job(path: web::Path<Uuid>, pool: web::Data<SqlitePool>, user: User) -> Result<HttpResponse> {
     let uuid = path.into_inner();
     let pool = pool.as_ref();
 
     match PrintJob::find_by_uuid(uuid, pool).await {
+        Ok(Some(job)) if !user.can_access(job.user_id) => json_error("Print job not found".to_string()),
         Ok(job) => json_success(job.clone()),
         Err(e) => internal_error(format!("Print job not found. {e}")),
     }
 }
 
 /// POST /api/print/jobs/{job_id} - Cancel print job
-pub async fn cancel_print_job(path: web::Path<Uuid>, pool: web::Data<SqlitePool>) -> Result<HttpResponse> {
+pub async fn cancel_print_job(path: web::Path<Uuid>, pool: web::Data<SqlitePool>, app_state: web::Data<AppState>, user: User) -> Result<HttpResponse> {
     let job_id = path.into_inner();
     let pool = pool.as_ref();
 
-    let cups_service = CupsService::new();
-
     if let Some(mut job) = PrintJob::find_by_uuid(job_id, pool).await.map_err(|e| {ErrorInternalServerError(e.to_string())})? {
+        if !user.can_access(job.user_id) {
+            log::warn!("User '{}' tried to cancel print job {} of another user", user.username, job_id);
+            return Err(ErrorForbidden("Not your print job"));
+        }
+
         if let Some(cups_job_id) = job.cups_job_id {
-            match cups_service.cancel_job(&job.printer, cups_job_id).await {
+            match app_state.backend().cancel_job(cups_job_id).await {
                 Ok(_) => {
                     job.set_status(PrintJobStatus::Cancelled);
                     job.update_in_db(pool).await.map_err(|e| {ErrorInternalServerError(e.to_string())})?;
@@ -241,9 +500,7 @@ pub async fn cancel_print_job(path: web::Path<Uuid>, pool: web::Data<SqlitePool>
                 },
                 Err(e) => {
                     log::warn!("Failed to cancel CUPS job {}: {}", cups_job_id, e);
-                    job.set_status(PrintJobStatus::Cancelled);
-                    job.update_in_db(pool).await.map_err(|e| {ErrorInternalServerError(e.to_string())})?;
-                    json_success(serde_json::json!({"message": "Print job marked as cancelled"}))
+                    internal_error(format!("Failed to cancel print job: {}", e))
                 }
             }
         } else {
@@ -257,9 +514,15 @@ pub async fn cancel_print_job(path: web::Path<Uuid>, pool: web::Data<SqlitePool>
 }
 
 /// DELETE /api/print/jobs/{job_id} - Delete specific print job form database
-pub async fn delete_print_job_record(path: web::Path<Uuid>, pool: web::Data<SqlitePool>) -> Result<HttpResponse> {
+pub async fn delete_print_job_record(path: web::Path<Uuid>, pool: web::Data<SqlitePool>, user: User) -> Result<HttpResponse> {
     let job_id = path.into_inner();
 
+    if let Some(job) = PrintJob::find_by_uuid(job_id, pool.as_ref()).await.map_err(|e| ErrorInternalServerError(e.to_string()))?
+        && !user.can_access(job.user_id) {
+        log::warn!("User '{}' tried to delete print job {} of another user", user.username, job_id);
+        return Err(ErrorForbidden("Not your print job"));
+    }
+
     match PrintJob::remove_by_uuid(job_id, pool.as_ref()).await {
         Ok(_) => { 
             log::info!("Removed Print Job record for {}", job_id);
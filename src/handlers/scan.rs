@@ -1,12 +1,14 @@
 use actix_web::{web, HttpRequest, HttpResponse, Result};
-use actix_web::error::{ErrorBadRequest, ErrorInternalServerError};
+use actix_web::error::{ErrorBadRequest, ErrorForbidden, ErrorInternalServerError};
 use sqlx::{SqlitePool};
+use std::path::Path;
 use uuid::Uuid;
 
 use crate::handlers::{json_success, json_error, internal_error};
 use crate::handlers::events::EventState;
-use crate::models::{ScanJob, ScanRequest, ScanJobStatus, JobQueue, add_to_job_queue, notify_scan_queue, AppState, Job};
-use crate::services::sane::SaneService;
+use crate::models::{ScanJob, ScanRequest, ScanJobStatus, JobQueue, add_to_job_queue, notify_scan_queue, AppState, Job, SystemSettings, User, ApiResponse};
+use crate::services::backend::ScanBackend;
+use crate::settings::Settings;
 
 
 /// GET /api/scanners - List all available scanners
@@ -22,11 +24,16 @@ pub async fn start_scan(
     pool: web::Data<SqlitePool>,
     job_queue: web::Data<JobQueue>,
     app_state: web::Data<AppState>,
-    event_state: web::Data<EventState>
+    event_state: web::Data<EventState>,
+    settings: web::Data<Settings>,
+    user: User
 ) -> Result<HttpResponse> {
-    let sane_service = SaneService::new();
+    if job_queue.is_shutting_down().await {
+        return Ok(HttpResponse::ServiceUnavailable()
+            .json(ApiResponse::<()>::error("Server is shutting down, try again shortly".to_string())));
+    }
 
-    if !sane_service.is_available().await {
+    if !ScanBackend::is_available(app_state.backend()).await {
         return json_error("SANE service is not available".to_string());
     }
 
@@ -45,12 +52,16 @@ pub async fn start_scan(
             ErrorInternalServerError("Scanner not found".to_string())
         })?;
 
+    let system_settings = SystemSettings::load(&pool)
+        .await
+        .map_err(|e| ErrorInternalServerError(e.to_string()))?;
+
     // Create scan job
-    let scan_job = ScanJob::new(scanner_name, vendor, model, req.into_inner());
+    let scan_job = ScanJob::new(scanner_name, vendor, model, req.into_inner(), settings.scans_dir(), &system_settings, user.id);
     let job_id = scan_job.id;
 
     // Store job in database
-    scan_job.save_to_db(&pool)
+    scan_job.save_to_db(&pool, settings.scans_dir())
         .await
         .map_err(|e| ErrorBadRequest(e.to_string()))?;
 
@@ -61,8 +72,9 @@ pub async fn start_scan(
         .map_err(|e| ErrorInternalServerError(e.to_string()))?;
 
     let event_state_clone = event_state.clone();
+    let backend = app_state.backend().clone();
     tokio::spawn(async move {
-        if let Err(e) = notify_scan_queue(&job_queue, &pool, &event_state_clone).await {
+        if let Err(e) = notify_scan_queue(&job_queue, &pool, &event_state_clone, &settings, &backend).await {
             log::error!("Failed to notify scan queue: {}", e);
         };
     });
@@ -75,20 +87,20 @@ pub async fn start_scan(
     }))
 }
 
-/// GET /api/scan/jobs - List all scan jobs
-pub async fn list_scan_jobs(pool: web::Data<SqlitePool>) -> Result<HttpResponse> {
-    //change to all in the future
-    match ScanJob::get_all(pool.as_ref()).await {
+/// GET /api/scan/jobs - List the caller's scan jobs, all of them for admins
+pub async fn list_scan_jobs(pool: web::Data<SqlitePool>, user: User) -> Result<HttpResponse> {
+    match ScanJob::get_all(user.job_owner_filter(), pool.as_ref()).await {
         Ok(jobs) => json_success(jobs),
         Err(e) => internal_error(format!("Failed to get recent jobs: {}", e)),
     }
 }
 
 /// GET /api/scan/jobs/{job_id} - Get specific scan job
-pub async fn get_scan_job(path: web::Path<Uuid>, pool: web::Data<SqlitePool>) -> Result<HttpResponse> {
+pub async fn get_scan_job(path: web::Path<Uuid>, pool: web::Data<SqlitePool>, user: User) -> Result<HttpResponse> {
     let job_id = path.into_inner();
 
     match ScanJob::find_by_uuid(job_id, pool.as_ref()).await {
+        Ok(Some(job)) if !user.can_access(job.user_id) => json_error("Scan job not found".to_string()),
         Ok(job) => json_success(job),
         Err(e) => internal_error(format!("Failed to find job: {}", e)),
     }
@@ -96,18 +108,23 @@ pub async fn get_scan_job(path: web::Path<Uuid>, pool: web::Data<SqlitePool>) ->
 
 
 /// DELETE /api/scan/jobs/{job_id} - Delete specific scan job form database
-pub async fn delete_scan_job_record(path: web::Path<Uuid>, pool: web::Data<SqlitePool>) -> Result<HttpResponse> {
+pub async fn delete_scan_job_record(path: web::Path<Uuid>, pool: web::Data<SqlitePool>, settings: web::Data<Settings>, user: User) -> Result<HttpResponse> {
     let job_id = path.into_inner();
 
     match ScanJob::find_by_uuid(job_id, pool.as_ref())
         .await
         .map_err(|e| ErrorInternalServerError(e.to_string()))? {
         Some(scan_job) => {
+            if !user.can_access(scan_job.user_id) {
+                log::warn!("User '{}' tried to delete scan job {} of another user", user.username, job_id);
+                return Err(ErrorForbidden("Not your scan job"));
+            }
+
             let filename = match scan_job.output_filename {
                 Some(filename) => filename,
                 None => return Err(ErrorInternalServerError("Validation error".to_string())),
             };
-            delete_scan(filename.clone(), &pool)
+            delete_scan(filename.clone(), settings.scans_dir(), &pool)
                 .await
                 .map_err(|e| ErrorInternalServerError(e.to_string()))?;
 
@@ -131,7 +148,7 @@ pub async fn delete_scan_job_record(path: web::Path<Uuid>, pool: web::Data<Sqlit
 }
 
 /// GET /api/scan/download/{job_id} - Download scanned file
-pub async fn download_scan(path: web::Path<Uuid>, req: HttpRequest, pool: web::Data<SqlitePool>) -> Result<HttpResponse> {
+pub async fn download_scan(path: web::Path<Uuid>, req: HttpRequest, pool: web::Data<SqlitePool>, settings: web::Data<Settings>, user: User) -> Result<HttpResponse> {
     let job_id = path.into_inner();
 
     let job = match ScanJob::find_by_uuid(job_id, pool.as_ref()).await {
@@ -140,9 +157,13 @@ pub async fn download_scan(path: web::Path<Uuid>, req: HttpRequest, pool: web::D
     };
 
     match job {
+        Some(job) if !user.can_access(job.user_id) => {
+            log::warn!("User '{}' tried to download scan job {} of another user", user.username, job_id);
+            Err(ErrorForbidden("Not your scan job"))
+        },
         Some(job) => {
             if let ScanJobStatus::Completed = job.status {
-                if let Some(file_path) = job.get_file_path() {
+                if let Some(file_path) = job.get_file_path(settings.scans_dir()) {
                     match actix_files::NamedFile::open_async(&file_path).await {
                         Ok(file) => Ok(file.into_response(&req)),
                         Err(_) => json_error("Scan file not found".to_string()),
@@ -158,8 +179,8 @@ pub async fn download_scan(path: web::Path<Uuid>, req: HttpRequest, pool: web::D
     }
 }
 
-async fn delete_scan(filename: String, pool: &SqlitePool) -> Result<(), Box<dyn std::error::Error>> {
-    match std::fs::remove_file(format!("scans/{}", filename)) {
+async fn delete_scan(filename: String, scans_dir: &Path, pool: &SqlitePool) -> Result<(), Box<dyn std::error::Error>> {
+    match std::fs::remove_file(scans_dir.join(&filename)) {
         Ok(_) => {
             ScanJob::update_file_available_by_filename(filename.clone(), false, pool).await
                 .map_err(|e| format!("Failed to delete scan: {}", e))?;
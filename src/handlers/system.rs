@@ -4,10 +4,11 @@ use actix_web::error::ErrorInternalServerError;
 use tokio::time::{Instant};
 
 use crate::handlers::{json_success, internal_error, json_error};
-use crate::models::{AppState, SystemStatus};
-use crate::services::cups::CupsService;
-use crate::services::sane::SaneService;
-use crate::services::escputil::MaintenanceService;
+use sqlx::SqlitePool;
+use crate::handlers::events::EventState;
+use crate::models::{AppState, JobQueue, SystemStatus, SystemSettings, SystemSettingsResponse, SystemSettingsUpdate};
+use crate::services::backend::{MaintenanceBackend, PrintBackend, ScanBackend};
+use crate::settings::Settings;
 use crate::utils::get_disk_space;
 
 
@@ -17,6 +18,11 @@ pub async fn index() -> Result<NamedFile> {
     Ok(NamedFile::open_async("templates/index.html").await?)
 }
 
+/// GET /login - Serve login page
+pub async fn login_page() -> Result<NamedFile> {
+    Ok(NamedFile::open_async("templates/login.html").await?)
+}
+
 /// GET /print - Serve print management page
 pub async fn print_page() -> Result<NamedFile> {
     Ok(NamedFile::open_async("templates/print.html").await?)
@@ -29,14 +35,13 @@ pub async fn scan_page() -> Result<NamedFile> {
 
 /// GET /api/system/status - Get system status
 pub async fn get_status(app_state: web::Data<AppState>) -> Result<HttpResponse> {
-    let cups_service = CupsService::new();
-    let sane_service = SaneService::new();
+    let backend = app_state.backend();
 
-    let cups_available = cups_service.is_available().await;
+    let cups_available = PrintBackend::is_available(backend).await;
 
-    let sane_available = sane_service.is_available().await;
+    let sane_available = ScanBackend::is_available(backend).await;
 
-    let active_print_jobs = cups_service.get_active_jobs()
+    let active_print_jobs = backend.get_active_jobs()
         .await
         .map_err(|e| { ErrorInternalServerError(e)})?
         .len();
@@ -52,38 +57,76 @@ pub async fn get_status(app_state: web::Data<AppState>) -> Result<HttpResponse>
         active_print_jobs,
         active_scan_jobs,
         disk_space_mb,
-        uptime_ms
+        uptime_ms,
+        ink_levels: app_state.get_ink_levels().await,
     };
 
     json_success(status)
 }
 
-/// GET /api/system/settings - Get system settings
-pub async fn get_settings() -> Result<HttpResponse> {
-    // TODO create table with default settings and store in database
+/// POST /api/system/devices/refresh - Look for printers and scanners now instead of waiting for the next refresh
+pub async fn refresh_devices(
+    app_state: web::Data<AppState>,
+    job_queue: web::Data<JobQueue>,
+    event_state: web::Data<EventState>
+) -> Result<HttpResponse> {
+    let update = app_state.refresh_devices(job_queue.is_processing().await).await;
+    if !update.is_empty() {
+        log::info!("Devices changed: {:?}", update);
+        event_state.publish_device_update(update.clone()).await;
+    }
 
-    let settings = serde_json::json!({
-        "default_resolution": 300,
-        "auto_cleanup": true,
-        "max_file_size_mb": 50,
-        "supported_formats": ["pdf", "jpeg", "png", "tiff"]
-    });
+    json_success(serde_json::json!({
+        "changes": update,
+        "printers": app_state.get_printers().await,
+        "scanners": app_state.get_scanners().await
+    }))
+}
 
-    json_success(settings)
+/// GET /api/system/settings - Get system settings
+pub async fn get_settings(pool: web::Data<SqlitePool>) -> Result<HttpResponse> {
+    match SystemSettings::load(&pool).await {
+        Ok(system_settings) => json_success(SystemSettingsResponse::from(system_settings)),
+        Err(e) => {
+            log::error!("Failed to load system settings: {}", e);
+            internal_error(format!("Failed to load settings: {}", e))
+        }
+    }
 }
 
-/// POST /api/system/settings - Update system settings
-pub async fn update_settings() -> Result<HttpResponse> {
-    // TODO Implement settings update logic here
+/// POST /api/system/settings - Update system settings, omitted fields keep their value
+pub async fn update_settings(
+    update: web::Json<SystemSettingsUpdate>,
+    pool: web::Data<SqlitePool>,
+    event_state: web::Data<EventState>,
+    settings: web::Data<Settings>
+) -> Result<HttpResponse> {
+    let mut system_settings = SystemSettings::load(&pool)
+        .await
+        .map_err(|e| ErrorInternalServerError(e.to_string()))?;
+
+    system_settings.apply(update.into_inner());
+
+    if let Err(e) = system_settings.validate(settings.server.max_upload_size) {
+        return json_error(e);
+    }
+
+    if let Err(e) = system_settings.save(&pool).await {
+        log::error!("Failed to save system settings: {}", e);
+        return internal_error(format!("Failed to save settings: {}", e));
+    }
+
+    log::info!("Updated system settings: {:?}", system_settings);
+    event_state.increment_settings_version().await;
 
-    internal_error("Not implemented".to_string())
+    json_success(SystemSettingsResponse::from(system_settings))
 }
 
 /// POST /api/system/nozzle/check
-pub async fn nozzle_check() -> Result<HttpResponse> {
-    let service = MaintenanceService::new();
+pub async fn nozzle_check(app_state: web::Data<AppState>) -> Result<HttpResponse> {
+    let service = app_state.backend();
     
-    if !service.is_available().await {
+    if !MaintenanceBackend::is_available(service).await {
         return json_error("false".to_string())
     }
     
@@ -95,10 +138,10 @@ pub async fn nozzle_check() -> Result<HttpResponse> {
 }
 
 /// POST /api/system/nozzle/clean
-pub async fn nozzle_clean() -> Result<HttpResponse> {
-    let service = MaintenanceService::new();
+pub async fn nozzle_clean(app_state: web::Data<AppState>) -> Result<HttpResponse> {
+    let service = app_state.backend();
 
-    if !service.is_available().await {
+    if !MaintenanceBackend::is_available(service).await {
         return json_error("false".to_string())
     }
     
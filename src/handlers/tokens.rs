@@ -0,0 +1,70 @@
+use actix_web::error::{ErrorForbidden, ErrorInternalServerError};
+use actix_web::{web, HttpResponse, Result};
+use chrono::{Duration, Utc};
+use sqlx::SqlitePool;
+use crate::handlers::{json_error, json_success};
+use crate::models::{validate_token_name, ApiToken, NewApiTokenRequest, TokenScope, User};
+
+const MAX_TOKEN_DAYS: u32 = 3650;
+
+
+/// GET /api/tokens - List the logged in user's API tokens
+pub async fn list_tokens(user: User, pool: web::Data<SqlitePool>) -> Result<HttpResponse> {
+    let tokens = ApiToken::get_by_user(user.id, &pool)
+        .await
+        .map_err(|e| ErrorInternalServerError(e.to_string()))?;
+
+    json_success(tokens)
+}
+
+/// POST /api/tokens - Create an API token, the token itself is only returned here
+pub async fn create_token(
+    body: web::Json<NewApiTokenRequest>,
+    user: User,
+    pool: web::Data<SqlitePool>
+) -> Result<HttpResponse> {
+    let NewApiTokenRequest { name, scopes, expires_in_days } = body.into_inner();
+    let name = name.trim().to_string();
+    if let Err(e) = validate_token_name(&name) {
+        return json_error(e);
+    }
+
+    let mut scopes = scopes.unwrap_or_else(|| vec![TokenScope::Print, TokenScope::Scan]);
+    scopes = scopes.iter().enumerate()
+        .filter(|(i, scope)| !scopes[..*i].contains(scope))
+        .map(|(_, scope)| *scope)
+        .collect();
+    if scopes.is_empty() {
+        return json_error("At least one scope is required".to_string());
+    }
+    if scopes.contains(&TokenScope::Admin) && !user.is_admin() {
+        return Err(ErrorForbidden("Only admins can create tokens with the admin scope"));
+    }
+
+    let expires_at = match expires_in_days {
+        Some(days @ 1..=MAX_TOKEN_DAYS) => Some(Utc::now() + Duration::days(days as i64)),
+        Some(_) => return json_error(format!("Expiry must be between 1 and {} days", MAX_TOKEN_DAYS)),
+        None => None,
+    };
+
+    let created = ApiToken::create(user.id, &name, &scopes, expires_at, &pool)
+        .await
+        .map_err(|e| ErrorInternalServerError(e.to_string()))?;
+
+    log::info!("User '{}' created API token '{}'", user.username, name);
+    json_success(created)
+}
+
+/// DELETE /api/tokens/{token_id} - Revoke one of the logged in user's API tokens
+pub async fn revoke_token(path: web::Path<i64>, user: User, pool: web::Data<SqlitePool>) -> Result<HttpResponse> {
+    let token_id = path.into_inner();
+
+    match ApiToken::remove(token_id, user.id, &pool).await {
+        Ok(0) => json_error("API token not found".to_string()),
+        Ok(_) => {
+            log::info!("User '{}' revoked API token {}", user.username, token_id);
+            json_success(format!("Successfully revoked token {}", token_id))
+        },
+        Err(e) => Err(ErrorInternalServerError(e.to_string())),
+    }
+}
@@ -0,0 +1,242 @@
+//! Multipart fields streamed to disk, so an upload never sits in memory as a whole
+
+use std::fmt::Display;
+use std::io;
+use std::path::{Path, PathBuf};
+use actix_web::{HttpResponse, Result};
+use bytes::Bytes;
+use futures_util::{Stream, TryStreamExt};
+use tokio::io::AsyncWriteExt;
+use uuid::Uuid;
+use crate::handlers::{internal_error, json_error};
+use crate::models::ApiResponse;
+
+/// Limit of a plain form field such as `copies` or `printer`
+pub const MAX_FORM_FIELD_SIZE: usize = 16 * 1024;
+
+/// Bytes kept from the start of an upload to recognise its format
+const HEAD_LENGTH: usize = 8 * 1024;
+
+/// Longest file name kept for display, in characters
+const MAX_FILENAME_LENGTH: usize = 255;
+
+const TEMP_PREFIX: &str = ".upload-";
+const TEMP_SUFFIX: &str = ".part";
+
+pub enum UploadError {
+    /// The field went over its limit, answered with 413
+    TooLarge(String),
+    /// The request body could not be read
+    Multipart(String),
+    /// The temporary file could not be written
+    Io(io::Error),
+}
+
+impl UploadError {
+    pub fn into_response(self) -> Result<HttpResponse> {
+        match self {
+            UploadError::TooLarge(message) => {
+                log::warn!("Rejected upload: {}", message);
+                Ok(HttpResponse::PayloadTooLarge().json(ApiResponse::<()>::error(message)))
+            },
+            UploadError::Multipart(message) => {
+                log::warn!("Error reading multipart upload: {}", message);
+                json_error(format!("Invalid upload: {}", message))
+            },
+            UploadError::Io(e) => {
+                log::error!("Failed to save uploaded file: {}", e);
+                internal_error("Failed to save uploaded file".to_string())
+            },
+        }
+    }
+}
+
+/// Upload written to a hidden file in the uploads directory, removed again unless it is persisted
+pub struct TempUpload {
+    path: PathBuf,
+    size: u64,
+    head: Vec<u8>,
+    persisted: bool,
+}
+
+impl TempUpload {
+    pub fn size(&self) -> u64 {
+        self.size
+    }
+
+    /// The first bytes of the file, enough to tell its format
+    pub fn head(&self) -> &[u8] {
+        &self.head
+    }
+
+    /// Move the file to `destination`, a rename within the same directory so it appears complete or not at all
+    pub async fn persist(mut self, destination: &Path) -> io::Result<()> {
+        tokio::fs::rename(&self.path, destination).await?;
+        self.persisted = true;
+        Ok(())
+    }
+}
+
+impl Drop for TempUpload {
+    fn drop(&mut self) {
+        if !self.persisted {
+            let _ = std::fs::remove_file(&self.path);
+        }
+    }
+}
+
+/// Delete temporary files left behind by uploads cut off when the server stopped, returns how many
+pub fn remove_stale_uploads(dir: &Path) -> usize {
+    let Ok(entries) = std::fs::read_dir(dir) else { return 0 };
+
+    entries.filter_map(|entry| entry.ok())
+        .filter(|entry| entry.file_name().to_str().is_some_and(|name| name.starts_with(TEMP_PREFIX) && name.ends_with(TEMP_SUFFIX)))
+        .filter(|entry| std::fs::remove_file(entry.path()).is_ok())
+        .count()
+}
+
+/// Check a client-supplied file name and clean it up for display
+///
+/// A name with a path in it is refused rather than cut down to its last part, control characters
+/// and surrounding whitespace are dropped and an overlong name is shortened, keeping its extension.
+pub fn sanitize_filename(name: &str) -> Result<String, String> {
+    if name.contains(['/', '\\']) || name.trim() == ".." {
+        return Err("File name must not contain a path".to_string());
+    }
+
+    let name = name.chars().filter(|c| !c.is_control()).collect::<String>();
+    let name = name.trim();
+    if name.is_empty() || name == "." {
+        return Err("File name is empty".to_string());
+    }
+    if name.chars().count() <= MAX_FILENAME_LENGTH {
+        return Ok(name.to_string());
+    }
+
+    let (stem, extension) = match name.rsplit_once('.') {
+        Some((stem, extension)) if !stem.is_empty() && extension.chars().count() < 16 => (stem, format!(".{}", extension)),
+        _ => (name, String::new()),
+    };
+    let stem = stem.chars().take(MAX_FILENAME_LENGTH - extension.chars().count()).collect::<String>();
+    Ok(format!("{}{}", stem.trim_end(), extension))
+}
+
+/// Write the chunks of a field to a temporary file in `dir`, stopping as soon as they exceed `max_size` bytes
+pub async fn stream_to_temp_file<S, E>(mut chunks: S, dir: &Path, max_size: usize, too_large: impl FnOnce() -> String) -> Result<TempUpload, UploadError>
+where
+    S: Stream<Item = Result<Bytes, E>> + Unpin,
+    E: Display,
+{
+    let mut upload = TempUpload {
+        path: dir.join(format!("{}{}{}", TEMP_PREFIX, Uuid::new_v4(), TEMP_SUFFIX)),
+        size: 0,
+        head: Vec::new(),
+        persisted: false,
+    };
+    let mut file = tokio::fs::File::create(&upload.path).await.map_err(UploadError::Io)?;
+
+    while let Some(chunk) = chunks.try_next().await.map_err(|e| UploadError::Multipart(e.to_string()))? {
+        if upload.size as usize + chunk.len() > max_size {
+            return Err(UploadError::TooLarge(too_large()));
+        }
+        file.write_all(&chunk).await.map_err(UploadError::Io)?;
+        upload.size += chunk.len() as u64;
+        if upload.head.len() < HEAD_LENGTH {
+            let missing = HEAD_LENGTH - upload.head.len();
+            upload.head.extend_from_slice(&chunk[..chunk.len().min(missing)]);
+        }
+    }
+    file.flush().await.map_err(UploadError::Io)?;
+
+    Ok(upload)
+}
+
+/// Collect a small text field, at most [`MAX_FORM_FIELD_SIZE`] bytes
+pub async fn read_text_field<S, E>(mut chunks: S, name: &str) -> Result<String, UploadError>
+where
+    S: Stream<Item = Result<Bytes, E>> + Unpin,
+    E: Display,
+{
+    let mut data = Vec::new();
+
+    while let Some(chunk) = chunks.try_next().await.map_err(|e| UploadError::Multipart(e.to_string()))? {
+        if data.len() + chunk.len() > MAX_FORM_FIELD_SIZE {
+            return Err(UploadError::TooLarge(format!("Form field '{}' exceeds {} KB", name, MAX_FORM_FIELD_SIZE / 1024)));
+        }
+        data.extend_from_slice(&chunk);
+    }
+
+    String::from_utf8(data).map_err(|_| UploadError::Multipart(format!("Form field '{}' is not valid UTF-8", name)))
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use futures_util::stream;
+    use crate::test_utils::TempDir;
+
+    fn chunks(parts: &[&'static [u8]]) -> impl Stream<Item = Result<Bytes, String>> + Unpin {
+        stream::iter(parts.iter().map(|part| Ok(Bytes::from_static(part))).collect::<Vec<_>>())
+    }
+
+    #[tokio::test]
+    async fn test_stream_to_temp_file() {
+        let dir = TempDir::new("upload");
+        let too_large = || "too large".to_string();
+
+        let upload = stream_to_temp_file(chunks(&[b"%PDF-1.4\n", b"body"]), &dir, 13, too_large).await.ok().unwrap();
+        assert_eq!(upload.size(), 13);
+        assert_eq!(upload.head(), b"%PDF-1.4\nbody");
+        let destination = dir.join("report.pdf");
+        upload.persist(&destination).await.unwrap();
+        assert_eq!(std::fs::read(&destination).unwrap(), b"%PDF-1.4\nbody");
+
+        // Over the limit, or dropped without persisting, nothing is left behind
+        let result = stream_to_temp_file(chunks(&[b"%PDF-1.4\n", b"body"]), &dir, 12, too_large).await;
+        assert!(matches!(result, Err(UploadError::TooLarge(message)) if message == "too large"));
+        let upload = stream_to_temp_file(chunks(&[b"data"]), &dir, 12, too_large).await.ok().unwrap();
+        assert!(upload.path.exists());
+        let temp_path = upload.path.clone();
+        drop(upload);
+        assert!(!temp_path.exists());
+
+        let broken = stream::iter(vec![Ok(Bytes::from_static(b"data")), Err("connection reset".to_string())]);
+        assert!(matches!(stream_to_temp_file(broken, &dir, 12, too_large).await, Err(UploadError::Multipart(_))));
+
+        let files = std::fs::read_dir(&dir).unwrap().map(|entry| entry.unwrap().file_name()).collect::<Vec<_>>();
+        assert_eq!(files, vec!["report.pdf"]);
+
+        // As if the server stopped halfway through an upload
+        std::mem::forget(stream_to_temp_file(chunks(&[b"data"]), &dir, 12, too_large).await.ok().unwrap());
+        assert_eq!(remove_stale_uploads(&dir), 1);
+        assert!(destination.exists());
+    }
+
+    #[tokio::test]
+    async fn test_read_text_field() {
+        assert_eq!(read_text_field(chunks(&[b"EPSON_", b"L3110"]), "printer").await.ok().unwrap(), "EPSON_L3110");
+        assert!(matches!(read_text_field(chunks(&[b"\xff"]), "printer").await, Err(UploadError::Multipart(_))));
+
+        let large = Box::leak(vec![b'1'; MAX_FORM_FIELD_SIZE + 1].into_boxed_slice());
+        assert!(matches!(read_text_field(chunks(&[large]), "copies").await, Err(UploadError::TooLarge(_))));
+    }
+
+    #[test]
+    fn test_sanitize_filename() {
+        assert_eq!(sanitize_filename("  Quarterly report.pdf ").unwrap(), "Quarterly report.pdf");
+        assert_eq!(sanitize_filename("in\u{1b}[31mvoice\n.pdf").unwrap(), "in[31mvoice.pdf");
+        assert_eq!(sanitize_filename(".bashrc").unwrap(), ".bashrc");
+
+        for name in ["../../etc/passwd", "..\\boot.ini", "/tmp/report.pdf", "C:\\Users\\me\\report.pdf", ".."] {
+            assert_eq!(sanitize_filename(name), Err("File name must not contain a path".to_string()), "{}", name);
+        }
+        assert!(sanitize_filename(" \t").is_err());
+        assert!(sanitize_filename(".").is_err());
+
+        let long = format!("{}.pdf", "a".repeat(300));
+        let sanitized = sanitize_filename(&long).unwrap();
+        assert_eq!(sanitized.chars().count(), MAX_FILENAME_LENGTH);
+        assert!(sanitized.ends_with("a.pdf"));
+    }
+}
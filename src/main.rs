@@ -1,80 +1,116 @@
-use actix_session::{SessionExt, SessionMiddleware, storage::RedisSessionStore};
+use actix_session::{SessionMiddleware, storage::RedisSessionStore};
 use actix_files::Files;
-use actix_web::{web, App, HttpServer, middleware::Logger, cookie::Key};
-use actix_limitation::{Limiter, RateLimiter};
-use std::{io, io::BufReader};
+use actix_web::{web, App, HttpRequest, HttpServer, middleware::{from_fn, Logger}, cookie::Key};
+use actix_limitation::Limiter;
+use std::io;
+use std::sync::Arc;
 use std::time::Duration;
-use rustls::ServerConfig;
-use std::fs::File;
 mod handlers;
 mod services;
 mod models;
 mod utils;
 mod database;
+mod settings;
+mod tls;
+mod store;
+mod auth;
+mod shutdown;
+#[cfg(test)]
+mod test_utils;
 
-use handlers::{print, scan, system, events};
+use handlers::{print, scan, system, events, tokens, upload, auth as auth_handlers};
 use crate::database::init_database;
-use crate::models::{AppState, JobQueue};
+use crate::shutdown::ShutdownCoordinator;
+use crate::auth::{LoginThrottle, RequireRole};
+use crate::models::{AppState, JobQueue, Role, SystemSettings, bootstrap_admin, notify_scan_queue, recover_job_queue, run_device_refresh, run_ink_monitor};
+use crate::services::backend::Backend;
+use crate::settings::{DeviceBackend, Settings, StoreBackend, TlsMode};
+use crate::store::{SessionBackend, SqliteSessionStore};
+use crate::store::rate_limit::{MemoryLimiter, RateLimitBackend, RateLimiter};
+use crate::tls::{acme, get_tls_config};
 
+const RATE_LIMIT_REQUESTS: usize = 120;
+const RATE_LIMIT_PERIOD: Duration = Duration::from_secs(60);
 
-const REDIS_URL: &str = "redis://127.0.0.1:6379";
-const BIND_ADDRESS: &str = "0.0.0.0:8080";
-const MAX_UPLOAD_SIZE: usize = 50 * 1024 * 1024;
-
-fn get_tls_config() -> Result<ServerConfig, io::Error> {
-    let mut certs_file = BufReader::new(File::open("certs/cert.pem")?);
-    let mut keys_file = BufReader::new(File::open("certs/key.pem")?);
-
-    let tls_certs = rustls_pemfile::certs(&mut certs_file)
-        .collect::<Result<Vec<_>, _>>()?;
-    let tls_keys = rustls_pemfile::pkcs8_private_keys(&mut keys_file)
-        .next().unwrap()?;
-
-    let tls_config = ServerConfig::builder()
-        .with_no_client_auth()
-        .with_single_cert(tls_certs, rustls::pki_types::PrivateKeyDer::Pkcs8(tls_keys))
-        .map_err(|e| io::Error::new(io::ErrorKind::Other, e))?;
-
-    Ok(tls_config)
-}
 
 #[actix_web::main]
 async fn main() -> io::Result<()> {
     rustls::crypto::aws_lc_rs::default_provider()
-        .install_default().map_err(|_| io::Error::new(io::ErrorKind::Other, "Failed to install crypto provider"))?;
+        .install_default().map_err(|_| io::Error::other("Failed to install crypto provider"))?;
 
     env_logger::init_from_env(env_logger::Env::new().default_filter_or("info"));
 
     log::info!("Starting Print/Scan Manager server");
 
-    std::fs::create_dir_all("uploads").unwrap_or_else(|e| {
+    let settings = Settings::load().map_err(|e| {
+        log::error!("Failed to load settings: {}", e);
+        io::Error::new(io::ErrorKind::InvalidInput, e)
+    })?;
+    log::info!("Loaded settings: {:?}", settings);
+
+    std::fs::create_dir_all(settings.uploads_dir()).unwrap_or_else(|e| {
         log::warn!("Could not create uploads directory: {}", e);
     });
-    std::fs::create_dir_all("scans").unwrap_or_else(|e| {
+    std::fs::create_dir_all(settings.scans_dir()).unwrap_or_else(|e| {
         log::warn!("Could not create scans directory: {}", e);
     });
+    let stale_uploads = upload::remove_stale_uploads(settings.uploads_dir());
+    if stale_uploads > 0 {
+        log::info!("Removed {} unfinished uploads", stale_uploads);
+    }
 
     log::info!("Starting database local server");
-    let pool = init_database().await.map_err(|e| io::Error::new(io::ErrorKind::Other, e))?;
+    let pool = init_database(&settings.storage.database_path).await.map_err(io::Error::other)?;
+
+    let system_settings = SystemSettings::init(&pool, settings.server.max_upload_size).await.map_err(io::Error::other)?;
+    log::info!("Loaded system settings: {:?}", system_settings);
+
+    let generated_password = bootstrap_admin(&settings.auth.admin_username, settings.auth.admin_password.as_deref(), &pool)
+        .await
+        .map_err(io::Error::other)?;
+    if let Some(password) = generated_password {
+        log::warn!("Generated password for admin account '{}': {} - change it after logging in",
+            settings.auth.admin_username, password);
+    }
+    let login_throttle = web::Data::new(LoginThrottle::new(
+        settings.auth.max_failed_logins,
+        Duration::from_secs(settings.auth.lockout_minutes * 60)
+    ));
 
     log::info!("Creating app state");
-    let app_state = AppState::new().await;
+    if settings.devices.backend == DeviceBackend::Simulated {
+        log::warn!("Using simulated printers and scanner, nothing is really printed or scanned");
+    }
+    let app_state = AppState::new(Backend::new(settings.devices.backend)).await;
 
     log::info!("Found devices:\n{}", app_state.show_devices().await);
 
     let job_queue = JobQueue::new();
     let event_state = events::EventState::new();
 
-    // Setting up Redis session store
-    let redis_connection_string = REDIS_URL;
-    log::info!("Connecting to Redis for sessions...");
-    let redis_store = RedisSessionStore::new(redis_connection_string)
-        .await
-        .map_err(|e| {
-            log::error!("Failed to connect to Redis: {}", e);
-            io::Error::new(io::ErrorKind::Other, e)
-        })?;
-    log::info!("Successfully connected to Redis session store");
+    match recover_job_queue(&job_queue, &pool, &settings, app_state.backend()).await {
+        Ok(0) => {},
+        Ok(recovered) => log::info!("Recovered {} queued jobs from the database", recovered),
+        Err(e) => log::error!("Failed to recover the job queue: {}", e),
+    }
+
+    let session_store = match settings.store.backend {
+        StoreBackend::Redis => {
+            log::info!("Connecting to Redis for sessions...");
+            let redis_store = RedisSessionStore::new(settings.redis.url.as_str())
+                .await
+                .map_err(|e| {
+                    log::error!("Failed to connect to Redis: {}", e);
+                    io::Error::other(e)
+                })?;
+            log::info!("Successfully connected to Redis session store");
+            SessionBackend::Redis(redis_store)
+        },
+        StoreBackend::Embedded => {
+            log::info!("Using embedded SQLite session store");
+            SessionBackend::Sqlite(SqliteSessionStore::new(pool.clone()))
+        },
+    };
 
     let secret_key = if let Ok(key_str) = std::env::var("WEB_PRINTER_SESSION_SECRET_KEY") {
         let key_bytes = key_str.as_bytes();
@@ -88,45 +124,80 @@ async fn main() -> io::Result<()> {
         Key::generate()
     };
 
-    let limiter = web::Data::new(
-        Limiter::builder(redis_connection_string)
-            .key_by(|req| {
-                req.get_session()
-                    .get::<String>("user_id")
-                    .ok()
-                    .flatten()
-                    .or_else(|| {
-                        req.cookie("rate-api-id")
-                            .map(|c| c.value().to_string())
-                    })
-                    .or_else(|| {
-                        req.connection_info()
-                            .realip_remote_addr()
-                            .map(|s| s.to_string())
-                    })
-            })
-            .limit(120usize)
-            .period(Duration::from_secs(60u64))
-            .build()
-            .map_err(|e| {
-                log::error!("Redis error: {}", e);
-                io::Error::new(io::ErrorKind::Other, e) })?,
-    );
+    let rate_limit = match settings.store.backend {
+        StoreBackend::Redis => RateLimitBackend::Redis(
+            Limiter::builder(settings.redis.url.as_str())
+                .limit(RATE_LIMIT_REQUESTS)
+                .period(RATE_LIMIT_PERIOD)
+                .build()
+                .map_err(|e| {
+                    log::error!("Redis error: {}", e);
+                    io::Error::other(e) })?
+        ),
+        StoreBackend::Embedded => RateLimitBackend::Memory(MemoryLimiter::new(RATE_LIMIT_REQUESTS, RATE_LIMIT_PERIOD)),
+    };
+    let rate_limit = Arc::new(rate_limit);
     log::info!("Successfully created rate-limiter");
 
-    let tls_config = get_tls_config().map_err(|e| {
-        log::error!("Failed to load TLS certificates: {}. Have you created cert files?", e);
-        e
-    })?;
-    log::info!("Successfully loaded cert files");
+    let tls_mode = settings.tls.mode;
+    let tls_config = match tls_mode {
+        TlsMode::Http => None,
+        TlsMode::Https | TlsMode::Both => {
+            let tls_config = get_tls_config(&settings.tls).map_err(|e| {
+                log::error!("Failed to load TLS certificates: {}. Have you created cert files?", e);
+                e
+            })?;
+            log::info!("Successfully loaded cert files");
+            Some(tls_config)
+        }
+    };
 
-    HttpServer::new(move || {
+    let acme_challenges = acme::AcmeChallenges::new();
+    if settings.tls.acme.enabled
+        && let Some((_, resolver)) = &tls_config {
+        log::info!("Starting ACME certificate renewal for {}", settings.tls.acme.domains.join(", "));
+        tokio::spawn(acme::run_renewal(settings.tls.clone(), resolver.clone(), acme_challenges.clone()));
+    }
+
+    let bind_address = settings.server.bind_address.clone();
+    let tls_bind_address = settings.tls.bind_address.clone();
+    let https_port = settings.https_port().unwrap_or(443);
+    let workers = settings.server.workers;
+    let max_upload_size = settings.server.max_upload_size;
+    let settings = web::Data::new(settings);
+
+    let shutdown = ShutdownCoordinator::new(job_queue.clone(), pool.clone(), settings.clone());
+
+    if settings.devices.refresh_interval_secs > 0 {
+        let interval = Duration::from_secs(settings.devices.refresh_interval_secs);
+        tokio::spawn(run_device_refresh(app_state.clone(), job_queue.clone(), event_state.clone(), interval));
+    }
+
+    if settings.devices.ink_check_interval_secs > 0 {
+        let interval = Duration::from_secs(settings.devices.ink_check_interval_secs);
+        tokio::spawn(run_ink_monitor(app_state.clone(), job_queue.clone(), pool.clone(), event_state.clone(), interval));
+    }
+
+    // Start on the recovered jobs
+    {
+        let (job_queue, pool, event_state, settings) = (job_queue.clone(), pool.clone(), event_state.clone(), settings.clone());
+        let backend = app_state.backend().clone();
+        tokio::spawn(async move {
+            if let Err(e) = notify_scan_queue(&job_queue, &pool, &event_state, &settings, &backend).await {
+                log::error!("Failed to start recovered jobs: {}", e);
+            }
+        });
+    }
+
+    let server = HttpServer::new(move || {
         App::new()
             .wrap(Logger::default())
+            .wrap(from_fn(auth::require_login))
+            .wrap(RateLimiter::new(rate_limit.clone()))
             .wrap(
-                SessionMiddleware::builder(redis_store.clone(), secret_key.clone())
+                SessionMiddleware::builder(session_store.clone(), secret_key.clone())
                     .cookie_name("session-id".to_string())
-                    .cookie_secure(true)
+                    .cookie_secure(tls_mode != TlsMode::Http)
                     .cookie_http_only(true)
                     .cookie_same_site(actix_web::cookie::SameSite::Lax)
                     .session_lifecycle(
@@ -135,40 +206,68 @@ async fn main() -> io::Result<()> {
                     )
                     .build()
             )
-            .wrap(RateLimiter::default())
-            .app_data(limiter.clone())
 
             // API routes
             .service(
                 web::scope("/api")
+                    // Auth endpoints
+                    .route("/auth/login", web::post().to(auth_handlers::login))
+                    .route("/auth/logout", web::post().to(auth_handlers::logout))
+                    .route("/auth/me", web::get().to(auth_handlers::me))
+                    .route("/auth/password", web::post().to(auth_handlers::change_password))
+                    .service(
+                        web::scope("/users")
+                            .wrap(RequireRole::new(Role::Admin))
+                            .route("", web::get().to(auth_handlers::list_users))
+                            .route("", web::post().to(auth_handlers::create_user))
+                            .route("/{user_id}", web::delete().to(auth_handlers::delete_user))
+                            .route("/{user_id}/role", web::put().to(auth_handlers::update_role))
+                    )
+                    .route("/tokens", web::get().to(tokens::list_tokens))
+                    .route("/tokens", web::post().to(tokens::create_token))
+                    .route("/tokens/{token_id}", web::delete().to(tokens::revoke_token))
+
                     // Print endpoints
                     .route("/printers", web::get().to(print::list_printers))
-                    .route("/print", web::post().to(print::submit_print_job))
+                    .route("/printers", web::post().to(print::add_printer).wrap(RequireRole::new(Role::Admin)))
+                    .route("/printers/discover", web::get().to(print::discover_printers).wrap(RequireRole::new(Role::Admin)))
+                    .route("/printers/{name}", web::delete().to(print::delete_printer).wrap(RequireRole::new(Role::Admin)))
+                    .route("/printers/{name}/capabilities", web::get().to(print::get_printer_capabilities))
+                    .route("/printers/{name}/ink/history", web::get().to(print::get_ink_history))
+                    .route("/printers/{name}/enable", web::post().to(print::enable_printer).wrap(RequireRole::new(Role::Admin)))
+                    .route("/printers/{name}/disable", web::post().to(print::disable_printer).wrap(RequireRole::new(Role::Admin)))
+                    .route("/printers/{name}/accept", web::post().to(print::accept_printer_jobs).wrap(RequireRole::new(Role::Admin)))
+                    .route("/printers/{name}/reject", web::post().to(print::reject_printer_jobs).wrap(RequireRole::new(Role::Admin)))
+                    .route("/printers/{name}/purge", web::post().to(print::purge_printer_jobs).wrap(RequireRole::new(Role::Admin)))
+                    .route("/printers/{name}/default", web::post().to(print::set_default_printer).wrap(RequireRole::new(Role::Admin)))
+                    .route("/print", web::post().to(print::submit_print_job).wrap(RequireRole::new(Role::User)))
                     .route("/print/jobs", web::get().to(print::list_print_jobs))
                     .route("/print/jobs/{job_id}", web::get().to(print::get_print_job))
-                    .route("/print/jobs/{job_id}", web::post().to(print::cancel_print_job))
-                    .route("/print/jobs/{job_id}", web::delete().to(print::delete_print_job_record))
+                    .route("/print/jobs/{job_id}", web::post().to(print::cancel_print_job).wrap(RequireRole::new(Role::User)))
+                    .route("/print/jobs/{job_id}", web::delete().to(print::delete_print_job_record).wrap(RequireRole::new(Role::User)))
 
                     // Scan endpoints
                     .route("/scanners", web::get().to(scan::list_scanners))
-                    .route("/scan", web::post().to(scan::start_scan))
+                    .route("/scan", web::post().to(scan::start_scan).wrap(RequireRole::new(Role::User)))
                     .route("/scan/jobs", web::get().to(scan::list_scan_jobs))
                     .route("/scan/jobs/{job_id}", web::get().to(scan::get_scan_job))
-                    .route("/scan/jobs/{job_id}", web::delete().to(scan::delete_scan_job_record))
+                    .route("/scan/jobs/{job_id}", web::delete().to(scan::delete_scan_job_record).wrap(RequireRole::new(Role::User)))
                     .route("/scan/download/{job_id}", web::get().to(scan::download_scan))
 
                     // System endpoints
                     .route("/system/status", web::get().to(system::get_status))
                     .route("/system/settings", web::get().to(system::get_settings))
-                    .route("/system/settings", web::post().to(system::update_settings))
-                    .route("/system/nozzle/check", web::post().to(system::nozzle_check))
-                    .route("/system/nozzle/clean", web::post().to(system::nozzle_clean))
+                    .route("/system/devices/refresh", web::post().to(system::refresh_devices).wrap(RequireRole::new(Role::User)))
+                    .route("/system/settings", web::post().to(system::update_settings).wrap(RequireRole::new(Role::Admin)))
+                    .route("/system/nozzle/check", web::post().to(system::nozzle_check).wrap(RequireRole::new(Role::Admin)))
+                    .route("/system/nozzle/clean", web::post().to(system::nozzle_clean).wrap(RequireRole::new(Role::Admin)))
 
                     // SSE endpoint
                     .route("/events/stream", web::get().to(events::event_stream))
 
             )
             // Web pages
+            .route("/login", web::get().to(system::login_page))
             .route("/", web::get().to(system::index))
             .route("/print", web::get().to(system::print_page))
             .route("/scan", web::get().to(system::scan_page))
@@ -177,15 +276,48 @@ async fn main() -> io::Result<()> {
             .service(Files::new("/static", "./static").show_files_listing())
 
             // JSON payload size (for file uploads)
-            .app_data(web::PayloadConfig::new(MAX_UPLOAD_SIZE))
+            .app_data(web::PayloadConfig::new(max_upload_size))
             .app_data(web::Data::new(pool.clone()))
             .app_data(web::Data::new(app_state.clone()))
             .app_data(web::Data::new(job_queue.clone()))
             .app_data(web::Data::new(event_state.clone()))
+            .app_data(settings.clone())
+            .app_data(login_throttle.clone())
     })
-        .bind(BIND_ADDRESS)?
-        // .bind_rustls_0_23(BIND_ADDRESS, tls_config)?
-        .workers(4)
-        .run()
-        .await
+        .workers(workers)
+        // Signals are handled by the shutdown coordinator, SSE streams would otherwise hold the stop
+        .disable_signals()
+        .shutdown_timeout(5);
+
+    let server = match tls_config {
+        Some((tls_config, _)) => {
+            log::info!("Listening for HTTPS on {}", tls_bind_address);
+            server.bind_rustls_0_23(&tls_bind_address, tls_config)?
+        },
+        None => {
+            log::info!("Listening for HTTP on {}", bind_address);
+            server.bind(&bind_address)?
+        },
+    }.run();
+
+    if tls_mode != TlsMode::Both {
+        tokio::spawn(shutdown.run(vec![server.handle()]));
+        return server.await;
+    }
+
+    log::info!("Redirecting HTTP on {} to HTTPS port {}", bind_address, https_port);
+    let redirect_server = HttpServer::new(move || {
+        App::new()
+            .wrap(Logger::default())
+            .app_data(web::Data::new(acme_challenges.clone()))
+            .route("/.well-known/acme-challenge/{token}", web::get().to(acme::acme_challenge))
+            .default_service(web::to(move |req: HttpRequest| tls::redirect_to_https(req, https_port)))
+    })
+        .workers(1)
+        .disable_signals()
+        .bind(&bind_address)?
+        .run();
+
+    tokio::spawn(shutdown.run(vec![server.handle(), redirect_server.handle()]));
+    futures::try_join!(server, redirect_server).map(|_| ())
 }
\ No newline at end of file
@@ -0,0 +1,238 @@
+use std::fmt::{Display, Formatter};
+use argon2::password_hash::rand_core::{OsRng, RngCore};
+use chrono::{DateTime, Duration, Utc};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use sqlx::sqlite::SqliteRow;
+use sqlx::{Row, SqlitePool};
+use crate::query_bind;
+
+
+/// Prefix of every generated token, makes leaked tokens easy to recognize
+pub const TOKEN_PREFIX: &str = "wpt_";
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct ApiToken {
+    pub id: i64,
+    pub user_id: i64,
+    pub name: String,
+    pub scopes: Vec<TokenScope>,
+    pub created_at: DateTime<Utc>,
+    pub expires_at: Option<DateTime<Utc>>,
+    pub last_used_at: Option<DateTime<Utc>>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum TokenScope {
+    Print,
+    Scan,
+    Admin,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct NewApiTokenRequest {
+    pub name: String,
+    pub scopes: Option<Vec<TokenScope>>,
+    pub expires_in_days: Option<u32>,
+}
+
+/// Returned once on creation, the plain token cannot be read back later
+#[derive(Debug, Serialize)]
+pub struct CreatedApiToken {
+    pub token: String,
+    #[serde(flatten)]
+    pub details: ApiToken,
+}
+
+impl Display for TokenScope {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        let scope_str = match self {
+            TokenScope::Print => { "print" }
+            TokenScope::Scan => { "scan" }
+            TokenScope::Admin => { "admin" }
+        };
+
+        f.write_str(scope_str)
+    }
+}
+
+impl TryFrom<&SqliteRow> for ApiToken {
+    type Error = sqlx::Error;
+
+    fn try_from(row: &SqliteRow) -> Result<Self, Self::Error> {
+        let scopes = row.try_get::<&str, _>("scopes")?
+            .split(',')
+            .filter(|scope| !scope.is_empty())
+            .map(|scope| match scope {
+                "print" => Ok(TokenScope::Print),
+                "scan" => Ok(TokenScope::Scan),
+                "admin" => Ok(TokenScope::Admin),
+                _ => Err(sqlx::Error::InvalidArgument("Unrecognized token scope".to_string())),
+            })
+            .collect::<Result<Vec<TokenScope>, sqlx::Error>>()?;
+
+        Ok(ApiToken {
+            id: row.try_get("id")?,
+            user_id: row.try_get("user_id")?,
+            name: row.try_get("name")?,
+            scopes,
+            created_at: row.try_get("created_at")?,
+            expires_at: row.try_get("expires_at")?,
+            last_used_at: row.try_get("last_used_at")?,
+        })
+    }
+}
+
+impl ApiToken {
+    /// Store a new token for `user_id`, returns it with the plain token to hand out
+    pub async fn create(
+        user_id: i64,
+        name: &str,
+        scopes: &[TokenScope],
+        expires_at: Option<DateTime<Utc>>,
+        pool: &SqlitePool
+    ) -> Result<CreatedApiToken, sqlx::Error> {
+        let token = generate_token();
+        let scopes_str = scopes.iter().map(|scope| scope.to_string()).collect::<Vec<String>>().join(",");
+
+        let row = query_bind!(
+            r#"
+            INSERT INTO api_tokens (user_id, name, token_hash, scopes, created_at, expires_at)
+            VALUES (?, ?, ?, ?, ?, ?)
+            RETURNING *;
+            "#,
+            user_id,
+            name,
+            hash_token(&token),
+            scopes_str,
+            Utc::now(),
+            expires_at
+        ).fetch_one(pool).await?;
+
+        Ok(CreatedApiToken {
+            token,
+            details: ApiToken::try_from(&row)?,
+        })
+    }
+
+    /// Look a token up by its plain value, expired tokens are not returned
+    pub async fn find_by_token(token: &str, pool: &SqlitePool) -> Result<Option<ApiToken>, sqlx::Error> {
+        let row = query_bind!("SELECT * FROM api_tokens WHERE token_hash = ?;", hash_token(token))
+            .fetch_optional(pool).await?;
+
+        let api_token = row.as_ref().map(ApiToken::try_from).transpose()?;
+        Ok(api_token.filter(|api_token| !api_token.is_expired()))
+    }
+
+    pub async fn get_by_user(user_id: i64, pool: &SqlitePool) -> Result<Vec<ApiToken>, sqlx::Error> {
+        let rows = query_bind!("SELECT * FROM api_tokens WHERE user_id = ? ORDER BY created_at DESC;", user_id)
+            .fetch_all(pool).await?;
+
+        rows.iter().map(ApiToken::try_from).collect()
+    }
+
+    /// Revoke one of `user_id`'s tokens
+    pub async fn remove(id: i64, user_id: i64, pool: &SqlitePool) -> Result<u64, sqlx::Error> {
+        let result = query_bind!("DELETE FROM api_tokens WHERE id = ? AND user_id = ?;", id, user_id)
+            .execute(pool).await?;
+
+        Ok(result.rows_affected())
+    }
+
+    /// Revoke every token of `user_id`
+    pub async fn remove_by_user(user_id: i64, pool: &SqlitePool) -> Result<u64, sqlx::Error> {
+        let result = query_bind!("DELETE FROM api_tokens WHERE user_id = ?;", user_id)
+            .execute(pool).await?;
+
+        Ok(result.rows_affected())
+    }
+
+    /// Record a use, at most once a minute to spare the SD card
+    pub async fn touch(&mut self, pool: &SqlitePool) -> Result<(), sqlx::Error> {
+        let now = Utc::now();
+        if self.last_used_at.is_some_and(|last_used_at| now - last_used_at < Duration::minutes(1)) {
+            return Ok(());
+        }
+        self.last_used_at = Some(now);
+
+        query_bind!(
+            "UPDATE api_tokens SET last_used_at = ? WHERE id = ?;",
+            self.last_used_at,
+            self.id
+        ).execute(pool).await?;
+
+        Ok(())
+    }
+
+    pub fn is_expired(&self) -> bool {
+        self.expires_at.is_some_and(|expires_at| expires_at <= Utc::now())
+    }
+
+    pub fn has_scope(&self, scope: TokenScope) -> bool {
+        self.scopes.contains(&scope)
+    }
+}
+
+/// SHA-256 of a token as stored in the database, tokens are random so no salt is needed
+pub fn hash_token(token: &str) -> String {
+    Sha256::digest(token.as_bytes())
+        .iter()
+        .map(|byte| format!("{:02x}", byte))
+        .collect()
+}
+
+fn generate_token() -> String {
+    let mut bytes = [0u8; 32];
+    OsRng.fill_bytes(&mut bytes);
+
+    let random = bytes.iter().map(|byte| format!("{:02x}", byte)).collect::<String>();
+    format!("{}{}", TOKEN_PREFIX, random)
+}
+
+pub fn validate_token_name(name: &str) -> Result<(), String> {
+    match (1..=64).contains(&name.chars().count()) {
+        true => Ok(()),
+        false => Err("Token name must be 1-64 characters".to_string()),
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::test_pool;
+    use crate::models::{Role, User};
+
+    #[tokio::test]
+    async fn test_token_lifecycle() {
+        let pool = test_pool().await;
+        let user = User::create("cron", "cron password", Role::User, &pool).await.unwrap();
+        let other = User::create("other", "other password", Role::User, &pool).await.unwrap();
+
+        let created = ApiToken::create(user.id, "nightly", &[TokenScope::Print], None, &pool).await.unwrap();
+        assert!(created.token.starts_with(TOKEN_PREFIX));
+
+        let found = ApiToken::find_by_token(&created.token, &pool).await.unwrap().unwrap();
+        assert_eq!(found.id, created.details.id);
+        assert!(found.has_scope(TokenScope::Print));
+        assert!(!found.has_scope(TokenScope::Scan));
+        assert!(ApiToken::find_by_token("wpt_guessed", &pool).await.unwrap().is_none());
+
+        let expired = ApiToken::create(user.id, "old", &[TokenScope::Scan], Some(Utc::now() - Duration::days(1)), &pool).await.unwrap();
+        assert!(ApiToken::find_by_token(&expired.token, &pool).await.unwrap().is_none());
+        assert_eq!(ApiToken::get_by_user(user.id, &pool).await.unwrap().len(), 2);
+
+        assert_eq!(ApiToken::remove(found.id, other.id, &pool).await.unwrap(), 0);
+        assert_eq!(ApiToken::remove(found.id, user.id, &pool).await.unwrap(), 1);
+        assert!(ApiToken::find_by_token(&created.token, &pool).await.unwrap().is_none());
+
+        ApiToken::create(other.id, "backup", &[TokenScope::Scan], None, &pool).await.unwrap();
+        assert_eq!(ApiToken::remove_by_user(user.id, &pool).await.unwrap(), 1);
+        assert!(ApiToken::get_by_user(user.id, &pool).await.unwrap().is_empty());
+        assert_eq!(ApiToken::get_by_user(other.id, &pool).await.unwrap().len(), 1);
+
+        User::remove_by_id(user.id, &pool).await.unwrap();
+        assert!(ApiToken::get_by_user(user.id, &pool).await.unwrap().is_empty());
+    }
+}
@@ -1,18 +1,31 @@
 use sqlx::SqlitePool;
 use std::collections::VecDeque;
+use std::path::Path;
 use std::sync::Arc;
+use std::time::Duration;
 use tokio::sync::Mutex;
+use tokio::time::Instant;
 use uuid::Uuid;
 use crate::handlers::events::EventState;
-use crate::models::{Job, PrintJob, PrintJobStatus, ScanJob, ScanJobStatus};
-use crate::services::cups::CupsService;
-use crate::services::sane::SaneService;
+use crate::models::{Job, PrintJob, PrintJobStatus, ScanJob, ScanJobStatus, SystemSettings};
+use crate::services::backend::{Backend, PrintBackend, ScanBackend};
+use crate::services::convert::{convert_to_pdf, Conversion};
+use crate::services::cups::{CupsJobState, CupsJobStatus};
+use crate::settings::Settings;
+
+/// Time between two job state requests to CUPS
+const MONITOR_INTERVAL: Duration = Duration::from_secs(5);
+/// Longest wait between retries while CUPS cannot be reached
+const MONITOR_MAX_BACKOFF: Duration = Duration::from_secs(300);
+/// A job held or stopped for longer is left alone, the next start picks it up again
+const MONITOR_DEADLINE: Duration = Duration::from_secs(24 * 60 * 60);
 
 #[derive(Clone)]
 pub struct JobQueue {
     queue: Arc<Mutex<VecDeque<Job>>>,
     processing: Arc<Mutex<bool>>,
     processing_job_id: Arc<Mutex<Option<Uuid>>>,
+    shutting_down: Arc<Mutex<bool>>,
 }
 
 impl JobQueue {
@@ -21,6 +34,7 @@ impl JobQueue {
             queue: Arc::new(Mutex::new(VecDeque::with_capacity(5))),
             processing: Arc::new(Mutex::new(false)),
             processing_job_id: Arc::new(Mutex::new(None)),
+            shutting_down: Arc::new(Mutex::new(false)),
         }
     }
 
@@ -35,19 +49,18 @@ impl JobQueue {
         Ok(queue.pop_front())
     }
 
-    async fn is_empty(&self) -> bool {
+    pub async fn is_empty(&self) -> bool {
         let queue = self.queue.lock().await;
         queue.is_empty()
     }
 
-    async fn len(&self) -> usize {
+    pub async fn len(&self) -> usize {
         let queue = self.queue.lock().await;
         queue.len()
     }
 
-    async fn is_processing(&self) -> bool {
-        let status = *self.processing.lock().await;
-        status
+    pub async fn is_processing(&self) -> bool {
+        *self.processing.lock().await
     }
 
     async fn set_processing(&self, value: bool) {
@@ -56,11 +69,10 @@ impl JobQueue {
 
     pub async fn get_current_queue(&self, pool: &SqlitePool) -> Vec<Job> {
         let mut q = self.queue.lock().await
-            .iter()
-            .map(|j| j.clone())
+            .iter().cloned()
             .collect::<Vec<Job>>();
 
-        if let Some(job_uuid) = self.processing_job_id.lock().await.clone() {
+        if let Some(job_uuid) = *self.processing_job_id.lock().await {
             match Job::get_job_by_id(job_uuid, pool).await {
                 Err(e) => { log::warn!("Failed to get job: {}", e) },
                 Ok(job_op) => {
@@ -77,33 +89,163 @@ impl JobQueue {
     async fn set_processing_job_id(&self, value: Option<Uuid>) {
         *self.processing_job_id.lock().await = value;
     }
+
+    pub async fn is_shutting_down(&self) -> bool {
+        *self.shutting_down.lock().await
+    }
+
+    /// Refuse new jobs and stop starting queued ones, the running job is left to finish
+    pub async fn begin_shutdown(&self) {
+        *self.shutting_down.lock().await = true;
+    }
+
+    /// Wait for the running job to finish, `false` when `timeout` passed first
+    pub async fn wait_idle(&self, timeout: Duration) -> bool {
+        let deadline = Instant::now() + timeout;
+
+        while self.is_processing().await {
+            if Instant::now() >= deadline {
+                return false;
+            }
+            tokio::time::sleep(Duration::from_millis(250)).await;
+        }
+
+        true
+    }
+
+    /// Put the running job back to queued so the next start runs it again
+    ///
+    /// Print jobs already handed to CUPS are left alone, startup recovery reconciles them with CUPS.
+    pub async fn checkpoint_running_job(&self, pool: &SqlitePool, settings: &Settings) -> Result<(), sqlx::Error> {
+        let job_id = match *self.processing_job_id.lock().await {
+            Some(job_id) => job_id,
+            None => return Ok(()),
+        };
+
+        match Job::get_job_by_id(job_id, pool).await? {
+            Some(Job::Scan(mut scan_job)) => {
+                if let Some(file_path) = scan_job.get_file_path(settings.scans_dir()) {
+                    let _ = std::fs::remove_file(file_path);
+                }
+                scan_job.status = ScanJobStatus::Queued;
+                scan_job.started_at = None;
+                scan_job.file_available = false;
+                scan_job.update_statues_in_db(pool).await?;
+                log::warn!("Scan job {} interrupted by shutdown, it will run again on the next start", job_id);
+            },
+            Some(Job::Print(mut print_job)) if print_job.cups_job_id.is_none() => {
+                print_job.status = PrintJobStatus::Queued;
+                print_job.started_at = None;
+                print_job.update_in_db(pool).await?;
+                log::warn!("Print job {} interrupted by shutdown, it will run again on the next start", job_id);
+            },
+            Some(Job::Print(_)) | None => {},
+        }
+
+        Ok(())
+    }
+}
+
+/// Rebuild the queue from the database after a restart
+///
+/// Jobs still queued are pushed back in `created_at` order. Jobs interrupted mid-run are reconciled:
+/// print jobs already handed to CUPS take the CUPS state, everything else is marked failed.
+/// Returns the number of jobs put back in the queue.
+pub async fn recover_job_queue(job_queue: &JobQueue, pool: &SqlitePool, settings: &Settings, backend: &Backend) -> Result<usize, Box<dyn std::error::Error + Send + Sync>> {
+    let auto_cleanup = SystemSettings::load(pool).await?.auto_cleanup;
+
+    let in_progress = vec![PrintJobStatus::Converting, PrintJobStatus::Processing, PrintJobStatus::Printing, PrintJobStatus::Held, PrintJobStatus::Stopped];
+    for mut print_job in PrintJob::find_by_statuses(in_progress, pool).await? {
+        let cups_job_id = match print_job.cups_job_id {
+            Some(cups_job_id) => cups_job_id,
+            None => {
+                print_job.set_error("Interrupted by a server restart before reaching CUPS".to_string());
+                print_job.update_in_db(pool).await?;
+                continue;
+            }
+        };
+
+        match backend.get_job_status(cups_job_id).await {
+            Ok(cups_status) if is_final(&cups_status) => {
+                log::info!("Print job {} finished while the server was down: {:?}", print_job.id, cups_status.state);
+                apply_cups_status(&mut print_job, &cups_status);
+                print_job.update_in_db(pool).await?;
+            },
+            Ok(_) => {
+                log::info!("Print job {} is still in CUPS, resuming monitoring", print_job.id);
+                spawn_print_job_monitor(print_job.id, cups_job_id, pool, settings.uploads_dir(), auto_cleanup, backend);
+            },
+            Err(e) => {
+                print_job.set_error(format!("Interrupted by a server restart, CUPS job {} state unknown: {}", cups_job_id, e));
+                print_job.update_in_db(pool).await?;
+            },
+        }
+    }
+
+    for mut scan_job in ScanJob::find_by_statuses(vec![ScanJobStatus::Scanning, ScanJobStatus::Processing], pool).await? {
+        // The scanner stopped with the process, whatever was written is incomplete
+        if let Some(file_path) = scan_job.get_file_path(settings.scans_dir()) {
+            let _ = std::fs::remove_file(file_path);
+        }
+        scan_job.file_available = false;
+        scan_job.set_error("Interrupted by a server restart".to_string());
+        scan_job.update_statues_in_db(pool).await?;
+    }
+
+    let mut jobs = Vec::new();
+    for mut print_job in PrintJob::find_by_statuses(vec![PrintJobStatus::Queued], pool).await? {
+        let file_exists = print_job.get_file_path(settings.uploads_dir())
+            .is_some_and(|file_path| Path::new(&file_path).is_file());
+
+        if file_exists {
+            jobs.push(Job::Print(print_job));
+        } else {
+            print_job.set_error("Uploaded file is missing after a server restart".to_string());
+            print_job.update_in_db(pool).await?;
+        }
+    }
+    jobs.extend(ScanJob::find_by_statuses(vec![ScanJobStatus::Queued], pool).await?
+        .into_iter()
+        .map(Job::Scan));
+    jobs.sort_by_key(|job| job.created_at());
+
+    let recovered = jobs.len();
+    for job in jobs {
+        job_queue.push(job).await?;
+    }
+
+    Ok(recovered)
 }
 
 pub async fn add_to_job_queue(job_queue: &JobQueue, job: Job) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    if job_queue.is_shutting_down().await {
+        return Err("Server is shutting down".into());
+    }
+
     log::warn!("Added new job to queue: {}", job);
     job_queue.push(job).await
 }
 
-pub async fn notify_scan_queue(job_queue: &JobQueue, pool: &SqlitePool, event_state: &EventState) -> Result<(), Box<dyn std::error::Error>> {
+pub async fn notify_scan_queue(job_queue: &JobQueue, pool: &SqlitePool, event_state: &EventState, settings: &Settings, backend: &Backend) -> Result<(), Box<dyn std::error::Error>> {
     if job_queue.is_empty().await {
         return Ok(());
     }
 
-    if job_queue.is_processing().await {
+    if job_queue.is_processing().await || job_queue.is_shutting_down().await {
         return Ok(());
     }
 
     let queue_len = job_queue.len().await;
     log::info!("Requests in queue: {}", queue_len);
 
-    if let Err(e) = handle_job(job_queue, pool, event_state).await {
+    if let Err(e) = handle_job(job_queue, pool, event_state, settings, backend).await {
         log::error!("Failed to handle next job in queue: {}", e);
     }
 
     Ok(())
 }
 
-async fn handle_job(job_queue: &JobQueue, pool: &SqlitePool, event_state: &EventState) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+async fn handle_job(job_queue: &JobQueue, pool: &SqlitePool, event_state: &EventState, settings: &Settings, backend: &Backend) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
     if let Some(mut job) = job_queue.pop().await? {
         log::warn!("Processing job: {}", job);
         // FIXME wrap me
@@ -111,7 +253,7 @@ async fn handle_job(job_queue: &JobQueue, pool: &SqlitePool, event_state: &Event
         job_queue.set_processing_job_id(Some(job.id())).await;
         event_state.increment_status_version().await;
 
-        job.execute(pool).await;
+        job.execute(pool, settings, backend).await;
 
         job_queue.set_processing(false).await;
         job_queue.set_processing_job_id(None).await;
@@ -120,7 +262,7 @@ async fn handle_job(job_queue: &JobQueue, pool: &SqlitePool, event_state: &Event
         event_state.increment_recent_activity_version().await;
     }
 
-    if let Err(e) = Box::pin(notify_scan_queue(job_queue, pool, event_state)).await {
+    if let Err(e) = Box::pin(notify_scan_queue(job_queue, pool, event_state, settings, backend)).await {
         log::error!("Failed to notify scan queue: {}", e);
     }
 
@@ -128,9 +270,7 @@ async fn handle_job(job_queue: &JobQueue, pool: &SqlitePool, event_state: &Event
 }
 
 /// Background task to execute scan job
-pub async fn execute_scan_job(job_id: Uuid, pool: &SqlitePool) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
-    let sane_service = SaneService::new();
-
+pub async fn execute_scan_job(job_id: Uuid, pool: &SqlitePool, scans_dir: &Path, backend: &Backend) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
     // Get job from storage
     let mut job = match ScanJob::find_by_uuid(job_id, pool).await {
         Ok(Some(job)) => job,
@@ -148,13 +288,15 @@ pub async fn execute_scan_job(job_id: Uuid, pool: &SqlitePool) -> Result<(), Box
     job.update_statues_in_db(pool).await?;
 
     // Execute the scan
-    match sane_service.start_scan(&job).await {
+    match backend.start_scan(&job, scans_dir).await {
         Ok(output_path) => {
             // Update job with file metadata if available
             if let Ok(metadata) = std::fs::metadata(&output_path) {
                 job.file_size = Some(metadata.len());
                 job.file_available = true;
             }
+            // scanimage scans a single page per run
+            job.page_count = Some(1);
             job.set_status(ScanJobStatus::Completed);
             job.update_statues_in_db(pool).await?;
 
@@ -173,16 +315,52 @@ pub async fn execute_scan_job(job_id: Uuid, pool: &SqlitePool) -> Result<(), Box
 }
 
 
-pub async fn execute_print_job(print_job: &mut PrintJob, pool: &SqlitePool) -> Result<(), Box<dyn std::error::Error + Send + Sync>>  {
-    let cups_service = CupsService::new();
+pub async fn execute_print_job(print_job: &mut PrintJob, pool: &SqlitePool, uploads_dir: &Path, backend: &Backend) -> Result<(), Box<dyn std::error::Error + Send + Sync>>  {
     let pool = pool.clone(); // fixme maybe its possible not cloning
 
-    let file_path = print_job.get_file_path().unwrap_or_else(|| {
-        log::warn!("Could not get file path from print job. Trying default one: uploads/{}", print_job.filename);
-        format!("uploads/{}", print_job.filename)
-    });
+    let mut file_path = print_job.get_file_path(uploads_dir)
+        .ok_or_else(|| format!("Could not get file path for print job {}", print_job.id))?;
+    let auto_cleanup = SystemSettings::load(&pool).await?.auto_cleanup;
+
+    if let Some(conversion) = Conversion::for_file(&print_job.filename) {
+        print_job.set_status(PrintJobStatus::Converting);
+        print_job.update_in_db(&pool).await?;
+
+        let pdf_filename = format!("{}.pdf", print_job.id);
+        let pdf_path = uploads_dir.join(&pdf_filename);
+        let (width, height) = print_job.page_size.dimensions();
+        let page_size = (width as f64 / 100.0, height as f64 / 100.0);
+
+        if let Err(e) = convert_to_pdf(conversion, Path::new(&file_path), &pdf_path, page_size).await {
+            log::error!("Print job {} could not be converted: {}", print_job.id, e);
+            print_job.set_error(format!("Could not convert {} to PDF: {}", print_job.original_filename, e));
+            print_job.update_in_db(&pool).await?;
+
+            let _ = std::fs::remove_file(&pdf_path);
+            if auto_cleanup {
+                let _ = std::fs::remove_file(&file_path);
+            }
+            return Ok(());
+        }
 
-    match cups_service.submit_print_job(&print_job, &file_path).await {
+        // The job may have been cancelled while the document was converted
+        let cancelled = PrintJob::find_by_uuid(print_job.id, &pool).await?
+            .is_none_or(|job| job.status == PrintJobStatus::Cancelled);
+        if cancelled {
+            log::info!("Print job {} was cancelled during the conversion", print_job.id);
+            let _ = std::fs::remove_file(&pdf_path);
+            if auto_cleanup {
+                let _ = std::fs::remove_file(&file_path);
+            }
+            return Ok(());
+        }
+
+        let _ = std::fs::remove_file(&file_path);
+        print_job.set_converted_file(pdf_filename, &pool, uploads_dir).await?;
+        file_path = pdf_path.to_string_lossy().into_owned();
+    }
+
+    match backend.submit_print_job(print_job, &file_path).await {
         Ok(cups_job_id) => {
             print_job.set_cups_job_id(cups_job_id);
             print_job.set_status(PrintJobStatus::Processing);
@@ -194,12 +372,8 @@ pub async fn execute_print_job(print_job: &mut PrintJob, pool: &SqlitePool) -> R
                 e.to_string()
             })?;
 
-            if let Err(e) = monitor_print_job(job_id, cups_job_id, &pool).await {
-                log::error!("Monitor print job {} failed: {}", job_id, e);
-            };
-
-            // CUPS ended print but printer was still busy, so there is 10 sec delay
-            tokio::time::sleep(tokio::time::Duration::from_secs(10)).await;
+            // CUPS queues the job itself, the next one can start while this one prints
+            spawn_print_job_monitor(job_id, cups_job_id, &pool, uploads_dir, auto_cleanup, backend);
         },
         Err(e) => {
             print_job.set_error(e.clone());
@@ -209,7 +383,9 @@ pub async fn execute_print_job(print_job: &mut PrintJob, pool: &SqlitePool) -> R
                 e.to_string()
             })?;
 
-            let _ = std::fs::remove_file(&file_path);
+            if auto_cleanup {
+                let _ = std::fs::remove_file(&file_path);
+            }
         }
     }
 
@@ -217,58 +393,381 @@ pub async fn execute_print_job(print_job: &mut PrintJob, pool: &SqlitePool) -> R
 }
 
 
-/// Background task to monitor print job status
-async fn monitor_print_job(job_id: Uuid, cups_job_id: i32, pool: &SqlitePool) -> actix_web::Result<(), sqlx::Error> {
-    let cups_service = CupsService::new();
-    let mut last_status = String::new();
-
-    // Monitor for up to 5 minutes
-    for _ in 0..60 {
-        tokio::time::sleep(tokio::time::Duration::from_secs(5)).await;
-
-        match cups_service.get_job_status(cups_job_id).await {
-            Ok(status) => {
-                if status != last_status {
-                    last_status = status.clone();
-
-                    if let Some(mut job) = PrintJob::find_by_uuid(job_id, pool).await? {
-                        let new_status = match status.as_str() {
-                            "queued" | "pending" => PrintJobStatus::Queued,
-                            "printing" => PrintJobStatus::Printing,
-                            "completed" => PrintJobStatus::Completed,
-                            "stopped" | "aborted" => PrintJobStatus::Failed,
-                            "cancelled" => PrintJobStatus::Cancelled,
-                            "idle" => PrintJobStatus::Completed,
-                            _ => PrintJobStatus::Processing,
-                        };
-
-                        job.set_status(new_status.clone());
-                        job.update_in_db(pool).await?;
-
-                        // If job is finished, stop monitoring
-                        match new_status {
-                            PrintJobStatus::Completed |
-                            PrintJobStatus::Failed |
-                            PrintJobStatus::Cancelled => {
-                                // Clean up uploaded file after a delay
-                                let filename = job.filename.clone();
-                                tokio::spawn(async move {
-                                    tokio::time::sleep(tokio::time::Duration::from_secs(300)).await;
-                                    let _ = std::fs::remove_file(format!("uploads/{}", filename));
-                                });
-                                break;
-                            },
-                            _ => {}
-                        }
-                    }
-                }
-            },
+fn spawn_print_job_monitor(job_id: Uuid, cups_job_id: i32, pool: &SqlitePool, uploads_dir: &Path, auto_cleanup: bool, backend: &Backend) {
+    let (pool, uploads_dir, backend) = (pool.clone(), uploads_dir.to_path_buf(), backend.clone());
+
+    tokio::spawn(async move {
+        if let Err(e) = monitor_print_job(job_id, cups_job_id, &pool, &uploads_dir, auto_cleanup, &backend).await {
+            log::error!("Monitor print job {} failed: {}", job_id, e);
+        }
+    });
+}
+
+/// Background task to monitor print job status, polls CUPS until the job reaches a final state
+///
+/// Failed requests are retried with a growing delay. After `MONITOR_DEADLINE` the job keeps its last state,
+/// or is marked failed when CUPS could not be asked at the end.
+async fn monitor_print_job(job_id: Uuid, cups_job_id: i32, pool: &SqlitePool, uploads_dir: &Path, auto_cleanup: bool, backend: &Backend) -> actix_web::Result<(), sqlx::Error> {
+    let deadline = Instant::now() + MONITOR_DEADLINE;
+    let mut last_status: Option<CupsJobStatus> = None;
+    let mut delay = MONITOR_INTERVAL;
+    let mut last_error = None;
+
+    while Instant::now() < deadline {
+        tokio::time::sleep(delay).await;
+
+        let cups_status = match backend.get_job_status(cups_job_id).await {
+            Ok(cups_status) => cups_status,
             Err(e) => {
-                log::error!("Failed to get job status for {}: {}", cups_job_id, e);
-                break;
+                delay = (delay * 2).min(MONITOR_MAX_BACKOFF);
+                log::warn!("Failed to get job status for {}, retrying in {} s: {}", cups_job_id, delay.as_secs(), e);
+                last_error = Some(e);
+                continue;
             }
+        };
+        delay = MONITOR_INTERVAL;
+        last_error = None;
+        if last_status.as_ref() == Some(&cups_status) {
+            continue;
+        }
+
+        let Some(mut job) = PrintJob::find_by_uuid(job_id, pool).await? else {
+            return Ok(());
+        };
+        apply_cups_status(&mut job, &cups_status);
+        job.update_in_db(pool).await?;
+
+        if is_final(&cups_status) {
+            // Clean up uploaded file after a delay
+            if auto_cleanup {
+                let file_path = uploads_dir.join(&job.filename);
+                tokio::spawn(async move {
+                    tokio::time::sleep(tokio::time::Duration::from_secs(300)).await;
+                    let _ = std::fs::remove_file(file_path);
+                });
+            }
+            return Ok(());
         }
+        last_status = Some(cups_status);
+    }
+
+    match last_error {
+        Some(e) => {
+            let job = PrintJob::find_by_uuid(job_id, pool).await?
+                .filter(|job| !matches!(job.status, PrintJobStatus::Completed | PrintJobStatus::Failed | PrintJobStatus::Cancelled));
+            if let Some(mut job) = job {
+                job.set_error(format!("CUPS job {} state unknown: {}", cups_job_id, e));
+                job.update_in_db(pool).await?;
+            }
+        },
+        None => log::warn!("Print job {} did not finish in CUPS within {} hours, monitoring stopped", job_id, MONITOR_DEADLINE.as_secs() / 3600),
     }
 
     Ok(())
-}
\ No newline at end of file
+}
+
+fn is_final(cups_status: &CupsJobStatus) -> bool {
+    matches!(cups_status.state, CupsJobState::Canceled | CupsJobState::Aborted | CupsJobState::Completed)
+}
+
+/// Update a print job from the state CUPS reports for it
+fn apply_cups_status(print_job: &mut PrintJob, cups_status: &CupsJobStatus) {
+    // A job CUPS no longer reports progress for keeps the last counts,
+    // pages reported without sheets leave the sheets unknown
+    if let Some(pages) = cups_status.impressions_completed {
+        print_job.pages_printed = Some(pages);
+        print_job.sheets_printed = cups_status.media_sheets_completed;
+    } else if let Some(sheets) = cups_status.media_sheets_completed {
+        print_job.sheets_printed = Some(sheets);
+    }
+
+    // A cancel CUPS accepted is kept until CUPS reports the job finished, a job that completed first still wins
+    if print_job.status == PrintJobStatus::Cancelled && !is_final(cups_status) {
+        return;
+    }
+
+    match print_status_from_cups(cups_status) {
+        PrintJobStatus::Failed => print_job.set_error(cups_failure_message(cups_status)),
+        status => print_job.set_status(status),
+    }
+}
+
+/// Map the IPP job-state reported by CUPS to the print job status
+fn print_status_from_cups(cups_status: &CupsJobStatus) -> PrintJobStatus {
+    match cups_status.state {
+        CupsJobState::Pending => PrintJobStatus::Queued,
+        CupsJobState::Held => PrintJobStatus::Held,
+        // CUPS runs the filters first, the printer only has the job once job-printing is set
+        CupsJobState::Processing if cups_status.has_reason("job-printing")
+            || cups_status.impressions_completed.is_some_and(|impressions| impressions > 0) => PrintJobStatus::Printing,
+        CupsJobState::Processing => PrintJobStatus::Processing,
+        CupsJobState::Stopped => PrintJobStatus::Stopped,
+        CupsJobState::Canceled => PrintJobStatus::Cancelled,
+        CupsJobState::Aborted => PrintJobStatus::Failed,
+        CupsJobState::Completed => PrintJobStatus::Completed,
+    }
+}
+
+fn cups_failure_message(cups_status: &CupsJobStatus) -> String {
+    if cups_status.has_reason("aborted-by-system") || cups_status.has_reason("job-aborted-by-system") {
+        return "Aborted by the print system".to_string();
+    }
+
+    match cups_status.reasons.is_empty() {
+        true => "Aborted by CUPS".to_string(),
+        false => format!("Aborted by CUPS: {}", cups_status.reasons.join(", ")),
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::{PrintRequest, Role, ScanRequest, User};
+    use crate::services::backend::simulated::SimulatedBackend;
+    use crate::test_utils::{test_pool, TempDir};
+
+    fn print_request() -> PrintRequest {
+        PrintRequest::default()
+    }
+
+    fn simulated_backend() -> Backend {
+        Backend::Simulated(SimulatedBackend::new())
+    }
+
+    fn cups_status(state: CupsJobState, reasons: &[&str], impressions_completed: Option<i32>) -> CupsJobStatus {
+        CupsJobStatus {
+            state,
+            reasons: reasons.iter().map(|reason| reason.to_string()).collect(),
+            impressions_completed,
+            media_sheets_completed: impressions_completed.map(|impressions| (impressions + 1) / 2),
+        }
+    }
+
+    #[test]
+    fn test_print_status_from_cups() {
+        let expected = [
+            (cups_status(CupsJobState::Pending, &[], Some(0)), PrintJobStatus::Queued),
+            (cups_status(CupsJobState::Held, &["job-hold-until-specified"], None), PrintJobStatus::Held),
+            (cups_status(CupsJobState::Processing, &[], Some(0)), PrintJobStatus::Processing),
+            (cups_status(CupsJobState::Processing, &["job-printing"], Some(0)), PrintJobStatus::Printing),
+            (cups_status(CupsJobState::Processing, &[], Some(3)), PrintJobStatus::Printing),
+            (cups_status(CupsJobState::Stopped, &["printer-stopped"], Some(1)), PrintJobStatus::Stopped),
+            (cups_status(CupsJobState::Canceled, &["job-canceled-by-user"], None), PrintJobStatus::Cancelled),
+            (cups_status(CupsJobState::Aborted, &["aborted-by-system"], None), PrintJobStatus::Failed),
+            (cups_status(CupsJobState::Completed, &["job-completed-successfully"], Some(4)), PrintJobStatus::Completed),
+        ];
+        for (cups_status, status) in expected {
+            assert_eq!(print_status_from_cups(&cups_status), status, "{:?}", cups_status);
+        }
+
+        let finished = [CupsJobState::Canceled, CupsJobState::Aborted, CupsJobState::Completed];
+        for state in [CupsJobState::Pending, CupsJobState::Held, CupsJobState::Processing, CupsJobState::Stopped].into_iter().chain(finished) {
+            assert_eq!(is_final(&cups_status(state, &[], None)), finished.contains(&state));
+        }
+    }
+
+    #[test]
+    fn test_apply_cups_status() {
+        let mut print_job = PrintJob::new("report.pdf".to_string(), "EPSON".to_string(), "Epson".to_string(), "L3110".to_string(), print_request(), 1);
+
+        apply_cups_status(&mut print_job, &cups_status(CupsJobState::Aborted, &["aborted-by-system"], None));
+        assert_eq!(print_job.status, PrintJobStatus::Failed);
+        assert_eq!(print_job.error_message.as_deref(), Some("Aborted by the print system"));
+        assert!(print_job.completed_at.is_some());
+
+        apply_cups_status(&mut print_job, &cups_status(CupsJobState::Aborted, &["document-format-error"], None));
+        assert_eq!(print_job.error_message.as_deref(), Some("Aborted by CUPS: document-format-error"));
+
+        apply_cups_status(&mut print_job, &cups_status(CupsJobState::Aborted, &[], None));
+        assert_eq!(print_job.error_message.as_deref(), Some("Aborted by CUPS"));
+
+        // CUPS is still stopping the job after accepting the cancel
+        print_job.set_status(PrintJobStatus::Cancelled);
+        apply_cups_status(&mut print_job, &cups_status(CupsJobState::Processing, &["job-printing"], Some(1)));
+        assert_eq!(print_job.status, PrintJobStatus::Cancelled);
+        apply_cups_status(&mut print_job, &cups_status(CupsJobState::Canceled, &["job-canceled-by-user"], Some(1)));
+        assert_eq!(print_job.status, PrintJobStatus::Cancelled);
+        apply_cups_status(&mut print_job, &cups_status(CupsJobState::Completed, &["job-completed-successfully"], Some(2)));
+        assert_eq!(print_job.status, PrintJobStatus::Completed);
+    }
+
+    #[tokio::test]
+    async fn test_print_job_page_counts() {
+        let pool = test_pool().await;
+        let user = User::create("owner", "owner password", Role::User, &pool).await.unwrap();
+        let mut print_job = PrintJob::new("report.pdf".to_string(), "EPSON".to_string(), "Epson".to_string(), "L3110".to_string(), print_request(), user.id);
+        print_job.save_to_db(&pool, Path::new("uploads")).await.unwrap();
+        assert_eq!((print_job.pages_printed, print_job.sheets_printed), (None, None));
+
+        apply_cups_status(&mut print_job, &cups_status(CupsJobState::Processing, &["job-printing"], Some(3)));
+        // No counts in the final state, the last ones are kept
+        apply_cups_status(&mut print_job, &cups_status(CupsJobState::Completed, &["job-completed-successfully"], None));
+        print_job.update_in_db(&pool).await.unwrap();
+
+        let stored = PrintJob::find_by_uuid(print_job.id, &pool).await.unwrap().unwrap();
+        assert_eq!(stored.filename, format!("{}.pdf", print_job.id));
+        assert_eq!(stored.original_filename, "report.pdf");
+        assert_eq!(stored.status, PrintJobStatus::Completed);
+        assert_eq!((stored.pages_printed, stored.sheets_printed), (Some(3), Some(2)));
+
+        // The driver reported nothing, the pages come from page_log, which has no sheets
+        apply_cups_status(&mut print_job, &cups_status(CupsJobState::Processing, &["job-printing"], Some(0)));
+        let from_page_log = CupsJobStatus { media_sheets_completed: None, ..cups_status(CupsJobState::Completed, &[], Some(3)) };
+        apply_cups_status(&mut print_job, &from_page_log);
+        print_job.update_in_db(&pool).await.unwrap();
+
+        let stored = PrintJob::find_by_uuid(print_job.id, &pool).await.unwrap().unwrap();
+        assert_eq!((stored.pages_printed, stored.sheets_printed), (Some(3), None));
+    }
+
+    #[tokio::test]
+    async fn test_print_job_conversion_failure() {
+        let pool = test_pool().await;
+        let uploads_dir = TempDir::new("convert_job");
+        let user = User::create("owner", "owner password", Role::User, &pool).await.unwrap();
+
+        // The upload is gone, so the text cannot be read for the conversion
+        let mut print_job = PrintJob::new("notes.txt".to_string(), "printer".to_string(), "vendor".to_string(), "model".to_string(), print_request(), user.id);
+        print_job.save_to_db(&pool, &uploads_dir).await.unwrap();
+        execute_print_job(&mut print_job, &pool, &uploads_dir, &simulated_backend()).await.unwrap();
+
+        let stored = PrintJob::find_by_uuid(print_job.id, &pool).await.unwrap().unwrap();
+        assert_eq!(stored.status, PrintJobStatus::Failed);
+        assert!(stored.started_at.is_some());
+        assert!(stored.cups_job_id.is_none());
+        assert!(stored.error_message.unwrap().starts_with("Could not convert notes.txt to PDF: Failed to read"));
+        assert!(!uploads_dir.join(format!("{}.pdf", print_job.id)).exists());
+    }
+
+    #[tokio::test]
+    async fn test_print_job_cancelled_during_conversion() {
+        let pool = test_pool().await;
+        let uploads_dir = TempDir::new("cancel_convert");
+        let user = User::create("owner", "owner password", Role::User, &pool).await.unwrap();
+        let backend = simulated_backend();
+
+        let print_job = PrintJob::new("notes.txt".to_string(), "Simulated_Laser".to_string(), "Simulated".to_string(), "Laser".to_string(), print_request(), user.id);
+        print_job.save_to_db(&pool, &uploads_dir).await.unwrap();
+        // Reading a FIFO blocks until it is written, which holds the conversion until the job is cancelled
+        let upload = uploads_dir.join(&print_job.filename);
+        assert!(std::process::Command::new("mkfifo").arg(&upload).status().unwrap().success());
+
+        let run = {
+            let (mut print_job, pool, uploads_dir, backend) = (print_job.clone(), pool.clone(), uploads_dir.to_path_buf(), backend.clone());
+            tokio::spawn(async move { execute_print_job(&mut print_job, &pool, &uploads_dir, &backend).await.unwrap() })
+        };
+        while PrintJob::find_by_uuid(print_job.id, &pool).await.unwrap().unwrap().status != PrintJobStatus::Converting {
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+        let mut cancelled = PrintJob::find_by_uuid(print_job.id, &pool).await.unwrap().unwrap();
+        cancelled.set_status(PrintJobStatus::Cancelled);
+        cancelled.update_in_db(&pool).await.unwrap();
+        tokio::task::spawn_blocking(move || std::fs::write(upload, "Shopping list\n")).await.unwrap().unwrap();
+        run.await.unwrap();
+
+        let stored = PrintJob::find_by_uuid(print_job.id, &pool).await.unwrap().unwrap();
+        assert_eq!(stored.status, PrintJobStatus::Cancelled);
+        assert!(stored.cups_job_id.is_none());
+        assert!(!uploads_dir.join(format!("{}.pdf", print_job.id)).exists());
+        assert!(backend.get_active_jobs().await.unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_print_job_does_not_wait_for_cups() {
+        let pool = test_pool().await;
+        let uploads_dir = TempDir::new("held_job");
+        let user = User::create("owner", "owner password", Role::User, &pool).await.unwrap();
+        let backend = simulated_backend();
+        // The job stays in the queue of a disabled printer until it is enabled again
+        backend.disable_printer("Simulated_Laser", None).await.unwrap();
+
+        let mut print_job = PrintJob::new("report.pdf".to_string(), "Simulated_Laser".to_string(), "Simulated".to_string(), "Laser".to_string(), print_request(), user.id);
+        std::fs::write(uploads_dir.join(&print_job.filename), b"%PDF-1.4").unwrap();
+        print_job.save_to_db(&pool, &uploads_dir).await.unwrap();
+        let run = execute_print_job(&mut print_job, &pool, &uploads_dir, &backend);
+        tokio::time::timeout(Duration::from_secs(2), run).await.unwrap().unwrap();
+
+        let stored = PrintJob::find_by_uuid(print_job.id, &pool).await.unwrap().unwrap();
+        assert_eq!(stored.status, PrintJobStatus::Processing);
+        assert!(stored.cups_job_id.is_some());
+    }
+
+    #[tokio::test]
+    async fn test_recover_job_queue() {
+        let pool = test_pool().await;
+        let mut settings = Settings::load_from("config/does_not_exist").unwrap();
+        let storage_dir = TempDir::new("recover");
+        settings.storage.uploads_dir = storage_dir.join("uploads");
+        settings.storage.scans_dir = storage_dir.join("scans");
+        std::fs::create_dir_all(settings.uploads_dir()).unwrap();
+        std::fs::create_dir_all(settings.scans_dir()).unwrap();
+        let user = User::create("owner", "owner password", Role::User, &pool).await.unwrap();
+        let new_print_job = |filename: &str| PrintJob::new(filename.to_string(), "printer".to_string(), "vendor".to_string(), "model".to_string(), print_request(), user.id);
+        let new_scan_job = || ScanJob::new("scanner".to_string(), "vendor".to_string(), "model".to_string(), ScanRequest::default(), settings.scans_dir(), &SystemSettings::default(), user.id);
+
+        // Queued, recovered in creation order
+        let queued_print = new_print_job("queued.pdf");
+        std::fs::write(settings.uploads_dir().join(&queued_print.filename), b"%PDF").unwrap();
+        queued_print.save_to_db(&pool, settings.uploads_dir()).await.unwrap();
+        let queued_scan = new_scan_job();
+        queued_scan.save_to_db(&pool, settings.scans_dir()).await.unwrap();
+
+        // Queued, but the upload is gone
+        let missing_file = new_print_job("missing.pdf");
+        missing_file.save_to_db(&pool, settings.uploads_dir()).await.unwrap();
+
+        // Interrupted mid-run
+        let mut processing_print = new_print_job("processing.pdf");
+        processing_print.set_status(PrintJobStatus::Processing);
+        processing_print.save_to_db(&pool, settings.uploads_dir()).await.unwrap();
+        let mut scanning = new_scan_job();
+        scanning.set_status(ScanJobStatus::Scanning);
+        scanning.save_to_db(&pool, settings.scans_dir()).await.unwrap();
+        let partial_scan = scanning.get_file_path(settings.scans_dir()).unwrap();
+        std::fs::write(&partial_scan, b"partial").unwrap();
+
+        let job_queue = JobQueue::new();
+        let recovered = recover_job_queue(&job_queue, &pool, &settings, &simulated_backend()).await.unwrap();
+        assert_eq!(recovered, 2);
+        assert_eq!(job_queue.pop().await.unwrap().map(|job| job.id()), Some(queued_print.id));
+        assert_eq!(job_queue.pop().await.unwrap().map(|job| job.id()), Some(queued_scan.id));
+
+        for id in [missing_file.id, processing_print.id] {
+            let job = PrintJob::find_by_uuid(id, &pool).await.unwrap().unwrap();
+            assert!(matches!(job.status, PrintJobStatus::Failed));
+            assert!(job.error_message.is_some());
+        }
+        let scanning = ScanJob::find_by_uuid(scanning.id, &pool).await.unwrap().unwrap();
+        assert!(matches!(scanning.status, ScanJobStatus::Failed));
+        assert!(!Path::new(&partial_scan).exists());
+    }
+
+    #[tokio::test]
+    async fn test_shutdown_checkpoints_running_job() {
+        let pool = test_pool().await;
+        let mut settings = Settings::load_from("config/does_not_exist").unwrap();
+        let scans_dir = TempDir::new("checkpoint");
+        settings.storage.scans_dir = scans_dir.to_path_buf();
+        let user = User::create("owner", "owner password", Role::User, &pool).await.unwrap();
+
+        let mut running = ScanJob::new("scanner".to_string(), "vendor".to_string(), "model".to_string(), ScanRequest::default(), settings.scans_dir(), &SystemSettings::default(), user.id);
+        running.set_status(ScanJobStatus::Scanning);
+        running.save_to_db(&pool, settings.scans_dir()).await.unwrap();
+
+        let job_queue = JobQueue::new();
+        job_queue.set_processing(true).await;
+        job_queue.set_processing_job_id(Some(running.id)).await;
+
+        job_queue.begin_shutdown().await;
+        assert!(add_to_job_queue(&job_queue, Job::Scan(running.clone())).await.is_err());
+        assert!(!job_queue.wait_idle(Duration::from_millis(300)).await);
+
+        job_queue.checkpoint_running_job(&pool, &settings).await.unwrap();
+        let checkpointed = ScanJob::find_by_uuid(running.id, &pool).await.unwrap().unwrap();
+        assert!(matches!(checkpointed.status, ScanJobStatus::Queued));
+        assert!(checkpointed.started_at.is_none());
+
+        assert_eq!(recover_job_queue(&JobQueue::new(), &pool, &settings, &simulated_backend()).await.unwrap(), 1);
+    }
+}
@@ -0,0 +1,256 @@
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use sqlx::sqlite::SqliteRow;
+use sqlx::{Row, SqlitePool};
+use tokio::time::{Duration, MissedTickBehavior};
+use crate::handlers::events::EventState;
+use crate::models::{AppState, JobQueue, SystemSettings};
+use crate::query_bind;
+
+
+/// Marker levels older than this are removed from the history
+const HISTORY_RETENTION_DAYS: i64 = 365;
+
+/// Ink cartridge, ink tank or toner of a printer
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub struct MarkerLevel {
+    pub name: String,
+    /// "#00FFFF", a combined cartridge lists every colour, e.g. "#00FFFF#FF00FF#FFFF00"
+    pub color: Option<String>,
+    /// marker-types keyword such as "ink-cartridge" or "toner"
+    pub marker_type: Option<String>,
+    /// Percentage left, None when the printer cannot tell
+    pub level: Option<i32>,
+    /// Percentage the printer itself considers low
+    pub low_level: Option<i32>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub struct PrinterMarkers {
+    pub printer: String,
+    /// "ipp" when CUPS reported the levels, "escputil" when they were read from the Epson directly
+    pub source: String,
+    pub markers: Vec<MarkerLevel>,
+}
+
+/// Sent with the ink_warning event when a marker drops below `low_ink_threshold`
+#[derive(Debug, Serialize, Clone, PartialEq)]
+pub struct LowInkWarning {
+    pub printer: String,
+    pub marker: String,
+    pub color: Option<String>,
+    pub level: i32,
+    pub threshold: u32,
+}
+
+#[derive(Debug, Serialize, Clone)]
+pub struct MarkerLevelRecord {
+    pub printer: String,
+    pub marker: String,
+    pub color: Option<String>,
+    pub level: i32,
+    pub recorded_at: DateTime<Utc>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct MarkerHistoryQuery {
+    pub days: Option<u32>,
+}
+
+impl TryFrom<&SqliteRow> for MarkerLevelRecord {
+    type Error = sqlx::Error;
+
+    fn try_from(row: &SqliteRow) -> Result<Self, Self::Error> {
+        Ok(MarkerLevelRecord {
+            printer: row.try_get("printer")?,
+            marker: row.try_get("marker")?,
+            color: row.try_get("color")?,
+            level: row.try_get("level")?,
+            recorded_at: row.try_get("recorded_at")?,
+        })
+    }
+}
+
+impl PrinterMarkers {
+    fn level_of(&self, marker: &str) -> Option<i32> {
+        self.markers.iter()
+            .find(|other| other.name == marker)
+            .and_then(|other| other.level)
+    }
+
+    /// Markers that dropped below `threshold` since `previous`, a marker that was already low is not reported again
+    pub fn low_ink_warnings(&self, previous: Option<&PrinterMarkers>, threshold: u32) -> Vec<LowInkWarning> {
+        let threshold_level = threshold as i32;
+
+        self.markers.iter()
+            .filter_map(|marker| {
+                let level = marker.level.filter(|level| *level < threshold_level)?;
+                let was_low = previous
+                    .and_then(|previous| previous.level_of(&marker.name))
+                    .is_some_and(|previous_level| previous_level < threshold_level);
+
+                (!was_low).then(|| LowInkWarning {
+                    printer: self.printer.clone(),
+                    marker: marker.name.clone(),
+                    color: marker.color.clone(),
+                    level,
+                    threshold,
+                })
+            })
+            .collect()
+    }
+}
+
+impl MarkerLevelRecord {
+    /// Store the known levels of `markers` that differ from `previous`, returns how many were stored
+    pub async fn record(markers: &PrinterMarkers, previous: Option<&PrinterMarkers>, pool: &SqlitePool) -> Result<usize, sqlx::Error> {
+        let now = Utc::now();
+        let mut recorded = 0;
+
+        for marker in &markers.markers {
+            let Some(level) = marker.level else { continue };
+            if previous.and_then(|previous| previous.level_of(&marker.name)) == Some(level) {
+                continue;
+            }
+
+            query_bind!(
+                "INSERT INTO marker_levels (printer, marker, color, level, recorded_at) VALUES (?, ?, ?, ?, ?);",
+                &markers.printer,
+                &marker.name,
+                &marker.color,
+                level,
+                now
+            ).execute(pool).await?;
+            recorded += 1;
+        }
+
+        Ok(recorded)
+    }
+
+    pub async fn get_history(printer: &str, since: DateTime<Utc>, pool: &SqlitePool) -> Result<Vec<Self>, sqlx::Error> {
+        let rows = query_bind!(
+            "SELECT * FROM marker_levels WHERE printer = ? AND recorded_at >= ? ORDER BY recorded_at, id;",
+            printer,
+            since
+        ).fetch_all(pool).await?;
+
+        rows.iter().map(MarkerLevelRecord::try_from).collect()
+    }
+
+    pub async fn delete_older_than(before: DateTime<Utc>, pool: &SqlitePool) -> Result<u64, sqlx::Error> {
+        let result = query_bind!("DELETE FROM marker_levels WHERE recorded_at < ?;", before)
+            .execute(pool).await?;
+
+        Ok(result.rows_affected())
+    }
+}
+
+/// Background task reading the marker levels every `interval`, records changes and warns about low ink
+pub async fn run_ink_monitor(app_state: AppState, job_queue: JobQueue, pool: SqlitePool, event_state: EventState, interval: Duration) {
+    let mut ticker = tokio::time::interval(interval);
+    ticker.set_missed_tick_behavior(MissedTickBehavior::Delay);
+
+    loop {
+        // The first tick fires right away, so the dashboard has levels shortly after start
+        ticker.tick().await;
+
+        let previous = app_state.get_ink_levels().await;
+        let current = app_state.refresh_ink_levels(job_queue.is_processing().await).await;
+
+        if let Err(e) = process_ink_levels(&previous, &current, &pool, &event_state).await {
+            log::error!("Failed to process ink levels: {}", e);
+        }
+    }
+}
+
+async fn process_ink_levels(
+    previous: &[PrinterMarkers],
+    current: &[PrinterMarkers],
+    pool: &SqlitePool,
+    event_state: &EventState
+) -> Result<(), sqlx::Error> {
+    let threshold = SystemSettings::load(pool).await?.low_ink_threshold;
+    let mut warnings = Vec::new();
+
+    for markers in current {
+        let before = previous.iter().find(|previous| previous.printer == markers.printer);
+        MarkerLevelRecord::record(markers, before, pool).await?;
+
+        if threshold > 0 {
+            warnings.extend(markers.low_ink_warnings(before, threshold));
+        }
+    }
+    MarkerLevelRecord::delete_older_than(Utc::now() - chrono::Duration::days(HISTORY_RETENTION_DAYS), pool).await?;
+
+    if previous != current {
+        event_state.increment_status_version().await;
+    }
+
+    if !warnings.is_empty() {
+        for warning in &warnings {
+            log::warn!("{} of printer '{}' is low: {}%", warning.marker, warning.printer, warning.level);
+        }
+        event_state.publish_ink_warnings(warnings).await;
+    }
+
+    Ok(())
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::test_pool;
+
+    fn markers(levels: &[(&str, Option<i32>)]) -> PrinterMarkers {
+        PrinterMarkers {
+            printer: "EPSON_L3110".to_string(),
+            source: "escputil".to_string(),
+            markers: levels.iter()
+                .map(|(name, level)| MarkerLevel {
+                    name: name.to_string(),
+                    color: None,
+                    marker_type: None,
+                    level: *level,
+                    low_level: None,
+                })
+                .collect(),
+        }
+    }
+
+    #[test]
+    fn test_low_ink_warnings() {
+        let before = markers(&[("Black", Some(40)), ("Cyan", Some(16)), ("Magenta", Some(9)), ("Yellow", None)]);
+        let after = markers(&[("Black", Some(38)), ("Cyan", Some(14)), ("Magenta", Some(8)), ("Yellow", Some(5))]);
+
+        let warnings = after.low_ink_warnings(Some(&before), 15);
+        let warned = warnings.iter().map(|warning| warning.marker.as_str()).collect::<Vec<&str>>();
+        assert_eq!(warned, vec!["Cyan", "Yellow"]);
+        assert_eq!(warnings[0].level, 14);
+        assert_eq!(warnings[0].threshold, 15);
+
+        // Without a previous reading every low marker is reported
+        assert_eq!(after.low_ink_warnings(None, 15).len(), 3);
+        assert!(after.low_ink_warnings(Some(&before), 5).is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_marker_level_history() {
+        let pool = test_pool().await;
+
+        let first = markers(&[("Black", Some(40)), ("Cyan", None)]);
+        let second = markers(&[("Black", Some(40)), ("Cyan", Some(70))]);
+        let third = markers(&[("Black", Some(35)), ("Cyan", Some(70))]);
+
+        assert_eq!(MarkerLevelRecord::record(&first, None, &pool).await.unwrap(), 1);
+        assert_eq!(MarkerLevelRecord::record(&second, Some(&first), &pool).await.unwrap(), 1);
+        assert_eq!(MarkerLevelRecord::record(&third, Some(&second), &pool).await.unwrap(), 1);
+
+        let history = MarkerLevelRecord::get_history("EPSON_L3110", Utc::now() - chrono::Duration::days(1), &pool).await.unwrap();
+        let levels = history.iter().map(|record| (record.marker.as_str(), record.level)).collect::<Vec<(&str, i32)>>();
+        assert_eq!(levels, vec![("Black", 40), ("Cyan", 70), ("Black", 35)]);
+        assert!(MarkerLevelRecord::get_history("Office_Laser", Utc::now() - chrono::Duration::days(1), &pool).await.unwrap().is_empty());
+
+        assert_eq!(MarkerLevelRecord::delete_older_than(Utc::now() + chrono::Duration::seconds(1), &pool).await.unwrap(), 3);
+    }
+}
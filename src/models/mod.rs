@@ -1,6 +1,10 @@
 pub mod print_job;
 pub mod scan_job;
 pub mod job_queue;
+pub mod system_settings;
+pub mod user;
+pub mod api_token;
+pub mod marker_level;
 
 use std::cmp::Ordering;
 use std::fmt::{Display, Formatter};
@@ -8,15 +12,20 @@ use chrono::{DateTime, Utc};
 pub use print_job::*;
 pub use scan_job::*;
 pub use job_queue::*;
+pub use system_settings::*;
+pub use user::*;
+pub use api_token::*;
+pub use marker_level::*;
 
 use serde::{Deserialize, Serialize};
 use std::sync::{Arc};
 use sqlx::SqlitePool;
 use tokio::sync::{RwLock};
-use tokio::time::Instant;
+use tokio::time::{Duration, Instant, MissedTickBehavior};
 use uuid::Uuid;
-use crate::services::cups::CupsService;
-use crate::services::sane::SaneService;
+use crate::handlers::events::EventState;
+use crate::services::backend::{Backend, MaintenanceBackend, PrintBackend, ScanBackend};
+use crate::settings::Settings;
 
 #[macro_export]
 macro_rules! query_bind {
@@ -29,18 +38,71 @@ macro_rules! query_bind {
     }};
 }
 
-#[derive(Debug, Serialize, Deserialize, Clone)]
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
 pub struct Printer {
     pub name: String,
     pub vendor: String,
     pub model: String,
     pub description: String,
+    /// idle, printing, stopped (disabled) or rejecting (enabled but not accepting new jobs)
     pub status: String,
     pub location: Option<String>,
     pub is_default: bool,
+    pub accepting_jobs: bool,
+    /// Reason given when the queue was disabled or set to reject jobs
+    pub state_message: Option<String>,
 }
 
-#[derive(Debug, Serialize, Deserialize, Clone)]
+/// Body of POST /api/printers/{name}/disable and /reject
+#[derive(Debug, Deserialize, Default)]
+pub struct PrinterStateRequest {
+    pub reason: Option<String>,
+}
+
+/// A device CUPS or DNS-SD found that could become a printer queue
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub struct DiscoveredPrinter {
+    /// Device URI to pass to lpadmin -v, e.g. `usb://EPSON/L3110%20Series?serial=X8JK001234`
+    pub uri: String,
+    /// direct (USB, parallel) or network
+    pub connection: String,
+    pub make_and_model: Option<String>,
+    pub info: Option<String>,
+    /// lpinfo or dnssd
+    pub source: String,
+}
+
+/// Body of POST /api/printers
+#[derive(Debug, Deserialize, Clone, Default)]
+pub struct NewPrinterRequest {
+    /// Queue name, letters, digits, `_`, `-` and `.` only
+    pub name: String,
+    pub uri: String,
+    /// lpinfo -m model name or path of a PPD file, driverless IPP Everywhere when omitted
+    pub ppd: Option<String>,
+    pub description: Option<String>,
+    pub location: Option<String>,
+}
+
+/// Options a printer queue supports, empty lists mean CUPS did not report the attribute
+#[derive(Debug, Serialize, Deserialize, Clone, Default)]
+pub struct PrinterCapabilities {
+    /// PWG media names, e.g. `iso_a4_210x297mm`
+    pub media: Vec<String>,
+    pub media_default: Option<String>,
+    pub sides: Vec<String>,
+    pub color_modes: Vec<String>,
+    /// e.g. `600x600dpi`
+    pub resolutions: Vec<String>,
+    pub input_trays: Vec<String>,
+    pub media_types: Vec<String>,
+    pub quality: Vec<String>,
+    pub pages_per_sheet: Vec<u32>,
+    pub max_copies: Option<u32>,
+    pub page_ranges: bool,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
 pub struct Scanner {
     pub name: String,
     pub vendor: String,
@@ -48,6 +110,20 @@ pub struct Scanner {
     pub device_type: String,
 }
 
+/// Names of the devices that appeared, disappeared or changed between two refreshes
+#[derive(Debug, Serialize, Clone, Default, PartialEq)]
+pub struct DeviceChanges {
+    pub added: Vec<String>,
+    pub removed: Vec<String>,
+    pub changed: Vec<String>,
+}
+
+#[derive(Debug, Serialize, Clone, Default, PartialEq)]
+pub struct DeviceUpdate {
+    pub printers: DeviceChanges,
+    pub scanners: DeviceChanges,
+}
+
 #[derive(Debug, Serialize, Deserialize)]
 pub struct SystemStatus {
     pub cups_available: bool,
@@ -55,7 +131,8 @@ pub struct SystemStatus {
     pub active_print_jobs: usize,
     pub active_scan_jobs: usize,
     pub disk_space_mb: Option<u64>,
-    pub uptime_ms: u128
+    pub uptime_ms: u128,
+    pub ink_levels: Vec<PrinterMarkers>,
 }
 
 
@@ -64,6 +141,8 @@ pub struct AppState {
     pub start_time: Instant,
     scanners: Arc<RwLock<Vec<Scanner>>>,
     printers: Arc<RwLock<Vec<Printer>>>,
+    ink_levels: Arc<RwLock<Vec<PrinterMarkers>>>,
+    backend: Backend,
 }
 
 impl Display for Scanner {
@@ -78,13 +157,115 @@ impl Display for Printer {
     }
 }
 
+impl DeviceChanges {
+    /// Compare two device lists by name, a device whose fields differ counts as changed
+    pub fn between<T: PartialEq>(old: &[T], new: &[T], name: impl Fn(&T) -> &String) -> Self {
+        let find = |devices: &'_ [T], device: &T| devices.iter().position(|other| name(other) == name(device));
+        let mut changes = Self::default();
+
+        for device in new {
+            match find(old, device) {
+                None => changes.added.push(name(device).clone()),
+                Some(i) if old[i] != *device => changes.changed.push(name(device).clone()),
+                Some(_) => {},
+            }
+        }
+        changes.removed = old.iter()
+            .filter(|device| find(new, device).is_none())
+            .map(|device| name(device).clone())
+            .collect();
+
+        changes
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.added.is_empty() && self.removed.is_empty() && self.changed.is_empty()
+    }
+}
+
+impl DeviceUpdate {
+    pub fn is_empty(&self) -> bool {
+        self.printers.is_empty() && self.scanners.is_empty()
+    }
+}
+
+/// Background task reloading the devices every `interval`, so printers switched on later or unplugged show up
+pub async fn run_device_refresh(app_state: AppState, job_queue: JobQueue, event_state: EventState, interval: Duration) {
+    let mut ticker = tokio::time::interval(interval);
+    ticker.set_missed_tick_behavior(MissedTickBehavior::Delay);
+    // The first tick fires right away, AppState::new() has just loaded the devices
+    ticker.tick().await;
+
+    loop {
+        ticker.tick().await;
+
+        let update = app_state.refresh_devices(job_queue.is_processing().await).await;
+        if !update.is_empty() {
+            log::info!("Devices changed: {:?}", update);
+            event_state.publish_device_update(update).await;
+        }
+    }
+}
+
+impl PrinterCapabilities {
+    pub fn supports_duplex(&self) -> bool {
+        self.sides.iter().any(|sides| sides == "two-sided-long-edge")
+    }
+
+    pub fn supports_color(&self) -> bool {
+        self.color_modes.is_empty() || self.color_modes.iter().any(|color_mode| color_mode == "color")
+    }
+
+    /// Reject options the printer cannot honour, colour is left to the backend to adapt
+    pub fn check(&self, job: &PrintJob) -> Result<(), String> {
+        let media = job.page_size.media_keyword();
+        if !self.media.is_empty() && !self.media.iter().any(|supported| supported == media) {
+            return Err(format!("Printer '{}' does not support the {} page size", job.printer, job.page_size));
+        }
+
+        if job.duplex && !self.sides.is_empty() && !self.supports_duplex() {
+            return Err(format!("Printer '{}' cannot print double-sided", job.printer));
+        }
+
+        if let Some(max_copies) = self.max_copies
+            && job.copies > max_copies {
+            return Err(format!("Printer '{}' prints at most {} copies", job.printer, max_copies));
+        }
+
+        if job.pages.as_ref().is_some_and(|pages| !pages.trim().is_empty()) && !self.page_ranges {
+            return Err(format!("Printer '{}' cannot print page ranges", job.printer));
+        }
+
+        let quality = job.quality.to_string();
+        if !self.quality.is_empty() && !self.quality.contains(&quality) {
+            return Err(format!("Printer '{}' does not support {} quality", job.printer, quality));
+        }
+
+        if job.pages_per_sheet > 1 && !self.pages_per_sheet.is_empty() && !self.pages_per_sheet.contains(&job.pages_per_sheet) {
+            return Err(format!("Printer '{}' cannot print {} pages per sheet", job.printer, job.pages_per_sheet));
+        }
+
+        if let Some(ref media_type) = job.media_type
+            && !self.media_types.is_empty() && !self.media_types.contains(media_type) {
+            return Err(format!("Printer '{}' does not support the '{}' media type", job.printer, media_type));
+        }
+
+        if let Some(ref input_tray) = job.input_tray
+            && !self.input_trays.is_empty() && !self.input_trays.contains(input_tray) {
+            return Err(format!("Printer '{}' has no '{}' input tray", job.printer, input_tray));
+        }
+
+        Ok(())
+    }
+}
+
 impl AppState {
-    pub async fn new() -> Self {
-        let scanners  = SaneService::new().get_scanners().await.unwrap_or_else(|e| {
+    pub async fn new(backend: Backend) -> Self {
+        let scanners  = backend.get_scanners().await.unwrap_or_else(|e| {
             log::warn!("No scanners collected: {}", e);
             Vec::new()
         });
-        let printers = CupsService::new().get_printers().await.unwrap_or_else(|e| {
+        let printers = backend.get_printers().await.unwrap_or_else(|e| {
             log::warn!("No printers collected: {}", e);
             Vec::new()
         });
@@ -93,6 +274,8 @@ impl AppState {
             start_time: Instant::now(),
             scanners: Arc::new(RwLock::new(scanners)),
             printers:Arc::new(RwLock::new(printers)),
+            ink_levels: Arc::new(RwLock::new(Vec::new())),
+            backend,
         }
     }
 
@@ -112,6 +295,97 @@ impl AppState {
         self.printers.read().await.clone()
     }
 
+    /// Printers, scanners and maintenance commands the app works with
+    pub fn backend(&self) -> &Backend {
+        &self.backend
+    }
+
+    /// Reload the printers from the backend, the current list is kept when CUPS cannot be reached
+    pub async fn refresh_printers(&self) -> Result<DeviceChanges, String> {
+        let printers = self.backend.get_printers().await?;
+
+        let mut current = self.printers.write().await;
+        let changes = DeviceChanges::between(&current, &printers, |printer| &printer.name);
+        *current = printers;
+
+        Ok(changes)
+    }
+
+    pub async fn refresh_scanners(&self) -> Result<DeviceChanges, String> {
+        let scanners = self.backend.get_scanners().await?;
+
+        let mut current = self.scanners.write().await;
+        let changes = DeviceChanges::between(&current, &scanners, |scanner| &scanner.name);
+        *current = scanners;
+
+        Ok(changes)
+    }
+
+    /// Reload printers and scanners, scanners are left alone while `scanner_busy` as SANE cannot list a scanner in use
+    pub async fn refresh_devices(&self, scanner_busy: bool) -> DeviceUpdate {
+        let refresh_scanners = async {
+            match scanner_busy {
+                true => Ok(DeviceChanges::default()),
+                false => self.refresh_scanners().await,
+            }
+        };
+        let (printers, scanners) = tokio::join!(self.refresh_printers(), refresh_scanners);
+
+        DeviceUpdate {
+            printers: printers.unwrap_or_else(|e| {
+                log::warn!("Failed to refresh printers: {}", e);
+                DeviceChanges::default()
+            }),
+            scanners: scanners.unwrap_or_else(|e| {
+                log::warn!("Failed to refresh scanners: {}", e);
+                DeviceChanges::default()
+            }),
+        }
+    }
+
+    pub async fn get_ink_levels(&self) -> Vec<PrinterMarkers> {
+        self.ink_levels.read().await.clone()
+    }
+
+    /// Read the marker levels of every printer, escputil is skipped while `printer_busy` as it talks to the printer directly
+    pub async fn refresh_ink_levels(&self, printer_busy: bool) -> Vec<PrinterMarkers> {
+        let previous = self.get_ink_levels().await;
+        let mut escputil_used = false;
+        let mut levels = Vec::new();
+
+        for printer in self.get_printers().await {
+            let markers = self.backend.get_marker_levels(&printer.name).await.unwrap_or_else(|e| {
+                log::warn!("Failed to get marker levels of printer '{}': {}", printer.name, e);
+                Vec::new()
+            });
+            if !markers.is_empty() {
+                levels.push(PrinterMarkers { printer: printer.name, source: "ipp".to_string(), markers });
+                continue;
+            }
+
+            // CUPS only knows the levels when the driver reports them, the Epson USB driver does not
+            if !printer.vendor.eq_ignore_ascii_case("epson") || escputil_used || !MaintenanceBackend::is_available(&self.backend).await {
+                continue;
+            }
+            escputil_used = true;
+
+            if printer_busy || printer.status == "printing" {
+                levels.extend(previous.iter().find(|markers| markers.printer == printer.name).cloned());
+                continue;
+            }
+            match self.backend.get_ink_levels().await {
+                Ok(markers) if !markers.is_empty() => {
+                    levels.push(PrinterMarkers { printer: printer.name, source: "escputil".to_string(), markers });
+                },
+                Ok(_) => {},
+                Err(e) => log::warn!("Failed to read ink levels of printer '{}': {}", printer.name, e),
+            }
+        }
+
+        *self.ink_levels.write().await = levels.clone();
+        levels
+    }
+
     pub async fn show_devices(&self) -> String {
         let mut devices = String::from("Scanners:\n\t");
         let scanners = self.scanners.read().await.iter().
@@ -158,22 +432,36 @@ impl Job {
         }
     }
 
+    pub fn created_at(&self) -> DateTime<Utc> {
+        match self {
+            Job::Scan(sj) => sj.created_at,
+            Job::Print(pj) => pj.created_at,
+        }
+    }
+
     pub fn id(&self) -> Uuid {
         match self {
-            Job::Scan(sj) => sj.id.clone(),
-            Job::Print(pj) => pj.id.clone(),
+            Job::Scan(sj) => sj.id,
+            Job::Print(pj) => pj.id,
+        }
+    }
+
+    pub fn user_id(&self) -> Option<i64> {
+        match self {
+            Job::Scan(sj) => sj.user_id,
+            Job::Print(pj) => pj.user_id,
         }
     }
 
-    pub async fn execute(&mut self, pool: &SqlitePool) {
+    pub async fn execute(&mut self, pool: &SqlitePool, settings: &Settings, backend: &Backend) {
         match self {
             Job::Scan(sj) => {
-                if let Err(e) = execute_scan_job(sj.id, pool).await {
+                if let Err(e) = execute_scan_job(sj.id, pool, settings.scans_dir(), backend).await {
                     log::error!("Failed to execute scan job: {}", e);
                 };
             }
             Job::Print(pj) => {
-                if let Err(e) = execute_print_job(pj, pool).await {
+                if let Err(e) = execute_print_job(pj, pool, settings.uploads_dir(), backend).await {
                     log::error!("Failed to execute print job: {}", e);
                 };
             }
@@ -237,3 +525,38 @@ impl<T> ApiResponse<T> {
         }
     }
 }
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn printer(name: &str, status: &str) -> Printer {
+        Printer {
+            name: name.to_string(),
+            vendor: "Epson".to_string(),
+            model: "L3110 Series".to_string(),
+            description: name.to_string(),
+            status: status.to_string(),
+            location: None,
+            is_default: false,
+            accepting_jobs: true,
+            state_message: None,
+        }
+    }
+
+    #[test]
+    fn test_device_changes_between() {
+        let old = vec![printer("Epson", "idle"), printer("Laser", "idle"), printer("Unplugged", "idle")];
+        let new = vec![printer("Epson", "idle"), printer("Laser", "stopped"), printer("Photo", "idle")];
+
+        let changes = DeviceChanges::between(&old, &new, |printer| &printer.name);
+        assert_eq!(changes, DeviceChanges {
+            added: vec!["Photo".to_string()],
+            removed: vec!["Unplugged".to_string()],
+            changed: vec!["Laser".to_string()],
+        });
+
+        assert!(DeviceChanges::between(&new, &new, |printer| &printer.name).is_empty());
+    }
+}
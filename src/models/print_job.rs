@@ -11,7 +11,13 @@ use std::path::Path;
 #[derive(Debug, Serialize, Deserialize, Clone)]
 pub struct PrintJob {
     pub id: Uuid,
+    pub user_id: Option<i64>,
+    /// Name of the file in the uploads directory, generated from the job id
     pub filename: String,
+    /// Name the file was uploaded with, shown to users and used as the CUPS job name
+    pub original_filename: String,
+    /// Type of the file content, e.g. `application/pdf`
+    pub mime_type: Option<String>,
     pub printer: String,
     pub vendor: String,
     pub model: String,
@@ -26,14 +32,35 @@ pub struct PrintJob {
     pub error_message: Option<String>,
     pub cups_job_id: Option<i32>,
     pub page_size: PrintPageSize,
+    pub orientation: PrintOrientation,
+    pub scaling: PrintScaling,
+    /// Pages printed on each sheet (n-up)
+    pub pages_per_sheet: u32,
+    pub quality: PrintQuality,
+    /// IPP media-type, e.g. `photographic-glossy`, printer default when `None`
+    pub media_type: Option<String>,
+    /// IPP media-source, e.g. `tray-1`, printer default when `None`
+    pub input_tray: Option<String>,
+    /// Print copies as complete sets instead of page by page
+    pub collate: bool,
+    /// Sides printed so far as reported by CUPS (job-impressions-completed), copies included
+    pub pages_printed: Option<i32>,
+    /// Sheets of paper used so far (job-media-sheets-completed), fewer than the pages for duplex or n-up
+    pub sheets_printed: Option<i32>,
 }
 
-#[derive(Debug, Serialize, Deserialize, Clone)]
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
 #[serde(rename_all = "lowercase")]
 pub enum PrintJobStatus {
     Queued,
+    /// Being turned into a PDF before it is sent to CUPS
+    Converting,
     Processing,
     Printing,
+    /// Held in CUPS until released
+    Held,
+    /// CUPS stopped processing it, e.g. the printer is paused or out of paper
+    Stopped,
     Completed,
     Failed,
     Cancelled,
@@ -52,14 +79,52 @@ pub enum PrintPageSize {
     Legal,
 }
 
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum PrintOrientation {
+    Portrait,
+    Landscape,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum PrintScaling {
+    /// Let CUPS decide, shrinks pages that do not fit
+    Auto,
+    /// Fit the whole page on the sheet
+    Fit,
+    /// Fill the sheet, cropping the edges
+    Fill,
+    /// Actual size
+    None,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum PrintQuality {
+    Draft,
+    Normal,
+    High,
+}
+
+/// Pages per sheet CUPS can lay out
+pub const PAGES_PER_SHEET: [u32; 6] = [1, 2, 4, 6, 9, 16];
+
+#[derive(Debug, Deserialize, Default)]
 pub struct PrintRequest {
     pub printer: Option<String>,
     pub copies: Option<u32>,
     pub pages: Option<String>,
     pub duplex: Option<bool>,
     pub color: Option<bool>,
-    pub page_size: Option<PrintPageSize>
+    pub page_size: Option<PrintPageSize>,
+    pub orientation: Option<PrintOrientation>,
+    pub scaling: Option<PrintScaling>,
+    pub pages_per_sheet: Option<u32>,
+    pub quality: Option<PrintQuality>,
+    pub media_type: Option<String>,
+    pub input_tray: Option<String>,
+    pub collate: Option<bool>,
 }
 
 
@@ -67,8 +132,11 @@ impl Display for PrintJobStatus {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         let str = match self {
             PrintJobStatus::Queued => String::from("queued"),
+            PrintJobStatus::Converting => String::from("converting"),
             PrintJobStatus::Processing => String::from("processing"),
             PrintJobStatus::Printing => String::from("printing"),
+            PrintJobStatus::Held => String::from("held"),
+            PrintJobStatus::Stopped => String::from("stopped"),
             PrintJobStatus::Completed => String::from("completed"),
             PrintJobStatus::Failed => String::from("failed"),
             PrintJobStatus::Cancelled => String::from("cancelled"),
@@ -95,7 +163,113 @@ impl Display for PrintPageSize {
     }
 }
 
+impl Display for PrintOrientation {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let str = match self {
+            PrintOrientation::Portrait => "portrait",
+            PrintOrientation::Landscape => "landscape",
+        };
+
+        write!(f, "{}", str)
+    }
+}
+
+impl Display for PrintScaling {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let str = match self {
+            PrintScaling::Auto => "auto",
+            PrintScaling::Fit => "fit",
+            PrintScaling::Fill => "fill",
+            PrintScaling::None => "none",
+        };
+
+        write!(f, "{}", str)
+    }
+}
+
+impl Display for PrintQuality {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let str = match self {
+            PrintQuality::Draft => "draft",
+            PrintQuality::Normal => "normal",
+            PrintQuality::High => "high",
+        };
+
+        write!(f, "{}", str)
+    }
+}
+
+impl PrintOrientation {
+    pub fn from(s: String) -> PrintOrientation {
+        match s.as_ref() {
+            "portrait" => PrintOrientation::Portrait,
+            "landscape" => PrintOrientation::Landscape,
+            _ => {
+                log::warn!("Unsupported orientation: {}.\tUsing portrait", s);
+                PrintOrientation::Portrait
+            },
+        }
+    }
+}
+
+impl PrintScaling {
+    pub fn from(s: String) -> PrintScaling {
+        match s.as_ref() {
+            "auto" => PrintScaling::Auto,
+            "fit" => PrintScaling::Fit,
+            "fill" => PrintScaling::Fill,
+            "none" => PrintScaling::None,
+            _ => {
+                log::warn!("Unsupported scaling: {}.\tUsing auto scaling", s);
+                PrintScaling::Auto
+            },
+        }
+    }
+}
+
+impl PrintQuality {
+    pub fn from(s: String) -> PrintQuality {
+        match s.as_ref() {
+            "draft" => PrintQuality::Draft,
+            "normal" => PrintQuality::Normal,
+            "high" => PrintQuality::High,
+            _ => {
+                log::warn!("Unsupported print quality: {}.\tUsing normal quality", s);
+                PrintQuality::Normal
+            },
+        }
+    }
+}
+
 impl PrintPageSize {
+    /// Width and height in hundredths of a millimetre, as IPP media-size expects
+    pub fn dimensions(&self) -> (i32, i32) {
+        match self {
+            PrintPageSize::A4 => (21000, 29700),
+            PrintPageSize::A5 => (14800, 21000),
+            PrintPageSize::A6 => (10500, 14800),
+            PrintPageSize::B5 => (17600, 25000),
+            PrintPageSize::B6 => (12500, 17600),
+            PrintPageSize::Postcard => (10000, 14800),
+            PrintPageSize::Letter => (21590, 27940),
+            PrintPageSize::Legal => (21590, 35560),
+        }
+    }
+
+    /// PWG media size name understood by IPP printers and CUPS
+    pub fn media_keyword(&self) -> &'static str {
+        match self {
+            PrintPageSize::A4 => "iso_a4_210x297mm",
+            PrintPageSize::A5 => "iso_a5_148x210mm",
+            PrintPageSize::A6 => "iso_a6_105x148mm",
+            PrintPageSize::B5 => "iso_b5_176x250mm",
+            PrintPageSize::B6 => "iso_b6_125x176mm",
+            PrintPageSize::Postcard => "jpn_hagaki_100x148mm",
+            PrintPageSize::Letter => "na_letter_8.5x11in",
+            PrintPageSize::Legal => "na_legal_8.5x14in",
+        }
+    }
+
     pub fn from(s: String) -> PrintPageSize {
         match s.as_ref() {
             "a4" => PrintPageSize::A4,
@@ -121,8 +295,11 @@ impl TryFrom<&SqliteRow> for PrintJob {
     fn try_from(row: &SqliteRow) -> Result<Self, Self::Error> {
         let status = match row.try_get("status")? {
             "queued" => PrintJobStatus::Queued,
+            "converting" => PrintJobStatus::Converting,
             "processing" => PrintJobStatus::Processing,
             "printing" => PrintJobStatus::Printing,
+            "held" => PrintJobStatus::Held,
+            "stopped" => PrintJobStatus::Stopped,
             "completed" => PrintJobStatus::Completed,
             "failed" => PrintJobStatus::Failed,
             "cancelled" => PrintJobStatus::Cancelled,
@@ -143,6 +320,27 @@ impl TryFrom<&SqliteRow> for PrintJob {
             "legal" => PrintPageSize::Legal,
             _ => return Err(sqlx::error::Error::InvalidArgument("Unrecognized status".to_string()))
         };
+
+        let orientation = match row.try_get("orientation")? {
+            "portrait" => PrintOrientation::Portrait,
+            "landscape" => PrintOrientation::Landscape,
+            _ => return Err(sqlx::Error::InvalidArgument("Unrecognized orientation".to_string()))
+        };
+
+        let scaling = match row.try_get("scaling")? {
+            "auto" => PrintScaling::Auto,
+            "fit" => PrintScaling::Fit,
+            "fill" => PrintScaling::Fill,
+            "none" => PrintScaling::None,
+            _ => return Err(sqlx::Error::InvalidArgument("Unrecognized scaling".to_string()))
+        };
+
+        let quality = match row.try_get("quality")? {
+            "draft" => PrintQuality::Draft,
+            "normal" => PrintQuality::Normal,
+            "high" => PrintQuality::High,
+            _ => return Err(sqlx::Error::InvalidArgument("Unrecognized quality".to_string()))
+        };
         
 
         let uuid = Uuid::parse_str(row.try_get("job_uuid")?)
@@ -151,7 +349,11 @@ impl TryFrom<&SqliteRow> for PrintJob {
 
         Ok(PrintJob {
             id: uuid,
+            user_id: row.try_get("user_id")?,
+            original_filename: row.try_get::<Option<String>, _>("original_filename")?
+                .unwrap_or_else(|| row.get("filename")),
             filename: row.try_get("filename")?,
+            mime_type: row.try_get("mime_type")?,
             printer: row.try_get("printer_name")?,
             vendor: row.try_get("vendor")?,
             model: row.try_get("model")?,
@@ -166,15 +368,34 @@ impl TryFrom<&SqliteRow> for PrintJob {
             error_message: row.try_get("error_message")?,
             cups_job_id: row.try_get("cups_id_job")?,
             page_size,
+            orientation,
+            scaling,
+            pages_per_sheet: row.try_get("pages_per_sheet")?,
+            quality,
+            media_type: row.try_get("media_type")?,
+            input_tray: row.try_get("input_tray")?,
+            collate: row.try_get("collate_copies")?,
+            pages_printed: row.try_get("pages_printed")?,
+            sheets_printed: row.try_get("sheets_printed")?,
         })
     }
 }
 
 impl PrintJob {
-    pub fn new(filename: String, printer: String, vendor: String, model: String, request: PrintRequest) -> Self {
+    /// `original_filename` must already be sanitised, the file is stored as `{id}.{extension}`
+    pub fn new(original_filename: String, printer: String, vendor: String, model: String, request: PrintRequest, user_id: i64) -> Self {
+        let id = Uuid::new_v4();
+        let filename = match Path::new(&original_filename).extension().and_then(OsStr::to_str) {
+            Some(extension) if extension.chars().all(|c| c.is_ascii_alphanumeric()) => format!("{}.{}", id, extension.to_lowercase()),
+            _ => id.to_string(),
+        };
+
         Self {
-            id: Uuid::new_v4(),
+            id,
+            user_id: Some(user_id),
             filename,
+            original_filename,
+            mime_type: None,
             printer,
             vendor,
             model,
@@ -189,21 +410,26 @@ impl PrintJob {
             error_message: None,
             cups_job_id: None,
             page_size: request.page_size.unwrap_or(PrintPageSize::A4),
+            orientation: request.orientation.unwrap_or(PrintOrientation::Portrait),
+            scaling: request.scaling.unwrap_or(PrintScaling::Auto),
+            pages_per_sheet: request.pages_per_sheet.unwrap_or(1),
+            quality: request.quality.unwrap_or(PrintQuality::Normal),
+            media_type: request.media_type.filter(|media_type| !media_type.trim().is_empty()),
+            input_tray: request.input_tray.filter(|input_tray| !input_tray.trim().is_empty()),
+            collate: request.collate.unwrap_or(true),
+            pages_printed: None,
+            sheets_printed: None,
         }
     }
 
     pub fn set_status(&mut self, status: PrintJobStatus) {
         self.status = status;
         match &self.status {
-            PrintJobStatus::Processing => {
-                if self.started_at.is_none() {
-                    self.started_at = Some(Utc::now());
-                }
+            PrintJobStatus::Converting | PrintJobStatus::Processing if self.started_at.is_none() => {
+                self.started_at = Some(Utc::now());
             },
-            PrintJobStatus::Completed | PrintJobStatus::Failed | PrintJobStatus::Cancelled => {
-                if self.completed_at.is_none() {
-                    self.completed_at = Some(Utc::now());
-                }
+            PrintJobStatus::Completed | PrintJobStatus::Failed | PrintJobStatus::Cancelled if self.completed_at.is_none() => {
+                self.completed_at = Some(Utc::now());
             },
             _ => {}
         }
@@ -218,24 +444,23 @@ impl PrintJob {
         self.cups_job_id = Some(cups_job_id);
     }
 
-    pub fn get_file_path(&self) -> Option<String> {
-        Some(format!("uploads/{}", self.filename))
+    pub fn get_file_path(&self, uploads_dir: &Path) -> Option<String> {
+        uploads_dir.join(&self.filename).to_str().map(|s| s.to_string())
     }
 
 
-    pub async fn save_to_db(&self, pool: &SqlitePool) -> Result<u64, sqlx::Error> {
+    pub async fn save_to_db(&self, pool: &SqlitePool, uploads_dir: &Path) -> Result<u64, sqlx::Error> {
         let status_str = self.status.to_string();
         let page_size_str = self.page_size.to_string();
 
-        let format = Path::new(self.filename.as_str()).extension().and_then(OsStr::to_str);
-
         let query = query_bind!(
             r#"
             INSERT INTO print_jobs (
                 job_uuid, cups_id_job, printer_name, vendor, model, filename, filepath, status,
                 created_at, started_at, completed_at, error_message, copies,
-                pages_range, duplex, color, page_size, original_filename, mime_type
-            ) VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?);
+                pages_range, duplex, color, page_size, original_filename, mime_type, user_id,
+                orientation, scaling, pages_per_sheet, quality, media_type, input_tray, collate_copies
+            ) VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?);
             "#,
             self.id.to_string(),
             self.cups_job_id,
@@ -243,7 +468,7 @@ impl PrintJob {
             self.vendor.clone(),
             self.model.clone(),
             self.filename.clone(),
-            self.get_file_path(),
+            self.get_file_path(uploads_dir),
             status_str,
             self.created_at,
             self.started_at,
@@ -254,8 +479,16 @@ impl PrintJob {
             self.duplex,
             self.color,
             page_size_str,
-            self.filename.clone(),
-            format
+            self.original_filename.clone(),
+            self.mime_type.clone(),
+            self.user_id,
+            self.orientation.to_string(),
+            self.scaling.to_string(),
+            self.pages_per_sheet,
+            self.quality.to_string(),
+            self.media_type.clone(),
+            self.input_tray.clone(),
+            self.collate
         ).execute(pool).await?;
 
         Ok(query.rows_affected())
@@ -268,13 +501,32 @@ impl PrintJob {
         let query = query_bind!(
             r#"
             UPDATE print_jobs
-            SET cups_id_job = ?,status = ?, started_at = ?, completed_at = ?, error_message = ? WHERE job_uuid = ?;
+            SET cups_id_job = ?,status = ?, started_at = ?, completed_at = ?, error_message = ?, pages_printed = ?, sheets_printed = ?
+            WHERE job_uuid = ?;
             "#,
             self.cups_job_id,
             status_str,
             self.started_at,
             self.completed_at,
             self.error_message.clone(),
+            self.pages_printed,
+            self.sheets_printed,
+            self.id.to_string()
+        ).execute(pool).await?;
+
+        Ok(query.rows_affected())
+    }
+
+    /// Print the PDF the upload was converted to instead of the upload
+    pub async fn set_converted_file(&mut self, filename: String, pool: &SqlitePool, uploads_dir: &Path) -> Result<u64, sqlx::Error> {
+        self.filename = filename;
+
+        let query = query_bind!(
+            r#"
+            UPDATE print_jobs SET filename = ?, filepath = ? WHERE job_uuid = ?;
+            "#,
+            self.filename.clone(),
+            self.get_file_path(uploads_dir),
             self.id.to_string()
         ).execute(pool).await?;
 
@@ -324,40 +576,41 @@ impl PrintJob {
         }
 
         let rows = query.fetch_all(pool).await?;
-        let print_jobs = rows.iter()
-            .map(|x| {PrintJob::try_from(x)}).collect::<Result<Vec<PrintJob>, sqlx::Error>>();
-        
-        Ok(print_jobs?)
+
+        rows.iter()
+            .map(|x| {PrintJob::try_from(x)}).collect::<Result<Vec<PrintJob>, sqlx::Error>>()
     }
 
-    pub async fn get_recent(limit: u32, pool: &SqlitePool) -> Result<Vec<PrintJob>, sqlx::Error> {
+    /// Latest jobs, only those of `owner` unless it is `None`
+    pub async fn get_recent(limit: u32, owner: Option<i64>, pool: &SqlitePool) -> Result<Vec<PrintJob>, sqlx::Error> {
         let rows = query_bind!(
             r#"
             SELECT * FROM print_jobs
+            WHERE ?1 IS NULL OR user_id = ?1
             ORDER BY created_at DESC
-            LIMIT ?
+            LIMIT ?2
             ;"#,
+            owner,
             limit
         ).fetch_all(pool).await?;
     
-        let print_jobs = rows.iter()
-            .map(|x| {PrintJob::try_from(x)}).collect::<Result<Vec<PrintJob>, sqlx::Error>>();
-    
-        Ok(print_jobs?)
+        rows.iter()
+            .map(|x| {PrintJob::try_from(x)}).collect::<Result<Vec<PrintJob>, sqlx::Error>>()
     }
     
-    pub async fn get_all(pool: &SqlitePool) -> Result<Vec<PrintJob>, sqlx::Error> {
-        let rows = sqlx::query(
+    /// All jobs, only those of `owner` unless it is `None`
+    pub async fn get_all(owner: Option<i64>, pool: &SqlitePool) -> Result<Vec<PrintJob>, sqlx::Error> {
+        let rows = query_bind!(
             r#"
             SELECT * FROM print_jobs
+            WHERE ?1 IS NULL OR user_id = ?1
             ORDER BY created_at DESC;
             "#,
+            owner
         ).fetch_all(pool).await?;
 
-        let print_jobs = rows.iter()
-            .map(|x| {PrintJob::try_from(x)}).collect::<Result<Vec<PrintJob>, sqlx::Error>>();
-
-        Ok(print_jobs?)
+        rows.iter()
+            .map(|x| {PrintJob::try_from(x)}).collect::<Result<Vec<PrintJob>, sqlx::Error>>()
     }
 
 }
\ No newline at end of file
@@ -7,11 +7,13 @@ use sqlx::sqlite::SqliteRow;
 use uuid::Uuid;
 use std::path::Path;
 use crate::query_bind;
+use crate::models::SystemSettings;
 
 
 #[derive(Debug, Serialize, Deserialize, Clone)]
 pub struct ScanJob {
     pub id: Uuid,
+    pub user_id: Option<i64>,
     pub scanner: String,
     pub vendor: String,
     pub model: String,
@@ -28,6 +30,8 @@ pub struct ScanJob {
     pub completed_at: Option<DateTime<Utc>>,
     pub error_message: Option<String>,
     pub file_size: Option<u64>,
+    /// Pages in the output file, set once the scan completed
+    pub page_count: Option<i32>,
     pub file_available: bool
 }
 
@@ -69,7 +73,7 @@ pub enum ScanPageSize {
     Custom,
 }
 
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Deserialize, Default)]
 pub struct ScanRequest {
     pub scanner: Option<String>,
     pub resolution: Option<u32>,
@@ -160,6 +164,7 @@ impl TryFrom<&SqliteRow> for ScanJob {
 
         Ok(ScanJob {
             id: uuid,
+            user_id: row.try_get("user_id")?,
             scanner: row.try_get("scanner_name")?,
             vendor: row.try_get("vendor")?,
             model: row.try_get("model")?,
@@ -176,6 +181,7 @@ impl TryFrom<&SqliteRow> for ScanJob {
             completed_at: row.try_get("completed_at")?,
             error_message: row.try_get("error_message")?,
             file_size: row.try_get("file_size")?,
+            page_count: row.try_get("page_count")?,
             file_available: row.try_get("file_available")?,
         })
     }
@@ -184,7 +190,7 @@ impl TryFrom<&SqliteRow> for ScanJob {
 
 
 impl ScanJob {
-    pub fn new(scanner: String, vendor: String, model: String, request: ScanRequest) -> Self {
+    pub fn new(scanner: String, vendor: String, model: String, request: ScanRequest, scans_dir: &Path, system_settings: &SystemSettings, user_id: i64) -> Self {
         let id = Uuid::new_v4();
         let format = request.format.unwrap_or(ScanFormat::Pdf);
         let extension = match format {
@@ -194,18 +200,19 @@ impl ScanJob {
             ScanFormat::Tiff => "tiff",
         };
 
-        let mut filename = request.filename.and_then(|s| Some(add_missing_extension(&s, extension)))
+        let mut filename = request.filename.map(|s| add_missing_extension(&s, extension))
             .unwrap_or_else(|| format!("scan_{}_{}.{}", Utc::now().format("%Y%m%d_%H%M%S"), &id.to_string()[..8], extension));
 
-        validate_filename(&mut filename);
+        validate_filename(&mut filename, scans_dir);
 
         Self {
             id,
+            user_id: Some(user_id),
             scanner,
             vendor,
             model,
             status: ScanJobStatus::Queued,
-            resolution: request.resolution.unwrap_or(300),
+            resolution: request.resolution.unwrap_or(system_settings.default_resolution),
             format,
             color_mode: request.color_mode.unwrap_or(ColorMode::Color),
             page_size: request.page_size.unwrap_or(ScanPageSize::A4),
@@ -217,6 +224,7 @@ impl ScanJob {
             completed_at: None,
             error_message: None,
             file_size: None,
+            page_count: None,
             file_available: false
         }
     }
@@ -224,15 +232,11 @@ impl ScanJob {
     pub fn set_status(&mut self, status: ScanJobStatus) {
         self.status = status;
         match &self.status {
-            ScanJobStatus::Scanning => {
-                if self.started_at.is_none() {
-                    self.started_at = Some(Utc::now());
-                }
+            ScanJobStatus::Scanning if self.started_at.is_none() => {
+                self.started_at = Some(Utc::now());
             },
-            ScanJobStatus::Completed | ScanJobStatus::Failed | ScanJobStatus::Cancelled => {
-                if self.completed_at.is_none() {
-                    self.completed_at = Some(Utc::now());
-                }
+            ScanJobStatus::Completed | ScanJobStatus::Failed | ScanJobStatus::Cancelled if self.completed_at.is_none() => {
+                self.completed_at = Some(Utc::now());
             },
             _ => {}
         }
@@ -243,14 +247,14 @@ impl ScanJob {
         self.set_status(ScanJobStatus::Failed);
     }
 
-    pub fn get_file_path(&self) -> Option<String> {
-        self.output_filename.as_ref().map(|filename| {
-            format!("scans/{}", filename)
+    pub fn get_file_path(&self, scans_dir: &Path) -> Option<String> {
+        self.output_filename.as_ref().and_then(|filename| {
+            scans_dir.join(filename).to_str().map(|s| s.to_string())
         })
     }
 
 
-    pub async fn save_to_db(&self, pool: &SqlitePool) -> Result<u64, sqlx::Error> {
+    pub async fn save_to_db(&self, pool: &SqlitePool, scans_dir: &Path) -> Result<u64, sqlx::Error> {
         let format_str = match self.format {
             ScanFormat::Pdf => { "pdf" }
             ScanFormat::Jpeg => { "jpeg" }
@@ -286,8 +290,8 @@ impl ScanJob {
             INSERT INTO scan_jobs (
                 job_uuid, scanner_name, vendor, model, filename, file_path, status,
                 created_at, started_at, completed_at, error_message, resolution,
-                format, color_mode, page_size, brightness, contrast, file_size, file_available
-            ) VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?)
+                format, color_mode, page_size, brightness, contrast, file_size, file_available, user_id
+            ) VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?)
             RETURNING id;
             "#,
             self.id.to_string(),
@@ -295,7 +299,7 @@ impl ScanJob {
             self.vendor.clone(),
             self.model.clone(),
             self.output_filename.clone().unwrap_or(format!("unnamed-{}", self.id)),
-            self.get_file_path(),
+            self.get_file_path(scans_dir),
             status_str,
             self.created_at,
             self.started_at,
@@ -308,7 +312,8 @@ impl ScanJob {
             self.brightness,
             self.contrast,
             self.file_size.map(|s| s as i64),
-            self.file_available
+            self.file_available,
+            self.user_id
         ).execute(pool).await?;
 
         Ok(query.rows_affected())
@@ -327,13 +332,15 @@ impl ScanJob {
         let query = query_bind!(
             r#"
             UPDATE scan_jobs
-            SET status = ?, started_at = ?, completed_at = ?, error_message = ?, file_size = ?, file_available = ? WHERE job_uuid = ?;
+            SET status = ?, started_at = ?, completed_at = ?, error_message = ?, file_size = ?, page_count = ?, file_available = ?
+            WHERE job_uuid = ?;
             "#,
             status_str,
             self.started_at,
             self.completed_at,
             self.error_message.clone(),
             self.file_size.map(|s| s as i64),
+            self.page_count,
             self.file_available,
             self.id.to_string()
         ).execute(pool).await?;
@@ -369,33 +376,35 @@ impl ScanJob {
         }
     }
 
-    pub async fn get_recent(limit: u32, pool: &SqlitePool ) -> Result<Vec<ScanJob>, sqlx::Error> {
+    /// Latest jobs, only those of `owner` unless it is `None`
+    pub async fn get_recent(limit: u32, owner: Option<i64>, pool: &SqlitePool ) -> Result<Vec<ScanJob>, sqlx::Error> {
         let rows = query_bind!(r#"
         SELECT * FROM scan_jobs
+        WHERE ?1 IS NULL OR user_id = ?1
         ORDER BY created_at DESC
-        LIMIT ?
+        LIMIT ?2
         ;"#,
+        owner,
         limit,
         ).fetch_all(pool).await?;
 
-        let scan_jobs = rows
+        rows
             .iter()
             .map(ScanJob::try_from)
-            .collect::<Result<Vec<ScanJob>, sqlx::Error>>();
-
-        Ok(scan_jobs?)
+            .collect::<Result<Vec<ScanJob>, sqlx::Error>>()
     }
 
-    pub async fn get_all(pool: &SqlitePool) -> Result<Vec<ScanJob>, sqlx::Error> {
-        let rows = sqlx::query(r#"
+    /// All jobs, only those of `owner` unless it is `None`
+    pub async fn get_all(owner: Option<i64>, pool: &SqlitePool) -> Result<Vec<ScanJob>, sqlx::Error> {
+        let rows = query_bind!(r#"
             SELECT * FROM scan_jobs
+            WHERE ?1 IS NULL OR user_id = ?1
             ORDER BY created_at DESC;
-        "#
+        "#,
+        owner
         ).fetch_all(pool).await?;
     
-        let scan_jobs = rows.iter().map(ScanJob::try_from).collect::<Result<Vec<ScanJob>, sqlx::Error>>();
-    
-        Ok(scan_jobs?)
+        rows.iter().map(ScanJob::try_from).collect::<Result<Vec<ScanJob>, sqlx::Error>>()
     }
 
     pub async fn find_by_statuses(statuses: Vec<ScanJobStatus>, pool: &SqlitePool) -> Result<Vec<ScanJob>, sqlx::Error> {
@@ -413,10 +422,9 @@ impl ScanJob {
         }
 
         let rows = query.fetch_all(pool).await?;
-        let scan_jobs = rows.iter()
-            .map(|x| {ScanJob::try_from(x)}).collect::<Result<Vec<ScanJob>, sqlx::Error>>();
 
-        Ok(scan_jobs?)
+        rows.iter()
+            .map(|x| {ScanJob::try_from(x)}).collect::<Result<Vec<ScanJob>, sqlx::Error>>()
     }
     
     pub async fn update_file_available_by_filename(filename: String, status: bool, pool: &SqlitePool) -> Result<u64, sqlx::Error> {
@@ -448,9 +456,9 @@ fn add_missing_extension(filename: &str, extension: &str) -> String {
 }
 
 
-fn is_file_existing(filename: &str) -> bool {
-    let filepath = format!("scans/{}", filename);
-    let path = Path::new(filepath.as_str());
+fn is_file_existing(filename: &str, scans_dir: &Path) -> bool {
+    let filepath = scans_dir.join(filename);
+    let path = filepath.as_path();
 
     match path.try_exists() {
         Ok(true) => {
@@ -460,38 +468,33 @@ fn is_file_existing(filename: &str) -> bool {
             false
         }
         _ => {
-            log::warn!("Error during checking file: {}", filepath);
+            log::warn!("Error during checking file: {}", filepath.display());
             false
         }
     }
 }
 
-fn validate_filename(filename: &mut String) -> &mut String {
+fn validate_filename<'a>(filename: &'a mut String, scans_dir: &Path) -> &'a mut String {
     let mut is_first_encounter = true;
-    loop {
-        match is_file_existing(&filename) {
-            true => {
-                let index = filename.find('.').unwrap();
-                let mut count = 0;
-                if let Some(slice) = filename.split('_').last() {
-                    let number = slice
-                        .split('.')
-                        .take(1).collect::<String>()
-                        .parse::<i32>().unwrap_or_else(|e| {
-                        log::warn!("Could not parse the string {}", e); 0});
-
-                    count = number + 1i32;
-                }
-                if is_first_encounter {
-                    filename.insert_str(index, format!("_{count}").as_str());
-                    is_first_encounter = false;
-                } else {
-                    let start = filename.find('_').unwrap();
-                    let end = filename.find('.').unwrap();
-                    filename.replace_range(start..end, format!("_{count}").as_str());
-                }
-            },
-            false => break
+    while is_file_existing(filename, scans_dir) {
+        let index = filename.find('.').unwrap();
+        let mut count = 0;
+        if let Some(slice) = filename.split('_').next_back() {
+            let number = slice
+                .split('.')
+                .take(1).collect::<String>()
+                .parse::<i32>().unwrap_or_else(|e| {
+                log::warn!("Could not parse the string {}", e); 0});
+
+            count = number + 1i32;
+        }
+        if is_first_encounter {
+            filename.insert_str(index, format!("_{count}").as_str());
+            is_first_encounter = false;
+        } else {
+            let start = filename.find('_').unwrap();
+            let end = filename.find('.').unwrap();
+            filename.replace_range(start..end, format!("_{count}").as_str());
         }
     }
 
@@ -501,18 +504,48 @@ fn validate_filename(filename: &mut String) -> &mut String {
 
 
 
+#[cfg(test)]
 mod tests {
     use super::*;
+    use crate::models::{Role, User};
+    use crate::test_utils::{test_pool, TempDir};
 
     #[test]
     fn validating_filename_test_existing() {
+        let scans_dir = TempDir::new("scans");
+        std::fs::write(scans_dir.join("scan.png"), b"").unwrap();
+        std::fs::write(scans_dir.join("scan_1.png"), b"").unwrap();
+
         let mut test_filename = String::from("scan.png");
 
-        validate_filename(&mut test_filename);
+        validate_filename(&mut test_filename, &scans_dir);
 
         assert_eq!(test_filename, String::from("scan_2.png"));
 
     }
-}
 
+    #[tokio::test]
+    async fn test_get_all_filters_by_owner() {
+        let pool = test_pool().await;
+        let scans_dir = TempDir::new("scans");
+
+        let alice = User::create("alice", "alice password", Role::User, &pool).await.unwrap();
+        let bob = User::create("bob", "bob password", Role::User, &pool).await.unwrap();
+
+        for (user, filename) in [(&alice, "alice_scan"), (&bob, "bob_scan")] {
+            let request = ScanRequest { filename: Some(filename.to_string()), ..Default::default() };
+            ScanJob::new("scanner".to_string(), "vendor".to_string(), "model".to_string(), request, &scans_dir, &SystemSettings::default(), user.id)
+                .save_to_db(&pool, &scans_dir).await.unwrap();
+        }
 
+        let alice_jobs = ScanJob::get_all(Some(alice.id), &pool).await.unwrap();
+        assert_eq!(alice_jobs.len(), 1);
+        assert_eq!(alice_jobs[0].user_id, Some(alice.id));
+        assert_eq!(ScanJob::get_all(None, &pool).await.unwrap().len(), 2);
+        assert_eq!(ScanJob::get_recent(4, Some(bob.id), &pool).await.unwrap().len(), 1);
+
+        // Jobs outlive their owner and are left to admins
+        User::remove_by_id(bob.id, &pool).await.unwrap();
+        assert!(ScanJob::get_all(None, &pool).await.unwrap().iter().any(|job| job.user_id.is_none()));
+    }
+}
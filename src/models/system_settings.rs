@@ -0,0 +1,434 @@
+use serde::{Deserialize, Serialize};
+use sqlx::{Row, SqlitePool};
+use crate::query_bind;
+
+/// Resolutions offered by the scan page
+pub const SCAN_RESOLUTIONS: [u32; 4] = [150, 300, 600, 1200];
+
+/// Document formats that can be printed, text, Markdown and office documents are converted to PDF first
+pub const PRINTABLE_FORMATS: [&str; 12] = ["pdf", "ps", "txt", "md", "jpeg", "png", "tiff", "gif", "doc", "docx", "odt", "rtf"];
+
+/// MIME type and file extensions of each printable format
+const FORMAT_TYPES: [(&str, &str, &[&str]); 12] = [
+    ("pdf", "application/pdf", &["pdf"]),
+    ("ps", "application/postscript", &["ps"]),
+    ("txt", "text/plain", &["txt", "text"]),
+    ("md", "text/markdown", &["md", "markdown"]),
+    ("jpeg", "image/jpeg", &["jpg", "jpeg"]),
+    ("png", "image/png", &["png"]),
+    ("tiff", "image/tiff", &["tif", "tiff"]),
+    ("gif", "image/gif", &["gif"]),
+    ("doc", "application/msword", &["doc"]),
+    ("docx", "application/vnd.openxmlformats-officedocument.wordprocessingml.document", &["docx"]),
+    ("odt", "application/vnd.oasis.opendocument.text", &["odt"]),
+    ("rtf", "application/rtf", &["rtf"]),
+];
+
+
+/// Runtime settings editable from the UI, stored in the `settings` table
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub struct SystemSettings {
+    /// Scan resolution used when a scan request does not specify one
+    pub default_resolution: u32,
+    /// Remove uploaded print files once their job has finished
+    pub auto_cleanup: bool,
+    /// Maximum size of an uploaded print file
+    pub max_file_size_mb: u64,
+    /// Accepted print upload formats
+    pub supported_formats: Vec<String>,
+    /// Percentage of ink or toner below which a low-ink warning is raised, 0 turns the warnings off
+    pub low_ink_threshold: u32,
+}
+
+/// A format print uploads are accepted in
+#[derive(Debug, Serialize, Clone, PartialEq)]
+pub struct AcceptedFormat {
+    pub format: String,
+    pub mime_type: &'static str,
+    /// Without the dot, e.g. `jpg`
+    pub extensions: Vec<&'static str>,
+}
+
+/// Settings as sent to clients, with the accepted upload formats spelled out for the file picker
+#[derive(Debug, Serialize)]
+pub struct SystemSettingsResponse {
+    #[serde(flatten)]
+    pub settings: SystemSettings,
+    pub accepted_formats: Vec<AcceptedFormat>,
+}
+
+/// Partial update accepted by POST /api/system/settings
+#[derive(Debug, Deserialize)]
+pub struct SystemSettingsUpdate {
+    pub default_resolution: Option<u32>,
+    pub auto_cleanup: Option<bool>,
+    pub max_file_size_mb: Option<u64>,
+    pub supported_formats: Option<Vec<String>>,
+    pub low_ink_threshold: Option<u32>,
+}
+
+impl Default for SystemSettings {
+    fn default() -> Self {
+        Self {
+            default_resolution: 300,
+            auto_cleanup: true,
+            max_file_size_mb: 50,
+            supported_formats: ["pdf", "jpeg", "png", "tiff", "txt", "md", "doc", "docx", "odt"].iter().map(|s| s.to_string()).collect(),
+            low_ink_threshold: 15,
+        }
+    }
+}
+
+impl SystemSettings {
+    /// Load the stored settings, keys missing from the table keep their default value
+    pub async fn load(pool: &SqlitePool) -> Result<Self, sqlx::Error> {
+        let rows = sqlx::query("SELECT key, value FROM settings;").fetch_all(pool).await?;
+
+        let mut settings = serde_json::to_value(Self::default())
+            .map_err(|e| sqlx::Error::Decode(e.into()))?;
+        for row in rows {
+            let key: String = row.try_get("key")?;
+            let value: String = row.try_get("value")?;
+
+            match serde_json::from_str(&value) {
+                Ok(value) => { settings[key] = value; },
+                Err(e) => log::warn!("Ignoring invalid stored setting '{}': {}", key, e),
+            }
+        }
+
+        serde_json::from_value(settings).map_err(|e| sqlx::Error::Decode(e.into()))
+    }
+
+    /// Store every setting, replacing the previous values
+    pub async fn save(&self, pool: &SqlitePool) -> Result<(), sqlx::Error> {
+        let values = serde_json::to_value(self).map_err(|e| sqlx::Error::Decode(e.into()))?;
+        let values = values.as_object()
+            .ok_or_else(|| sqlx::Error::Protocol("Settings must serialize to an object".to_string()))?;
+
+        let mut transaction = pool.begin().await?;
+        for (key, value) in values {
+            query_bind!(
+                r#"
+                INSERT INTO settings (key, value, updated_at) VALUES (?, ?, ?)
+                ON CONFLICT(key) DO UPDATE SET value = excluded.value, updated_at = excluded.updated_at;
+                "#,
+                key,
+                value.to_string(),
+                chrono::Utc::now()
+            ).execute(&mut *transaction).await?;
+        }
+        transaction.commit().await?;
+
+        Ok(())
+    }
+
+    /// Store the defaults on first start, and cap the upload limit at `server.max_upload_size`,
+    /// which can have been lowered since the limit was stored
+    pub async fn init(pool: &SqlitePool, max_upload_size: usize) -> Result<Self, sqlx::Error> {
+        let count: i64 = sqlx::query("SELECT COUNT(*) AS count FROM settings;")
+            .fetch_one(pool).await?
+            .try_get("count")?;
+
+        let mut settings = Self::load(pool).await?;
+        let limit_mb = max_file_size_limit_mb(max_upload_size);
+        let capped = settings.max_file_size_mb > limit_mb;
+        if capped {
+            if count > 0 {
+                log::warn!("Lowering max_file_size_mb from {} to {} to stay within server.max_upload_size", settings.max_file_size_mb, limit_mb);
+            }
+            settings.max_file_size_mb = limit_mb;
+        }
+
+        if count == 0 || capped {
+            settings.save(pool).await?;
+        }
+        if count == 0 {
+            log::info!("Stored default system settings");
+        }
+
+        Ok(settings)
+    }
+
+    pub fn apply(&mut self, update: SystemSettingsUpdate) {
+        if let Some(default_resolution) = update.default_resolution {
+            self.default_resolution = default_resolution;
+        }
+        if let Some(auto_cleanup) = update.auto_cleanup {
+            self.auto_cleanup = auto_cleanup;
+        }
+        if let Some(max_file_size_mb) = update.max_file_size_mb {
+            self.max_file_size_mb = max_file_size_mb;
+        }
+        if let Some(supported_formats) = update.supported_formats {
+            self.supported_formats.clear();
+            for format in supported_formats.iter().map(|format| normalize_format(format)) {
+                if !self.supported_formats.contains(&format) {
+                    self.supported_formats.push(format);
+                }
+            }
+        }
+        if let Some(low_ink_threshold) = update.low_ink_threshold {
+            self.low_ink_threshold = low_ink_threshold;
+        }
+    }
+
+    /// `max_upload_size` is the hard limit from `server.max_upload_size`
+    pub fn validate(&self, max_upload_size: usize) -> Result<(), String> {
+        let mut errors = Vec::new();
+
+        if !SCAN_RESOLUTIONS.contains(&self.default_resolution) {
+            errors.push(format!("default_resolution must be one of {:?}", SCAN_RESOLUTIONS));
+        }
+
+        let max_upload_mb = max_file_size_limit_mb(max_upload_size);
+        if self.max_file_size_mb == 0 || self.max_file_size_mb > max_upload_mb {
+            errors.push(format!("max_file_size_mb must be between 1 and {}", max_upload_mb));
+        }
+
+        if self.supported_formats.is_empty() {
+            errors.push("supported_formats must not be empty".to_string());
+        }
+        for format in &self.supported_formats {
+            if !PRINTABLE_FORMATS.contains(&format.as_str()) {
+                errors.push(format!("Unsupported format '{}', expected one of {:?}", format, PRINTABLE_FORMATS));
+            }
+        }
+
+        if self.low_ink_threshold > 100 {
+            errors.push("low_ink_threshold must be between 0 and 100".to_string());
+        }
+
+        match errors.is_empty() {
+            true => Ok(()),
+            false => Err(errors.join("; ")),
+        }
+    }
+
+    pub fn max_file_size_bytes(&self) -> usize {
+        (self.max_file_size_mb * 1024 * 1024) as usize
+    }
+
+    /// Check the upload's extension against `supported_formats`
+    pub fn is_supported_file(&self, filename: &str) -> bool {
+        file_format(filename).is_some_and(|format| self.supported_formats.contains(&format))
+    }
+
+    /// Check an upload by its name and first bytes, returns the MIME type of its content
+    ///
+    /// The content has to be one of `supported_formats` and match the extension, so a renamed
+    /// file is refused here instead of failing in CUPS.
+    pub fn check_upload(&self, filename: &str, head: &[u8]) -> Result<&'static str, String> {
+        let supported = || format!("Supported formats: {}", self.supported_formats.join(", "));
+
+        if !self.is_supported_file(filename) {
+            return Err(format!("Unsupported file format. {}", supported()));
+        }
+        let Some(detected) = detect_format(head) else {
+            return Err(format!("The file content is not a recognised format. {}", supported()));
+        };
+        let format = file_format(filename).unwrap_or_default();
+        // Markdown is plain text as far as its content goes
+        let expected = if format == "md" { "txt" } else { format.as_str() };
+        if detected != expected {
+            return Err(format!("The file content is {} but its name says otherwise. {}", detected, supported()));
+        }
+
+        Ok(mime_type(&format))
+    }
+
+    pub fn accepted_formats(&self) -> Vec<AcceptedFormat> {
+        self.supported_formats.iter()
+            .filter_map(|format| FORMAT_TYPES.iter().find(|(name, _, _)| name == format))
+            .map(|(format, mime_type, extensions)| AcceptedFormat {
+                format: format.to_string(),
+                mime_type,
+                extensions: extensions.to_vec(),
+            })
+            .collect()
+    }
+}
+
+impl From<SystemSettings> for SystemSettingsResponse {
+    fn from(settings: SystemSettings) -> Self {
+        Self { accepted_formats: settings.accepted_formats(), settings }
+    }
+}
+
+fn file_format(filename: &str) -> Option<String> {
+    std::path::Path::new(filename)
+        .extension()
+        .and_then(|ext| ext.to_str())
+        .map(normalize_format)
+}
+
+/// MIME type of one of the [`PRINTABLE_FORMATS`]
+pub fn mime_type(format: &str) -> &'static str {
+    FORMAT_TYPES.iter()
+        .find(|(name, _, _)| *name == format)
+        .map(|(_, mime_type, _)| *mime_type)
+        .unwrap_or("application/octet-stream")
+}
+
+/// Recognise a printable format from the first bytes of a file
+///
+/// Anything that is not one of the binary formats counts as text when it is UTF-8 without control
+/// characters other than tabs, line breaks and form feeds; a character cut off at the end is fine.
+/// Markdown cannot be told apart from text and is reported as `txt`.
+pub fn detect_format(head: &[u8]) -> Option<&'static str> {
+    const SIGNATURES: [(&[u8], &str); 9] = [
+        (b"%!", "ps"),
+        (b"\xff\xd8\xff", "jpeg"),
+        (b"\x89PNG\r\n\x1a\n", "png"),
+        (b"II*\0", "tiff"),
+        (b"MM\0*", "tiff"),
+        (b"GIF87a", "gif"),
+        (b"GIF89a", "gif"),
+        // OLE2 compound file, Word 97-2003
+        (b"\xd0\xcf\x11\xe0\xa1\xb1\x1a\xe1", "doc"),
+        (b"{\\rtf", "rtf"),
+    ];
+    let contains = |needle: &[u8]| head.windows(needle.len()).any(|window| window == needle);
+
+    // Readers accept junk before the header, the PDF specification allows it within the first 1024 bytes.
+    // Text that merely mentions the header is still text.
+    let pdf_header = head[..head.len().min(1024)].windows(5).position(|window| window == b"%PDF-");
+    if pdf_header.is_some_and(|position| !is_text(&head[..position])) {
+        return Some("pdf");
+    }
+    if let Some((_, format)) = SIGNATURES.iter().find(|(signature, _)| head.starts_with(signature)) {
+        return Some(format);
+    }
+    // Both are ZIP archives: OpenDocument starts with an uncompressed `mimetype` entry, Word keeps its parts under `word/`
+    if head.starts_with(b"PK\x03\x04") {
+        if contains(b"mimetypeapplication/vnd.oasis.opendocument.text") {
+            return Some("odt");
+        }
+        return contains(b"word/").then_some("docx");
+    }
+
+    is_text(head).then_some("txt")
+}
+
+fn is_text(bytes: &[u8]) -> bool {
+    let bytes = bytes.strip_prefix(b"\xef\xbb\xbf").unwrap_or(bytes);
+    let text = match std::str::from_utf8(bytes) {
+        Ok(text) => text,
+        Err(e) if e.error_len().is_none() => std::str::from_utf8(&bytes[..e.valid_up_to()]).unwrap_or_default(),
+        Err(_) => return false,
+    };
+
+    !text.trim().is_empty()
+        && !text.chars().any(|c| c.is_control() && !matches!(c, '\t' | '\n' | '\r' | '\x0c'))
+}
+
+fn normalize_format(format: &str) -> String {
+    match format.to_lowercase().as_str() {
+        "jpg" => "jpeg".to_string(),
+        "tif" => "tiff".to_string(),
+        "text" => "txt".to_string(),
+        "markdown" => "md".to_string(),
+        other => other.to_string(),
+    }
+}
+
+/// Largest `max_file_size_mb` within `server.max_upload_size`, at least 1 so a limit below a MiB
+/// still leaves a valid value
+fn max_file_size_limit_mb(max_upload_size: usize) -> u64 {
+    (max_upload_size as u64 / (1024 * 1024)).max(1)
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::test_pool;
+
+    #[tokio::test]
+    async fn test_settings_roundtrip() {
+        let pool = test_pool().await;
+
+        let mut settings = SystemSettings::init(&pool, 20 * 1024 * 1024).await.unwrap();
+        assert_eq!(settings.max_file_size_mb, 20);
+
+        settings.apply(SystemSettingsUpdate {
+            default_resolution: Some(600),
+            auto_cleanup: Some(false),
+            max_file_size_mb: None,
+            supported_formats: Some(vec!["PDF".to_string(), "jpg".to_string()]),
+            low_ink_threshold: Some(10),
+        });
+        settings.save(&pool).await.unwrap();
+
+        let loaded = SystemSettings::load(&pool).await.unwrap();
+        assert_eq!(loaded, settings);
+        assert_eq!(loaded.supported_formats, vec!["pdf", "jpeg"]);
+        assert_eq!(loaded.low_ink_threshold, 10);
+        assert!(loaded.is_supported_file("photo.JPG"));
+        assert!(!loaded.is_supported_file("notes.txt"));
+
+        // server.max_upload_size lowered since the last start
+        let capped = SystemSettings::init(&pool, 10 * 1024 * 1024).await.unwrap();
+        assert_eq!(capped.max_file_size_mb, 10);
+        assert_eq!(SystemSettings::load(&pool).await.unwrap().max_file_size_mb, 10);
+        assert_eq!(capped.supported_formats, vec!["pdf", "jpeg"]);
+        assert!(capped.validate(10 * 1024 * 1024).is_ok());
+    }
+
+    #[test]
+    fn test_check_upload() {
+        let settings = SystemSettings::default();
+
+        assert_eq!(settings.check_upload("report.pdf", b"%PDF-1.7\n%\xe2\xe3"), Ok("application/pdf"));
+        assert_eq!(settings.check_upload("photo.JPG", b"\xff\xd8\xff\xe0\0\x10JFIF"), Ok("image/jpeg"));
+        assert_eq!(settings.check_upload("scan.tif", b"MM\0*\0\0\0\x08"), Ok("image/tiff"));
+        assert_eq!(settings.check_upload("notes.txt", "Zażółć gęślą jaźń\r\n\tindented\x0c".as_bytes()), Ok("text/plain"));
+        // Cut off in the middle of a character
+        assert_eq!(settings.check_upload("notes.txt", &"jaźń".as_bytes()[..5]), Ok("text/plain"));
+
+        assert_eq!(settings.check_upload("README.md", b"# Title\n\nSome *text*.\n"), Ok("text/markdown"));
+        assert_eq!(settings.check_upload("letter.doc", b"\xd0\xcf\x11\xe0\xa1\xb1\x1a\xe1\0\0"), Ok("application/msword"));
+        let docx = b"PK\x03\x04\x14\0\x06\0\x08\0\0\0!\0[Content_Types].xml....PK\x03\x04word/document.xml";
+        assert!(settings.check_upload("letter.docx", docx).is_ok());
+        let odt = b"PK\x03\x04\x14\0\0\x08\0\0\0\0\0\0\0\0\0\0\0\0\0\0\0\0\x08\0\0\0mimetypeapplication/vnd.oasis.opendocument.text";
+        assert!(settings.check_upload("letter.odt", odt).is_ok());
+        assert!(settings.check_upload("letter.docx", odt).is_err());
+        assert!(settings.check_upload("archive.docx", b"PK\x03\x04\x14\0\0\0\x08\0photos/1.jpg").is_err());
+
+        let error = settings.check_upload("report.pdf", b"\x89PNG\r\n\x1a\n").unwrap_err();
+        assert!(error.contains("png") && error.contains("Supported formats: pdf, jpeg, png, tiff, txt, md, doc, docx, odt"), "{}", error);
+        assert!(settings.check_upload("notes.txt", b"%PDF-1.4").is_err());
+        assert_eq!(settings.check_upload("notes.txt", b"Every PDF starts with %PDF-1.x\n"), Ok("text/plain"));
+        assert_eq!(settings.check_upload("report.pdf", b"\0\x01junk\r\n%PDF-1.4\n"), Ok("application/pdf"));
+        assert_eq!(settings.check_upload("report.pdf", b"\r\n%PDF-1.4\n"), Ok("application/pdf"));
+        assert!(settings.check_upload("program.txt", b"\x7fELF\x02\x01\x01\0").is_err());
+        assert!(settings.check_upload("empty.pdf", b"").is_err());
+        assert!(settings.check_upload("animation.gif", b"GIF89a").unwrap_err().starts_with("Unsupported file format"));
+
+        let accepted = settings.accepted_formats();
+        assert_eq!(accepted.iter().map(|format| format.format.as_str()).collect::<Vec<_>>(), settings.supported_formats);
+        assert_eq!(accepted[1].extensions, vec!["jpg", "jpeg"]);
+        assert!(PRINTABLE_FORMATS.iter().all(|format| mime_type(format) != "application/octet-stream"));
+    }
+
+    #[test]
+    fn test_validate() {
+        let mut settings = SystemSettings::default();
+        assert!(settings.validate(50 * 1024 * 1024).is_ok());
+
+        // A limit below a MiB still allows 1 MB
+        assert!(settings.validate(512 * 1024).is_err());
+        settings.max_file_size_mb = 1;
+        assert!(settings.validate(512 * 1024).is_ok());
+
+        settings.default_resolution = 250;
+        settings.max_file_size_mb = 100;
+        settings.supported_formats = vec!["exe".to_string()];
+        settings.low_ink_threshold = 101;
+
+        let err = settings.validate(50 * 1024 * 1024).unwrap_err();
+        assert!(err.contains("default_resolution"));
+        assert!(err.contains("max_file_size_mb"));
+        assert!(err.contains("exe"));
+        assert!(err.contains("low_ink_threshold"));
+    }
+}
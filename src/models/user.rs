@@ -0,0 +1,326 @@
+use std::fmt::{Display, Formatter};
+use argon2::password_hash::rand_core::OsRng;
+use argon2::password_hash::{PasswordHash, PasswordHasher, PasswordVerifier, SaltString};
+use argon2::Argon2;
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use sqlx::sqlite::SqliteRow;
+use sqlx::{Row, SqlitePool};
+use crate::query_bind;
+
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct User {
+    pub id: i64,
+    pub username: String,
+    #[serde(skip)]
+    pub password_hash: String,
+    pub role: Role,
+    pub created_at: DateTime<Utc>,
+    pub last_login_at: Option<DateTime<Utc>>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum Role {
+    Admin,
+    User,
+    Guest,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct LoginRequest {
+    pub username: String,
+    pub password: String,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct NewUserRequest {
+    pub username: String,
+    pub password: String,
+    pub role: Option<Role>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct RoleUpdateRequest {
+    pub role: Role,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct ChangePasswordRequest {
+    pub current_password: String,
+    pub new_password: String,
+}
+
+pub const MIN_PASSWORD_LENGTH: usize = 8;
+
+impl Display for Role {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        let role_str = match self {
+            Role::Admin => { "admin" }
+            Role::User => { "user" }
+            Role::Guest => { "guest" }
+        };
+
+        f.write_str(role_str)
+    }
+}
+
+impl Role {
+    /// Whether this role grants everything `required` does: admin > user > guest
+    pub fn at_least(self, required: Role) -> bool {
+        self.rank() >= required.rank()
+    }
+
+    fn rank(self) -> u8 {
+        match self {
+            Role::Admin => 2,
+            Role::User => 1,
+            Role::Guest => 0,
+        }
+    }
+}
+
+impl TryFrom<&SqliteRow> for User {
+    type Error = sqlx::Error;
+
+    fn try_from(row: &SqliteRow) -> Result<Self, Self::Error> {
+        let role = match row.try_get("role")? {
+            "admin" => Role::Admin,
+            "user" => Role::User,
+            "guest" => Role::Guest,
+            _ => return Err(sqlx::Error::InvalidArgument("Unrecognized role".to_string()))
+        };
+
+        Ok(User {
+            id: row.try_get("id")?,
+            username: row.try_get("username")?,
+            password_hash: row.try_get("password_hash")?,
+            role,
+            created_at: row.try_get("created_at")?,
+            last_login_at: row.try_get("last_login_at")?,
+        })
+    }
+}
+
+impl User {
+    pub async fn create(username: &str, password: &str, role: Role, pool: &SqlitePool) -> Result<User, sqlx::Error> {
+        let password_hash = hash_password(password)
+            .map_err(|e| sqlx::Error::Protocol(format!("Failed to hash password: {}", e)))?;
+
+        let row = query_bind!(
+            r#"
+            INSERT INTO users (username, password_hash, role, created_at)
+            VALUES (?, ?, ?, ?)
+            RETURNING *;
+            "#,
+            username,
+            password_hash,
+            role.to_string(),
+            Utc::now()
+        ).fetch_one(pool).await?;
+
+        User::try_from(&row)
+    }
+
+    pub async fn find_by_id(id: i64, pool: &SqlitePool) -> Result<Option<User>, sqlx::Error> {
+        let row = query_bind!("SELECT * FROM users WHERE id = ?;", id)
+            .fetch_optional(pool).await?;
+
+        row.as_ref().map(User::try_from).transpose()
+    }
+
+    pub async fn find_by_username(username: &str, pool: &SqlitePool) -> Result<Option<User>, sqlx::Error> {
+        let row = query_bind!("SELECT * FROM users WHERE username = ?;", username)
+            .fetch_optional(pool).await?;
+
+        row.as_ref().map(User::try_from).transpose()
+    }
+
+    pub async fn get_all(pool: &SqlitePool) -> Result<Vec<User>, sqlx::Error> {
+        let rows = sqlx::query("SELECT * FROM users ORDER BY username;")
+            .fetch_all(pool).await?;
+
+        rows.iter().map(User::try_from).collect()
+    }
+
+    pub async fn remove_by_id(id: i64, pool: &SqlitePool) -> Result<u64, sqlx::Error> {
+        let result = query_bind!("DELETE FROM users WHERE id = ?;", id)
+            .execute(pool).await?;
+
+        Ok(result.rows_affected())
+    }
+
+    pub async fn set_password(&mut self, password: &str, pool: &SqlitePool) -> Result<(), sqlx::Error> {
+        self.password_hash = hash_password(password)
+            .map_err(|e| sqlx::Error::Protocol(format!("Failed to hash password: {}", e)))?;
+
+        query_bind!(
+            "UPDATE users SET password_hash = ? WHERE id = ?;",
+            self.password_hash.clone(),
+            self.id
+        ).execute(pool).await?;
+
+        Ok(())
+    }
+
+    pub async fn set_role(&mut self, role: Role, pool: &SqlitePool) -> Result<(), sqlx::Error> {
+        self.role = role;
+
+        query_bind!(
+            "UPDATE users SET role = ? WHERE id = ?;",
+            self.role.to_string(),
+            self.id
+        ).execute(pool).await?;
+
+        Ok(())
+    }
+
+    pub async fn count_admins(pool: &SqlitePool) -> Result<i64, sqlx::Error> {
+        query_bind!("SELECT COUNT(*) AS count FROM users WHERE role = ?;", Role::Admin.to_string())
+            .fetch_one(pool).await?
+            .try_get("count")
+    }
+
+    pub async fn count(pool: &SqlitePool) -> Result<i64, sqlx::Error> {
+        sqlx::query("SELECT COUNT(*) AS count FROM users;")
+            .fetch_one(pool).await?
+            .try_get("count")
+    }
+
+    pub async fn update_last_login(&mut self, pool: &SqlitePool) -> Result<(), sqlx::Error> {
+        self.last_login_at = Some(Utc::now());
+
+        query_bind!(
+            "UPDATE users SET last_login_at = ? WHERE id = ?;",
+            self.last_login_at,
+            self.id
+        ).execute(pool).await?;
+
+        Ok(())
+    }
+
+    pub fn is_admin(&self) -> bool {
+        self.role == Role::Admin
+    }
+
+    /// Owner to filter job listings by, `None` lets admins see every job
+    pub fn job_owner_filter(&self) -> Option<i64> {
+        match self.is_admin() {
+            true => None,
+            false => Some(self.id),
+        }
+    }
+
+    /// Whether the user may see and manage a job owned by `owner_id`
+    ///
+    /// Jobs without an owner were created before accounts existed and are left to admins.
+    pub fn can_access(&self, owner_id: Option<i64>) -> bool {
+        self.is_admin() || owner_id == Some(self.id)
+    }
+}
+
+pub fn validate_username(username: &str) -> Result<(), String> {
+    let valid = (1..=64).contains(&username.len())
+        && username.chars().all(|c| c.is_ascii_alphanumeric() || matches!(c, '.' | '_' | '-'));
+
+    match valid {
+        true => Ok(()),
+        false => Err("Username must be 1-64 characters of letters, digits, '.', '_' or '-'".to_string()),
+    }
+}
+
+pub fn validate_password(password: &str) -> Result<(), String> {
+    match password.chars().count() >= MIN_PASSWORD_LENGTH {
+        true => Ok(()),
+        false => Err(format!("Password must be at least {} characters", MIN_PASSWORD_LENGTH)),
+    }
+}
+
+pub fn hash_password(password: &str) -> Result<String, argon2::password_hash::Error> {
+    let salt = SaltString::generate(&mut OsRng);
+    Ok(Argon2::default().hash_password(password.as_bytes(), &salt)?.to_string())
+}
+
+pub fn verify_password(password: &str, password_hash: &str) -> bool {
+    match PasswordHash::new(password_hash) {
+        Ok(hash) => Argon2::default().verify_password(password.as_bytes(), &hash).is_ok(),
+        Err(e) => {
+            log::error!("Stored password hash is invalid: {}", e);
+            false
+        }
+    }
+}
+
+/// Create the first admin account when the users table is empty
+///
+/// Returns the generated password when none was configured, so it can be shown once.
+pub async fn bootstrap_admin(username: &str, password: Option<&str>, pool: &SqlitePool) -> Result<Option<String>, sqlx::Error> {
+    if User::count(pool).await? > 0 {
+        return Ok(None);
+    }
+
+    let generated = match password {
+        Some(_) => None,
+        None => Some(uuid::Uuid::new_v4().simple().to_string()),
+    };
+    let password = password.or(generated.as_deref()).unwrap_or_default();
+
+    User::create(username, password, Role::Admin, pool).await?;
+    log::info!("Created admin account '{}'", username);
+
+    Ok(generated)
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::test_pool;
+
+    #[tokio::test]
+    async fn test_bootstrap_admin_and_login() {
+        let pool = test_pool().await;
+
+        let generated = bootstrap_admin("admin", None, &pool).await.unwrap().unwrap();
+        assert_eq!(bootstrap_admin("admin", None, &pool).await.unwrap(), None);
+        assert_eq!(User::count(&pool).await.unwrap(), 1);
+
+        let admin = User::find_by_username("Admin", &pool).await.unwrap().unwrap();
+        assert_eq!(admin.role, Role::Admin);
+        assert!(verify_password(&generated, &admin.password_hash));
+        assert!(!verify_password("wrong password", &admin.password_hash));
+        assert_ne!(admin.password_hash, generated);
+
+        let user = User::create("jan", "jan password", Role::User, &pool).await.unwrap();
+        assert!(user.can_access(Some(user.id)));
+        assert!(!user.can_access(Some(admin.id)));
+        assert!(!user.can_access(None));
+        assert!(admin.can_access(Some(user.id)) && admin.can_access(None));
+        assert_eq!(user.job_owner_filter(), Some(user.id));
+        assert_eq!(admin.job_owner_filter(), None);
+    }
+
+    #[tokio::test]
+    async fn test_roles() {
+        let pool = test_pool().await;
+
+        bootstrap_admin("admin", Some("admin password"), &pool).await.unwrap();
+        let mut guest = User::create("guest", "guest password", Role::Guest, &pool).await.unwrap();
+        assert_eq!(User::find_by_id(guest.id, &pool).await.unwrap().unwrap().role, Role::Guest);
+        assert!(!guest.role.at_least(Role::User));
+
+        guest.set_role(Role::Admin, &pool).await.unwrap();
+        assert_eq!(User::count_admins(&pool).await.unwrap(), 2);
+        assert!(User::find_by_id(guest.id, &pool).await.unwrap().unwrap().role.at_least(Role::User));
+    }
+
+    #[test]
+    fn test_validate_credentials() {
+        assert!(validate_username("jan.kowalski").is_ok());
+        assert!(validate_username("").is_err());
+        assert!(validate_username("a b").is_err());
+        assert!(validate_password("short").is_err());
+        assert!(validate_password("long enough").is_ok());
+    }
+}
@@ -0,0 +1,297 @@
+//! Printing, scanning and maintenance behind traits, so the app runs on real devices or a simulation
+
+pub mod simulated;
+mod sample_image;
+
+use std::future::Future;
+use std::path::Path;
+use std::sync::Arc;
+use crate::models::{DiscoveredPrinter, MarkerLevel, NewPrinterRequest, Printer, PrinterCapabilities, PrintJob, Scanner, ScanJob};
+use crate::services::cups::{CupsJobStatus, CupsService};
+use crate::services::escputil::MaintenanceService;
+use crate::services::sane::SaneService;
+use crate::settings::DeviceBackend;
+use simulated::SimulatedBackend;
+
+
+/// Printer queues and the jobs sent to them, CUPS on a real system
+pub trait PrintBackend {
+    fn is_available(&self) -> impl Future<Output = bool> + Send;
+
+    fn get_printers(&self) -> impl Future<Output = Result<Vec<Printer>, String>> + Send;
+
+    fn get_printer(&self, printer_name: &str) -> impl Future<Output = Result<Printer, String>> + Send;
+
+    fn get_capabilities(&self, printer_name: &str) -> impl Future<Output = Result<PrinterCapabilities, String>> + Send;
+
+    /// Ink or toner levels, empty when the printer does not report them
+    fn get_marker_levels(&self, printer_name: &str) -> impl Future<Output = Result<Vec<MarkerLevel>, String>> + Send;
+
+    /// Hand a document over for printing, returns the backend's job id
+    fn submit_print_job(&self, job: &PrintJob, file_path: &str) -> impl Future<Output = Result<i32, String>> + Send;
+
+    fn get_job_status(&self, job_id: i32) -> impl Future<Output = Result<CupsJobStatus, String>> + Send;
+
+    fn cancel_job(&self, job_id: i32) -> impl Future<Output = Result<(), String>> + Send;
+
+    /// Unfinished jobs as (job id, printer name, user name)
+    fn get_active_jobs(&self) -> impl Future<Output = Result<Vec<(i32, String, String)>, String>> + Send;
+
+    fn enable_printer(&self, printer_name: &str) -> impl Future<Output = Result<(), String>> + Send;
+
+    fn disable_printer(&self, printer_name: &str, reason: Option<&str>) -> impl Future<Output = Result<(), String>> + Send;
+
+    fn accept_jobs(&self, printer_name: &str) -> impl Future<Output = Result<(), String>> + Send;
+
+    fn reject_jobs(&self, printer_name: &str, reason: Option<&str>) -> impl Future<Output = Result<(), String>> + Send;
+
+    fn purge_jobs(&self, printer_name: &str) -> impl Future<Output = Result<(), String>> + Send;
+
+    fn set_default_printer(&self, printer_name: &str) -> impl Future<Output = Result<(), String>> + Send;
+
+    /// Devices a printer queue could be added for
+    fn discover_devices(&self) -> impl Future<Output = Result<Vec<DiscoveredPrinter>, String>> + Send;
+
+    fn add_printer(&self, request: &NewPrinterRequest) -> impl Future<Output = Result<(), String>> + Send;
+
+    fn delete_printer(&self, printer_name: &str) -> impl Future<Output = Result<(), String>> + Send;
+}
+
+/// Scanners and scanning, SANE on a real system
+pub trait ScanBackend {
+    fn is_available(&self) -> impl Future<Output = bool> + Send;
+
+    fn get_scanners(&self) -> impl Future<Output = Result<Vec<Scanner>, String>> + Send;
+
+    /// Scan into the job's file in `scans_dir`, returns the file path
+    fn start_scan(&self, job: &ScanJob, scans_dir: &Path) -> impl Future<Output = Result<String, String>> + Send;
+}
+
+/// Print head maintenance and ink levels read from the printer itself, escputil on a real system
+pub trait MaintenanceBackend {
+    fn is_available(&self) -> impl Future<Output = bool> + Send;
+
+    fn do_nozzle_heads_check(&self) -> impl Future<Output = Result<(), String>> + Send;
+
+    fn do_nozzle_heads_cleaning(&self) -> impl Future<Output = Result<(), String>> + Send;
+
+    fn get_ink_levels(&self) -> impl Future<Output = Result<Vec<MarkerLevel>, String>> + Send;
+}
+
+/// CUPS, SANE and escputil on the machine the server runs on
+#[derive(Clone)]
+pub struct SystemBackend {
+    cups: Arc<CupsService>,
+    sane: SaneService,
+    escputil: MaintenanceService,
+}
+
+/// Backend selected by `devices.backend`
+#[derive(Clone)]
+pub enum Backend {
+    System(SystemBackend),
+    Simulated(SimulatedBackend),
+}
+
+impl std::fmt::Debug for Backend {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Backend::System(_) => write!(f, "System"),
+            Backend::Simulated(_) => write!(f, "Simulated"),
+        }
+    }
+}
+
+impl Backend {
+    pub fn new(kind: DeviceBackend) -> Self {
+        match kind {
+            DeviceBackend::System => Backend::System(SystemBackend {
+                cups: Arc::new(CupsService::new()),
+                sane: SaneService::new(),
+                escputil: MaintenanceService::new(),
+            }),
+            DeviceBackend::Simulated => Backend::Simulated(SimulatedBackend::new()),
+        }
+    }
+}
+
+impl PrintBackend for Backend {
+    async fn is_available(&self) -> bool {
+        match self {
+            Backend::System(system) => system.cups.is_available().await,
+            Backend::Simulated(simulated) => PrintBackend::is_available(simulated).await,
+        }
+    }
+
+    async fn get_printers(&self) -> Result<Vec<Printer>, String> {
+        match self {
+            Backend::System(system) => system.cups.get_printers().await,
+            Backend::Simulated(simulated) => simulated.get_printers().await,
+        }
+    }
+
+    async fn get_printer(&self, printer_name: &str) -> Result<Printer, String> {
+        match self {
+            Backend::System(system) => system.cups.get_printer(printer_name).await,
+            Backend::Simulated(simulated) => simulated.get_printer(printer_name).await,
+        }
+    }
+
+    async fn get_capabilities(&self, printer_name: &str) -> Result<PrinterCapabilities, String> {
+        match self {
+            Backend::System(system) => system.cups.get_capabilities(printer_name).await,
+            Backend::Simulated(simulated) => simulated.get_capabilities(printer_name).await,
+        }
+    }
+
+    async fn get_marker_levels(&self, printer_name: &str) -> Result<Vec<MarkerLevel>, String> {
+        match self {
+            Backend::System(system) => system.cups.get_marker_levels(printer_name).await,
+            Backend::Simulated(simulated) => simulated.get_marker_levels(printer_name).await,
+        }
+    }
+
+    async fn submit_print_job(&self, job: &PrintJob, file_path: &str) -> Result<i32, String> {
+        match self {
+            Backend::System(system) => system.cups.submit_print_job(job, file_path).await,
+            Backend::Simulated(simulated) => simulated.submit_print_job(job, file_path).await,
+        }
+    }
+
+    async fn get_job_status(&self, job_id: i32) -> Result<CupsJobStatus, String> {
+        match self {
+            Backend::System(system) => system.cups.get_job_status(job_id).await,
+            Backend::Simulated(simulated) => simulated.get_job_status(job_id).await,
+        }
+    }
+
+    async fn cancel_job(&self, job_id: i32) -> Result<(), String> {
+        match self {
+            Backend::System(system) => system.cups.cancel_job(job_id).await,
+            Backend::Simulated(simulated) => simulated.cancel_job(job_id).await,
+        }
+    }
+
+    async fn get_active_jobs(&self) -> Result<Vec<(i32, String, String)>, String> {
+        match self {
+            Backend::System(system) => system.cups.get_active_jobs().await,
+            Backend::Simulated(simulated) => simulated.get_active_jobs().await,
+        }
+    }
+
+    async fn enable_printer(&self, printer_name: &str) -> Result<(), String> {
+        match self {
+            Backend::System(system) => system.cups.enable_printer(printer_name).await,
+            Backend::Simulated(simulated) => simulated.enable_printer(printer_name).await,
+        }
+    }
+
+    async fn disable_printer(&self, printer_name: &str, reason: Option<&str>) -> Result<(), String> {
+        match self {
+            Backend::System(system) => system.cups.disable_printer(printer_name, reason).await,
+            Backend::Simulated(simulated) => simulated.disable_printer(printer_name, reason).await,
+        }
+    }
+
+    async fn accept_jobs(&self, printer_name: &str) -> Result<(), String> {
+        match self {
+            Backend::System(system) => system.cups.accept_jobs(printer_name).await,
+            Backend::Simulated(simulated) => simulated.accept_jobs(printer_name).await,
+        }
+    }
+
+    async fn reject_jobs(&self, printer_name: &str, reason: Option<&str>) -> Result<(), String> {
+        match self {
+            Backend::System(system) => system.cups.reject_jobs(printer_name, reason).await,
+            Backend::Simulated(simulated) => simulated.reject_jobs(printer_name, reason).await,
+        }
+    }
+
+    async fn purge_jobs(&self, printer_name: &str) -> Result<(), String> {
+        match self {
+            Backend::System(system) => system.cups.purge_jobs(printer_name).await,
+            Backend::Simulated(simulated) => simulated.purge_jobs(printer_name).await,
+        }
+    }
+
+    async fn set_default_printer(&self, printer_name: &str) -> Result<(), String> {
+        match self {
+            Backend::System(system) => system.cups.set_default_printer(printer_name).await,
+            Backend::Simulated(simulated) => simulated.set_default_printer(printer_name).await,
+        }
+    }
+
+    async fn discover_devices(&self) -> Result<Vec<DiscoveredPrinter>, String> {
+        match self {
+            Backend::System(system) => system.cups.discover_devices().await,
+            Backend::Simulated(simulated) => simulated.discover_devices().await,
+        }
+    }
+
+    async fn add_printer(&self, request: &NewPrinterRequest) -> Result<(), String> {
+        match self {
+            Backend::System(system) => system.cups.add_printer(request).await,
+            Backend::Simulated(simulated) => simulated.add_printer(request).await,
+        }
+    }
+
+    async fn delete_printer(&self, printer_name: &str) -> Result<(), String> {
+        match self {
+            Backend::System(system) => system.cups.delete_printer(printer_name).await,
+            Backend::Simulated(simulated) => simulated.delete_printer(printer_name).await,
+        }
+    }
+}
+
+impl ScanBackend for Backend {
+    async fn is_available(&self) -> bool {
+        match self {
+            Backend::System(system) => ScanBackend::is_available(&system.sane).await,
+            Backend::Simulated(simulated) => ScanBackend::is_available(simulated).await,
+        }
+    }
+
+    async fn get_scanners(&self) -> Result<Vec<Scanner>, String> {
+        match self {
+            Backend::System(system) => system.sane.get_scanners().await,
+            Backend::Simulated(simulated) => simulated.get_scanners().await,
+        }
+    }
+
+    async fn start_scan(&self, job: &ScanJob, scans_dir: &Path) -> Result<String, String> {
+        match self {
+            Backend::System(system) => system.sane.start_scan(job, scans_dir).await,
+            Backend::Simulated(simulated) => simulated.start_scan(job, scans_dir).await,
+        }
+    }
+}
+
+impl MaintenanceBackend for Backend {
+    async fn is_available(&self) -> bool {
+        match self {
+            Backend::System(system) => MaintenanceBackend::is_available(&system.escputil).await,
+            Backend::Simulated(simulated) => MaintenanceBackend::is_available(simulated).await,
+        }
+    }
+
+    async fn do_nozzle_heads_check(&self) -> Result<(), String> {
+        match self {
+            Backend::System(system) => system.escputil.do_nozzle_heads_check().await,
+            Backend::Simulated(simulated) => simulated.do_nozzle_heads_check().await,
+        }
+    }
+
+    async fn do_nozzle_heads_cleaning(&self) -> Result<(), String> {
+        match self {
+            Backend::System(system) => system.escputil.do_nozzle_heads_cleaning().await,
+            Backend::Simulated(simulated) => simulated.do_nozzle_heads_cleaning().await,
+        }
+    }
+
+    async fn get_ink_levels(&self) -> Result<Vec<MarkerLevel>, String> {
+        match self {
+            Backend::System(system) => system.escputil.get_ink_levels().await,
+            Backend::Simulated(simulated) => simulated.get_ink_levels().await,
+        }
+    }
+}
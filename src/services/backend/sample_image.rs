@@ -0,0 +1,433 @@
+//! Made-up scanned pages for the simulated scanner, encoded by hand as no image library is available
+
+use std::io::Write;
+use flate2::write::ZlibEncoder;
+use flate2::{Compression, Crc};
+
+/// 8-bit pixels, one channel for grey or three for RGB
+pub struct Raster {
+    pub width: u32,
+    pub height: u32,
+    pub channels: u8,
+    pub pixels: Vec<u8>,
+    /// Stored in the file headers so viewers show the page at its real size
+    pub dpi: u32,
+}
+
+impl Raster {
+    fn new(width: u32, height: u32, dpi: u32) -> Self {
+        Self { width, height, channels: 3, pixels: vec![0xff; (width * height * 3) as usize], dpi }
+    }
+
+    fn set_pixel(&mut self, x: u32, y: u32, rgb: [u8; 3]) {
+        if x < self.width && y < self.height {
+            let i = ((y * self.width + x) * 3) as usize;
+            self.pixels[i..i + 3].copy_from_slice(&rgb);
+        }
+    }
+
+    fn fill_rect(&mut self, x: u32, y: u32, width: u32, height: u32, rgb: [u8; 3]) {
+        for row in y..(y + height).min(self.height) {
+            for column in x..(x + width).min(self.width) {
+                self.set_pixel(column, row, rgb);
+            }
+        }
+    }
+
+    pub fn into_grayscale(self) -> Self {
+        let pixels = self.pixels.chunks(3)
+            .map(|rgb| (0.299 * rgb[0] as f32 + 0.587 * rgb[1] as f32 + 0.114 * rgb[2] as f32).round() as u8)
+            .collect();
+
+        Self { pixels, channels: 1, ..self }
+    }
+
+    /// Black and white, stored as grey pixels that are either 0 or 255
+    pub fn into_monochrome(self) -> Self {
+        let mut gray = self.into_grayscale();
+        gray.pixels.iter_mut().for_each(|pixel| *pixel = if *pixel < 160 { 0 } else { 0xff });
+        gray
+    }
+
+    fn pixel(&self, x: u32, y: u32) -> &[u8] {
+        let channels = self.channels as usize;
+        let i = (y * self.width + x) as usize * channels;
+        &self.pixels[i..i + channels]
+    }
+}
+
+/// A letter-like page of `width_mm` by `height_mm`: a coloured heading, paragraphs of "text" and a photo
+pub fn sample_page(width_mm: f64, height_mm: f64, dpi: u32) -> Raster {
+    let px = |mm: f64| (mm / 25.4 * dpi as f64).round() as u32;
+    let (width, height) = (px(width_mm).max(8), px(height_mm).max(8));
+    let mut page = Raster::new(width, height, dpi);
+
+    let margin = px(20.0);
+    let text_width = width.saturating_sub(2 * margin);
+    page.fill_rect(margin, px(18.0), text_width * 2 / 3, px(10.0), [0x25, 0x63, 0xeb]);
+
+    // Ragged lines with a blank line between paragraphs, the lengths come from a fixed LCG
+    let photo_height = px(45.0);
+    let text_bottom = height.saturating_sub(margin + photo_height + px(10.0));
+    let mut seed = 0x2545_f491_u32;
+    let mut y = px(38.0);
+    let mut line = 0;
+    while y + px(3.0) < text_bottom {
+        line += 1;
+        if line % 6 != 0 {
+            seed = seed.wrapping_mul(1_103_515_245).wrapping_add(12_345);
+            let length = text_width * (60 + (seed >> 16) % 41) / 100;
+            page.fill_rect(margin, y, length, px(3.0).max(1), [0x33, 0x33, 0x33]);
+        }
+        y += px(6.0).max(2);
+    }
+
+    let photo_top = height.saturating_sub(margin + photo_height);
+    let photo_width = px(60.0);
+    for row in 0..photo_height {
+        for column in 0..photo_width {
+            let red = (column * 255 / photo_width.max(1)) as u8;
+            let blue = (row * 255 / photo_height.max(1)) as u8;
+            page.set_pixel(margin + column, photo_top + row, [red, 0x90, blue]);
+        }
+    }
+
+    page
+}
+
+pub fn encode_png(raster: &Raster) -> std::io::Result<Vec<u8>> {
+    let mut header = Vec::with_capacity(13);
+    header.extend_from_slice(&raster.width.to_be_bytes());
+    header.extend_from_slice(&raster.height.to_be_bytes());
+    // 8 bits per sample, grey or truecolour, deflate, adaptive filtering, no interlace
+    header.extend_from_slice(&[8, if raster.channels == 1 { 0 } else { 2 }, 0, 0, 0]);
+
+    let pixels_per_metre = (raster.dpi as f64 / 0.0254).round() as u32;
+    let mut physical = Vec::with_capacity(9);
+    physical.extend_from_slice(&pixels_per_metre.to_be_bytes());
+    physical.extend_from_slice(&pixels_per_metre.to_be_bytes());
+    physical.push(1);
+
+    let mut encoder = ZlibEncoder::new(Vec::new(), Compression::fast());
+    for row in raster.pixels.chunks((raster.width * raster.channels as u32) as usize) {
+        // Filter type none
+        encoder.write_all(&[0])?;
+        encoder.write_all(row)?;
+    }
+    let data = encoder.finish()?;
+
+    let mut png = b"\x89PNG\r\n\x1a\n".to_vec();
+    png_chunk(&mut png, b"IHDR", &header);
+    png_chunk(&mut png, b"pHYs", &physical);
+    png_chunk(&mut png, b"IDAT", &data);
+    png_chunk(&mut png, b"IEND", &[]);
+    Ok(png)
+}
+
+fn png_chunk(png: &mut Vec<u8>, kind: &[u8; 4], data: &[u8]) {
+    let mut crc = Crc::new();
+    crc.update(kind);
+    crc.update(data);
+
+    png.extend_from_slice(&(data.len() as u32).to_be_bytes());
+    png.extend_from_slice(kind);
+    png.extend_from_slice(data);
+    png.extend_from_slice(&crc.sum().to_be_bytes());
+}
+
+/// Uncompressed little-endian baseline TIFF with a single strip
+pub fn encode_tiff(raster: &Raster) -> Vec<u8> {
+    const SHORT: u16 = 3;
+    const LONG: u16 = 4;
+    const RATIONAL: u16 = 5;
+    const ENTRIES: u32 = 12;
+
+    // Header, then the directory, then the values too large for an entry, then the pixels
+    let bits_offset = 8 + 2 + ENTRIES * 12 + 4;
+    let x_resolution_offset = bits_offset + 8;
+    let y_resolution_offset = x_resolution_offset + 8;
+    let pixels_offset = y_resolution_offset + 8;
+
+    let (bits_per_sample, photometric) = match raster.channels {
+        1 => ((1, 8), 1),
+        _ => ((3, bits_offset), 2),
+    };
+    let entries: [(u16, u16, u32, u32); ENTRIES as usize] = [
+        (256, LONG, 1, raster.width),
+        (257, LONG, 1, raster.height),
+        (258, SHORT, bits_per_sample.0, bits_per_sample.1),
+        (259, SHORT, 1, 1),
+        (262, SHORT, 1, photometric),
+        (273, LONG, 1, pixels_offset),
+        (277, SHORT, 1, raster.channels as u32),
+        (278, LONG, 1, raster.height),
+        (279, LONG, 1, raster.pixels.len() as u32),
+        (282, RATIONAL, 1, x_resolution_offset),
+        (283, RATIONAL, 1, y_resolution_offset),
+        (296, SHORT, 1, 2),
+    ];
+
+    let mut tiff = Vec::with_capacity(pixels_offset as usize + raster.pixels.len());
+    tiff.extend_from_slice(b"II*\0");
+    tiff.extend_from_slice(&8u32.to_le_bytes());
+    tiff.extend_from_slice(&(ENTRIES as u16).to_le_bytes());
+    for (tag, kind, count, value) in entries {
+        tiff.extend_from_slice(&tag.to_le_bytes());
+        tiff.extend_from_slice(&kind.to_le_bytes());
+        tiff.extend_from_slice(&count.to_le_bytes());
+        match (kind, count) {
+            (SHORT, 1) => {
+                tiff.extend_from_slice(&(value as u16).to_le_bytes());
+                tiff.extend_from_slice(&[0, 0]);
+            },
+            _ => tiff.extend_from_slice(&value.to_le_bytes()),
+        }
+    }
+    tiff.extend_from_slice(&0u32.to_le_bytes());
+
+    for _ in 0..3 {
+        tiff.extend_from_slice(&8u16.to_le_bytes());
+    }
+    tiff.extend_from_slice(&[0, 0]);
+    for _ in 0..2 {
+        tiff.extend_from_slice(&raster.dpi.to_le_bytes());
+        tiff.extend_from_slice(&1u32.to_le_bytes());
+    }
+    tiff.extend_from_slice(&raster.pixels);
+
+    tiff
+}
+
+/// Baseline JPEG keeping only the average of every 8x8 block, which is all a fake scan needs
+///
+/// With every AC coefficient zero the encoder needs no DCT: a block's DC coefficient is eight times
+/// its level-shifted average, so a DC quantizer of 8 makes the coded value the average itself.
+pub fn encode_jpeg(raster: &Raster) -> Vec<u8> {
+    let components = raster.channels;
+    let mut jpeg = vec![0xff, 0xd8];
+
+    let mut jfif = b"JFIF\0\x01\x01\x01".to_vec();
+    jfif.extend_from_slice(&(raster.dpi as u16).to_be_bytes());
+    jfif.extend_from_slice(&(raster.dpi as u16).to_be_bytes());
+    jfif.extend_from_slice(&[0, 0]);
+    jpeg_segment(&mut jpeg, 0xe0, &jfif);
+
+    let mut quantization = vec![0, 8];
+    quantization.extend_from_slice(&[1; 63]);
+    jpeg_segment(&mut jpeg, 0xdb, &quantization);
+
+    let mut frame = vec![8];
+    frame.extend_from_slice(&(raster.height as u16).to_be_bytes());
+    frame.extend_from_slice(&(raster.width as u16).to_be_bytes());
+    frame.push(components);
+    for id in 1..=components {
+        frame.extend_from_slice(&[id, 0x11, 0]);
+    }
+    jpeg_segment(&mut jpeg, 0xc0, &frame);
+
+    // DC categories 0 to 11 are all 4-bit codes equal to the category, EOB is the only AC code: "0"
+    let mut dc_table = vec![0x00, 0, 0, 0, 12];
+    dc_table.extend_from_slice(&[0; 12]);
+    dc_table.extend(0..12u8);
+    jpeg_segment(&mut jpeg, 0xc4, &dc_table);
+    let mut ac_table = vec![0x10, 1];
+    ac_table.extend_from_slice(&[0; 15]);
+    ac_table.push(0x00);
+    jpeg_segment(&mut jpeg, 0xc4, &ac_table);
+
+    let mut scan = vec![components];
+    for id in 1..=components {
+        scan.extend_from_slice(&[id, 0x00]);
+    }
+    scan.extend_from_slice(&[0, 63, 0]);
+    jpeg_segment(&mut jpeg, 0xda, &scan);
+
+    let mut bits = BitWriter::default();
+    let mut previous = [0i32; 3];
+    for block_y in 0..raster.height.div_ceil(8) {
+        for block_x in 0..raster.width.div_ceil(8) {
+            let averages = block_averages(raster, block_x * 8, block_y * 8);
+
+            for (component, value) in averages.iter().take(components as usize).enumerate() {
+                let dc = value.round() as i32 - 128;
+                let diff = dc - previous[component];
+                previous[component] = dc;
+
+                let category = 32 - diff.unsigned_abs().leading_zeros();
+                bits.write(category, 4);
+                if category > 0 {
+                    let value = if diff < 0 { diff - 1 } else { diff };
+                    bits.write(value as u32 & ((1 << category) - 1), category);
+                }
+                bits.write(0, 1);
+            }
+        }
+    }
+    jpeg.extend(bits.finish());
+
+    jpeg.extend_from_slice(&[0xff, 0xd9]);
+    jpeg
+}
+
+/// Average Y, Cb and Cr of the 8x8 block at `x`, `y`, or just the grey level for grey rasters
+fn block_averages(raster: &Raster, x: u32, y: u32) -> [f32; 3] {
+    let mut sums = [0f32; 3];
+    let mut count = 0f32;
+
+    for row in y..(y + 8).min(raster.height) {
+        for column in x..(x + 8).min(raster.width) {
+            let pixel = raster.pixel(column, row);
+            match pixel {
+                [gray] => sums[0] += *gray as f32,
+                [red, green, blue] => {
+                    let (red, green, blue) = (*red as f32, *green as f32, *blue as f32);
+                    sums[0] += 0.299 * red + 0.587 * green + 0.114 * blue;
+                    sums[1] += 128.0 - 0.168_736 * red - 0.331_264 * green + 0.5 * blue;
+                    sums[2] += 128.0 + 0.5 * red - 0.418_688 * green - 0.081_312 * blue;
+                },
+                _ => {},
+            }
+            count += 1.0;
+        }
+    }
+
+    sums.map(|sum| sum / count)
+}
+
+fn jpeg_segment(jpeg: &mut Vec<u8>, marker: u8, data: &[u8]) {
+    jpeg.extend_from_slice(&[0xff, marker]);
+    jpeg.extend_from_slice(&(data.len() as u16 + 2).to_be_bytes());
+    jpeg.extend_from_slice(data);
+}
+
+/// Entropy-coded segment writer, a 0xFF byte is followed by a stuffed zero
+#[derive(Default)]
+struct BitWriter {
+    bytes: Vec<u8>,
+    current: u32,
+    length: u32,
+}
+
+impl BitWriter {
+    fn write(&mut self, value: u32, length: u32) {
+        for bit in (0..length).rev() {
+            self.current = (self.current << 1) | ((value >> bit) & 1);
+            self.length += 1;
+
+            if self.length == 8 {
+                self.push_byte();
+            }
+        }
+    }
+
+    fn push_byte(&mut self) {
+        let byte = self.current as u8;
+        self.bytes.push(byte);
+        if byte == 0xff {
+            self.bytes.push(0);
+        }
+        self.current = 0;
+        self.length = 0;
+    }
+
+    /// Pad the last byte with one bits
+    fn finish(mut self) -> Vec<u8> {
+        if self.length > 0 {
+            let padding = 8 - self.length;
+            self.write((1 << padding) - 1, padding);
+        }
+        self.bytes
+    }
+}
+
+/// Single page PDF showing the raster at its real size, embedded as a JPEG
+pub fn encode_pdf(raster: &Raster) -> Vec<u8> {
+    let image = encode_jpeg(raster);
+    let points = |pixels: u32| pixels as f64 * 72.0 / raster.dpi as f64;
+    let (width, height) = (points(raster.width), points(raster.height));
+    let color_space = if raster.channels == 1 { "DeviceGray" } else { "DeviceRGB" };
+    let contents = format!("q {:.2} 0 0 {:.2} 0 0 cm /Im0 Do Q", width, height);
+
+    let mut pdf = b"%PDF-1.4\n".to_vec();
+    let mut offsets = Vec::new();
+    let mut object = |pdf: &mut Vec<u8>, body: &[u8]| {
+        offsets.push(pdf.len());
+        pdf.extend_from_slice(format!("{} 0 obj\n", offsets.len()).as_bytes());
+        pdf.extend_from_slice(body);
+        pdf.extend_from_slice(b"\nendobj\n");
+    };
+
+    object(&mut pdf, b"<< /Type /Catalog /Pages 2 0 R >>");
+    object(&mut pdf, b"<< /Type /Pages /Kids [3 0 R] /Count 1 >>");
+    object(&mut pdf, format!(
+        "<< /Type /Page /Parent 2 0 R /MediaBox [0 0 {:.2} {:.2}] /Resources << /XObject << /Im0 4 0 R >> >> /Contents 5 0 R >>",
+        width, height
+    ).as_bytes());
+    let mut image_object = format!(
+        "<< /Type /XObject /Subtype /Image /Width {} /Height {} /ColorSpace /{} /BitsPerComponent 8 /Filter /DCTDecode /Length {} >>\nstream\n",
+        raster.width, raster.height, color_space, image.len()
+    ).into_bytes();
+    image_object.extend_from_slice(&image);
+    image_object.extend_from_slice(b"\nendstream");
+    object(&mut pdf, &image_object);
+    object(&mut pdf, format!("<< /Length {} >>\nstream\n{}\nendstream", contents.len(), contents).as_bytes());
+
+    let xref_offset = pdf.len();
+    pdf.extend_from_slice(format!("xref\n0 {}\n0000000000 65535 f \n", offsets.len() + 1).as_bytes());
+    for offset in &offsets {
+        pdf.extend_from_slice(format!("{:010} 00000 n \n", offset).as_bytes());
+    }
+    pdf.extend_from_slice(format!(
+        "trailer\n<< /Size {} /Root 1 0 R >>\nstartxref\n{}\n%%EOF\n",
+        offsets.len() + 1, xref_offset
+    ).as_bytes());
+
+    pdf
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use flate2::read::ZlibDecoder;
+    use std::io::Read;
+
+    #[test]
+    fn test_encode_sample_page() {
+        let page = sample_page(210.0, 297.0, 50);
+        assert_eq!((page.width, page.height, page.channels), (413, 585, 3));
+        // The heading is blue, the corner is paper white
+        assert_eq!(page.pixel(page.width / 3, (23.0 / 25.4 * 50.0) as u32), [0x25, 0x63, 0xeb]);
+        assert_eq!(page.pixel(0, 0), [0xff, 0xff, 0xff]);
+
+        let png = encode_png(&page).unwrap();
+        assert!(png.starts_with(b"\x89PNG\r\n\x1a\n"));
+        assert!(png.ends_with(b"IEND\xae\x42\x60\x82"));
+        let data_length = u32::from_be_bytes(png[54..58].try_into().unwrap()) as usize;
+        assert_eq!(&png[58..62], b"IDAT");
+        let mut pixels = Vec::new();
+        ZlibDecoder::new(&png[62..62 + data_length]).read_to_end(&mut pixels).unwrap();
+        assert_eq!(pixels.len(), (page.width * 3 + 1) as usize * page.height as usize);
+
+        let gray = sample_page(148.0, 210.0, 50).into_monochrome();
+        assert!(gray.pixels.iter().all(|pixel| *pixel == 0 || *pixel == 0xff));
+        let tiff = encode_tiff(&gray);
+        assert!(tiff.starts_with(b"II*\0"));
+        assert_eq!(tiff.len(), 8 + 2 + 12 * 12 + 4 + 24 + gray.pixels.len());
+
+        let jpeg = encode_jpeg(&page);
+        assert!(jpeg.starts_with(&[0xff, 0xd8, 0xff, 0xe0]));
+        assert!(jpeg.ends_with(&[0xff, 0xd9]));
+        // Markers only appear in the headers, entropy-coded 0xFF bytes are stuffed
+        let scan_start = jpeg.windows(2).position(|marker| marker == [0xff, 0xda]).unwrap();
+        let scan = &jpeg[scan_start + 14..jpeg.len() - 2];
+        assert!(scan.windows(2).all(|pair| pair[0] != 0xff || pair[1] == 0));
+
+        let pdf = encode_pdf(&page);
+        let pdf_text = String::from_utf8_lossy(&pdf);
+        assert!(pdf_text.starts_with("%PDF-1.4"));
+        assert!(pdf_text.contains("/MediaBox [0 0 594.72 842.40]"));
+        let xref_offset = pdf_text.rsplit("startxref\n").next().unwrap().lines().next().unwrap().parse::<usize>().unwrap();
+        assert!(pdf[xref_offset..].starts_with(b"xref\n0 6\n"));
+    }
+}
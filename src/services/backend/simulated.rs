@@ -0,0 +1,779 @@
+//! Printers, a scanner and ink that only exist in memory, for demos and development without CUPS or SANE
+//!
+//! Jobs move through pending, processing and printing as time passes, one page every few seconds,
+//! and use up ink while they print. Documents and scans with "fail" in their name fail on purpose.
+
+use std::path::Path;
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::time::Duration;
+use tokio::time::Instant;
+use crate::models::{ColorMode, DiscoveredPrinter, MarkerLevel, NewPrinterRequest, Printer, PrinterCapabilities, PrintJob, ScanFormat, ScanJob, ScanPageSize, Scanner};
+use crate::services::backend::{sample_image, MaintenanceBackend, PrintBackend, ScanBackend};
+use crate::services::cups::{parse_page_ranges, CupsJobState, CupsJobStatus};
+
+/// Documents and scans whose file name contains this fail, to try out error handling
+const FAILURE_KEYWORD: &str = "fail";
+/// Scans are generated at no more than this resolution, a 1200 dpi colour A4 page would take 400 MB
+const MAX_SCAN_DPI: u32 = 100;
+const SCANNER_NAME: &str = "simulated:flatbed";
+
+/// How long the simulated devices take
+#[derive(Debug, Clone, Copy)]
+pub struct Timing {
+    /// Spooling and filtering before the first page comes out
+    pub processing: Duration,
+    pub page: Duration,
+    pub scan: Duration,
+}
+
+impl Default for Timing {
+    fn default() -> Self {
+        Self {
+            processing: Duration::from_secs(3),
+            page: Duration::from_secs(4),
+            scan: Duration::from_secs(5),
+        }
+    }
+}
+
+struct SimulatedPrinter {
+    printer: Printer,
+    capabilities: PrinterCapabilities,
+    markers: Vec<MarkerLevel>,
+    enabled: bool,
+}
+
+struct SimulatedJob {
+    id: i32,
+    printer: String,
+    user: String,
+    impressions: i32,
+    color: bool,
+    /// 2 when printing double-sided
+    sides_per_sheet: i32,
+    /// Page the job aborts on, for documents named to fail
+    fail_on: Option<i32>,
+    state: CupsJobState,
+    reasons: Vec<String>,
+    printed: i32,
+    /// Time spent on the job, only runs while the printer is enabled
+    elapsed: Duration,
+}
+
+struct State {
+    printers: Vec<SimulatedPrinter>,
+    jobs: Vec<SimulatedJob>,
+    next_job_id: i32,
+    updated_at: Instant,
+}
+
+#[derive(Clone)]
+pub struct SimulatedBackend {
+    state: Arc<Mutex<State>>,
+    timing: Timing,
+}
+
+impl SimulatedBackend {
+    pub fn new() -> Self {
+        Self::with_timing(Timing::default())
+    }
+
+    pub fn with_timing(timing: Timing) -> Self {
+        let state = State {
+            printers: vec![inkjet(), laser()],
+            jobs: Vec::new(),
+            next_job_id: 1,
+            updated_at: Instant::now(),
+        };
+
+        Self { state: Arc::new(Mutex::new(state)), timing }
+    }
+
+    /// Lock the state with the jobs brought up to date
+    fn state(&self) -> MutexGuard<'_, State> {
+        let mut state = self.state.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+        state.advance(&self.timing);
+        state
+    }
+}
+
+impl Default for SimulatedBackend {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl State {
+    fn printer(&self, name: &str) -> Result<&SimulatedPrinter, String> {
+        self.printers.iter()
+            .find(|printer| printer.printer.name == name)
+            .ok_or_else(|| format!("Printer '{}' not found", name))
+    }
+
+    fn printer_mut(&mut self, name: &str) -> Result<&mut SimulatedPrinter, String> {
+        self.printers.iter_mut()
+            .find(|printer| printer.printer.name == name)
+            .ok_or_else(|| format!("Printer '{}' not found", name))
+    }
+
+    fn job_mut(&mut self, job_id: i32) -> Result<&mut SimulatedJob, String> {
+        self.jobs.iter_mut()
+            .find(|job| job.id == job_id)
+            .ok_or_else(|| format!("Job {} not found", job_id))
+    }
+
+    /// Give the time since the last update to the first unfinished job of every enabled printer
+    fn advance(&mut self, timing: &Timing) {
+        let now = Instant::now();
+        let delta = now - self.updated_at;
+        self.updated_at = now;
+
+        for printer in &mut self.printers {
+            let name = &printer.printer.name;
+            let Some(job) = self.jobs.iter_mut().find(|job| &job.printer == name && !job.is_finished()) else {
+                continue;
+            };
+
+            if !printer.enabled {
+                if job.elapsed > Duration::ZERO {
+                    job.state = CupsJobState::Stopped;
+                    job.reasons = vec!["printer-stopped".to_string()];
+                }
+                continue;
+            }
+
+            job.elapsed += delta;
+            job.state = CupsJobState::Processing;
+            job.reasons.clear();
+
+            let Some(printing_time) = job.elapsed.checked_sub(timing.processing) else { continue };
+            let pages = (printing_time.as_millis() / timing.page.as_millis().max(1)) as i32;
+            let last_page = job.fail_on.map_or(job.impressions, |fail_on| fail_on - 1);
+            let printed = pages.min(last_page);
+
+            for _ in job.printed..printed {
+                use_ink(&mut printer.markers, job.color, 1);
+            }
+            job.printed = printed;
+
+            if job.fail_on.is_some() && pages > last_page {
+                job.state = CupsJobState::Aborted;
+                job.reasons = vec!["aborted-by-system".to_string()];
+            } else if printed == job.impressions {
+                job.state = CupsJobState::Completed;
+                job.reasons = vec!["job-completed-successfully".to_string()];
+            } else {
+                job.reasons = vec!["job-printing".to_string()];
+            }
+        }
+    }
+}
+
+impl SimulatedJob {
+    fn is_finished(&self) -> bool {
+        matches!(self.state, CupsJobState::Canceled | CupsJobState::Aborted | CupsJobState::Completed)
+    }
+}
+
+impl PrintBackend for SimulatedBackend {
+    async fn is_available(&self) -> bool {
+        true
+    }
+
+    async fn get_printers(&self) -> Result<Vec<Printer>, String> {
+        let state = self.state();
+
+        Ok(state.printers.iter()
+            .map(|printer| printer.status(&state.jobs))
+            .collect())
+    }
+
+    async fn get_printer(&self, printer_name: &str) -> Result<Printer, String> {
+        let state = self.state();
+
+        state.printer(printer_name).map(|printer| printer.status(&state.jobs))
+    }
+
+    async fn get_capabilities(&self, printer_name: &str) -> Result<PrinterCapabilities, String> {
+        self.state().printer(printer_name).map(|printer| printer.capabilities.clone())
+    }
+
+    async fn get_marker_levels(&self, printer_name: &str) -> Result<Vec<MarkerLevel>, String> {
+        self.state().printer(printer_name).map(|printer| printer.markers.clone())
+    }
+
+    async fn submit_print_job(&self, job: &PrintJob, file_path: &str) -> Result<i32, String> {
+        let document = tokio::fs::read(file_path)
+            .await
+            .map_err(|e| format!("Print job failed: {}", e))?;
+        let impressions = count_impressions(job, count_pages(&document))?;
+
+        let mut state = self.state();
+        let printer = state.printer(&job.printer)?;
+        if !printer.printer.accepting_jobs {
+            return Err(format!("Print job failed: printer '{}' is not accepting jobs", job.printer));
+        }
+        printer.capabilities.check(job)?;
+        let color = job.color && printer.capabilities.supports_color();
+        let sides_per_sheet = if job.duplex { 2 } else { 1 };
+
+        let job_id = state.next_job_id;
+        state.next_job_id += 1;
+        state.jobs.push(SimulatedJob {
+            id: job_id,
+            printer: job.printer.clone(),
+            user: std::env::var("USER").unwrap_or_else(|_| "web_printer".to_string()),
+            impressions,
+            color,
+            sides_per_sheet,
+            fail_on: job.original_filename.to_lowercase().contains(FAILURE_KEYWORD).then_some((impressions + 1) / 2),
+            state: CupsJobState::Pending,
+            reasons: Vec::new(),
+            printed: 0,
+            elapsed: Duration::ZERO,
+        });
+
+        log::info!("Submitted {} to simulated printer {} as job {}", job.original_filename, job.printer, job_id);
+        Ok(job_id)
+    }
+
+    async fn get_job_status(&self, job_id: i32) -> Result<CupsJobStatus, String> {
+        let mut state = self.state();
+        let job = state.job_mut(job_id)?;
+
+        Ok(CupsJobStatus {
+            state: job.state,
+            reasons: job.reasons.clone(),
+            impressions_completed: Some(job.printed),
+            media_sheets_completed: Some((job.printed + job.sides_per_sheet - 1) / job.sides_per_sheet),
+        })
+    }
+
+    async fn cancel_job(&self, job_id: i32) -> Result<(), String> {
+        let mut state = self.state();
+        let job = state.job_mut(job_id)?;
+        if job.is_finished() {
+            return Err(format!("Failed to cancel job {}: the job is already finished", job_id));
+        }
+
+        job.state = CupsJobState::Canceled;
+        job.reasons = vec!["job-canceled-by-user".to_string()];
+        Ok(())
+    }
+
+    async fn get_active_jobs(&self) -> Result<Vec<(i32, String, String)>, String> {
+        Ok(self.state().jobs.iter()
+            .filter(|job| !job.is_finished())
+            .map(|job| (job.id, job.printer.clone(), job.user.clone()))
+            .collect())
+    }
+
+    async fn enable_printer(&self, printer_name: &str) -> Result<(), String> {
+        let mut state = self.state();
+        let printer = state.printer_mut(printer_name)?;
+        printer.enabled = true;
+        printer.printer.state_message = None;
+        Ok(())
+    }
+
+    async fn disable_printer(&self, printer_name: &str, reason: Option<&str>) -> Result<(), String> {
+        let mut state = self.state();
+        let printer = state.printer_mut(printer_name)?;
+        printer.enabled = false;
+        printer.printer.state_message = reason.map(|reason| reason.to_string());
+        Ok(())
+    }
+
+    async fn accept_jobs(&self, printer_name: &str) -> Result<(), String> {
+        let mut state = self.state();
+        let printer = state.printer_mut(printer_name)?;
+        printer.printer.accepting_jobs = true;
+        printer.printer.state_message = None;
+        Ok(())
+    }
+
+    async fn reject_jobs(&self, printer_name: &str, reason: Option<&str>) -> Result<(), String> {
+        let mut state = self.state();
+        let printer = state.printer_mut(printer_name)?;
+        printer.printer.accepting_jobs = false;
+        printer.printer.state_message = reason.map(|reason| reason.to_string());
+        Ok(())
+    }
+
+    async fn purge_jobs(&self, printer_name: &str) -> Result<(), String> {
+        let mut state = self.state();
+        state.printer(printer_name)?;
+
+        for job in state.jobs.iter_mut().filter(|job| job.printer == printer_name && !job.is_finished()) {
+            job.state = CupsJobState::Canceled;
+            job.reasons = vec!["job-canceled-by-operator".to_string()];
+        }
+        Ok(())
+    }
+
+    async fn set_default_printer(&self, printer_name: &str) -> Result<(), String> {
+        let mut state = self.state();
+        state.printer(printer_name)?;
+
+        for printer in &mut state.printers {
+            printer.printer.is_default = printer.printer.name == printer_name;
+        }
+        Ok(())
+    }
+
+    async fn discover_devices(&self) -> Result<Vec<DiscoveredPrinter>, String> {
+        let device = |uri: &str, connection: &str, make_and_model: &str| DiscoveredPrinter {
+            uri: uri.to_string(),
+            connection: connection.to_string(),
+            make_and_model: Some(make_and_model.to_string()),
+            info: Some(make_and_model.to_string()),
+            source: "lpinfo".to_string(),
+        };
+
+        Ok(vec![
+            device("simulated://inkjet", "direct", "Simulated Inkjet"),
+            device("simulated://laser", "network", "Simulated Laser"),
+            device("simulated://photo", "direct", "Simulated Photo"),
+        ])
+    }
+
+    async fn add_printer(&self, request: &NewPrinterRequest) -> Result<(), String> {
+        let valid_name = |c: char| c.is_ascii_alphanumeric() || matches!(c, '_' | '-' | '.');
+        if request.name.is_empty() || !request.name.chars().all(valid_name) {
+            return Err(format!("Invalid printer name '{}'", request.name));
+        }
+        if request.uri.trim().is_empty() {
+            return Err("Device URI is required".to_string());
+        }
+
+        let mut state = self.state();
+        if state.printer(&request.name).is_ok() {
+            return Err(format!("Printer '{}' already exists", request.name));
+        }
+
+        let model = request.uri.strip_prefix("simulated://").unwrap_or("Printer");
+        let mut printer = inkjet();
+        printer.printer = Printer {
+            name: request.name.clone(),
+            vendor: "Simulated".to_string(),
+            model: capitalize(model),
+            description: request.description.clone().unwrap_or_else(|| format!("Simulated {}", capitalize(model))),
+            location: request.location.clone(),
+            is_default: false,
+            ..printer.printer
+        };
+        printer.markers.iter_mut().for_each(|marker| marker.level = Some(100));
+        state.printers.push(printer);
+
+        log::info!("Added simulated printer {}", request.name);
+        Ok(())
+    }
+
+    async fn delete_printer(&self, printer_name: &str) -> Result<(), String> {
+        let mut state = self.state();
+        state.printer(printer_name)?;
+
+        state.printers.retain(|printer| printer.printer.name != printer_name);
+        for job in state.jobs.iter_mut().filter(|job| job.printer == printer_name && !job.is_finished()) {
+            job.state = CupsJobState::Canceled;
+            job.reasons = vec!["job-canceled-by-operator".to_string()];
+        }
+        Ok(())
+    }
+}
+
+impl ScanBackend for SimulatedBackend {
+    async fn is_available(&self) -> bool {
+        true
+    }
+
+    async fn get_scanners(&self) -> Result<Vec<Scanner>, String> {
+        Ok(vec![Scanner {
+            name: SCANNER_NAME.to_string(),
+            vendor: "Simulated".to_string(),
+            model: "Flatbed Scanner".to_string(),
+            device_type: "flatbed scanner".to_string(),
+        }])
+    }
+
+    async fn start_scan(&self, job: &ScanJob, scans_dir: &Path) -> Result<String, String> {
+        let output_path = job.get_file_path(scans_dir)
+            .ok_or("No output filename specified")?;
+        if job.scanner != SCANNER_NAME {
+            return Err(format!("Scan failed: no such device '{}'", job.scanner));
+        }
+
+        tokio::time::sleep(self.timing.scan).await;
+
+        if job.output_filename.as_ref().is_some_and(|filename| filename.to_lowercase().contains(FAILURE_KEYWORD)) {
+            return Err("Scan failed: Error during device I/O".to_string());
+        }
+
+        let (width_mm, height_mm) = match job.page_size {
+            ScanPageSize::A4 | ScanPageSize::Custom => (210.0, 297.0),
+            ScanPageSize::A5 => (148.0, 210.0),
+            ScanPageSize::Letter => (215.9, 279.4),
+            ScanPageSize::Legal => (215.9, 355.6),
+        };
+        let (dpi, format, color_mode) = (job.resolution.clamp(1, MAX_SCAN_DPI), job.format.clone(), job.color_mode.clone());
+
+        let image = tokio::task::spawn_blocking(move || {
+            let page = sample_image::sample_page(width_mm, height_mm, dpi);
+            let page = match color_mode {
+                ColorMode::Color => page,
+                ColorMode::Grayscale => page.into_grayscale(),
+                ColorMode::Monochrome => page.into_monochrome(),
+            };
+
+            match format {
+                ScanFormat::Pdf => Ok(sample_image::encode_pdf(&page)),
+                ScanFormat::Jpeg => Ok(sample_image::encode_jpeg(&page)),
+                ScanFormat::Png => sample_image::encode_png(&page),
+                ScanFormat::Tiff => Ok(sample_image::encode_tiff(&page)),
+            }
+        })
+            .await
+            .map_err(|e| format!("Scan failed: {}", e))?
+            .map_err(|e| format!("Scan failed: {}", e))?;
+
+        tokio::fs::write(&output_path, image)
+            .await
+            .map_err(|e| format!("Scan failed: {}", e))?;
+
+        Ok(output_path)
+    }
+}
+
+impl MaintenanceBackend for SimulatedBackend {
+    async fn is_available(&self) -> bool {
+        true
+    }
+
+    /// Prints the nozzle pattern on the inkjet, one page of every colour
+    async fn do_nozzle_heads_check(&self) -> Result<(), String> {
+        tokio::time::sleep(self.timing.page).await;
+        self.use_inkjet_ink(1)
+    }
+
+    /// Cleaning flushes ink through the heads, a lot more than a page
+    async fn do_nozzle_heads_cleaning(&self) -> Result<(), String> {
+        tokio::time::sleep(self.timing.page).await;
+        self.use_inkjet_ink(3)
+    }
+
+    async fn get_ink_levels(&self) -> Result<Vec<MarkerLevel>, String> {
+        self.get_marker_levels(INKJET_NAME).await
+    }
+}
+
+impl SimulatedBackend {
+    fn use_inkjet_ink(&self, amount: i32) -> Result<(), String> {
+        let mut state = self.state();
+        let printer = state.printer_mut(INKJET_NAME)?;
+        use_ink(&mut printer.markers, true, amount);
+        Ok(())
+    }
+}
+
+impl SimulatedPrinter {
+    fn status(&self, jobs: &[SimulatedJob]) -> Printer {
+        let printing = jobs.iter().any(|job| job.printer == self.printer.name && job.state == CupsJobState::Processing);
+        let status = if !self.enabled {
+            "stopped"
+        } else if !self.printer.accepting_jobs {
+            "rejecting"
+        } else if printing {
+            "printing"
+        } else {
+            "idle"
+        };
+
+        Printer { status: status.to_string(), ..self.printer.clone() }
+    }
+}
+
+const INKJET_NAME: &str = "Simulated_Inkjet";
+
+fn inkjet() -> SimulatedPrinter {
+    let ink = |name: &str, color: &str, level: i32| MarkerLevel {
+        name: name.to_string(),
+        color: Some(color.to_string()),
+        marker_type: Some("ink-cartridge".to_string()),
+        level: Some(level),
+        low_level: Some(10),
+    };
+
+    SimulatedPrinter {
+        printer: Printer {
+            name: INKJET_NAME.to_string(),
+            vendor: "Simulated".to_string(),
+            model: "Inkjet".to_string(),
+            description: "Simulated Inkjet".to_string(),
+            status: "idle".to_string(),
+            location: Some("Nowhere".to_string()),
+            is_default: true,
+            accepting_jobs: true,
+            state_message: None,
+        },
+        capabilities: PrinterCapabilities {
+            media: strings(&["iso_a4_210x297mm", "iso_a5_148x210mm", "iso_a6_105x148mm", "jpn_hagaki_100x148mm", "na_letter_8.5x11in", "na_legal_8.5x14in"]),
+            media_default: Some("iso_a4_210x297mm".to_string()),
+            sides: strings(&["one-sided"]),
+            color_modes: strings(&["monochrome", "color"]),
+            resolutions: strings(&["360x360dpi", "720x720dpi"]),
+            input_trays: strings(&["main"]),
+            media_types: strings(&["stationery", "photographic-glossy"]),
+            quality: strings(&["draft", "normal", "high"]),
+            pages_per_sheet: vec![1, 2, 4, 6, 9, 16],
+            max_copies: Some(99),
+            page_ranges: true,
+        },
+        markers: vec![
+            ink("Black", "#000000", 64),
+            ink("Cyan", "#00FFFF", 38),
+            ink("Magenta", "#FF00FF", 21),
+            ink("Yellow", "#FFFF00", 17),
+        ],
+        enabled: true,
+    }
+}
+
+fn laser() -> SimulatedPrinter {
+    SimulatedPrinter {
+        printer: Printer {
+            name: "Simulated_Laser".to_string(),
+            vendor: "Simulated".to_string(),
+            model: "Laser".to_string(),
+            description: "Simulated Laser".to_string(),
+            status: "idle".to_string(),
+            location: Some("Nowhere".to_string()),
+            is_default: false,
+            accepting_jobs: true,
+            state_message: None,
+        },
+        capabilities: PrinterCapabilities {
+            media: strings(&["iso_a4_210x297mm", "iso_a5_148x210mm", "na_letter_8.5x11in", "na_legal_8.5x14in"]),
+            media_default: Some("iso_a4_210x297mm".to_string()),
+            sides: strings(&["one-sided", "two-sided-long-edge", "two-sided-short-edge"]),
+            color_modes: strings(&["monochrome"]),
+            resolutions: strings(&["600x600dpi", "1200x1200dpi"]),
+            input_trays: strings(&["auto", "tray-1", "manual"]),
+            media_types: strings(&["stationery", "labels"]),
+            quality: strings(&["draft", "normal", "high"]),
+            pages_per_sheet: vec![1, 2, 4],
+            max_copies: Some(999),
+            page_ranges: true,
+        },
+        markers: vec![MarkerLevel {
+            name: "Black Toner".to_string(),
+            color: Some("#000000".to_string()),
+            marker_type: Some("toner".to_string()),
+            level: Some(80),
+            low_level: Some(5),
+        }],
+        enabled: true,
+    }
+}
+
+fn strings(values: &[&str]) -> Vec<String> {
+    values.iter().map(|value| value.to_string()).collect()
+}
+
+fn capitalize(word: &str) -> String {
+    let mut chars = word.chars();
+    chars.next()
+        .map(|first| first.to_uppercase().chain(chars).collect())
+        .unwrap_or_default()
+}
+
+/// Black goes on every page, the colours only on colour pages
+fn use_ink(markers: &mut [MarkerLevel], color: bool, amount: i32) {
+    for marker in markers {
+        let is_black = marker.color.as_deref() == Some("#000000");
+        if let Some(level) = marker.level.as_mut()
+            && (is_black || color) {
+            *level = (*level - amount).max(0);
+        }
+    }
+}
+
+/// Pages of a PDF, other documents count as a single page
+fn count_pages(document: &[u8]) -> i32 {
+    if !document.starts_with(b"%PDF") {
+        return 1;
+    }
+
+    let pages = [&b"/Type /Page"[..], &b"/Type/Page"[..]].iter()
+        .map(|pattern| document.windows(pattern.len() + 1)
+            .filter(|window| window.starts_with(pattern) && window[pattern.len()] != b's')
+            .count())
+        .sum::<usize>();
+
+    (pages as i32).max(1)
+}
+
+/// Sides the printer will print for `job`, taking page ranges, n-up and copies into account
+fn count_impressions(job: &PrintJob, document_pages: i32) -> Result<i32, String> {
+    let pages = match job.pages.as_deref().map(str::trim).filter(|pages| !pages.is_empty()) {
+        Some(pages) => parse_page_ranges(pages)?
+            .iter()
+            .filter(|(first, _)| *first <= document_pages)
+            .map(|(first, last)| last.min(&document_pages) - first + 1)
+            .sum(),
+        None => document_pages,
+    };
+
+    let sides = (pages + job.pages_per_sheet.max(1) as i32 - 1) / job.pages_per_sheet.max(1) as i32;
+    Ok(sides.max(1) * job.copies.max(1) as i32)
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::{PrintRequest, ScanRequest, SystemSettings};
+    use crate::test_utils::TempDir;
+
+    fn fast() -> SimulatedBackend {
+        SimulatedBackend::with_timing(Timing {
+            processing: Duration::from_millis(20),
+            page: Duration::from_millis(20),
+            scan: Duration::ZERO,
+        })
+    }
+
+    fn print_job(filename: &str, printer: &str, request: PrintRequest) -> PrintJob {
+        PrintJob::new(filename.to_string(), printer.to_string(), "Simulated".to_string(), "Inkjet".to_string(), request, 1)
+    }
+
+    async fn wait_until_finished(backend: &SimulatedBackend, job_id: i32) -> CupsJobStatus {
+        for _ in 0..100 {
+            let status = backend.get_job_status(job_id).await.unwrap();
+            if matches!(status.state, CupsJobState::Completed | CupsJobState::Aborted | CupsJobState::Canceled) {
+                return status;
+            }
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+        panic!("Job {} did not finish", job_id);
+    }
+
+    #[tokio::test]
+    async fn test_simulated_print_job() {
+        let backend = fast();
+        let dir = TempDir::new("simulated_print");
+        let document = dir.join("letter.txt");
+        std::fs::write(&document, "Dear printer").unwrap();
+        let document = document.to_str().unwrap();
+
+        let printers = backend.get_printers().await.unwrap();
+        assert_eq!(printers.len(), 2);
+        assert!(printers.iter().all(|printer| printer.status == "idle"));
+
+        let request = PrintRequest { copies: Some(3), color: Some(true), ..Default::default() };
+        let job_id = backend.submit_print_job(&print_job("letter.txt", INKJET_NAME, request), document).await.unwrap();
+        assert_eq!(backend.get_active_jobs().await.unwrap().len(), 1);
+
+        let status = wait_until_finished(&backend, job_id).await;
+        assert_eq!(status.state, CupsJobState::Completed);
+        assert_eq!(status.impressions_completed, Some(3));
+        assert_eq!(status.media_sheets_completed, Some(3));
+        let markers = backend.get_marker_levels(INKJET_NAME).await.unwrap();
+        assert_eq!(markers.iter().map(|marker| marker.level.unwrap()).collect::<Vec<i32>>(), vec![61, 35, 18, 14]);
+        assert!(backend.get_active_jobs().await.unwrap().is_empty());
+        assert!(backend.cancel_job(job_id).await.is_err());
+
+        // Named to fail, it aborts halfway through
+        let request = PrintRequest { copies: Some(4), ..Default::default() };
+        let job_id = backend.submit_print_job(&print_job("fail.txt", INKJET_NAME, request), document).await.unwrap();
+        let status = wait_until_finished(&backend, job_id).await;
+        assert_eq!(status.state, CupsJobState::Aborted);
+        assert_eq!(status.impressions_completed, Some(1));
+
+        // Nothing comes out of a disabled printer and a rejecting one refuses jobs
+        backend.disable_printer(INKJET_NAME, Some("Paper jam")).await.unwrap();
+        let job_id = backend.submit_print_job(&print_job("letter.txt", INKJET_NAME, PrintRequest::default()), document).await.unwrap();
+        tokio::time::sleep(Duration::from_millis(60)).await;
+        let status = backend.get_job_status(job_id).await.unwrap();
+        assert_eq!((status.state, status.impressions_completed), (CupsJobState::Pending, Some(0)));
+        let printer = backend.get_printer(INKJET_NAME).await.unwrap();
+        assert_eq!((printer.status.as_str(), printer.state_message.as_deref()), ("stopped", Some("Paper jam")));
+        backend.purge_jobs(INKJET_NAME).await.unwrap();
+        assert_eq!(backend.get_job_status(job_id).await.unwrap().state, CupsJobState::Canceled);
+
+        backend.reject_jobs("Simulated_Laser", None).await.unwrap();
+        let result = backend.submit_print_job(&print_job("letter.txt", "Simulated_Laser", PrintRequest::default()), document).await;
+        assert!(result.unwrap_err().contains("not accepting jobs"));
+
+        // The laser cannot print on postcards
+        backend.accept_jobs("Simulated_Laser").await.unwrap();
+        let request = PrintRequest { page_size: Some(crate::models::PrintPageSize::Postcard), ..Default::default() };
+        assert!(backend.submit_print_job(&print_job("letter.txt", "Simulated_Laser", request), document).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_simulated_printer_administration() {
+        let backend = fast();
+
+        let devices = backend.discover_devices().await.unwrap();
+        let photo = devices.iter().find(|device| device.uri == "simulated://photo").unwrap();
+        let request = NewPrinterRequest { name: "Photo".to_string(), uri: photo.uri.clone(), ..Default::default() };
+        backend.add_printer(&request).await.unwrap();
+        assert!(backend.add_printer(&request).await.is_err());
+        assert!(backend.add_printer(&NewPrinterRequest { name: "bad name".to_string(), ..request.clone() }).await.is_err());
+
+        backend.set_default_printer("Photo").await.unwrap();
+        let defaults = backend.get_printers().await.unwrap().into_iter()
+            .filter(|printer| printer.is_default)
+            .map(|printer| printer.name)
+            .collect::<Vec<String>>();
+        assert_eq!(defaults, vec!["Photo"]);
+
+        backend.delete_printer("Photo").await.unwrap();
+        assert!(backend.get_printer("Photo").await.is_err());
+        assert!(backend.delete_printer("Photo").await.is_err());
+    }
+
+    #[test]
+    fn test_count_impressions() {
+        let pdf = b"%PDF-1.4 1 0 obj << /Type /Pages /Count 3 >> << /Type /Page >> << /Type/Page >> << /Type /Page >>";
+        assert_eq!(count_pages(pdf), 3);
+        assert_eq!(count_pages(b"Plain text"), 1);
+
+        let request = PrintRequest { copies: Some(2), pages: Some("2-".to_string()), ..Default::default() };
+        assert_eq!(count_impressions(&print_job("a.pdf", INKJET_NAME, request), 5).unwrap(), 8);
+        let request = PrintRequest { pages_per_sheet: Some(4), ..Default::default() };
+        assert_eq!(count_impressions(&print_job("a.pdf", INKJET_NAME, request), 5).unwrap(), 2);
+    }
+
+    #[tokio::test]
+    async fn test_simulated_scan() {
+        let backend = fast();
+        let scans_dir = TempDir::new("simulated_scans");
+        let scanner = backend.get_scanners().await.unwrap().remove(0);
+
+        let scan = |filename: &str, format: ScanFormat, color_mode: ColorMode| {
+            let request = ScanRequest {
+                resolution: Some(300),
+                format: Some(format),
+                color_mode: Some(color_mode),
+                page_size: Some(ScanPageSize::A5),
+                filename: Some(filename.to_string()),
+                ..Default::default()
+            };
+            ScanJob::new(scanner.name.clone(), scanner.vendor.clone(), scanner.model.clone(), request, &scans_dir, &SystemSettings::default(), 1)
+        };
+
+        let path = backend.start_scan(&scan("page", ScanFormat::Png, ColorMode::Grayscale), &scans_dir).await.unwrap();
+        let png = std::fs::read(&path).unwrap();
+        assert!(png.starts_with(b"\x89PNG"));
+        // A5 at 100 dpi, the highest resolution the simulation generates
+        assert_eq!(u32::from_be_bytes(png[16..20].try_into().unwrap()), 583);
+        assert_eq!(png[25], 0);
+
+        let path = backend.start_scan(&scan("page", ScanFormat::Pdf, ColorMode::Color), &scans_dir).await.unwrap();
+        assert!(std::fs::read(&path).unwrap().starts_with(b"%PDF"));
+
+        let result = backend.start_scan(&scan("fail", ScanFormat::Jpeg, ColorMode::Color), &scans_dir).await;
+        assert!(result.unwrap_err().starts_with("Scan failed"));
+    }
+}
@@ -0,0 +1,369 @@
+//! The common part of Markdown: headings, paragraphs, lists, quotes, code, rules and inline emphasis
+//!
+//! Tables and HTML are printed as they are written, which reads well enough on paper.
+
+use super::pdf::{Document, Font, Span};
+
+const BODY_SIZE: f64 = 11.0;
+const CODE_SIZE: f64 = 9.5;
+const HEADING_SIZES: [f64; 6] = [20.0, 16.0, 13.5, 12.0, 11.0, 11.0];
+const LIST_INDENT: f64 = 18.0;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Block {
+    Heading(usize, Vec<Span>),
+    Paragraph(Vec<Span>),
+    /// Nesting depth from 0, and the bullet or number
+    ListItem(usize, String, Vec<Span>),
+    Quote(Vec<Span>),
+    /// Fenced code and tables, printed monospaced line by line
+    Preformatted(String),
+    Rule,
+}
+
+/// Render Markdown to a PDF with pages of `width_mm` by `height_mm`
+pub fn to_pdf(text: &str, width_mm: f64, height_mm: f64) -> Vec<u8> {
+    let mut document = Document::new(width_mm, height_mm);
+
+    for block in parse(text) {
+        match block {
+            Block::Heading(level, spans) => {
+                let size = HEADING_SIZES[level - 1];
+                let spans = spans.into_iter().map(|span| Span::new(span.text, span.font.bold())).collect::<Vec<_>>();
+                document.space(size * 0.8);
+                document.paragraph(&spans, size, 0.0, None);
+                if level <= 2 {
+                    document.rule();
+                }
+                document.space(size * 0.2);
+            },
+            Block::Paragraph(spans) => {
+                document.paragraph(&spans, BODY_SIZE, 0.0, None);
+                document.space(BODY_SIZE * 0.6);
+            },
+            Block::ListItem(depth, marker, spans) => {
+                document.paragraph(&spans, BODY_SIZE, LIST_INDENT * (depth + 1) as f64, Some(&marker));
+                document.space(BODY_SIZE * 0.2);
+            },
+            Block::Quote(spans) => {
+                let spans = spans.into_iter().map(|span| Span::new(span.text, span.font.italic())).collect::<Vec<_>>();
+                document.paragraph(&spans, BODY_SIZE, LIST_INDENT, None);
+                document.space(BODY_SIZE * 0.6);
+            },
+            Block::Preformatted(text) => {
+                document.preformatted(&text, CODE_SIZE, CODE_SIZE * 0.4, true);
+                document.space(BODY_SIZE * 0.6);
+            },
+            Block::Rule => document.rule(),
+        }
+    }
+
+    document.finish()
+}
+
+pub fn parse(text: &str) -> Vec<Block> {
+    let mut blocks = Vec::new();
+    // Lines of the paragraph, list item or quote being read, joined once it ends
+    let mut pending: Option<(Block, String)> = None;
+    let mut fence: Option<(&str, String)> = None;
+    let mut table = String::new();
+
+    let flush = |pending: &mut Option<(Block, String)>, blocks: &mut Vec<Block>| {
+        if let Some((block, text)) = pending.take() {
+            let spans = inline(&text);
+            blocks.push(match block {
+                Block::ListItem(depth, marker, _) => Block::ListItem(depth, marker, spans),
+                Block::Quote(_) => Block::Quote(spans),
+                _ => Block::Paragraph(spans),
+            });
+        }
+    };
+
+    for line in text.lines() {
+        if let Some((marker, code)) = fence.as_mut() {
+            if line.trim_start().starts_with(*marker) {
+                blocks.push(Block::Preformatted(std::mem::take(code)));
+                fence = None;
+            } else {
+                code.push_str(line);
+                code.push('\n');
+            }
+            continue;
+        }
+
+        let trimmed = line.trim();
+        if trimmed.starts_with('|') {
+            flush(&mut pending, &mut blocks);
+            table.push_str(trimmed);
+            table.push('\n');
+            continue;
+        }
+        if !table.is_empty() {
+            blocks.push(Block::Preformatted(std::mem::take(&mut table)));
+        }
+
+        if trimmed.is_empty() {
+            flush(&mut pending, &mut blocks);
+            continue;
+        }
+        if let Some(marker) = ["```", "~~~"].into_iter().find(|marker| trimmed.starts_with(marker)) {
+            flush(&mut pending, &mut blocks);
+            fence = Some((marker, String::new()));
+            continue;
+        }
+
+        // Setext headings, the underline turns the paragraph above into a heading
+        if let Some((Block::Paragraph(_), text)) = &pending {
+            let level = if trimmed.chars().all(|c| c == '=') {
+                Some(1)
+            } else if trimmed.len() >= 2 && trimmed.chars().all(|c| c == '-') {
+                Some(2)
+            } else {
+                None
+            };
+            if let Some(level) = level {
+                blocks.push(Block::Heading(level, inline(text)));
+                pending = None;
+                continue;
+            }
+        }
+
+        if is_rule(trimmed) {
+            flush(&mut pending, &mut blocks);
+            blocks.push(Block::Rule);
+            continue;
+        }
+
+        let hashes = trimmed.chars().take_while(|c| *c == '#').count();
+        if (1..=6).contains(&hashes) && (trimmed.len() == hashes || trimmed[hashes..].starts_with(' ')) {
+            flush(&mut pending, &mut blocks);
+            let text = trimmed[hashes..].trim().trim_end_matches('#').trim_end();
+            blocks.push(Block::Heading(hashes, inline(text)));
+            continue;
+        }
+
+        if let Some((marker, text)) = list_item(line) {
+            flush(&mut pending, &mut blocks);
+            let depth = (line.len() - line.trim_start().len()) / 2;
+            pending = Some((Block::ListItem(depth.min(4), marker, Vec::new()), text.to_string()));
+            continue;
+        }
+
+        if let Some(quoted) = trimmed.strip_prefix('>') {
+            if !matches!(pending, Some((Block::Quote(_), _))) {
+                flush(&mut pending, &mut blocks);
+                pending = Some((Block::Quote(Vec::new()), String::new()));
+            }
+            if let Some((_, text)) = pending.as_mut() {
+                if !text.is_empty() {
+                    text.push(' ');
+                }
+                text.push_str(quoted.trim());
+            }
+            continue;
+        }
+
+        // A line continues the paragraph, list item or quote it follows
+        match pending.as_mut() {
+            Some((_, text)) => {
+                text.push(' ');
+                text.push_str(trimmed);
+            },
+            None => pending = Some((Block::Paragraph(Vec::new()), trimmed.to_string())),
+        }
+    }
+
+    flush(&mut pending, &mut blocks);
+    if let Some((_, code)) = fence {
+        blocks.push(Block::Preformatted(code));
+    }
+    if !table.is_empty() {
+        blocks.push(Block::Preformatted(table));
+    }
+
+    blocks
+}
+
+/// `---`, `***` or `___`, spaces allowed between the characters
+fn is_rule(line: &str) -> bool {
+    let chars = line.chars().filter(|c| !c.is_whitespace()).collect::<Vec<_>>();
+    chars.len() >= 3 && ['-', '*', '_'].iter().any(|marker| chars.iter().all(|c| c == marker))
+}
+
+/// Bullet or number of a list item and the text after it
+fn list_item(line: &str) -> Option<(String, &str)> {
+    let trimmed = line.trim_start();
+
+    if let Some(text) = ["- ", "* ", "+ "].iter().find_map(|bullet| trimmed.strip_prefix(bullet)) {
+        return Some(("•".to_string(), text.trim()));
+    }
+
+    let digits = trimmed.chars().take_while(|c| c.is_ascii_digit()).count();
+    let rest = &trimmed[digits..];
+    if (1..=9).contains(&digits) && (rest.starts_with(". ") || rest.starts_with(") ")) {
+        return Some((format!("{}.", &trimmed[..digits]), rest[2..].trim()));
+    }
+
+    None
+}
+
+/// Spans of a line with `**bold**`, `*italic*`, `` `code` ``, links and images
+///
+/// A marker without a closing one is kept as text, and `_` only counts at the edge of a word so
+/// names like `snake_case` survive. Links print their address after the text.
+pub fn inline(text: &str) -> Vec<Span> {
+    let chars = text.chars().collect::<Vec<_>>();
+    let mut spans: Vec<Span> = Vec::new();
+    let (mut bold, mut italic) = (false, false);
+    let mut i = 0;
+
+    let font = |bold: bool, italic: bool| match (bold, italic) {
+        (true, true) => Font::BoldItalic,
+        (true, false) => Font::Bold,
+        (false, true) => Font::Italic,
+        (false, false) => Font::Regular,
+    };
+    let push = |spans: &mut Vec<Span>, text: &str, font: Font| match spans.last_mut() {
+        Some(last) if last.font == font => last.text.push_str(text),
+        _ => spans.push(Span::new(text, font)),
+    };
+    let find = |from: usize, pattern: &[char]| (from..chars.len()).find(|&j| chars[j..].starts_with(pattern));
+
+    while i < chars.len() {
+        let c = chars[i];
+        let current = font(bold, italic);
+
+        match c {
+            '\\' if chars.get(i + 1).is_some_and(|next| next.is_ascii_punctuation()) => {
+                push(&mut spans, &chars[i + 1].to_string(), current);
+                i += 2;
+                continue;
+            },
+            '`' => {
+                if let Some(end) = find(i + 1, &['`']) {
+                    push(&mut spans, &chars[i + 1..end].iter().collect::<String>(), Font::Mono);
+                    i = end + 1;
+                    continue;
+                }
+            },
+            '!' | '[' => {
+                let open = if c == '!' { i + 1 } else { i };
+                if chars.get(open) == Some(&'[')
+                    && let Some(close) = find(open + 1, &[']', '('])
+                    && let Some(end) = find(close + 2, &[')']) {
+                    let label = chars[open + 1..close].iter().collect::<String>();
+                    let target = chars[close + 2..end].iter().collect::<String>();
+                    push(&mut spans, &label, current);
+                    // Images only keep their description, anchors within the document are left out
+                    if c == '[' && !target.is_empty() && !target.starts_with('#') && target != label {
+                        push(&mut spans, &format!(" ({})", target), current);
+                    }
+                    i = end + 1;
+                    continue;
+                }
+            },
+            '*' | '_' => {
+                let double = chars.get(i + 1) == Some(&c);
+                let marker = if double { vec![c, c] } else { vec![c] };
+                let inside_word = c == '_'
+                    && i > 0 && chars[i - 1].is_alphanumeric()
+                    && chars.get(i + marker.len()).is_some_and(|next| next.is_alphanumeric());
+                let opening = if double { !bold } else { !italic };
+                // An opening marker sits against the text it emphasises, `2 * 3` is not emphasis
+                let closed = match opening {
+                    true => chars.get(i + marker.len()).is_some_and(|next| !next.is_whitespace())
+                        && find(i + marker.len(), &marker).is_some(),
+                    false => true,
+                };
+
+                if !inside_word && closed {
+                    match double {
+                        true => bold = !bold,
+                        false => italic = !italic,
+                    }
+                    i += marker.len();
+                    continue;
+                }
+            },
+            _ => {},
+        }
+
+        push(&mut spans, &c.to_string(), current);
+        i += 1;
+    }
+
+    spans
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_inline() {
+        assert_eq!(inline("Plain **bold**, *italic* and `co*de`"), vec![
+            Span::new("Plain ", Font::Regular),
+            Span::new("bold", Font::Bold),
+            Span::new(", ", Font::Regular),
+            Span::new("italic", Font::Italic),
+            Span::new(" and ", Font::Regular),
+            Span::new("co*de", Font::Mono),
+        ]);
+        assert_eq!(inline("See [the docs](https://example.com) ![logo](logo.png)"), vec![
+            Span::new("See the docs (https://example.com) logo", Font::Regular),
+        ]);
+        assert_eq!(inline("snake_case_name, 2 * 3 and \\*escaped\\*"), vec![
+            Span::new("snake_case_name, 2 * 3 and *escaped*", Font::Regular),
+        ]);
+        assert_eq!(inline("__bold _and italic___"), vec![
+            Span::new("bold ", Font::Bold),
+            Span::new("and italic", Font::BoldItalic),
+        ]);
+    }
+
+    #[test]
+    fn test_parse() {
+        let markdown = "\
+Title
+=====
+
+Intro line one
+continues here.
+
+## Steps ##
+
+1. First
+   still first
+2. Second
+  - Nested
+
+> Quoted
+> text
+
+```rust
+fn main() {}
+```
+
+| Key | Value |
+|-----|-------|
+
+***
+";
+        assert_eq!(parse(markdown), vec![
+            Block::Heading(1, vec![Span::new("Title", Font::Regular)]),
+            Block::Paragraph(vec![Span::new("Intro line one continues here.", Font::Regular)]),
+            Block::Heading(2, vec![Span::new("Steps", Font::Regular)]),
+            Block::ListItem(0, "1.".to_string(), vec![Span::new("First still first", Font::Regular)]),
+            Block::ListItem(0, "2.".to_string(), vec![Span::new("Second", Font::Regular)]),
+            Block::ListItem(1, "•".to_string(), vec![Span::new("Nested", Font::Regular)]),
+            Block::Quote(vec![Span::new("Quoted text", Font::Regular)]),
+            Block::Preformatted("fn main() {}\n".to_string()),
+            Block::Preformatted("| Key | Value |\n|-----|-------|\n".to_string()),
+            Block::Rule,
+        ]);
+
+        let pdf = to_pdf(markdown, 210.0, 297.0);
+        assert!(String::from_utf8_lossy(&pdf).contains("/BaseFont /Helvetica-Bold"));
+    }
+}
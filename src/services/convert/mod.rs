@@ -0,0 +1,145 @@
+//! Documents CUPS cannot print reliably on its own, turned into PDF before they are submitted
+
+mod markdown;
+mod pdf;
+
+use std::ffi::OsStr;
+use std::path::Path;
+use std::time::Duration;
+use tokio::process::Command;
+use crate::services::command_exists;
+use pdf::Document;
+
+/// Time LibreOffice gets for one document, the first run on a Pi also sets up its profile
+const LIBREOFFICE_TIMEOUT: Duration = Duration::from_secs(180);
+
+const TEXT_SIZE: f64 = 10.0;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Conversion {
+    /// Word, OpenDocument and RTF documents, through headless LibreOffice
+    Office,
+    /// Plain text, monospaced
+    Text,
+    Markdown,
+}
+
+impl Conversion {
+    /// How a stored upload has to be converted, `None` when CUPS prints it as it is
+    pub fn for_file(filename: &str) -> Option<Self> {
+        let extension = Path::new(filename).extension().and_then(OsStr::to_str)?.to_lowercase();
+
+        match extension.as_str() {
+            "doc" | "docx" | "odt" | "rtf" => Some(Conversion::Office),
+            "txt" | "text" => Some(Conversion::Text),
+            "md" | "markdown" => Some(Conversion::Markdown),
+            _ => None,
+        }
+    }
+}
+
+/// Convert `input` to a PDF at `output`, text and Markdown are laid out on pages of `page_size_mm`
+pub async fn convert_to_pdf(conversion: Conversion, input: &Path, output: &Path, page_size_mm: (f64, f64)) -> Result<(), String> {
+    if conversion == Conversion::Office {
+        return libreoffice_to_pdf(input, output).await;
+    }
+
+    let bytes = tokio::fs::read(input)
+        .await
+        .map_err(|e| format!("Failed to read {}: {}", input.display(), e))?;
+    let text = String::from_utf8_lossy(bytes.strip_prefix(b"\xef\xbb\xbf").unwrap_or(&bytes)).into_owned();
+
+    let (width, height) = page_size_mm;
+    let pdf = tokio::task::spawn_blocking(move || match conversion {
+        Conversion::Markdown => markdown::to_pdf(&text, width, height),
+        _ => text_to_pdf(&text, width, height),
+    })
+        .await
+        .map_err(|e| format!("Text layout failed: {}", e))?;
+
+    tokio::fs::write(output, pdf)
+        .await
+        .map_err(|e| format!("Failed to write {}: {}", output.display(), e))
+}
+
+fn text_to_pdf(text: &str, width_mm: f64, height_mm: f64) -> Vec<u8> {
+    let mut document = Document::new(width_mm, height_mm);
+    document.preformatted(text, TEXT_SIZE, 0.0, false);
+    document.finish()
+}
+
+async fn libreoffice_to_pdf(input: &Path, output: &Path) -> Result<(), String> {
+    let program = ["soffice", "libreoffice"].into_iter()
+        .find(|program| command_exists(program))
+        .ok_or("LibreOffice is not installed, it is needed to print office documents")?;
+    let out_dir = output.parent().ok_or("Output file has no directory")?;
+    // A profile of its own in the uploads directory, so neither a LibreOffice the server user has open
+    // nor another local user can take the conversion over
+    let profile = std::path::absolute(out_dir.join(".libreoffice"))
+        .map_err(|e| format!("Failed to locate the LibreOffice profile: {}", e))?;
+
+    let run = Command::new(program)
+        .arg(format!("-env:UserInstallation=file://{}", profile.display()))
+        .args(["--headless", "--norestore", "--convert-to", "pdf", "--outdir"])
+        .arg(out_dir)
+        .arg(input)
+        .kill_on_drop(true)
+        .output();
+    let result = tokio::time::timeout(LIBREOFFICE_TIMEOUT, run)
+        .await
+        .map_err(|_| format!("LibreOffice did not finish within {} seconds", LIBREOFFICE_TIMEOUT.as_secs()))?
+        .map_err(|e| format!("Failed to execute {}: {}", program, e))?;
+
+    // It exits successfully even when the document could not be loaded, the PDF tells
+    let converted = out_dir.join(input.file_stem().unwrap_or_default()).with_extension("pdf");
+    if !result.status.success() || !converted.is_file() {
+        let message = [&result.stderr, &result.stdout].iter()
+            .map(|output| String::from_utf8_lossy(output).trim().to_string())
+            .find(|output| !output.is_empty())
+            .unwrap_or_else(|| format!("exit status {}", result.status));
+        return Err(format!("LibreOffice could not convert the document: {}", message));
+    }
+
+    if converted != output {
+        tokio::fs::rename(&converted, output)
+            .await
+            .map_err(|e| format!("Failed to move the converted document: {}", e))?;
+    }
+
+    Ok(())
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::TempDir;
+
+    #[test]
+    fn test_conversion_for_file() {
+        assert_eq!(Conversion::for_file("0b6c.DOCX"), Some(Conversion::Office));
+        assert_eq!(Conversion::for_file("0b6c.odt"), Some(Conversion::Office));
+        assert_eq!(Conversion::for_file("0b6c.txt"), Some(Conversion::Text));
+        assert_eq!(Conversion::for_file("0b6c.md"), Some(Conversion::Markdown));
+        assert_eq!(Conversion::for_file("0b6c.pdf"), None);
+        assert_eq!(Conversion::for_file("0b6c"), None);
+    }
+
+    #[tokio::test]
+    async fn test_convert_text_to_pdf() {
+        let dir = TempDir::new("convert");
+        let (input, output) = (dir.join("notes.txt"), dir.join("notes.pdf"));
+        std::fs::write(&input, "\u{feff}Shopping list\n\t- bread\n\x0cSecond page\n").unwrap();
+
+        // Letter, 215.9 by 279.4 mm
+        convert_to_pdf(Conversion::Text, &input, &output, (215.9, 279.4)).await.unwrap();
+        let pdf = std::fs::read(&output).unwrap();
+        let text = String::from_utf8_lossy(&pdf);
+        assert!(text.starts_with("%PDF-1.4"));
+        assert!(text.contains("/Count 2"));
+        assert!(text.contains("/MediaBox [0 0 612.00 792.00]"));
+
+        let missing = convert_to_pdf(Conversion::Markdown, &dir.join("missing.md"), &output, (210.0, 297.0)).await;
+        assert!(missing.unwrap_err().starts_with("Failed to read"));
+    }
+}
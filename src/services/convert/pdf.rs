@@ -0,0 +1,498 @@
+//! Text laid out on PDF pages in the standard fonts, which every PDF reader and CUPS filter has built in
+
+use std::fmt::Write as _;
+use std::io::Write;
+use flate2::write::ZlibEncoder;
+use flate2::Compression;
+
+const POINTS_PER_MM: f64 = 72.0 / 25.4;
+const MARGIN_MM: f64 = 20.0;
+const LINE_SPACING: f64 = 1.3;
+
+/// Helvetica advance widths of ' ' to '~', in thousandths of the font size
+const HELVETICA_WIDTHS: [u16; 95] = [
+    278, 278, 355, 556, 556, 889, 667, 191, 333, 333, 389, 584, 278, 333, 278, 278,
+    556, 556, 556, 556, 556, 556, 556, 556, 556, 556, 278, 278, 584, 584, 584, 556,
+    1015, 667, 667, 722, 722, 667, 611, 778, 722, 278, 500, 667, 556, 833, 722, 778,
+    667, 778, 722, 667, 611, 722, 667, 944, 667, 667, 611, 278, 278, 278, 469, 556,
+    333, 556, 556, 500, 556, 556, 278, 556, 556, 222, 222, 500, 222, 833, 556, 556,
+    556, 556, 333, 500, 278, 556, 500, 722, 500, 500, 500, 334, 260, 334, 584,
+];
+
+const HELVETICA_BOLD_WIDTHS: [u16; 95] = [
+    278, 333, 474, 556, 556, 889, 722, 238, 333, 333, 389, 584, 278, 333, 278, 278,
+    556, 556, 556, 556, 556, 556, 556, 556, 556, 556, 333, 333, 584, 584, 584, 611,
+    975, 722, 722, 722, 722, 667, 611, 778, 722, 278, 556, 722, 611, 833, 722, 778,
+    667, 778, 722, 667, 611, 722, 667, 944, 667, 667, 611, 333, 278, 333, 584, 556,
+    333, 556, 611, 556, 611, 556, 333, 611, 611, 278, 278, 556, 278, 889, 611, 611,
+    611, 611, 389, 556, 333, 611, 556, 778, 556, 556, 500, 389, 280, 389, 584,
+];
+
+/// Glyphs given the codes 128 to 159, which WinAnsiEncoding mostly spends on rarely used characters,
+/// so Polish and some Czech text prints. The widths are Helvetica's, used for the bold fonts too.
+const EXTRA_GLYPHS: [(char, &str, u16); 32] = [
+    ('€', "Euro", 556), ('„', "quotedblbase", 333), ('…', "ellipsis", 1000), ('‘', "quoteleft", 222),
+    ('’', "quoteright", 222), ('“', "quotedblleft", 333), ('”', "quotedblright", 333), ('•', "bullet", 350),
+    ('–', "endash", 556), ('—', "emdash", 1000), ('™', "trademark", 1000), ('Ą', "Aogonek", 667),
+    ('ą', "aogonek", 556), ('Ć', "Cacute", 722), ('ć', "cacute", 500), ('Ę', "Eogonek", 667),
+    ('ę', "eogonek", 556), ('Ł', "Lslash", 556), ('ł', "lslash", 222), ('Ń', "Nacute", 722),
+    ('ń', "nacute", 556), ('Ś', "Sacute", 667), ('ś', "sacute", 500), ('Ź', "Zacute", 611),
+    ('ź', "zacute", 500), ('Ż', "Zdotaccent", 611), ('ż', "zdotaccent", 500), ('Č', "Ccaron", 722),
+    ('č', "ccaron", 500), ('Š', "Scaron", 667), ('š', "scaron", 500), ('Ž', "Zcaron", 611),
+];
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Font {
+    Regular,
+    Bold,
+    Italic,
+    BoldItalic,
+    Mono,
+    MonoBold,
+}
+
+impl Font {
+    const ALL: [Font; 6] = [Font::Regular, Font::Bold, Font::Italic, Font::BoldItalic, Font::Mono, Font::MonoBold];
+
+    fn base_font(self) -> &'static str {
+        match self {
+            Font::Regular => "Helvetica",
+            Font::Bold => "Helvetica-Bold",
+            Font::Italic => "Helvetica-Oblique",
+            Font::BoldItalic => "Helvetica-BoldOblique",
+            Font::Mono => "Courier",
+            Font::MonoBold => "Courier-Bold",
+        }
+    }
+
+    /// Resource number, the font is `/F{number}` in content streams
+    fn number(self) -> usize {
+        Font::ALL.iter().position(|font| *font == self).unwrap_or(0) + 1
+    }
+
+    pub fn bold(self) -> Self {
+        match self {
+            Font::Regular => Font::Bold,
+            Font::Italic => Font::BoldItalic,
+            Font::Mono => Font::MonoBold,
+            other => other,
+        }
+    }
+
+    pub fn italic(self) -> Self {
+        match self {
+            Font::Regular => Font::Italic,
+            Font::Bold => Font::BoldItalic,
+            other => other,
+        }
+    }
+
+    fn is_mono(self) -> bool {
+        matches!(self, Font::Mono | Font::MonoBold)
+    }
+
+    /// Width of `c` at a font size of 1
+    fn char_width(self, c: char) -> f64 {
+        let units = match c {
+            _ if self.is_mono() => 600,
+            ' '..='~' if matches!(self, Font::Bold | Font::BoldItalic) => HELVETICA_BOLD_WIDTHS[c as usize - 32],
+            ' '..='~' => HELVETICA_WIDTHS[c as usize - 32],
+            _ => EXTRA_GLYPHS.iter().find(|(glyph, _, _)| *glyph == c).map(|(_, _, width)| *width).unwrap_or(556),
+        };
+        units as f64 / 1000.0
+    }
+
+    pub fn text_width(self, text: &str, size: f64) -> f64 {
+        text.chars().map(|c| self.char_width(c)).sum::<f64>() * size
+    }
+}
+
+/// Run of text in one font
+#[derive(Debug, Clone, PartialEq)]
+pub struct Span {
+    pub text: String,
+    pub font: Font,
+}
+
+impl Span {
+    pub fn new(text: impl Into<String>, font: Font) -> Self {
+        Self { text: text.into(), font }
+    }
+}
+
+/// Pages filled from the top, a new one is started whenever the next line does not fit
+pub struct Document {
+    width: f64,
+    height: f64,
+    margin: f64,
+    pages: Vec<String>,
+    content: String,
+    /// Top of the next line, in points from the bottom of the page
+    y: f64,
+}
+
+impl Document {
+    pub fn new(width_mm: f64, height_mm: f64) -> Self {
+        let (width, height) = (width_mm * POINTS_PER_MM, height_mm * POINTS_PER_MM);
+        // Small pages such as postcards get a smaller margin
+        let margin = (MARGIN_MM * POINTS_PER_MM).min(width.min(height) / 8.0);
+
+        Self { width, height, margin, pages: Vec::new(), content: String::new(), y: height - margin }
+    }
+
+    /// Width between the margins, in points
+    pub fn text_width(&self) -> f64 {
+        self.width - 2.0 * self.margin
+    }
+
+    fn at_top(&self) -> bool {
+        self.y >= self.height - self.margin
+    }
+
+    pub fn page_break(&mut self) {
+        self.pages.push(std::mem::take(&mut self.content));
+        self.y = self.height - self.margin;
+    }
+
+    /// Start a new page unless `height` more points fit on this one
+    fn ensure(&mut self, height: f64) {
+        if self.y - height < self.margin && !self.at_top() {
+            self.page_break();
+        }
+    }
+
+    /// Vertical space, left out at the top of a page
+    pub fn space(&mut self, height: f64) {
+        if !self.at_top() {
+            self.y -= height;
+        }
+    }
+
+    /// Spans wrapped between spaces, `marker` (a bullet or list number) hangs left of the first line
+    pub fn paragraph(&mut self, spans: &[Span], size: f64, indent: f64, marker: Option<&str>) {
+        let available = self.text_width() - indent;
+        let mut lines = Vec::new();
+        let mut line: Vec<Span> = Vec::new();
+        let mut line_width = 0.0;
+
+        for word in split_words(spans) {
+            let width = word.iter().map(|span| span.font.text_width(&span.text, size)).sum::<f64>();
+            let space_font = line.last().map(|span| span.font).unwrap_or(Font::Regular);
+            let space = space_font.text_width(" ", size);
+
+            if !line.is_empty() && line_width + space + width > available {
+                lines.push(std::mem::take(&mut line));
+                line_width = 0.0;
+            }
+            if !line.is_empty() {
+                push_text(&mut line, " ", space_font);
+                line_width += space;
+            }
+
+            for span in word {
+                for c in span.text.chars() {
+                    let char_width = span.font.char_width(c) * size;
+                    // A word longer than the line is broken wherever it runs out of room
+                    if line_width + char_width > available && !line.is_empty() {
+                        lines.push(std::mem::take(&mut line));
+                        line_width = 0.0;
+                    }
+                    push_text(&mut line, c.encode_utf8(&mut [0; 4]), span.font);
+                    line_width += char_width;
+                }
+            }
+        }
+        if !line.is_empty() {
+            lines.push(line);
+        }
+
+        let x = self.margin + indent;
+        for (i, line) in lines.iter().enumerate() {
+            self.ensure(size * LINE_SPACING);
+            let baseline = self.y - size;
+
+            if let (0, Some(marker)) = (i, marker) {
+                let marker_x = x - Font::Regular.text_width(marker, size) - size * 0.4;
+                self.draw_text(marker_x, baseline, &[Span::new(marker, Font::Regular)], size);
+            }
+            self.draw_text(x, baseline, line, size);
+            self.y -= size * LINE_SPACING;
+        }
+    }
+
+    /// Lines kept as they are in a monospaced font, cut where they reach the margin
+    ///
+    /// Tabs stop every 8 columns and a form feed starts a new page. `shaded` puts a grey background
+    /// behind the lines, for code blocks.
+    pub fn preformatted(&mut self, text: &str, size: f64, indent: f64, shaded: bool) {
+        let available = self.text_width() - indent;
+        let columns = ((available / (Font::Mono.char_width(' ') * size)) as usize).max(1);
+        let x = self.margin + indent;
+
+        for (page, text) in text.split('\x0c').enumerate() {
+            if page > 0 {
+                self.page_break();
+            }
+            let text = text.strip_suffix('\n').unwrap_or(text);
+            if text.is_empty() && page > 0 {
+                continue;
+            }
+
+            for line in text.split('\n') {
+                let line = expand_tabs(line.strip_suffix('\r').unwrap_or(line));
+                let chars = line.chars().filter(|c| !c.is_control()).collect::<Vec<_>>();
+
+                for chunk in chars.chunks(columns).map(|chunk| chunk.iter().collect::<String>()).chain(chars.is_empty().then(String::new)) {
+                    self.ensure(size * LINE_SPACING);
+                    let baseline = self.y - size;
+
+                    if shaded {
+                        let _ = writeln!(
+                            self.content,
+                            "0.94 g {:.2} {:.2} {:.2} {:.2} re f 0 g",
+                            x - size * 0.4, self.y - size * LINE_SPACING, available + size * 0.8, size * LINE_SPACING
+                        );
+                    }
+                    self.draw_text(x, baseline, &[Span::new(chunk, Font::Mono)], size);
+                    self.y -= size * LINE_SPACING;
+                }
+            }
+        }
+    }
+
+    /// Thin grey line across the text width
+    pub fn rule(&mut self) {
+        self.ensure(8.0);
+        self.y -= 4.0;
+        let _ = writeln!(
+            self.content,
+            "0.75 G 0.6 w {:.2} {:.2} m {:.2} {:.2} l S 0 G",
+            self.margin, self.y, self.width - self.margin, self.y
+        );
+        self.y -= 4.0;
+    }
+
+    fn draw_text(&mut self, x: f64, baseline: f64, spans: &[Span], size: f64) {
+        let _ = write!(self.content, "BT {:.2} {:.2} Td", x, baseline);
+        for span in spans {
+            let _ = write!(self.content, " /F{} {} Tf ({}) Tj", span.font.number(), size, escape(&span.text));
+        }
+        self.content.push_str(" ET\n");
+    }
+
+    pub fn finish(mut self) -> Vec<u8> {
+        if !self.content.is_empty() || self.pages.is_empty() {
+            self.pages.push(std::mem::take(&mut self.content));
+        }
+
+        // Catalog, page tree and encoding, then the fonts, then a page and its contents for each page
+        let first_font = 4;
+        let first_page = first_font + Font::ALL.len();
+        let page_ids = (0..self.pages.len()).map(|i| first_page + 2 * i).collect::<Vec<_>>();
+
+        let mut pdf = b"%PDF-1.4\n%\xe2\xe3\xcf\xd3\n".to_vec();
+        let mut offsets = Vec::new();
+        let mut object = |pdf: &mut Vec<u8>, body: &[u8]| {
+            offsets.push(pdf.len());
+            pdf.extend_from_slice(format!("{} 0 obj\n", offsets.len()).as_bytes());
+            pdf.extend_from_slice(body);
+            pdf.extend_from_slice(b"\nendobj\n");
+        };
+
+        object(&mut pdf, b"<< /Type /Catalog /Pages 2 0 R >>");
+        let kids = page_ids.iter().map(|id| format!("{} 0 R", id)).collect::<Vec<_>>().join(" ");
+        object(&mut pdf, format!("<< /Type /Pages /Kids [{}] /Count {} >>", kids, page_ids.len()).as_bytes());
+        let differences = EXTRA_GLYPHS.iter().map(|(_, name, _)| format!("/{}", name)).collect::<Vec<_>>().join(" ");
+        object(&mut pdf, format!("<< /Type /Encoding /BaseEncoding /WinAnsiEncoding /Differences [128 {}] >>", differences).as_bytes());
+        for font in Font::ALL {
+            object(&mut pdf, format!("<< /Type /Font /Subtype /Type1 /BaseFont /{} /Encoding 3 0 R >>", font.base_font()).as_bytes());
+        }
+
+        let fonts = Font::ALL.iter().enumerate()
+            .map(|(i, font)| format!("/F{} {} 0 R", font.number(), first_font + i))
+            .collect::<Vec<_>>()
+            .join(" ");
+        for (page, id) in self.pages.iter().zip(&page_ids) {
+            object(&mut pdf, format!(
+                "<< /Type /Page /Parent 2 0 R /MediaBox [0 0 {:.2} {:.2}] /Resources << /Font << {} >> >> /Contents {} 0 R >>",
+                self.width, self.height, fonts, id + 1
+            ).as_bytes());
+
+            let mut encoder = ZlibEncoder::new(Vec::new(), Compression::default());
+            let _ = encoder.write_all(page.as_bytes());
+            let stream = encoder.finish().unwrap_or_default();
+            let mut contents = format!("<< /Length {} /Filter /FlateDecode >>\nstream\n", stream.len()).into_bytes();
+            contents.extend_from_slice(&stream);
+            contents.extend_from_slice(b"\nendstream");
+            object(&mut pdf, &contents);
+        }
+
+        let xref_offset = pdf.len();
+        pdf.extend_from_slice(format!("xref\n0 {}\n0000000000 65535 f \n", offsets.len() + 1).as_bytes());
+        for offset in &offsets {
+            pdf.extend_from_slice(format!("{:010} 00000 n \n", offset).as_bytes());
+        }
+        pdf.extend_from_slice(format!(
+            "trailer\n<< /Size {} /Root 1 0 R >>\nstartxref\n{}\n%%EOF\n",
+            offsets.len() + 1, xref_offset
+        ).as_bytes());
+
+        pdf
+    }
+}
+
+/// Words of the spans, a word can change font halfway, e.g. `**bold**,`
+fn split_words(spans: &[Span]) -> Vec<Vec<Span>> {
+    let mut words = Vec::new();
+    let mut word: Vec<Span> = Vec::new();
+
+    for span in spans {
+        for c in span.text.chars() {
+            if c.is_whitespace() {
+                if !word.is_empty() {
+                    words.push(std::mem::take(&mut word));
+                }
+            } else {
+                push_text(&mut word, c.encode_utf8(&mut [0; 4]), span.font);
+            }
+        }
+    }
+    if !word.is_empty() {
+        words.push(word);
+    }
+
+    words
+}
+
+fn push_text(spans: &mut Vec<Span>, text: &str, font: Font) {
+    match spans.last_mut() {
+        Some(last) if last.font == font => last.text.push_str(text),
+        _ => spans.push(Span::new(text, font)),
+    }
+}
+
+fn expand_tabs(line: &str) -> String {
+    let mut expanded = String::with_capacity(line.len());
+    for c in line.chars() {
+        match c {
+            '\t' => {
+                let column = expanded.chars().count();
+                expanded.extend(std::iter::repeat_n(' ', 8 - column % 8));
+            },
+            c => expanded.push(c),
+        }
+    }
+    expanded
+}
+
+/// Byte of `c` in the fonts' encoding, characters it lacks print as `?`
+fn encode(c: char) -> u8 {
+    match c {
+        ' '..='~' | '\u{a0}'..='\u{ff}' => c as u8,
+        _ => EXTRA_GLYPHS.iter()
+            .position(|(glyph, _, _)| *glyph == c)
+            .map(|i| 128 + i as u8)
+            .unwrap_or(b'?'),
+    }
+}
+
+/// Literal string contents, bytes outside printable ASCII written as octal escapes
+fn escape(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for byte in text.chars().map(encode) {
+        match byte {
+            b'(' | b')' | b'\\' => {
+                escaped.push('\\');
+                escaped.push(byte as char);
+            },
+            b' '..=b'~' => escaped.push(byte as char),
+            _ => { let _ = write!(escaped, "\\{:03o}", byte); },
+        }
+    }
+    escaped
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use flate2::read::ZlibDecoder;
+    use std::io::Read;
+
+    /// Decompressed content streams of every page
+    fn page_contents(pdf: &[u8]) -> Vec<String> {
+        let mut pages = Vec::new();
+        let mut rest = pdf;
+        while let Some(start) = rest.windows(7).position(|window| window == b"stream\n") {
+            let stream = &rest[start + 7..];
+            let end = stream.windows(10).position(|window| window == b"\nendstream").unwrap();
+            let mut content = String::new();
+            ZlibDecoder::new(&stream[..end]).read_to_string(&mut content).unwrap();
+            pages.push(content);
+            rest = &stream[end + 10..];
+        }
+        pages
+    }
+
+    #[test]
+    fn test_escape() {
+        assert_eq!(escape("f(x) = a\\b"), "f\\(x\\) = a\\\\b");
+        assert_eq!(escape("Zażółć – €"), "Za\\232\\363\\222\\216 \\210 \\200");
+        assert_eq!(escape("日本"), "??");
+        assert_eq!(expand_tabs("a\tb\t\tc"), "a       b               c");
+    }
+
+    #[test]
+    fn test_paragraph_wrapping() {
+        let mut document = Document::new(210.0, 297.0);
+        let text = "Hello world ".repeat(40);
+        document.paragraph(&[Span::new(text.clone(), Font::Regular)], 10.0, 0.0, Some("•"));
+
+        let contents = &document.content;
+        let lines = contents.lines().filter(|line| line.contains("Hello")).collect::<Vec<_>>();
+        // The bullet hangs in the margin on its own
+        assert!(contents.lines().next().unwrap().contains("(\\207) Tj"));
+        assert!(lines.len() > 1);
+        for line in &lines {
+            let text = line.split('(').nth(1).unwrap().split(") Tj").next().unwrap();
+            assert!(Font::Regular.text_width(text, 10.0) <= document.text_width());
+        }
+        let words = lines.iter().map(|line| line.matches("Hello").count()).sum::<usize>();
+        assert_eq!(words, 40);
+
+        // Fonts change within a word without adding a space
+        let spans = [Span::new("plain ", Font::Regular), Span::new("bold", Font::Bold), Span::new(", on", Font::Regular)];
+        assert_eq!(split_words(&spans), vec![
+            vec![Span::new("plain", Font::Regular)],
+            vec![Span::new("bold", Font::Bold), Span::new(",", Font::Regular)],
+            vec![Span::new("on", Font::Regular)],
+        ]);
+    }
+
+    #[test]
+    fn test_preformatted_pages() {
+        let mut document = Document::new(210.0, 297.0);
+        let long_line = "x".repeat(100);
+        document.preformatted(&format!("first\tpage\n{}\n\x0csecond page\n", long_line), 10.0, 0.0, false);
+        let pdf = document.finish();
+
+        let text = String::from_utf8_lossy(&pdf);
+        assert!(text.starts_with("%PDF-1.4"));
+        assert!(text.contains("/Count 2"));
+        assert!(text.contains("/MediaBox [0 0 595.28 841.89]"));
+        let xref_offset = text.rsplit("startxref\n").next().unwrap().lines().next().unwrap().parse::<usize>().unwrap();
+        assert!(pdf[xref_offset..].starts_with(b"xref\n0 14\n"));
+
+        let pages = page_contents(&pdf);
+        assert_eq!(pages.len(), 2);
+        assert!(pages[0].contains("(first   page) Tj"));
+        // 80 columns of Courier fit between 20 mm margins on A4
+        assert!(pages[0].contains(&format!("({}) Tj", "x".repeat(80))));
+        assert!(pages[0].contains(&format!("({}) Tj", "x".repeat(20))));
+        assert!(pages[1].contains("(second page) Tj"));
+
+        let mut document = Document::new(210.0, 297.0);
+        document.preformatted(&"line\n".repeat(100), 10.0, 0.0, true);
+        assert_eq!(page_contents(&document.finish()).len(), 2);
+    }
+}
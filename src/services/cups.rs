@@ -1,344 +1,1147 @@
+use std::path::{Component, Path};
+use std::time::Duration;
 use tokio::process::Command;
-use crate::models::{Printer, PrintJob};
-use crate::services::command_exists;
+use crate::models::{DiscoveredPrinter, MarkerLevel, NewPrinterRequest, Printer, PrinterCapabilities, PrintJob, PrintOrientation, PrintQuality, PrintScaling, PAGES_PER_SHEET};
+use crate::services::backend::PrintBackend;
+use crate::services::ipp::{IppAttribute, IppAttributeGroup, IppClient, IppValue, Operation, DEFAULT_CUPS_URL};
 use crate::capitalize;
 
-pub struct CupsService;
+const PRINTER_ATTRIBUTES: [&str; 7] = [
+    "printer-name",
+    "printer-make-and-model",
+    "printer-info",
+    "printer-location",
+    "printer-state",
+    "printer-state-message",
+    "printer-is-accepting-jobs",
+];
+
+const CAPABILITY_ATTRIBUTES: [&str; 11] = [
+    "media-supported",
+    "media-default",
+    "sides-supported",
+    "print-color-mode-supported",
+    "printer-resolution-supported",
+    "media-source-supported",
+    "media-type-supported",
+    "print-quality-supported",
+    "number-up-supported",
+    "copies-supported",
+    "page-ranges-supported",
+];
+
+const MARKER_ATTRIBUTES: [&str; 5] = [
+    "marker-names",
+    "marker-colors",
+    "marker-types",
+    "marker-levels",
+    "marker-low-levels",
+];
+
+/// How long lpinfo waits for the network backends, it probes them all in parallel
+const LPINFO_TIMEOUT: Duration = Duration::from_secs(10);
+
+const JOB_STATUS_ATTRIBUTES: [&str; 4] = ["job-state", "job-state-reasons", "job-impressions-completed", "job-media-sheets-completed"];
+
+/// Where cupsd logs the pages of every job, see PageLogFormat in cupsd.conf
+const PAGE_LOG: &str = "/var/log/cups/page_log";
+
+/// CUPS backends that reach a printer, as found by lpinfo and DNS-SD, `file` and the like are left out
+const DEVICE_URI_SCHEMES: [&str; 10] = ["usb", "ipp", "ipps", "http", "https", "socket", "lpd", "dnssd", "smb", "hp"];
+
+/// Directories PPD files may be given from, anything else on the server stays unreadable to lpadmin
+const PPD_DIRS: [&str; 3] = ["/usr/share/ppd/", "/usr/share/cups/model/", "/usr/local/share/ppd/"];
+
+/// IPP job-state of a CUPS job
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CupsJobState {
+    Pending,
+    /// pending-held, waiting for a release or a hold-until time
+    Held,
+    Processing,
+    /// processing-stopped, usually the printer is stopped or out of paper
+    Stopped,
+    Canceled,
+    Aborted,
+    Completed,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct CupsJobStatus {
+    pub state: CupsJobState,
+    /// job-state-reasons keywords, without the "none" placeholder
+    pub reasons: Vec<String>,
+    pub impressions_completed: Option<i32>,
+    pub media_sheets_completed: Option<i32>,
+}
+
+pub struct CupsService {
+    ipp: IppClient,
+}
 
 impl CupsService {
     pub fn new() -> Self {
-        Self
+        Self { ipp: IppClient::new(DEFAULT_CUPS_URL) }
     }
 
+    /// Talk to a cupsd other than the local one, e.g. the IPP test stub
+    #[cfg(test)]
+    pub fn with_url(url: &str) -> Self {
+        Self { ipp: IppClient::new(url) }
+    }
+}
+
+impl PrintBackend for CupsService {
     /// Check if CUPS is available and running
-    pub async fn is_available(&self) -> bool {
-        command_exists("lpstat")
+    async fn is_available(&self) -> bool {
+        self.ipp.ping().await
     }
 
     /// Get list of available printers
-    pub async fn get_printers(&self) -> Result<Vec<Printer>, String> {
-        let output = Command::new("lpstat")
-            .args(["-p", "-d"])
-            .output()
-            .await
-            .map_err(|e| format!("Failed to execute lpstat: {}", e))?;
+    async fn get_printers(&self) -> Result<Vec<Printer>, String> {
+        let default_printer = self.ipp.get_default_printer().await?;
 
-        if !output.status.success() {
-            return Err("CUPS service not available".to_string());
-        }
+        let printers = self.ipp.get_printers(&PRINTER_ATTRIBUTES).await?
+            .iter()
+            .filter_map(|attributes| printer_from_attributes(attributes, default_printer.as_deref()))
+            .collect();
 
-        let stdout = String::from_utf8_lossy(&output.stdout);
-        let mut printers = Vec::new();
-        let mut default_printer = None;
-
-        // Parse lpstat output
-        for line in stdout.lines() {
-            if line.starts_with("printer ") {
-                // "printer EPSON_L3110_Series is idle.  enabled since <DATE>"
-                if let Some(name_part) = line.strip_prefix("printer ") {
-                    if let Some(space_pos) = name_part.find(' ') {
-                        let name = name_part[..space_pos].to_string();
-                        let status = if line.contains("idle") {
-                            "idle"
-                        } else if line.contains("printing") {
-                            "printing"
-                        } else if line.contains("stopped") {
-                            "stopped"
-                        } else {
-                            "unknown"
-                        }.to_string();
-
-                        let parts = name.split("_").collect::<Vec<&str>>().iter().map(|x| {capitalize!(x)}).collect::<Vec<String>>();
-                        let vendor = parts.get(0).map_or_else(|| "Unknown".to_string(), |x| x.to_string());
-                        let model = parts.get(1..).map_or_else(|| "Unknown".to_string(), |x| x.join(" "));
-
-                        printers.push(Printer {
-                            name: name.clone(),
-                            vendor,
-                            model,
-                            description: self.get_printer_description(&name).await.unwrap_or(name.clone()),
-                            status,
-                            location: self.get_printer_location(&name).await,
-                            is_default: false,
-                        });
-                    }
-                }
-            } else if line.starts_with("system default destination: ") {
-                default_printer = line.strip_prefix("system default destination: ")
-                    .map(|s| s.trim().to_string());
-            } else if line.starts_with("lpstat: ") {
-                return Err("No printer is available".to_string())
-            }
-        }
+        Ok(printers)
+    }
 
-        // Mark default printer
-        if let Some(default_name) = default_printer {
-            for printer in &mut printers {
-                if printer.name == default_name {
-                    printer.is_default = true;
-                    break;
-                }
-            }
-        }
+    /// Get a single printer straight from CUPS
+    async fn get_printer(&self, printer_name: &str) -> Result<Printer, String> {
+        let default_printer = self.ipp.get_default_printer().await?;
+        let attributes = self.ipp.get_printer_attributes(printer_name, &PRINTER_ATTRIBUTES).await?;
 
-        Ok(printers)
+        printer_from_attributes(&attributes, default_printer.as_deref())
+            .ok_or_else(|| format!("CUPS returned no name for printer '{}'", printer_name))
     }
 
-    /// Get printer description from CUPS
-    async fn get_printer_description(&self, printer_name: &str) -> Option<String> {
-        let output = Command::new("lpstat")
-            .args(["-p", printer_name, "-v"])
-            .output()
-            .await
-            .ok()?;
-
-        if output.status.success() {
-            let stdout = String::from_utf8_lossy(&output.stdout);
-            for line in stdout.lines() {
-                if line.contains("usb:") {
-                    return Some(format!("USB Printer [{}]", printer_name));
-                } else if line.contains("device-uri") {
-                    return Some(format!("{} [description: {}]", printer_name, line));
-                }
-            }
-        }
+    /// Get the media, sides, colour modes and other options a printer supports
+    async fn get_capabilities(&self, printer_name: &str) -> Result<PrinterCapabilities, String> {
+        let attributes = self.ipp.get_printer_attributes(printer_name, &CAPABILITY_ATTRIBUTES).await?;
 
-        None
+        Ok(capabilities_from_attributes(&attributes))
     }
 
-    /// Get printer location from CUPS
-    async fn get_printer_location(&self, printer_name: &str) -> Option<String> {
-        let output = Command::new("lpstat")
-            .args(["-p", printer_name, "-l"])
-            .output()
-            .await
-            .ok()?;
-
-        if output.status.success() {
-            let stdout = String::from_utf8_lossy(&output.stdout);
-            for line in stdout.lines() {
-                if line.contains("Location:") {
-                    return Some(line.to_string());
-                }
-            }
-        }
+    /// Get the ink or toner levels CUPS knows of, empty when the driver does not report them
+    async fn get_marker_levels(&self, printer_name: &str) -> Result<Vec<MarkerLevel>, String> {
+        let attributes = self.ipp.get_printer_attributes(printer_name, &MARKER_ATTRIBUTES).await?;
 
-        None
+        Ok(markers_from_attributes(&attributes))
     }
 
     /// Submit a print job to CUPS
-    pub async fn submit_print_job(&self, job: &PrintJob, file_path: &str) -> Result<i32, String> {
-        let mut cmd = Command::new("lp");
+    async fn submit_print_job(&self, job: &PrintJob, file_path: &str) -> Result<i32, String> {
+        let capabilities = self.get_capabilities(&job.printer).await?;
+        capabilities.check(job)?;
+        let attributes = print_job_attributes(job, &capabilities)?;
+
+        let job_id = self.ipp.print_job(&job.printer, &job.original_filename, attributes, Path::new(file_path))
+            .await
+            .map_err(|e| format!("Print job failed: {}", e))?;
 
-        cmd.args(["-d", &job.printer]);
+        log::info!("Submitted {} to {} as CUPS job {}", job.original_filename, job.printer, job_id);
+        Ok(job_id)
+    }
 
-        // Number of copies
-        if job.copies > 1 {
-            cmd.args(["-n", &job.copies.to_string()]);
-        }
+    /// Get the state of one CUPS job, with the reasons and progress CUPS reports for it
+    async fn get_job_status(&self, job_id: i32) -> Result<CupsJobStatus, String> {
+        let attributes = self.ipp.get_job_attributes(job_id, &JOB_STATUS_ATTRIBUTES).await?;
+
+        let state = attributes.get_int("job-state")
+            .and_then(CupsJobState::from_ipp)
+            .ok_or_else(|| format!("Job {} has no known state", job_id))?;
+
+        let mut status = CupsJobStatus {
+            state,
+            reasons: attributes.get_strs("job-state-reasons")
+                .into_iter()
+                .filter(|reason| *reason != "none")
+                .map(|reason| reason.to_string())
+                .collect(),
+            impressions_completed: attributes.get_int("job-impressions-completed"),
+            media_sheets_completed: attributes.get_int("job-media-sheets-completed"),
+        };
 
-        // Page range if specified
-        if let Some(ref pages) = job.pages {
-            if pages.len() > 0 {
-                cmd.args(["-P", pages]);
+        // Drivers that do not report progress leave the counter at 0, cupsd still logs the pages it sent
+        if state == CupsJobState::Completed && status.impressions_completed.unwrap_or(0) == 0 {
+            let page_log = tokio::fs::read_to_string(PAGE_LOG).await.unwrap_or_default();
+            if let Some(pages) = parse_page_log(&page_log, job_id) {
+                // The log has no sheets, the 0 the driver reported is no count either
+                status.impressions_completed = Some(pages);
+                status.media_sheets_completed = None;
             }
         }
 
-        // Duplex option
-        if job.duplex {
-            cmd.args(["-o", "sides=two -sided-long-edge"]);
-        }
+        Ok(status)
+    }
 
-        // Set color
-        let color_mode = match job.color {
-            true => "COLOR".to_string(),
-            false => "MONO".to_string()
-        };
-        cmd.args(["-o", format!("Ink={color_mode}").as_str()]);
+    /// Cancel a print job
+    async fn cancel_job(&self, job_id: i32) -> Result<(), String> {
+        self.ipp.cancel_job(job_id)
+            .await
+            .map_err(|e| format!("Failed to cancel job: {}", e))
+    }
 
-        // Set media size
-        let media_size = capitalize!(job.page_size.to_string());
-        cmd.args(["-o", format!("PageSize={media_size}").as_str()]);
+    /// Resume a stopped printer, like cupsenable
+    async fn enable_printer(&self, printer_name: &str) -> Result<(), String> {
+        self.ipp.printer_operation(Operation::ResumePrinter, printer_name, None)
+            .await
+            .map_err(|e| format!("Failed to enable printer '{}': {}", printer_name, e))
+    }
 
-        // Add the file to print
-        cmd.arg(file_path);
+    /// Stop a printer, queued jobs stay until it is enabled again, like cupsdisable -r
+    async fn disable_printer(&self, printer_name: &str, reason: Option<&str>) -> Result<(), String> {
+        self.ipp.printer_operation(Operation::PausePrinter, printer_name, reason)
+            .await
+            .map_err(|e| format!("Failed to disable printer '{}': {}", printer_name, e))
+    }
 
-        let output = cmd.output().await
-            .map_err(|e| format!("Failed to execute lp command: {}", e))?;
+    /// Let a printer queue take new jobs again, like cupsaccept
+    async fn accept_jobs(&self, printer_name: &str) -> Result<(), String> {
+        self.ipp.printer_operation(Operation::CupsAcceptJobs, printer_name, None)
+            .await
+            .map_err(|e| format!("Failed to accept jobs on '{}': {}", printer_name, e))
+    }
 
-        if !output.status.success() {
-            let stderr = String::from_utf8_lossy(&output.stderr);
-            return Err(format!("Print job failed: {}", stderr));
-        }
+    /// Refuse new jobs while still printing the queued ones, like cupsreject -r
+    async fn reject_jobs(&self, printer_name: &str, reason: Option<&str>) -> Result<(), String> {
+        self.ipp.printer_operation(Operation::CupsRejectJobs, printer_name, reason)
+            .await
+            .map_err(|e| format!("Failed to reject jobs on '{}': {}", printer_name, e))
+    }
 
-        // Parse job ID from output
-        let stdout = String::from_utf8_lossy(&output.stdout);
-
-        //request id is EPSON_L3110_Series-66 (1 file(s))
-        if let Some(job_id_str) = stdout.split_whitespace().nth(3) {
-            let id = job_id_str.split('-').last().unwrap().parse::<i32>();
-            log::info!("Request is like: {job_id_str}");
-            match id {
-                Ok(id) => Ok(id),
-                Err(e) => {
-                    log::error!("Failed to parse job id: {}", e);
-                    Err(format!("Prase ID error: {}", e))},
-            }
-        } else {
-            Err("No job ID returned".to_string())
-        }
+    /// Cancel every job queued on a printer
+    async fn purge_jobs(&self, printer_name: &str) -> Result<(), String> {
+        self.ipp.printer_operation(Operation::PurgeJobs, printer_name, None)
+            .await
+            .map_err(|e| format!("Failed to purge jobs of '{}': {}", printer_name, e))
     }
 
+    /// Make a printer the system default destination, like lpadmin -d
+    async fn set_default_printer(&self, printer_name: &str) -> Result<(), String> {
+        self.ipp.printer_operation(Operation::CupsSetDefault, printer_name, None)
+            .await
+            .map_err(|e| format!("Failed to set '{}' as default printer: {}", printer_name, e))
+    }
 
-    
-    /// Get status of a specific print job
-    pub async fn get_job_status(&self, job_id: i32) -> Result<String, String> {
-        // printer EPSON_L3110_Series now printing EPSON_L3110_Series-111.  enabled since Thu Oct 23 21:09:15 2025
-        // printer EPSON_L3110_Series is idle.  enabled since Thu Oct 23 21:09:42 2025
-        let active_output = Command::new("lpstat")
-            .arg("-p")
+    /// Devices the CUPS backends can see, configured or not, like lpinfo -v
+    async fn discover_devices(&self) -> Result<Vec<DiscoveredPrinter>, String> {
+        let output = Command::new("lpinfo")
+            .args(["-l", "-v", "--timeout", &LPINFO_TIMEOUT.as_secs().to_string()])
             .output()
             .await
-            .map_err(|e| format!("Failed to execute lp command: {}", e))?;
-
-        if active_output.status.success() {
-            let stdout = String::from_utf8_lossy(&active_output.stdout);
-            for line in stdout.lines() {
-                return match line.trim().split_whitespace().collect::<Vec<&str>>().get(3) {
-                    None => { Err("Abnormal line".to_string()) }
-                    Some(status) => { Ok(status.replace('.',"").trim().to_string()) }
-                };
-            }
+            .map_err(|e| format!("Failed to execute lpinfo: {}", e))?;
+
+        if !output.status.success() {
+            return Err(format!("lpinfo failed: {}", String::from_utf8_lossy(&output.stderr).trim()));
         }
 
-        Err(format!("Job {} not found", job_id))
+        Ok(parse_lpinfo(&String::from_utf8_lossy(&output.stdout)))
     }
 
+    /// Create and enable a queue for a device, with driverless IPP Everywhere unless a PPD is given
+    async fn add_printer(&self, request: &NewPrinterRequest) -> Result<(), String> {
+        let args = lpadmin_add_args(request)?;
+        execute_lpadmin(&args).await
+            .map_err(|e| format!("Failed to add printer '{}': {}", request.name, e))?;
 
-    /// Cancel a print job
-    pub async fn cancel_job(&self, printer_name: &str, job_id: i32) -> Result<(), String> {
-        let output = Command::new("cancel")
-            .arg(format!("{}-{}",printer_name, job_id))
-            .output()
-            .await
-            .map_err(|e| format!("Failed to execute cancel command: {}", e))?;
+        log::info!("Added printer '{}' for {}", request.name, request.uri);
+        Ok(())
+    }
 
-        if !output.status.success() {
-            let stderr = String::from_utf8_lossy(&output.stderr);
-            return Err(format!("Failed to cancel job: {}", stderr));
-        }
+    /// Remove a queue along with the jobs still queued on it
+    async fn delete_printer(&self, printer_name: &str) -> Result<(), String> {
+        validate_printer_name(printer_name)?;
+        execute_lpadmin(&["-x".to_string(), printer_name.to_string()]).await
+            .map_err(|e| format!("Failed to delete printer '{}': {}", printer_name, e))?;
 
+        log::info!("Deleted printer '{}'", printer_name);
         Ok(())
     }
 
     /// Get all active print jobs
-    pub async fn get_active_jobs(&self) -> Result<Vec<(i32, String, String)>, String> {
-        let output = Command::new("lpstat")
-            .arg("-o")
-            .output()
-            .await
-            .map_err(|e| format!("Failed to execute lpstat: {}", e))?;
+    async fn get_active_jobs(&self) -> Result<Vec<(i32, String, String)>, String> {
+        let jobs = self.ipp.get_jobs("not-completed", &["job-id", "job-printer-uri", "job-originating-user-name"]).await?;
+
+        let jobs = jobs.iter()
+            .filter_map(|job| {
+                let job_id = job.get_int("job-id")?;
+                let printer_name = job.get_str("job-printer-uri")?.rsplit('/').next()?;
+                let username = job.get_str("job-originating-user-name").unwrap_or_default();
+                Some((job_id, printer_name.to_string(), username.to_string()))
+            })
+            .collect();
 
-        if !output.status.success() {
-            return Ok(Vec::new()); // No active jobs
+        Ok(jobs)
+    }
+}
+
+impl CupsJobState {
+    pub fn from_ipp(job_state: i32) -> Option<Self> {
+        match job_state {
+            3 => Some(CupsJobState::Pending),
+            4 => Some(CupsJobState::Held),
+            5 => Some(CupsJobState::Processing),
+            6 => Some(CupsJobState::Stopped),
+            7 => Some(CupsJobState::Canceled),
+            8 => Some(CupsJobState::Aborted),
+            9 => Some(CupsJobState::Completed),
+            _ => None,
         }
+    }
+}
 
-        let stdout = String::from_utf8_lossy(&output.stdout);
-        let mut jobs = Vec::new();
-
-        for line in stdout.lines() {
-            // <Printer_name>-<Job_id>    <User_name>      1024   <Date>"
-            let parts: Vec<&str> = line.split_whitespace().collect();
-            if parts.len() >= 3 {
-                if let Some(dash_pos) = parts[0].rfind('-') {
-                    let printer_name = &parts[0][..dash_pos];
-                    if let Ok(job_id) = parts[0][dash_pos + 1..].parse::<i32>() {
-                        let username = parts[1];
-                        jobs.push((job_id, printer_name.to_string(), username.to_string()));
-                    }
-                }
-            }
+impl CupsJobStatus {
+    pub fn has_reason(&self, reason: &str) -> bool {
+        self.reasons.iter().any(|job_reason| job_reason == reason)
+    }
+}
+
+async fn execute_lpadmin(args: &[String]) -> Result<(), String> {
+    let output = Command::new("lpadmin")
+        .args(args)
+        .output()
+        .await
+        .map_err(|e| format!("Failed to execute lpadmin: {}", e))?;
+
+    if !output.status.success() {
+        return Err(String::from_utf8_lossy(&output.stderr).trim().to_string());
+    }
+
+    Ok(())
+}
+
+/// Pages logged for `job_id`, the "total" line cupsd writes at the end of the job when there is one
+///
+/// Lines look like `EPSON_L3110 pi 42 [16/Oct/2026:10:04:11 +0200] total 3 - 192.168.1.20 report.pdf iso_a4_210x297mm one-sided`,
+/// older CUPS versions log every page with its number and copies instead of the total.
+fn parse_page_log(page_log: &str, job_id: i32) -> Option<i32> {
+    let mut total = None;
+    let mut counted = None;
+
+    for line in page_log.lines() {
+        if line.split_whitespace().nth(2).and_then(|id| id.parse::<i32>().ok()) != Some(job_id) {
+            continue;
         }
+        let Some((_, page)) = line.split_once(']') else { continue };
 
-        Ok(jobs)
+        let mut fields = page.split_whitespace();
+        match (fields.next(), fields.next().and_then(|count| count.parse::<i32>().ok())) {
+            (Some("total"), Some(pages)) => total = Some(pages),
+            (Some(number), Some(copies)) if number.parse::<i32>().is_ok() => counted = Some(counted.unwrap_or(0) + copies),
+            _ => {},
+        }
     }
+
+    total.or(counted)
 }
 
+fn validate_printer_name(name: &str) -> Result<(), String> {
+    if name.is_empty() || name.len() > 127 || name.starts_with('-') || !name.chars().all(|c| c.is_ascii_alphanumeric() || "_-.".contains(c)) {
+        return Err("Printer name may only contain letters, digits, '_', '-' and '.', and may not start with '-'".to_string());
+    }
+
+    Ok(())
+}
 
+fn is_device_uri(uri: &str) -> bool {
+    uri.split_once("://").is_some_and(|(scheme, rest)| {
+        !rest.is_empty() && DEVICE_URI_SCHEMES.iter().any(|allowed| scheme.eq_ignore_ascii_case(allowed))
+    })
+}
 
-#[tokio::test]
-async fn test_is_cups_available() {
-    let service = CupsService::new();
+/// lpadmin arguments creating an enabled queue that accepts jobs
+fn lpadmin_add_args(request: &NewPrinterRequest) -> Result<Vec<String>, String> {
+    let name = request.name.trim();
+    validate_printer_name(name)?;
+    if !is_device_uri(&request.uri) {
+        return Err(format!("Invalid device URI '{}', supported schemes: {}", request.uri, DEVICE_URI_SCHEMES.join(", ")));
+    }
 
-    let available = service.is_available().await;
+    let mut args = vec!["-p".to_string(), name.to_string(), "-E".to_string(), "-v".to_string(), request.uri.clone()];
+    match request.ppd.as_deref().map(str::trim).filter(|ppd| !ppd.is_empty()) {
+        Some(ppd) if ppd.starts_with('/') => {
+            let in_ppd_dir = PPD_DIRS.iter().any(|dir| ppd.starts_with(dir))
+                && !Path::new(ppd).components().any(|component| component == Component::ParentDir);
+            if !in_ppd_dir {
+                return Err(format!("PPD files have to be in {}", PPD_DIRS.join(", ")));
+            }
+            args.extend(["-P".to_string(), ppd.to_string()]);
+        },
+        Some(model) => args.extend(["-m".to_string(), model.to_string()]),
+        None => args.extend(["-m".to_string(), "everywhere".to_string()]),
+    }
+    if let Some(description) = request.description.as_deref().filter(|description| !description.trim().is_empty()) {
+        args.extend(["-D".to_string(), description.trim().to_string()]);
+    }
+    if let Some(location) = request.location.as_deref().filter(|location| !location.trim().is_empty()) {
+        args.extend(["-L".to_string(), location.trim().to_string()]);
+    }
 
-    assert_eq!(true, available);
+    Ok(args)
 }
 
+/// Devices of `lpinfo -l -v`, backends that found nothing only print their scheme and are left out
+fn parse_lpinfo(stdout: &str) -> Vec<DiscoveredPrinter> {
+    let mut devices = Vec::new();
+    let mut fields: Vec<(&str, &str)> = Vec::new();
+
+    // "Device: uri = usb://EPSON/L3110%20Series?serial=X8JK001234" followed by indented "key = value" lines
+    for line in stdout.lines().chain(std::iter::once("Device:")) {
+        if line.starts_with("Device:") {
+            let field = |name: &str| fields.iter()
+                .find(|(key, _)| *key == name)
+                .map(|(_, value)| value.to_string())
+                .filter(|value| !value.is_empty() && value != "Unknown");
+
+            if let Some(uri) = field("uri").filter(|uri| is_device_uri(uri)) {
+                devices.push(DiscoveredPrinter {
+                    uri,
+                    connection: field("class").unwrap_or_else(|| "network".to_string()),
+                    make_and_model: field("make-and-model"),
+                    info: field("info"),
+                    source: "lpinfo".to_string(),
+                });
+            }
+            fields.clear();
+        }
+
+        let line = line.trim_start_matches("Device:");
+        if let Some((key, value)) = line.split_once(" = ").or_else(|| line.trim_end().strip_suffix(" =").map(|key| (key, ""))) {
+            fields.push((key.trim(), value.trim()));
+        }
+    }
 
-#[tokio::test]
-async fn test_get_printers() -> Result<(), String> {
-    let service = CupsService::new();
+    devices
+}
 
-    match service.is_available().await {
-        true => (),
-        false => return Err("Failed to check for available printers".to_string()),
+fn printer_from_attributes(attributes: &IppAttributeGroup, default_printer: Option<&str>) -> Option<Printer> {
+    let name = attributes.get_str("printer-name")?.to_string();
+
+    // "EPSON L3110 Series", fall back to the queue name when the driver reports nothing
+    let make_and_model = attributes.get_str("printer-make-and-model")
+        .filter(|make_and_model| !make_and_model.trim().is_empty())
+        .map(|make_and_model| make_and_model.trim().to_string())
+        .unwrap_or_else(|| name.replace('_', " "));
+    let (vendor, model) = match make_and_model.split_once(' ') {
+        Some((vendor, model)) => (capitalize!(vendor), model.to_string()),
+        None => (capitalize!(make_and_model), "Unknown".to_string()),
     };
 
-    let printers = service.get_printers().await?;
+    let accepting_jobs = attributes.get_bool("printer-is-accepting-jobs").unwrap_or(true);
+    let status = match attributes.get_int("printer-state") {
+        Some(5) => "stopped",
+        Some(3 | 4) if !accepting_jobs => "rejecting",
+        Some(3) => "idle",
+        Some(4) => "printing",
+        _ => "unknown",
+    }.to_string();
+
+    Some(Printer {
+        description: attributes.get_str("printer-info")
+            .filter(|info| !info.is_empty())
+            .unwrap_or(&name)
+            .to_string(),
+        location: attributes.get_str("printer-location")
+            .filter(|location| !location.is_empty())
+            .map(|location| location.to_string()),
+        is_default: default_printer == Some(name.as_str()),
+        state_message: attributes.get_str("printer-state-message")
+            .filter(|message| !message.trim().is_empty())
+            .map(|message| message.trim().to_string()),
+        name,
+        vendor,
+        model,
+        status,
+        accepting_jobs,
+    })
+}
 
-    assert_ne!(0, printers.len());
+fn capabilities_from_attributes(attributes: &IppAttributeGroup) -> PrinterCapabilities {
+    let strings = |name: &str| attributes.get_strs(name).into_iter().map(|value| value.to_string()).collect::<Vec<String>>();
+
+    let resolutions = attributes.get("printer-resolution-supported")
+        .map(|attribute| attribute.values.iter().filter_map(format_resolution).collect())
+        .unwrap_or_default();
+
+    let quality = attributes.get("print-quality-supported")
+        .map(|attribute| attribute.values.iter()
+            .filter_map(|value| match value.as_int()? {
+                3 => Some("draft".to_string()),
+                4 => Some("normal".to_string()),
+                5 => Some("high".to_string()),
+                _ => None,
+            })
+            .collect())
+        .unwrap_or_default();
+
+    let pages_per_sheet = attributes.get("number-up-supported")
+        .map(|attribute| attribute.values.iter()
+            .flat_map(|value| match value {
+                IppValue::RangeOfInteger(lower, upper) => PAGES_PER_SHEET.iter()
+                    .copied()
+                    .filter(|number_up| (*lower..=*upper).contains(&(*number_up as i32)))
+                    .collect(),
+                value => value.as_int().and_then(|number_up| u32::try_from(number_up).ok()).into_iter().collect::<Vec<u32>>(),
+            })
+            .collect())
+        .unwrap_or_default();
+
+    let max_copies = attributes.get("copies-supported")
+        .and_then(|attribute| match attribute.values.first()? {
+            IppValue::RangeOfInteger(_, upper) => u32::try_from(*upper).ok(),
+            value => value.as_int().and_then(|upper| u32::try_from(upper).ok()),
+        });
+
+    PrinterCapabilities {
+        media: strings("media-supported"),
+        media_default: attributes.get_str("media-default").map(|media| media.to_string()),
+        sides: strings("sides-supported"),
+        color_modes: strings("print-color-mode-supported"),
+        resolutions,
+        input_trays: strings("media-source-supported"),
+        media_types: strings("media-type-supported"),
+        quality,
+        pages_per_sheet,
+        max_copies,
+        // Every CUPS queue supports page ranges, the filters apply them
+        page_ranges: attributes.get_bool("page-ranges-supported").unwrap_or(true),
+    }
+}
 
-    Ok(())
+/// Ink/toner levels from the marker-* attributes, unknown (negative) levels dropped
+fn markers_from_attributes(attributes: &IppAttributeGroup) -> Vec<MarkerLevel> {
+    let colors = attributes.get_strs("marker-colors");
+    let types = attributes.get_strs("marker-types");
+    let levels = attributes.get_ints("marker-levels");
+    let low_levels = attributes.get_ints("marker-low-levels");
+
+    // Negative levels mean unavailable (-1), unknown (-2) or "some left" (-3)
+    attributes.get_strs("marker-names").into_iter()
+        .enumerate()
+        .map(|(i, name)| MarkerLevel {
+            name: name.to_string(),
+            color: colors.get(i)
+                .filter(|color| !color.is_empty() && **color != "none")
+                .map(|color| color.to_string()),
+            marker_type: types.get(i).map(|marker_type| marker_type.to_string()),
+            level: levels.get(i).copied().filter(|level| *level >= 0),
+            low_level: low_levels.get(i).copied().filter(|level| *level >= 0),
+        })
+        .collect()
 }
 
+/// "600dpi", or "1200x600dpi" when the two directions differ
+fn format_resolution(value: &IppValue) -> Option<String> {
+    let IppValue::Resolution { cross_feed, feed, units } = value else {
+        return None;
+    };
+    let units = match units {
+        4 => "dpcm",
+        _ => "dpi",
+    };
+
+    match cross_feed == feed {
+        true => Some(format!("{}{}", cross_feed, units)),
+        false => Some(format!("{}x{}{}", cross_feed, feed, units)),
+    }
+}
 
-#[tokio::test]
-async fn test_get_printer_metadata() -> Result<(), String> {
-    let service = CupsService::new();
+/// IPP job template attributes for the options of a print job, adapted to what the printer supports
+fn print_job_attributes(job: &PrintJob, capabilities: &PrinterCapabilities) -> Result<Vec<IppAttribute>, String> {
+    let mut attributes = Vec::new();
 
-    match service.is_available().await {
-        true => (),
-        false => return Err("Failed to check for available printers".to_string()),
+    if job.copies > 1 {
+        attributes.push(IppAttribute::new("copies", IppValue::Integer(job.copies as i32)));
     }
 
-    let printers = service.get_printers().await?;
+    if let Some(ref pages) = job.pages
+        && !pages.trim().is_empty() {
+        let ranges = parse_page_ranges(pages)?
+            .into_iter()
+            .map(|(first, last)| IppValue::RangeOfInteger(first, last))
+            .collect();
+        attributes.push(IppAttribute::with_values("page-ranges", ranges));
+    }
+
+    // A printer not reporting sides-supported still gets asked for double-sided printing
+    if job.duplex || !capabilities.sides.is_empty() {
+        let sides = match job.duplex {
+            true => "two-sided-long-edge",
+            false => "one-sided",
+        };
+        attributes.push(IppAttribute::new("sides", IppValue::Keyword(sides.to_string())));
+    }
 
-    let printer = match printers.first() {
-        Some(printer) => printer,
-        None => return Err("Failed to find printer".to_string()),
+    let color = job.color && capabilities.supports_color();
+    if job.color && !color {
+        log::warn!("Printer '{}' has no colour mode, printing {} in monochrome", job.printer, job.original_filename);
+    }
+    let color_mode = match color {
+        true => "color",
+        false => "monochrome",
     };
+    if capabilities.color_modes.is_empty() || capabilities.color_modes.iter().any(|supported| supported == color_mode) {
+        attributes.push(IppAttribute::new("print-color-mode", IppValue::Keyword(color_mode.to_string())));
+    }
 
-    assert_eq!("EPSON_L3110_Series_raspberrypi".to_string(), printer.name);
-    assert_eq!("idle".to_string(), printer.status);
-    assert_ne!("".to_string(), printer.description);
+    // The Epson ESC/P-R drivers ignore print-color-mode and only read their own Ink option
+    if job.vendor.eq_ignore_ascii_case("epson") {
+        let ink = match color {
+            true => "COLOR",
+            false => "MONO",
+        };
+        attributes.push(IppAttribute::new("Ink", IppValue::Name(ink.to_string())));
+    }
 
+    // media-col carries the type and tray along with the size, plain media is enough otherwise
+    if job.media_type.is_some() || job.input_tray.is_some() {
+        let (width, height) = job.page_size.dimensions();
+        let mut media_col = vec![IppAttribute::new("media-size", IppValue::Collection(vec![
+            IppAttribute::new("x-dimension", IppValue::Integer(width)),
+            IppAttribute::new("y-dimension", IppValue::Integer(height)),
+        ]))];
+        if let Some(ref media_type) = job.media_type {
+            media_col.push(IppAttribute::new("media-type", IppValue::Keyword(media_type.clone())));
+        }
+        if let Some(ref input_tray) = job.input_tray {
+            media_col.push(IppAttribute::new("media-source", IppValue::Keyword(input_tray.clone())));
+        }
+        attributes.push(IppAttribute::new("media-col", IppValue::Collection(media_col)));
+    } else {
+        attributes.push(IppAttribute::new("media", IppValue::Keyword(job.page_size.media_keyword().to_string())));
+    }
 
-    let description = service.get_printer_description(printer.name.clone().as_str()).await;
+    // Portrait is left out so CUPS keeps rotating landscape pages on its own
+    if job.orientation == PrintOrientation::Landscape {
+        attributes.push(IppAttribute::new("orientation-requested", IppValue::Enum(4)));
+    }
 
-    if let Some(printer_description) = description {
-        match printer_description.as_str() {
-            "" => return Err(format!("Printer ({}) description not found", printer.name)),
-            _ => ()
-        }
+    let scaling = match job.scaling {
+        PrintScaling::Auto => None,
+        PrintScaling::Fit => Some("fit"),
+        PrintScaling::Fill => Some("fill"),
+        PrintScaling::None => Some("none"),
+    };
+    if let Some(scaling) = scaling {
+        attributes.push(IppAttribute::new("print-scaling", IppValue::Keyword(scaling.to_string())));
     }
 
-    Ok(())
+    if job.pages_per_sheet > 1 {
+        attributes.push(IppAttribute::new("number-up", IppValue::Integer(job.pages_per_sheet as i32)));
+    }
+
+    let quality = match job.quality {
+        PrintQuality::Draft => 3,
+        PrintQuality::Normal => 4,
+        PrintQuality::High => 5,
+    };
+    attributes.push(IppAttribute::new("print-quality", IppValue::Enum(quality)));
+
+    if job.copies > 1 {
+        let handling = match job.collate {
+            true => "separate-documents-collated-copies",
+            false => "separate-documents-uncollated-copies",
+        };
+        attributes.push(IppAttribute::new("multiple-document-handling", IppValue::Keyword(handling.to_string())));
+    }
+
+    Ok(attributes)
+}
+
+/// Parse a page selection like "1-3,5,8-" into inclusive ranges
+pub(crate) fn parse_page_ranges(pages: &str) -> Result<Vec<(i32, i32)>, String> {
+    let invalid = || format!("Invalid page range '{}'", pages);
+    let page = |number: &str| number.trim().parse::<i32>().ok().filter(|page| *page > 0);
+
+    pages.split(',')
+        .map(|range| {
+            let (first, last) = match range.split_once('-') {
+                Some((first, "")) => (page(first), Some(i32::MAX)),
+                Some((first, last)) => (page(first), page(last)),
+                None => (page(range), page(range)),
+            };
+
+            match (first, last) {
+                (Some(first), Some(last)) if first <= last => Ok((first, last)),
+                _ => Err(invalid()),
+            }
+        })
+        .collect()
 }
 
 
-#[tokio::test]
-async fn test_get_job_status() -> Result<(), String> {
-    let service = CupsService::new();
 
-    match service.get_job_status(0).await {
-        Ok(status) => {
-            assert_eq!(status, "idle");
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::TempDir;
+    use crate::models::{PrintPageSize, PrintRequest};
+    use crate::services::ipp::stub::{IppStub, StubPrinter};
+
+    /// The queue name CUPS gives a USB printer shared from a Pi
+    fn raspberry_pi_stub() -> Vec<StubPrinter> {
+        let mut epson = StubPrinter::new("EPSON_L3110_Series_raspberrypi", "EPSON L3110 Series");
+        epson.info = "EPSON L3110 Series @ raspberrypi".to_string();
+        vec![epson]
+    }
+
+    #[tokio::test]
+    async fn test_is_cups_available() {
+        let stub = IppStub::start(raspberry_pi_stub(), None).await;
+        let service = CupsService::with_url(&stub.url());
+
+        let available = service.is_available().await;
+
+        assert!(available);
+        // Nothing listens on the discard port
+        assert!(!CupsService::with_url("http://127.0.0.1:9").is_available().await);
+    }
+
+    #[tokio::test]
+    async fn test_get_printers() -> Result<(), String> {
+        let stub = IppStub::start(raspberry_pi_stub(), None).await;
+        let service = CupsService::with_url(&stub.url());
+
+        match service.is_available().await {
+            true => (),
+            false => return Err("Failed to check for available printers".to_string()),
+        };
+
+        let printers = service.get_printers().await?;
+
+        assert_ne!(0, printers.len());
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_get_printer_metadata() -> Result<(), String> {
+        let stub = IppStub::start(raspberry_pi_stub(), None).await;
+        let service = CupsService::with_url(&stub.url());
+
+        match service.is_available().await {
+            true => (),
+            false => return Err("Failed to check for available printers".to_string()),
         }
-        Err(e) => { return Err(e) }
+
+        let printers = service.get_printers().await?;
+
+        let printer = match printers.first() {
+            Some(printer) => printer,
+            None => return Err("Failed to find printer".to_string()),
+        };
+
+        assert_eq!("EPSON_L3110_Series_raspberrypi".to_string(), printer.name);
+        assert_eq!("idle".to_string(), printer.status);
+        assert_ne!("".to_string(), printer.description);
+
+        Ok(())
     }
 
-    Ok(())
-}
+    #[tokio::test]
+    async fn test_get_job_status() -> Result<(), String> {
+        let stub = IppStub::start(raspberry_pi_stub(), None).await;
+        let service = CupsService::with_url(&stub.url());
+
+        match service.is_available().await {
+            true => (),
+            false => return Err("Failed to check for available printers".to_string()),
+        }
+
+        // Job ids start at 1, a status is only reported for jobs that exist
+        assert!(service.get_job_status(0).await.is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_parse_lpinfo() {
+        let stdout = "\
+Device: uri = socket
+        class = network
+        info = AppSocket/HP JetDirect
+        make-and-model = Unknown
+        device-id =
+        location =
+Device: uri = usb://EPSON/L3110%20Series?serial=X8JK001234&interface=1
+        class = direct
+        info = EPSON L3110 Series
+        make-and-model = EPSON L3110 Series
+        device-id = MFG:EPSON;CMD:ESCPL2,BDC,D4,END4;MDL:L3110 Series;
+        location =
+Device: uri = dnssd://Office%20Laser._ipp._tcp.local/?uuid=e3248000-80ce-11db-8000-30055c773bcf
+        class = network
+        info = Office Laser
+        make-and-model = HP LaserJet Pro M404
+        device-id =
+        location = Room 2
+";
+        assert_eq!(parse_lpinfo(stdout), vec![
+            DiscoveredPrinter {
+                uri: "usb://EPSON/L3110%20Series?serial=X8JK001234&interface=1".to_string(),
+                connection: "direct".to_string(),
+                make_and_model: Some("EPSON L3110 Series".to_string()),
+                info: Some("EPSON L3110 Series".to_string()),
+                source: "lpinfo".to_string(),
+            },
+            DiscoveredPrinter {
+                uri: "dnssd://Office%20Laser._ipp._tcp.local/?uuid=e3248000-80ce-11db-8000-30055c773bcf".to_string(),
+                connection: "network".to_string(),
+                make_and_model: Some("HP LaserJet Pro M404".to_string()),
+                info: Some("Office Laser".to_string()),
+                source: "lpinfo".to_string(),
+            },
+        ]);
+    }
+
+    #[test]
+    fn test_parse_page_log() {
+        let page_log = "\
+EPSON_L3110 pi 41 [16/Oct/2026:09:58:02 +0200] total 1 - 192.168.1.20 photo.jpg iso_a4_210x297mm one-sided
+Office_Laser pi 42 [16/Oct/2026:10:04:11 +0200] 1 2 - 192.168.1.20 report.pdf iso_a4_210x297mm one-sided
+Office_Laser pi 42 [16/Oct/2026:10:04:20 +0200] 2 2 - 192.168.1.20 report.pdf iso_a4_210x297mm one-sided
+EPSON_L3110 pi 43 [16/Oct/2026:10:10:45 +0200] 1 1 - localhost letter.txt iso_a4_210x297mm one-sided
+EPSON_L3110 pi 43 [16/Oct/2026:10:10:47 +0200] total 3 - localhost letter.txt iso_a4_210x297mm one-sided
+";
+
+        assert_eq!(parse_page_log(page_log, 41), Some(1));
+        assert_eq!(parse_page_log(page_log, 42), Some(4));
+        assert_eq!(parse_page_log(page_log, 43), Some(3));
+        assert_eq!(parse_page_log(page_log, 4), None);
+        assert_eq!(parse_page_log("", 41), None);
+    }
+
+    #[test]
+    fn test_lpadmin_add_args() {
+        let mut request = NewPrinterRequest {
+            name: "Office_Laser".to_string(),
+            uri: "ipp://laser.local:631/ipp/print".to_string(),
+            location: Some("Room 2".to_string()),
+            ..Default::default()
+        };
+        assert_eq!(lpadmin_add_args(&request).unwrap(), vec![
+            "-p", "Office_Laser", "-E", "-v", "ipp://laser.local:631/ipp/print", "-m", "everywhere", "-L", "Room 2",
+        ]);
+
+        request.ppd = Some("/usr/share/ppd/laser.ppd".to_string());
+        assert_eq!(lpadmin_add_args(&request).unwrap()[5..7], ["-P", "/usr/share/ppd/laser.ppd"]);
+        request.ppd = Some("drv:///sample.drv/generic.ppd".to_string());
+        assert_eq!(lpadmin_add_args(&request).unwrap()[5..7], ["-m", "drv:///sample.drv/generic.ppd"]);
+
+        request.ppd = Some("/etc/shadow".to_string());
+        assert!(lpadmin_add_args(&request).is_err());
+        request.ppd = Some("/usr/share/ppd/../../../etc/shadow".to_string());
+        assert!(lpadmin_add_args(&request).is_err());
+        request.ppd = None;
+
+        request.name = "Office Laser".to_string();
+        assert!(lpadmin_add_args(&request).is_err());
+        assert!(validate_printer_name("-E").is_err());
+        request.name = "Office_Laser".to_string();
+        for uri in ["laser.local", "file:///etc/passwd", "cups-pdf:/", "ipp://"] {
+            request.uri = uri.to_string();
+            assert!(lpadmin_add_args(&request).is_err(), "{}", uri);
+        }
+        for uri in ["usb://EPSON/L3110%20Series?serial=X8JK001234", "socket://192.168.1.30:9100", "dnssd://Laser._ipp._tcp.local/"] {
+            request.uri = uri.to_string();
+            assert!(lpadmin_add_args(&request).is_ok(), "{}", uri);
+        }
+    }
+
+    #[test]
+    fn test_parse_page_ranges() {
+        assert_eq!(parse_page_ranges("1-3,5").unwrap(), vec![(1, 3), (5, 5)]);
+        assert_eq!(parse_page_ranges(" 2 - 4 , 7-").unwrap(), vec![(2, 4), (7, i32::MAX)]);
+        assert!(parse_page_ranges("3-1").is_err());
+        assert!(parse_page_ranges("0").is_err());
+        assert!(parse_page_ranges("1,,2").is_err());
+        assert!(parse_page_ranges("all").is_err());
+    }
+
+    fn keywords(name: &str, values: &[&str]) -> IppAttribute {
+        IppAttribute::with_values(name, values.iter().map(|value| IppValue::Keyword(value.to_string())).collect())
+    }
+
+    fn letter_job(duplex: bool, color: bool) -> PrintJob {
+        let request = PrintRequest {
+            printer: None,
+            copies: Some(2),
+            pages: None,
+            duplex: Some(duplex),
+            color: Some(color),
+            page_size: Some(PrintPageSize::Letter),
+            ..Default::default()
+        };
+        PrintJob::new("report.pdf".to_string(), "Office_Laser".to_string(), "Hp".to_string(), "LaserJet".to_string(), request, 1)
+    }
+
+    #[tokio::test]
+    async fn test_printer_capabilities() {
+        let laser = StubPrinter::new("Office_Laser", "HP LaserJet Pro M404")
+            .with_attribute(keywords("media-supported", &["iso_a4_210x297mm", "na_letter_8.5x11in"]))
+            .with_attribute(keywords("media-default", &["iso_a4_210x297mm"]))
+            .with_attribute(keywords("sides-supported", &["one-sided"]))
+            .with_attribute(keywords("print-color-mode-supported", &["monochrome"]))
+            .with_attribute(keywords("media-source-supported", &["auto", "tray-1", "manual"]))
+            .with_attribute(IppAttribute::with_values("printer-resolution-supported", vec![
+                IppValue::Resolution { cross_feed: 600, feed: 600, units: 3 },
+                IppValue::Resolution { cross_feed: 1200, feed: 600, units: 3 },
+            ]))
+            .with_attribute(IppAttribute::with_values("print-quality-supported", vec![IppValue::Enum(3), IppValue::Enum(4)]))
+            .with_attribute(keywords("media-type-supported", &["stationery", "photographic"]))
+            .with_attribute(IppAttribute::new("number-up-supported", IppValue::RangeOfInteger(1, 4)))
+            .with_attribute(IppAttribute::new("copies-supported", IppValue::RangeOfInteger(1, 99)))
+            .with_attribute(IppAttribute::new("page-ranges-supported", IppValue::Boolean(true)));
+        let stub = IppStub::start(vec![laser], None).await;
+        let service = CupsService::with_url(&stub.url());
+
+        let capabilities = service.get_capabilities("Office_Laser").await.unwrap();
+        assert_eq!(capabilities.media, vec!["iso_a4_210x297mm", "na_letter_8.5x11in"]);
+        assert_eq!(capabilities.media_default.as_deref(), Some("iso_a4_210x297mm"));
+        assert_eq!(capabilities.resolutions, vec!["600dpi", "1200x600dpi"]);
+        assert_eq!(capabilities.input_trays, vec!["auto", "tray-1", "manual"]);
+        assert_eq!(capabilities.quality, vec!["draft", "normal"]);
+        assert_eq!(capabilities.max_copies, Some(99));
+        assert_eq!(capabilities.media_types, vec!["stationery", "photographic"]);
+        assert_eq!(capabilities.pages_per_sheet, vec![1, 2, 4]);
+        assert!(!capabilities.supports_duplex());
+        assert!(!capabilities.supports_color());
+        assert!(service.get_capabilities("Nope").await.is_err());
+
+        let mut job = letter_job(false, true);
+        assert!(capabilities.check(&job).is_ok());
+        assert!(capabilities.check(&letter_job(true, true)).unwrap_err().contains("double-sided"));
+        job.page_size = PrintPageSize::A6;
+        assert!(capabilities.check(&job).unwrap_err().contains("a6"));
+        job.page_size = PrintPageSize::A4;
+        job.copies = 100;
+        assert!(capabilities.check(&job).is_err());
+        job.copies = 1;
+        job.quality = PrintQuality::High;
+        assert!(capabilities.check(&job).unwrap_err().contains("high quality"));
+        job.quality = PrintQuality::Draft;
+        job.pages_per_sheet = 6;
+        assert!(capabilities.check(&job).is_err());
+        job.pages_per_sheet = 4;
+        job.media_type = Some("labels".to_string());
+        assert!(capabilities.check(&job).is_err());
+        job.media_type = Some("photographic".to_string());
+        job.input_tray = Some("tray-1".to_string());
+        assert!(capabilities.check(&job).is_ok());
+        job.input_tray = Some("tray-9".to_string());
+        assert!(capabilities.check(&job).unwrap_err().contains("tray-9"));
+
+        // Colour is adapted instead of rejected, the Epson Ink option is only sent to Epson printers
+        let dir = TempDir::new("cups");
+        let document = dir.join("report.pdf");
+        std::fs::write(&document, b"%PDF-1.4").unwrap();
+        let cups_job_id = service.submit_print_job(&letter_job(false, true), document.to_str().unwrap()).await.unwrap();
+        assert!(service.submit_print_job(&letter_job(true, false), document.to_str().unwrap()).await.is_err());
+
+        let submitted = stub.job(cups_job_id).unwrap().job_attributes;
+        assert_eq!(submitted.get_str("print-color-mode"), Some("monochrome"));
+        assert_eq!(submitted.get_str("sides"), Some("one-sided"));
+        assert!(submitted.get("Ink").is_none());
+    }
+
+    #[test]
+    fn test_print_job_attributes_without_capabilities() {
+        let capabilities = PrinterCapabilities::default();
+        let sides = |job: &PrintJob| print_job_attributes(job, &capabilities).unwrap()
+            .into_iter()
+            .find(|attribute| attribute.name == "sides")
+            .map(|attribute| attribute.values);
+
+        let duplex = letter_job(true, true);
+        assert!(capabilities.check(&duplex).is_ok());
+        assert_eq!(sides(&duplex), Some(vec![IppValue::Keyword("two-sided-long-edge".to_string())]));
+        assert_eq!(sides(&letter_job(false, true)), None);
+    }
+
+    #[test]
+    fn test_cups_job_state_from_ipp() {
+        let expected = [
+            (3, Some(CupsJobState::Pending)),
+            (4, Some(CupsJobState::Held)),
+            (5, Some(CupsJobState::Processing)),
+            (6, Some(CupsJobState::Stopped)),
+            (7, Some(CupsJobState::Canceled)),
+            (8, Some(CupsJobState::Aborted)),
+            (9, Some(CupsJobState::Completed)),
+            (2, None),
+            (10, None),
+        ];
+        for (job_state, state) in expected {
+            assert_eq!(CupsJobState::from_ipp(job_state), state, "job-state {}", job_state);
+        }
+    }
 
+    #[tokio::test]
+    async fn test_cups_over_ipp() {
+        let mut laser = StubPrinter::new("Office_Laser", "HP LaserJet Pro M404");
+        laser.location = "Room 2".to_string();
+        laser.state = 5;
+        let epson = StubPrinter::new("EPSON_L3110_Series", "EPSON L3110 Series")
+            .with_attribute(keywords("sides-supported", &["one-sided", "two-sided-long-edge"]));
+        let stub = IppStub::start(vec![epson, laser], Some("EPSON_L3110_Series")).await;
+        let service = CupsService::with_url(&stub.url());
+
+        assert!(service.is_available().await);
+
+        let printers = service.get_printers().await.unwrap();
+        assert_eq!(printers.len(), 2);
+        assert_eq!((printers[0].vendor.as_str(), printers[0].model.as_str()), ("Epson", "L3110 Series"));
+        assert!(printers[0].is_default);
+        assert_eq!(printers[0].status, "idle");
+        assert_eq!(printers[1].location.as_deref(), Some("Room 2"));
+        assert_eq!(printers[1].status, "stopped");
+        assert_eq!(service.get_printer("Office_Laser").await.unwrap().vendor, "Hp");
+
+        let dir = TempDir::new("cups");
+        let document = dir.join("report.pdf");
+        std::fs::write(&document, b"%PDF-1.4").unwrap();
+        let request = PrintRequest {
+            printer: None,
+            copies: Some(3),
+            pages: Some("1-2,4".to_string()),
+            duplex: Some(true),
+            color: Some(false),
+            page_size: Some(PrintPageSize::Letter),
+            orientation: Some(PrintOrientation::Landscape),
+            scaling: Some(PrintScaling::Fit),
+            pages_per_sheet: Some(2),
+            quality: Some(PrintQuality::High),
+            media_type: Some("photographic-glossy".to_string()),
+            input_tray: Some("rear".to_string()),
+            collate: Some(false),
+        };
+        let job = PrintJob::new("report.pdf".to_string(), "EPSON_L3110_Series".to_string(), "Epson".to_string(), "L3110".to_string(), request, 1);
+
+        let cups_job_id = service.submit_print_job(&job, document.to_str().unwrap()).await.unwrap();
+
+        let submitted = stub.job(cups_job_id).unwrap().job_attributes;
+        assert_eq!(submitted.get_int("copies"), Some(3));
+        assert_eq!(submitted.get("page-ranges").unwrap().values, vec![IppValue::RangeOfInteger(1, 2), IppValue::RangeOfInteger(4, 4)]);
+        assert_eq!(submitted.get_str("sides"), Some("two-sided-long-edge"));
+        assert_eq!(submitted.get_str("print-color-mode"), Some("monochrome"));
+        assert!(submitted.get("media").is_none());
+        assert_eq!(submitted.get("media-col").unwrap().values, vec![IppValue::Collection(vec![
+            IppAttribute::new("media-size", IppValue::Collection(vec![
+                IppAttribute::new("x-dimension", IppValue::Integer(21590)),
+                IppAttribute::new("y-dimension", IppValue::Integer(27940)),
+            ])),
+            IppAttribute::new("media-type", IppValue::Keyword("photographic-glossy".to_string())),
+            IppAttribute::new("media-source", IppValue::Keyword("rear".to_string())),
+        ])]);
+        assert_eq!(submitted.get_int("orientation-requested"), Some(4));
+        assert_eq!(submitted.get_str("print-scaling"), Some("fit"));
+        assert_eq!(submitted.get_int("number-up"), Some(2));
+        assert_eq!(submitted.get_int("print-quality"), Some(5));
+        assert_eq!(submitted.get_str("multiple-document-handling"), Some("separate-documents-uncollated-copies"));
+
+        let status = service.get_job_status(cups_job_id).await.unwrap();
+        assert_eq!(status, CupsJobStatus { state: CupsJobState::Pending, reasons: Vec::new(), impressions_completed: Some(0), media_sheets_completed: Some(0) });
+
+        stub.set_job_state(cups_job_id, 5, &["job-printing"], 2, 1);
+        let status = service.get_job_status(cups_job_id).await.unwrap();
+        assert_eq!(status.state, CupsJobState::Processing);
+        assert!(status.has_reason("job-printing"));
+        assert_eq!(status.impressions_completed, Some(2));
+        assert_eq!(status.media_sheets_completed, Some(1));
+
+        let active = service.get_active_jobs().await.unwrap();
+        assert_eq!(active.len(), 1);
+        assert_eq!((active[0].0, active[0].1.as_str()), (cups_job_id, "EPSON_L3110_Series"));
+
+        service.cancel_job(cups_job_id).await.unwrap();
+        assert_eq!(service.get_job_status(cups_job_id).await.unwrap().state, CupsJobState::Canceled);
+        assert!(service.get_job_status(cups_job_id + 1).await.is_err());
+        assert!(service.get_active_jobs().await.unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_printer_administration() {
+        let stub = IppStub::start(vec![
+            StubPrinter::new("EPSON_L3110_Series", "EPSON L3110 Series"),
+            StubPrinter::new("Office_Laser", "HP LaserJet Pro M404"),
+        ], Some("EPSON_L3110_Series")).await;
+        let service = CupsService::with_url(&stub.url());
+
+        service.disable_printer("Office_Laser", Some("Paper jam")).await.unwrap();
+        let printer = service.get_printer("Office_Laser").await.unwrap();
+        assert_eq!(printer.status, "stopped");
+        assert_eq!(printer.state_message.as_deref(), Some("Paper jam"));
+
+        service.enable_printer("Office_Laser").await.unwrap();
+        let printer = service.get_printer("Office_Laser").await.unwrap();
+        assert_eq!(printer.status, "idle");
+        assert_eq!(printer.state_message, None);
+
+        service.reject_jobs("Office_Laser", Some("Toner ordered")).await.unwrap();
+        let printer = service.get_printer("Office_Laser").await.unwrap();
+        assert_eq!(printer.status, "rejecting");
+        assert!(!printer.accepting_jobs);
+
+        let dir = TempDir::new("cups");
+        let document = dir.join("report.pdf");
+        std::fs::write(&document, b"%PDF-1.4").unwrap();
+        assert!(service.submit_print_job(&letter_job(false, false), document.to_str().unwrap()).await.is_err());
+
+        service.accept_jobs("Office_Laser").await.unwrap();
+        assert!(service.get_printer("Office_Laser").await.unwrap().accepting_jobs);
+
+        let cups_job_id = service.submit_print_job(&letter_job(false, false), document.to_str().unwrap()).await.unwrap();
+        service.purge_jobs("Office_Laser").await.unwrap();
+        assert_eq!(service.get_job_status(cups_job_id).await.unwrap().state, CupsJobState::Canceled);
+
+        service.set_default_printer("Office_Laser").await.unwrap();
+        let printers = service.get_printers().await.unwrap();
+        assert!(!printers[0].is_default);
+        assert!(printers[1].is_default);
+        assert_eq!(stub.printer("Office_Laser").unwrap().state, 3);
+
+        assert!(service.disable_printer("Nope", None).await.is_err());
+    }
 
+    #[tokio::test]
+    async fn test_get_marker_levels() {
+        let epson = StubPrinter::new("EPSON_L3110_Series", "EPSON L3110 Series")
+            .with_attribute(IppAttribute::with_values("marker-names", vec![
+                IppValue::Name("Black ink".to_string()),
+                IppValue::Name("Color ink".to_string()),
+            ]))
+            .with_attribute(IppAttribute::with_values("marker-colors", vec![
+                IppValue::Name("#000000".to_string()),
+                IppValue::Name("#00FFFF#FF00FF#FFFF00".to_string()),
+            ]))
+            .with_attribute(keywords("marker-types", &["ink-cartridge", "ink-cartridge"]))
+            .with_attribute(IppAttribute::with_values("marker-levels", vec![IppValue::Integer(62), IppValue::Integer(-2)]))
+            .with_attribute(IppAttribute::with_values("marker-low-levels", vec![IppValue::Integer(10), IppValue::Integer(10)]));
+        let stub = IppStub::start(vec![epson, StubPrinter::new("Office_Laser", "HP LaserJet Pro M404")], None).await;
+        let service = CupsService::with_url(&stub.url());
+
+        let markers = service.get_marker_levels("EPSON_L3110_Series").await.unwrap();
+        assert_eq!(markers, vec![
+            MarkerLevel {
+                name: "Black ink".to_string(),
+                color: Some("#000000".to_string()),
+                marker_type: Some("ink-cartridge".to_string()),
+                level: Some(62),
+                low_level: Some(10),
+            },
+            MarkerLevel {
+                name: "Color ink".to_string(),
+                color: Some("#00FFFF#FF00FF#FFFF00".to_string()),
+                marker_type: Some("ink-cartridge".to_string()),
+                level: None,
+                low_level: Some(10),
+            },
+        ]);
+        assert!(service.get_marker_levels("Office_Laser").await.unwrap().is_empty());
+        assert!(service.get_marker_levels("Nope").await.is_err());
+    }
+}
@@ -0,0 +1,136 @@
+use std::collections::HashMap;
+use tokio::process::Command;
+use crate::models::DiscoveredPrinter;
+use crate::services::command_exists;
+
+
+pub struct DnsSdService;
+
+impl DnsSdService {
+    pub fn new() -> Self {
+        Self
+    }
+
+    pub async fn is_available(&self) -> bool {
+        command_exists("avahi-browse")
+    }
+
+    /// Browse the local network for _ipp._tcp printers, returns once avahi has answered from its cache
+    pub async fn browse_ipp_printers(&self) -> Result<Vec<DiscoveredPrinter>, String> {
+        let output = Command::new("avahi-browse")
+            .args(["--resolve", "--parsable", "--terminate", "_ipp._tcp"])
+            .output()
+            .await
+            .map_err(|e| format!("Failed to execute avahi-browse: {}", e))?;
+
+        if !output.status.success() {
+            return Err(format!("avahi-browse failed: {}", String::from_utf8_lossy(&output.stderr).trim()));
+        }
+
+        Ok(parse_avahi_browse(&String::from_utf8_lossy(&output.stdout)))
+    }
+}
+
+/// Resolved services of `avahi-browse -rpt`, once per service even when announced over IPv4 and IPv6
+fn parse_avahi_browse(stdout: &str) -> Vec<DiscoveredPrinter> {
+    let mut printers: Vec<DiscoveredPrinter> = Vec::new();
+
+    for line in stdout.lines() {
+        // "=;eth0;IPv4;EPSON\032L3110\032Series;_ipp._tcp;local;EPSON1A2B3C.local;192.168.1.20;631;"txtvers=1" "rp=ipp/print""
+        let fields = line.splitn(10, ';').collect::<Vec<&str>>();
+        if fields.len() < 9 || fields[0] != "=" {
+            continue;
+        }
+
+        let name = unescape(fields[3]);
+        let host = fields[6];
+        let port = fields[8];
+        let txt = fields.get(9).map(|txt| parse_txt(txt)).unwrap_or_default();
+        let resource = txt.get("rp").map(|rp| rp.as_str()).unwrap_or("ipp/print");
+
+        let uri = format!("ipp://{}:{}/{}", host, port, resource);
+        if printers.iter().any(|printer| printer.uri == uri) {
+            continue;
+        }
+
+        printers.push(DiscoveredPrinter {
+            uri,
+            connection: "network".to_string(),
+            make_and_model: txt.get("ty").cloned(),
+            info: Some(name),
+            source: "dnssd".to_string(),
+        });
+    }
+
+    printers
+}
+
+/// avahi escapes names in parsable output, `\032` is a space and `\.` a dot
+fn unescape(value: &str) -> String {
+    let mut bytes = Vec::with_capacity(value.len());
+    let mut rest = value.as_bytes();
+
+    while let Some((&byte, tail)) = rest.split_first() {
+        match (byte, tail) {
+            (b'\\', [a, b, c, tail @ ..]) if a.is_ascii_digit() && b.is_ascii_digit() && c.is_ascii_digit() => {
+                let code = (a - b'0') as u16 * 100 + (b - b'0') as u16 * 10 + (c - b'0') as u16;
+                bytes.push(code as u8);
+                rest = tail;
+            },
+            (b'\\', [escaped, tail @ ..]) => {
+                bytes.push(*escaped);
+                rest = tail;
+            },
+            _ => {
+                bytes.push(byte);
+                rest = tail;
+            },
+        }
+    }
+
+    String::from_utf8_lossy(&bytes).to_string()
+}
+
+/// `"key=value" "key=value"` TXT records as printed by avahi-browse
+fn parse_txt(txt: &str) -> HashMap<String, String> {
+    txt.split('"')
+        .skip(1)
+        .step_by(2)
+        .filter_map(|record| record.split_once('='))
+        .map(|(key, value)| (key.to_string(), value.to_string()))
+        .collect()
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_avahi_browse() {
+        let stdout = concat!(
+            "+;eth0;IPv4;EPSON\\032L3110\\032Series;_ipp._tcp;local\n",
+            "=;eth0;IPv6;EPSON\\032L3110\\032Series;_ipp._tcp;local;EPSON1A2B3C.local;fe80::1;631;\"txtvers=1\" \"rp=ipp/print\" \"ty=EPSON L3110 Series\"\n",
+            "=;eth0;IPv4;EPSON\\032L3110\\032Series;_ipp._tcp;local;EPSON1A2B3C.local;192.168.1.20;631;\"txtvers=1\" \"rp=ipp/print\" \"ty=EPSON L3110 Series\"\n",
+            "=;eth0;IPv4;Office\\032Laser\\0402\\.0;_ipp._tcp;local;laser.local;192.168.1.30;631;\"rp=printers/laser\" \"note=Room 2\"\n",
+        );
+
+        let printers = parse_avahi_browse(stdout);
+        assert_eq!(printers, vec![
+            DiscoveredPrinter {
+                uri: "ipp://EPSON1A2B3C.local:631/ipp/print".to_string(),
+                connection: "network".to_string(),
+                make_and_model: Some("EPSON L3110 Series".to_string()),
+                info: Some("EPSON L3110 Series".to_string()),
+                source: "dnssd".to_string(),
+            },
+            DiscoveredPrinter {
+                uri: "ipp://laser.local:631/printers/laser".to_string(),
+                connection: "network".to_string(),
+                make_and_model: None,
+                info: Some("Office Laser(2.0".to_string()),
+                source: "dnssd".to_string(),
+            },
+        ]);
+    }
+}
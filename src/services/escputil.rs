@@ -1,34 +1,100 @@
+use std::path::PathBuf;
 use tokio::process::Command;
+use crate::models::MarkerLevel;
+use crate::services::backend::{MaintenanceBackend, PrintBackend};
 use crate::services::command_exists;
 use crate::services::cups::CupsService;
 
+/// USB printer class devices, escputil reads the ink levels from the printer itself rather than through CUPS
+const RAW_DEVICE_DIR: &str = "/dev/usb";
 
+#[derive(Clone)]
 pub struct MaintenanceService;
 
 impl MaintenanceService {
     pub fn new() -> Self {
         Self
     }
-    
-    pub async fn is_available(&self) -> bool {
+}
+
+impl MaintenanceBackend for MaintenanceService {
+    async fn is_available(&self) -> bool {
         command_exists("escputil")
     }
 
-    pub async fn do_nozzle_heads_check(&self) -> Result<(), String> {
+    async fn do_nozzle_heads_check(&self) -> Result<(), String> {
         execute_escputil("-n").await
     }
 
-    pub async fn do_nozzle_heads_cleaning(&self) -> Result<(), String> {
+    async fn do_nozzle_heads_cleaning(&self) -> Result<(), String> {
         execute_escputil("-c").await
     }
+
+    /// Ink levels reported by the first USB printer, for Epson drivers that do not pass them to CUPS
+    async fn get_ink_levels(&self) -> Result<Vec<MarkerLevel>, String> {
+        let device = raw_device().ok_or_else(|| format!("No USB printer device in {}", RAW_DEVICE_DIR))?;
+
+        let output = Command::new("escputil")
+            .arg("-r")
+            .arg(&device)
+            .args(["-i", "-q"])
+            .output()
+            .await
+            .map_err(|e| format!("Failed to execute escputil: {}", e))?;
+
+        if !output.status.success() {
+            return Err(format!("escputil could not read the ink levels: {}", String::from_utf8_lossy(&output.stderr).trim()));
+        }
+
+        Ok(parse_ink_levels(&String::from_utf8_lossy(&output.stdout)))
+    }
+}
+
+fn raw_device() -> Option<PathBuf> {
+    std::fs::read_dir(RAW_DEVICE_DIR).ok()?
+        .filter_map(|entry| entry.ok())
+        .map(|entry| entry.path())
+        .filter(|path| path.file_name().and_then(|name| name.to_str()).is_some_and(|name| name.starts_with("lp")))
+        .min()
+}
+
+/// `escputil -i` prints a header and then one "<ink name>  <percent>" line per ink
+fn parse_ink_levels(stdout: &str) -> Vec<MarkerLevel> {
+    stdout.lines()
+        .filter_map(|line| {
+            let (name, level) = line.trim().rsplit_once(char::is_whitespace)?;
+            let level = level.parse::<i32>().ok()?;
+            let name = name.trim();
+
+            (!name.is_empty()).then(|| MarkerLevel {
+                name: name.to_string(),
+                color: ink_color(name).map(|color| color.to_string()),
+                marker_type: Some("ink".to_string()),
+                level: Some(level.clamp(0, 100)),
+                low_level: None,
+            })
+        })
+        .collect()
+}
+
+/// marker-colors style colour of an Epson ink name
+fn ink_color(name: &str) -> Option<&'static str> {
+    match name.to_lowercase().as_str() {
+        "black" | "photo black" | "matte black" => Some("#000000"),
+        "cyan" => Some("#00FFFF"),
+        "light cyan" => Some("#80FFFF"),
+        "magenta" | "vivid magenta" => Some("#FF00FF"),
+        "light magenta" | "vivid light magenta" => Some("#FF80FF"),
+        "yellow" => Some("#FFFF00"),
+        _ => None,
+    }
 }
 
 // TODO choose which printer to clean
 async fn execute_escputil(argument: &str) -> Result<(), String> {
     let service = CupsService::new();
 
-    let printer_name = service.get_printers().await?
-        .get(0)
+    let printer_name = service.get_printers().await?.first()
         .map_or_else(|| "none".to_string(), |printer| printer.name.clone());
 
 
@@ -48,3 +114,34 @@ async fn execute_escputil(argument: &str) -> Result<(), String> {
 
     Ok(())
 }
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_ink_levels() {
+        let stdout = concat!(
+            "           Ink color    Percent remaining\n",
+            "               Black    71\n",
+            "                Cyan    85\n",
+            "             Magenta    40\n",
+            "              Yellow     9\n",
+            "          Light Cyan    100\n",
+            "\n",
+        );
+
+        let levels = parse_ink_levels(stdout);
+        let names = levels.iter().map(|marker| (marker.name.as_str(), marker.level)).collect::<Vec<(&str, Option<i32>)>>();
+        assert_eq!(names, vec![
+            ("Black", Some(71)),
+            ("Cyan", Some(85)),
+            ("Magenta", Some(40)),
+            ("Yellow", Some(9)),
+            ("Light Cyan", Some(100)),
+        ]);
+        assert_eq!(levels[1].color.as_deref(), Some("#00FFFF"));
+        assert!(parse_ink_levels("Cannot read ink levels\n").is_empty());
+    }
+}
@@ -0,0 +1,377 @@
+pub mod protocol;
+#[cfg(test)]
+pub mod stub;
+
+use std::path::Path;
+use std::sync::atomic::{AtomicU32, Ordering};
+use std::time::Duration;
+use bytes::{Bytes, BytesMut};
+use http_body_util::combinators::BoxBody;
+use http_body_util::{BodyExt, Full, StreamBody};
+use hyper::body::Frame;
+use hyper::header::{AUTHORIZATION, CONTENT_TYPE};
+use hyper::{Method, Request, StatusCode};
+use hyper_util::client::legacy::connect::HttpConnector;
+use hyper_util::client::legacy::Client;
+use hyper_util::rt::TokioExecutor;
+use tokio::io::AsyncReadExt;
+
+pub use protocol::{GroupTag, IppAttribute, IppAttributeGroup, IppMessage, IppValue, Operation, STATUS_NOT_FOUND};
+
+
+/// cupsd of the machine the server runs on
+pub const DEFAULT_CUPS_URL: &str = "http://localhost:631";
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(60);
+const DOCUMENT_CHUNK_SIZE: usize = 64 * 1024;
+/// Root certificate cupsd writes at startup, readable by its SystemGroup (lpadmin)
+const LOCAL_CERTIFICATE_PATHS: [&str; 2] = ["/run/cups/certs/0", "/var/run/cups/certs/0"];
+
+type IppBody = BoxBody<Bytes, std::io::Error>;
+
+/// Minimal IPP/1.1 client for the operations the app needs from cupsd
+pub struct IppClient {
+    /// HTTP address of cupsd, e.g. `http://localhost:631`
+    base_url: String,
+    /// Sent as requesting-user-name, CUPS only lets the owner cancel a job
+    user: String,
+    client: Client<HttpConnector, IppBody>,
+    request_id: AtomicU32,
+}
+
+impl IppClient {
+    pub fn new(base_url: &str) -> Self {
+        let mut connector = HttpConnector::new();
+        connector.set_connect_timeout(Some(Duration::from_secs(5)));
+
+        Self {
+            base_url: base_url.trim_end_matches('/').to_string(),
+            user: std::env::var("USER").unwrap_or_else(|_| "web_printer".to_string()),
+            client: Client::builder(TokioExecutor::new()).build(connector),
+            request_id: AtomicU32::new(1),
+        }
+    }
+
+    /// Any IPP answer means cupsd is up, even an error status
+    pub async fn ping(&self) -> bool {
+        let request = self.new_request(Operation::CupsGetDefault, None);
+        self.send("/", &request, None).await.is_ok()
+    }
+
+    /// CUPS-Get-Printers, one attribute group per configured queue
+    pub async fn get_printers(&self, requested: &[&str]) -> Result<Vec<IppAttributeGroup>, String> {
+        let mut request = self.new_request(Operation::CupsGetPrinters, None);
+        request.add(GroupTag::Operation, requested_attributes(requested));
+
+        let response = self.send("/", &request, None).await?;
+        match response.code {
+            STATUS_NOT_FOUND => Ok(Vec::new()),
+            _ => Ok(check_status(response)?.groups(GroupTag::Printer).cloned().collect()),
+        }
+    }
+
+    /// CUPS-Get-Default, `None` when no default destination is set
+    pub async fn get_default_printer(&self) -> Result<Option<String>, String> {
+        let mut request = self.new_request(Operation::CupsGetDefault, None);
+        request.add(GroupTag::Operation, requested_attributes(&["printer-name"]));
+
+        let response = self.send("/", &request, None).await?;
+        match response.code {
+            STATUS_NOT_FOUND => Ok(None),
+            _ => Ok(check_status(response)?
+                .group(GroupTag::Printer)
+                .and_then(|group| group.get_str("printer-name"))
+                .map(|name| name.to_string())),
+        }
+    }
+
+    pub async fn get_printer_attributes(&self, printer: &str, requested: &[&str]) -> Result<IppAttributeGroup, String> {
+        let mut request = self.new_request(Operation::GetPrinterAttributes, Some(("printer-uri", self.printer_uri(printer))));
+        request.add(GroupTag::Operation, requested_attributes(requested));
+
+        let response = check_status(self.send(&printer_path(printer), &request, None).await?)?;
+        response.group(GroupTag::Printer)
+            .cloned()
+            .ok_or_else(|| format!("CUPS returned no attributes for printer '{}'", printer))
+    }
+
+    /// Print-Job with the file streamed after the attributes, returns the CUPS job id
+    pub async fn print_job(
+        &self,
+        printer: &str,
+        job_name: &str,
+        job_attributes: Vec<IppAttribute>,
+        document: &Path
+    ) -> Result<i32, String> {
+        let mut request = self.new_request(Operation::PrintJob, Some(("printer-uri", self.printer_uri(printer))));
+        request.add(GroupTag::Operation, IppAttribute::new("job-name", IppValue::Name(job_name.to_string())));
+        // Let the CUPS filters detect the format
+        request.add(GroupTag::Operation, IppAttribute::new("document-format", IppValue::MimeMediaType("application/octet-stream".to_string())));
+        for attribute in job_attributes {
+            request.add(GroupTag::Job, attribute);
+        }
+
+        let response = check_status(self.send(&printer_path(printer), &request, Some(document)).await?)?;
+        response.group(GroupTag::Job)
+            .and_then(|group| group.get_int("job-id"))
+            .ok_or_else(|| "CUPS returned no job id".to_string())
+    }
+
+    pub async fn get_job_attributes(&self, job_id: i32, requested: &[&str]) -> Result<IppAttributeGroup, String> {
+        let mut request = self.new_request(Operation::GetJobAttributes, Some(("job-uri", self.job_uri(job_id))));
+        request.add(GroupTag::Operation, requested_attributes(requested));
+
+        let response = check_status(self.send("/jobs", &request, None).await?)?;
+        response.group(GroupTag::Job)
+            .cloned()
+            .ok_or_else(|| format!("CUPS returned no attributes for job {}", job_id))
+    }
+
+    /// Get-Jobs across all printers, `which_jobs` is e.g. `not-completed`
+    pub async fn get_jobs(&self, which_jobs: &str, requested: &[&str]) -> Result<Vec<IppAttributeGroup>, String> {
+        let mut request = self.new_request(Operation::GetJobs, Some(("printer-uri", format!("{}/", self.ipp_base()))));
+        request.add(GroupTag::Operation, IppAttribute::new("which-jobs", IppValue::Keyword(which_jobs.to_string())));
+        request.add(GroupTag::Operation, requested_attributes(requested));
+
+        let response = check_status(self.send("/", &request, None).await?)?;
+        Ok(response.groups(GroupTag::Job).cloned().collect())
+    }
+
+    pub async fn cancel_job(&self, job_id: i32) -> Result<(), String> {
+        let request = self.new_request(Operation::CancelJob, Some(("job-uri", self.job_uri(job_id))));
+
+        check_status(self.send("/jobs", &request, None).await?)?;
+        Ok(())
+    }
+
+    /// Pause-Printer, Resume-Printer, Purge-Jobs, CUPS-Accept-Jobs, CUPS-Reject-Jobs or CUPS-Set-Default on
+    /// one queue, `message` becomes the printer-state-message shown while it is stopped or rejecting
+    pub async fn printer_operation(&self, operation: Operation, printer: &str, message: Option<&str>) -> Result<(), String> {
+        let mut request = self.new_request(operation, Some(("printer-uri", self.printer_uri(printer))));
+        if let Some(message) = message {
+            request.add(GroupTag::Operation, IppAttribute::new("printer-state-message", IppValue::Text(message.to_string())));
+        }
+
+        // cupsd only allows these for the system group, authenticate as root with its local certificate
+        let authorization = local_certificate().await.map(|certificate| format!("Local {}", certificate));
+        check_status(self.send_with("/admin/", &request, None, authorization.as_deref()).await?)?;
+        Ok(())
+    }
+
+    /// Request with the operation attributes every operation starts with
+    fn new_request(&self, operation: Operation, target: Option<(&str, String)>) -> IppMessage {
+        let mut request = IppMessage::request(operation, self.request_id.fetch_add(1, Ordering::Relaxed));
+        if let Some((name, uri)) = target {
+            request.add(GroupTag::Operation, IppAttribute::new(name, IppValue::Uri(uri)));
+        }
+        request.add(GroupTag::Operation, IppAttribute::new("requesting-user-name", IppValue::Name(self.user.clone())));
+
+        request
+    }
+
+    /// POST one request to cupsd, the response status is left to the caller
+    async fn send(&self, path: &str, request: &IppMessage, document: Option<&Path>) -> Result<IppMessage, String> {
+        self.send_with(path, request, document, None).await
+    }
+
+    async fn send_with(
+        &self,
+        path: &str,
+        request: &IppMessage,
+        document: Option<&Path>,
+        authorization: Option<&str>
+    ) -> Result<IppMessage, String> {
+        let attributes = Bytes::from(request.encode());
+        let body: IppBody = match document {
+            None => Full::new(attributes).map_err(|never| match never {}).boxed(),
+            Some(document) => {
+                let mut file = tokio::fs::File::open(document)
+                    .await
+                    .map_err(|e| format!("Failed to open {}: {}", document.display(), e))?;
+
+                let frames = async_stream::stream! {
+                    yield Ok(Frame::data(attributes));
+                    loop {
+                        let mut chunk = BytesMut::with_capacity(DOCUMENT_CHUNK_SIZE);
+                        match file.read_buf(&mut chunk).await {
+                            Ok(0) => break,
+                            Ok(_) => yield Ok(Frame::data(chunk.freeze())),
+                            Err(e) => {
+                                yield Err(e);
+                                break;
+                            },
+                        }
+                    }
+                };
+                BodyExt::boxed(StreamBody::new(frames))
+            },
+        };
+
+        let mut http_request = Request::builder()
+            .method(Method::POST)
+            .uri(format!("{}{}", self.base_url, path))
+            .header(CONTENT_TYPE, "application/ipp");
+        if let Some(authorization) = authorization {
+            http_request = http_request.header(AUTHORIZATION, authorization);
+        }
+        let http_request = http_request
+            .body(body)
+            .map_err(|e| format!("Invalid IPP request: {}", e))?;
+
+        let response = tokio::time::timeout(REQUEST_TIMEOUT, self.client.request(http_request))
+            .await
+            .map_err(|_| "CUPS did not answer in time".to_string())?
+            .map_err(|e| format!("Failed to reach CUPS at {}: {}", self.base_url, e))?;
+
+        if response.status() == StatusCode::UNAUTHORIZED || response.status() == StatusCode::FORBIDDEN {
+            return Err(format!(
+                "CUPS refused the request with HTTP {}, the server user has to be in the lpadmin group",
+                response.status()
+            ));
+        }
+        if !response.status().is_success() {
+            return Err(format!("CUPS answered with HTTP {}", response.status()));
+        }
+
+        let bytes = response.into_body()
+            .collect()
+            .await
+            .map_err(|e| format!("Failed to read CUPS response: {}", e))?
+            .to_bytes();
+
+        IppMessage::decode(&bytes)
+    }
+
+    fn ipp_base(&self) -> String {
+        match self.base_url.strip_prefix("https://") {
+            Some(rest) => format!("ipps://{}", rest),
+            None => format!("ipp://{}", self.base_url.trim_start_matches("http://")),
+        }
+    }
+
+    fn printer_uri(&self, printer: &str) -> String {
+        format!("{}{}", self.ipp_base(), printer_path(printer))
+    }
+
+    fn job_uri(&self, job_id: i32) -> String {
+        format!("{}/jobs/{}", self.ipp_base(), job_id)
+    }
+}
+
+/// The name is percent-encoded, so `/`, `?`, `#` or a space in it cannot address another resource
+fn printer_path(printer: &str) -> String {
+    let mut path = String::from("/printers/");
+    for byte in printer.bytes() {
+        match byte {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'.' | b'_' | b'~' => path.push(byte as char),
+            _ => path.push_str(&format!("%{:02X}", byte)),
+        }
+    }
+    path
+}
+
+fn requested_attributes(names: &[&str]) -> IppAttribute {
+    let values = names.iter().map(|name| IppValue::Keyword(name.to_string())).collect();
+    IppAttribute::with_values("requested-attributes", values)
+}
+
+/// Certificate of the root user cupsd accepts as `Authorization: Local`, `None` when unreadable
+async fn local_certificate() -> Option<String> {
+    for path in LOCAL_CERTIFICATE_PATHS {
+        if let Ok(certificate) = tokio::fs::read_to_string(path).await {
+            return Some(certificate.trim().to_string());
+        }
+    }
+    None
+}
+
+fn check_status(response: IppMessage) -> Result<IppMessage, String> {
+    match response.is_success() {
+        true => Ok(response),
+        false => Err(format!("CUPS refused the request: {}", response.status_message())),
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::TempDir;
+    use stub::{IppStub, StubJob, StubPrinter};
+
+    #[tokio::test]
+    async fn test_printer_operations() {
+        let stub = IppStub::start(vec![
+            StubPrinter::new("EPSON_L3110_Series", "EPSON L3110 Series"),
+            StubPrinter::new("Office_Laser", "HP LaserJet Pro M404"),
+        ], Some("Office_Laser")).await;
+        let client = IppClient::new(&stub.url());
+
+        assert!(client.ping().await);
+        assert_eq!(client.get_default_printer().await.unwrap(), Some("Office_Laser".to_string()));
+
+        let printers = client.get_printers(&["printer-name", "printer-make-and-model"]).await.unwrap();
+        let names = printers.iter().filter_map(|printer| printer.get_str("printer-name")).collect::<Vec<&str>>();
+        assert_eq!(names, vec!["EPSON_L3110_Series", "Office_Laser"]);
+
+        let attributes = client.get_printer_attributes("Office_Laser", &["printer-state"]).await.unwrap();
+        assert_eq!(attributes.get_str("printer-make-and-model"), Some("HP LaserJet Pro M404"));
+        assert_eq!(attributes.get_int("printer-state"), Some(3));
+
+        let missing = client.get_printer_attributes("Nope", &[]).await.unwrap_err();
+        assert!(missing.contains("0x0406"), "{}", missing);
+
+        // A name from the URL cannot point at another queue
+        assert_eq!(printer_path("../admin?x#y z"), "/printers/..%2Fadmin%3Fx%23y%20z");
+        assert!(client.get_printer_attributes("Other/Office_Laser", &[]).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_job_operations() {
+        let stub = IppStub::start(vec![StubPrinter::new("EPSON_L3110_Series", "EPSON L3110 Series")], None).await;
+        let client = IppClient::new(&stub.url());
+
+        let dir = TempDir::new("ipp");
+        let document = dir.join("report.txt");
+        let content = "hello printer\n".repeat(10_000);
+        std::fs::write(&document, &content).unwrap();
+
+        let attributes = vec![
+            IppAttribute::new("copies", IppValue::Integer(2)),
+            IppAttribute::new("sides", IppValue::Keyword("two-sided-long-edge".to_string())),
+        ];
+        let job_id = client.print_job("EPSON_L3110_Series", "report.txt", attributes, &document).await.unwrap();
+
+        let StubJob { printer, job_attributes, document, .. } = stub.job(job_id).unwrap();
+        assert_eq!(printer, "EPSON_L3110_Series");
+        assert_eq!(document, content.as_bytes());
+        assert_eq!(job_attributes.get_int("copies"), Some(2));
+        assert_eq!(job_attributes.get_str("sides"), Some("two-sided-long-edge"));
+
+        let job = client.get_job_attributes(job_id, &["job-state"]).await.unwrap();
+        assert_eq!(job.get_int("job-state"), Some(3));
+
+        stub.set_job_state(job_id, 5, &["job-printing"], 1, 1);
+        let job = client.get_job_attributes(job_id, &["job-state", "job-impressions-completed"]).await.unwrap();
+        assert_eq!(job.get_int("job-state"), Some(5));
+        assert_eq!(job.get_int("job-impressions-completed"), Some(1));
+
+        let active = client.get_jobs("not-completed", &["job-id", "job-printer-uri"]).await.unwrap();
+        assert_eq!(active.len(), 1);
+        assert_eq!(active[0].get_int("job-id"), Some(job_id));
+
+        client.cancel_job(job_id).await.unwrap();
+        let job = client.get_job_attributes(job_id, &["job-state"]).await.unwrap();
+        assert_eq!(job.get_int("job-state"), Some(7));
+        assert!(client.get_jobs("not-completed", &[]).await.unwrap().is_empty());
+
+        assert!(client.cancel_job(9999).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_unreachable_cups() {
+        let client = IppClient::new("http://127.0.0.1:9");
+
+        assert!(!client.ping().await);
+        assert!(client.get_printers(&[]).await.is_err());
+    }
+}
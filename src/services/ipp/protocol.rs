@@ -0,0 +1,518 @@
+/// IPP operation ids used to talk to cupsd
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Operation {
+    PrintJob = 0x0002,
+    CancelJob = 0x0008,
+    GetJobAttributes = 0x0009,
+    GetJobs = 0x000A,
+    GetPrinterAttributes = 0x000B,
+    PausePrinter = 0x0010,
+    ResumePrinter = 0x0011,
+    PurgeJobs = 0x0012,
+    CupsGetDefault = 0x4001,
+    CupsGetPrinters = 0x4002,
+    CupsAcceptJobs = 0x4008,
+    CupsRejectJobs = 0x4009,
+    CupsSetDefault = 0x400A,
+}
+
+/// Delimiter tags starting an attribute group
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum GroupTag {
+    Operation = 0x01,
+    Job = 0x02,
+    Printer = 0x04,
+}
+
+const END_OF_ATTRIBUTES_TAG: u8 = 0x03;
+
+const INTEGER_TAG: u8 = 0x21;
+const BOOLEAN_TAG: u8 = 0x22;
+const ENUM_TAG: u8 = 0x23;
+const OCTET_STRING_TAG: u8 = 0x30;
+const DATE_TIME_TAG: u8 = 0x31;
+const RESOLUTION_TAG: u8 = 0x32;
+const RANGE_OF_INTEGER_TAG: u8 = 0x33;
+const BEGIN_COLLECTION_TAG: u8 = 0x34;
+const END_COLLECTION_TAG: u8 = 0x37;
+const TEXT_TAG: u8 = 0x41;
+const NAME_TAG: u8 = 0x42;
+const KEYWORD_TAG: u8 = 0x44;
+const URI_TAG: u8 = 0x45;
+const CHARSET_TAG: u8 = 0x47;
+const NATURAL_LANGUAGE_TAG: u8 = 0x48;
+const MIME_MEDIA_TYPE_TAG: u8 = 0x49;
+const MEMBER_ATTR_NAME_TAG: u8 = 0x4A;
+
+/// Highest status code of the successful-ok class
+const MAX_SUCCESS_STATUS: u16 = 0x00FF;
+pub const STATUS_NOT_FOUND: u16 = 0x0406;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum IppValue {
+    Integer(i32),
+    Boolean(bool),
+    Enum(i32),
+    OctetString(Vec<u8>),
+    DateTime(Vec<u8>),
+    Resolution { cross_feed: i32, feed: i32, units: u8 },
+    RangeOfInteger(i32, i32),
+    Collection(Vec<IppAttribute>),
+    Text(String),
+    Name(String),
+    Keyword(String),
+    Uri(String),
+    Charset(String),
+    NaturalLanguage(String),
+    MimeMediaType(String),
+    /// Out-of-band values like no-value or unknown
+    OutOfBand(u8),
+    /// Any other tag, kept raw
+    Other(u8, Vec<u8>),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct IppAttribute {
+    pub name: String,
+    pub values: Vec<IppValue>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct IppAttributeGroup {
+    pub tag: u8,
+    pub attributes: Vec<IppAttribute>,
+}
+
+/// An IPP request or response, `code` is the operation id or the status code
+#[derive(Debug, Clone, PartialEq)]
+pub struct IppMessage {
+    pub version: (u8, u8),
+    pub code: u16,
+    pub request_id: u32,
+    pub groups: Vec<IppAttributeGroup>,
+    /// Document following the attributes, only Print-Job carries one
+    pub data: Vec<u8>,
+}
+
+impl IppValue {
+    pub fn as_str(&self) -> Option<&str> {
+        match self {
+            IppValue::Text(value) | IppValue::Name(value) | IppValue::Keyword(value) | IppValue::Uri(value) |
+            IppValue::Charset(value) | IppValue::NaturalLanguage(value) | IppValue::MimeMediaType(value) => Some(value),
+            _ => None,
+        }
+    }
+
+    pub fn as_bool(&self) -> Option<bool> {
+        match self {
+            IppValue::Boolean(value) => Some(*value),
+            _ => None,
+        }
+    }
+
+    pub fn as_int(&self) -> Option<i32> {
+        match self {
+            IppValue::Integer(value) | IppValue::Enum(value) => Some(*value),
+            _ => None,
+        }
+    }
+
+    fn tag(&self) -> u8 {
+        match self {
+            IppValue::Integer(_) => INTEGER_TAG,
+            IppValue::Boolean(_) => BOOLEAN_TAG,
+            IppValue::Enum(_) => ENUM_TAG,
+            IppValue::OctetString(_) => OCTET_STRING_TAG,
+            IppValue::DateTime(_) => DATE_TIME_TAG,
+            IppValue::Resolution { .. } => RESOLUTION_TAG,
+            IppValue::RangeOfInteger(_, _) => RANGE_OF_INTEGER_TAG,
+            IppValue::Collection(_) => BEGIN_COLLECTION_TAG,
+            IppValue::Text(_) => TEXT_TAG,
+            IppValue::Name(_) => NAME_TAG,
+            IppValue::Keyword(_) => KEYWORD_TAG,
+            IppValue::Uri(_) => URI_TAG,
+            IppValue::Charset(_) => CHARSET_TAG,
+            IppValue::NaturalLanguage(_) => NATURAL_LANGUAGE_TAG,
+            IppValue::MimeMediaType(_) => MIME_MEDIA_TYPE_TAG,
+            IppValue::OutOfBand(tag) | IppValue::Other(tag, _) => *tag,
+        }
+    }
+
+    fn decode(tag: u8, bytes: &[u8]) -> Result<IppValue, String> {
+        let value = match tag {
+            0x10..=0x1F => IppValue::OutOfBand(tag),
+            INTEGER_TAG | ENUM_TAG => {
+                let value = Reader::new(bytes).i32()?;
+                match tag {
+                    INTEGER_TAG => IppValue::Integer(value),
+                    _ => IppValue::Enum(value),
+                }
+            },
+            BOOLEAN_TAG => IppValue::Boolean(bytes.first().is_some_and(|byte| *byte != 0)),
+            OCTET_STRING_TAG => IppValue::OctetString(bytes.to_vec()),
+            DATE_TIME_TAG => IppValue::DateTime(bytes.to_vec()),
+            RESOLUTION_TAG => {
+                let mut reader = Reader::new(bytes);
+                IppValue::Resolution { cross_feed: reader.i32()?, feed: reader.i32()?, units: reader.u8()? }
+            },
+            RANGE_OF_INTEGER_TAG => {
+                let mut reader = Reader::new(bytes);
+                IppValue::RangeOfInteger(reader.i32()?, reader.i32()?)
+            },
+            TEXT_TAG | NAME_TAG | KEYWORD_TAG | URI_TAG | CHARSET_TAG | NATURAL_LANGUAGE_TAG | MIME_MEDIA_TYPE_TAG => {
+                let value = String::from_utf8_lossy(bytes).to_string();
+                match tag {
+                    TEXT_TAG => IppValue::Text(value),
+                    NAME_TAG => IppValue::Name(value),
+                    KEYWORD_TAG => IppValue::Keyword(value),
+                    URI_TAG => IppValue::Uri(value),
+                    CHARSET_TAG => IppValue::Charset(value),
+                    NATURAL_LANGUAGE_TAG => IppValue::NaturalLanguage(value),
+                    _ => IppValue::MimeMediaType(value),
+                }
+            },
+            _ => IppValue::Other(tag, bytes.to_vec()),
+        };
+
+        Ok(value)
+    }
+
+    fn encode(&self, name: &str, buf: &mut Vec<u8>) {
+        buf.push(self.tag());
+        write_bytes(buf, name.as_bytes());
+
+        match self {
+            IppValue::Integer(value) | IppValue::Enum(value) => write_bytes(buf, &value.to_be_bytes()),
+            IppValue::Boolean(value) => write_bytes(buf, &[*value as u8]),
+            IppValue::Resolution { cross_feed, feed, units } => {
+                let mut value = Vec::with_capacity(9);
+                value.extend_from_slice(&cross_feed.to_be_bytes());
+                value.extend_from_slice(&feed.to_be_bytes());
+                value.push(*units);
+                write_bytes(buf, &value);
+            },
+            IppValue::RangeOfInteger(lower, upper) => {
+                let mut value = Vec::with_capacity(8);
+                value.extend_from_slice(&lower.to_be_bytes());
+                value.extend_from_slice(&upper.to_be_bytes());
+                write_bytes(buf, &value);
+            },
+            IppValue::Collection(members) => {
+                write_bytes(buf, &[]);
+                for member in members {
+                    buf.push(MEMBER_ATTR_NAME_TAG);
+                    write_bytes(buf, &[]);
+                    write_bytes(buf, member.name.as_bytes());
+                    for value in &member.values {
+                        value.encode("", buf);
+                    }
+                }
+                buf.push(END_COLLECTION_TAG);
+                write_bytes(buf, &[]);
+                write_bytes(buf, &[]);
+            },
+            IppValue::OctetString(value) | IppValue::DateTime(value) | IppValue::Other(_, value) => write_bytes(buf, value),
+            IppValue::OutOfBand(_) => write_bytes(buf, &[]),
+            IppValue::Text(value) | IppValue::Name(value) | IppValue::Keyword(value) | IppValue::Uri(value) |
+            IppValue::Charset(value) | IppValue::NaturalLanguage(value) | IppValue::MimeMediaType(value) => {
+                write_bytes(buf, value.as_bytes())
+            },
+        }
+    }
+}
+
+impl IppAttribute {
+    pub fn new(name: &str, value: IppValue) -> Self {
+        Self { name: name.to_string(), values: vec![value] }
+    }
+
+    pub fn with_values(name: &str, values: Vec<IppValue>) -> Self {
+        Self { name: name.to_string(), values }
+    }
+}
+
+impl IppAttributeGroup {
+    pub fn new(tag: GroupTag) -> Self {
+        Self { tag: tag as u8, attributes: Vec::new() }
+    }
+
+    pub fn get(&self, name: &str) -> Option<&IppAttribute> {
+        self.attributes.iter().find(|attribute| attribute.name == name)
+    }
+
+    /// First value of `name` as a string, for text, name, keyword and uri attributes
+    pub fn get_str(&self, name: &str) -> Option<&str> {
+        self.get(name)?.values.first()?.as_str()
+    }
+
+    /// Every string value of `name`, e.g. for 1setOf keyword attributes
+    pub fn get_strs(&self, name: &str) -> Vec<&str> {
+        self.get(name)
+            .map(|attribute| attribute.values.iter().filter_map(IppValue::as_str).collect())
+            .unwrap_or_default()
+    }
+
+    pub fn get_bool(&self, name: &str) -> Option<bool> {
+        self.get(name)?.values.first()?.as_bool()
+    }
+
+    /// First value of `name` as a number, for integer and enum attributes
+    pub fn get_int(&self, name: &str) -> Option<i32> {
+        self.get(name)?.values.first()?.as_int()
+    }
+
+    /// Every number of `name`, e.g. for 1setOf integer attributes
+    pub fn get_ints(&self, name: &str) -> Vec<i32> {
+        self.get(name)
+            .map(|attribute| attribute.values.iter().filter_map(IppValue::as_int).collect())
+            .unwrap_or_default()
+    }
+}
+
+impl IppMessage {
+    /// A request with the mandatory charset and language attributes already in the operation group
+    pub fn request(operation: Operation, request_id: u32) -> Self {
+        Self::with_code(operation as u16, request_id)
+    }
+
+    /// A response to `request` with the mandatory attributes, used by the test stub
+    #[cfg(test)]
+    pub fn response(request: &IppMessage, status: u16) -> Self {
+        let mut response = Self::with_code(status, request.request_id);
+        response.version = request.version;
+        response
+    }
+
+    fn with_code(code: u16, request_id: u32) -> Self {
+        let mut operation_group = IppAttributeGroup::new(GroupTag::Operation);
+        operation_group.attributes.push(IppAttribute::new("attributes-charset", IppValue::Charset("utf-8".to_string())));
+        operation_group.attributes.push(IppAttribute::new("attributes-natural-language", IppValue::NaturalLanguage("en".to_string())));
+
+        Self {
+            version: (2, 0),
+            code,
+            request_id,
+            groups: vec![operation_group],
+            data: Vec::new(),
+        }
+    }
+
+    /// Append an attribute to the last group with `tag`, starting that group when missing
+    pub fn add(&mut self, tag: GroupTag, attribute: IppAttribute) -> &mut Self {
+        match self.groups.iter_mut().rev().find(|group| group.tag == tag as u8) {
+            Some(group) => group.attributes.push(attribute),
+            None => {
+                let mut group = IppAttributeGroup::new(tag);
+                group.attributes.push(attribute);
+                self.groups.push(group);
+            },
+        }
+        self
+    }
+
+    pub fn groups(&self, tag: GroupTag) -> impl Iterator<Item = &IppAttributeGroup> {
+        self.groups.iter().filter(move |group| group.tag == tag as u8)
+    }
+
+    pub fn group(&self, tag: GroupTag) -> Option<&IppAttributeGroup> {
+        self.groups(tag).next()
+    }
+
+    pub fn is_success(&self) -> bool {
+        self.code <= MAX_SUCCESS_STATUS
+    }
+
+    /// Human readable status, cupsd usually sends a status-message along with the code
+    pub fn status_message(&self) -> String {
+        match self.group(GroupTag::Operation).and_then(|group| group.get_str("status-message")) {
+            Some(message) => format!("{} (0x{:04x})", message, self.code),
+            None => format!("status 0x{:04x}", self.code),
+        }
+    }
+
+    pub fn encode(&self) -> Vec<u8> {
+        let mut buf = Vec::new();
+        buf.push(self.version.0);
+        buf.push(self.version.1);
+        buf.extend_from_slice(&self.code.to_be_bytes());
+        buf.extend_from_slice(&self.request_id.to_be_bytes());
+
+        for group in &self.groups {
+            buf.push(group.tag);
+            for attribute in &group.attributes {
+                for (i, value) in attribute.values.iter().enumerate() {
+                    // Additional values of the same attribute have an empty name
+                    let name = if i == 0 { attribute.name.as_str() } else { "" };
+                    value.encode(name, &mut buf);
+                }
+            }
+        }
+        buf.push(END_OF_ATTRIBUTES_TAG);
+        buf.extend_from_slice(&self.data);
+
+        buf
+    }
+
+    pub fn decode(bytes: &[u8]) -> Result<IppMessage, String> {
+        let mut reader = Reader::new(bytes);
+        let version = (reader.u8()?, reader.u8()?);
+        let code = reader.u16()?;
+        let request_id = reader.u32()?;
+        let mut groups: Vec<IppAttributeGroup> = Vec::new();
+
+        loop {
+            let tag = reader.u8()?;
+            match tag {
+                END_OF_ATTRIBUTES_TAG => break,
+                0x00..=0x0F => groups.push(IppAttributeGroup { tag, attributes: Vec::new() }),
+                _ => {
+                    let group = groups.last_mut().ok_or("IPP attribute outside of a group")?;
+                    let name = String::from_utf8_lossy(reader.bytes()?).to_string();
+                    let value = reader.value(tag)?;
+
+                    match (name.is_empty(), group.attributes.last_mut()) {
+                        (true, Some(attribute)) => attribute.values.push(value),
+                        (true, None) => return Err("IPP additional value without an attribute".to_string()),
+                        (false, _) => group.attributes.push(IppAttribute::new(&name, value)),
+                    }
+                },
+            }
+        }
+
+        Ok(IppMessage {
+            version,
+            code,
+            request_id,
+            groups,
+            data: reader.rest().to_vec(),
+        })
+    }
+}
+
+fn write_bytes(buf: &mut Vec<u8>, bytes: &[u8]) {
+    buf.extend_from_slice(&(bytes.len() as u16).to_be_bytes());
+    buf.extend_from_slice(bytes);
+}
+
+struct Reader<'a> {
+    buf: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> Reader<'a> {
+    fn new(buf: &'a [u8]) -> Self {
+        Self { buf, pos: 0 }
+    }
+
+    fn take(&mut self, len: usize) -> Result<&'a [u8], String> {
+        let bytes = self.buf.get(self.pos..self.pos + len).ok_or("Truncated IPP message")?;
+        self.pos += len;
+        Ok(bytes)
+    }
+
+    fn u8(&mut self) -> Result<u8, String> {
+        Ok(self.take(1)?[0])
+    }
+
+    fn u16(&mut self) -> Result<u16, String> {
+        let bytes = self.take(2)?;
+        Ok(u16::from_be_bytes([bytes[0], bytes[1]]))
+    }
+
+    fn u32(&mut self) -> Result<u32, String> {
+        let bytes = self.take(4)?;
+        Ok(u32::from_be_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]))
+    }
+
+    fn i32(&mut self) -> Result<i32, String> {
+        self.u32().map(|value| value as i32)
+    }
+
+    /// Length prefixed bytes
+    fn bytes(&mut self) -> Result<&'a [u8], String> {
+        let len = self.u16()? as usize;
+        self.take(len)
+    }
+
+    fn rest(&self) -> &'a [u8] {
+        &self.buf[self.pos..]
+    }
+
+    /// Value of an attribute whose tag and name were already read
+    fn value(&mut self, tag: u8) -> Result<IppValue, String> {
+        let bytes = self.bytes()?;
+        match tag {
+            BEGIN_COLLECTION_TAG => self.collection().map(IppValue::Collection),
+            _ => IppValue::decode(tag, bytes),
+        }
+    }
+
+    /// Members up to the matching endCollection, each member name comes as a memberAttrName value
+    fn collection(&mut self) -> Result<Vec<IppAttribute>, String> {
+        let mut members: Vec<IppAttribute> = Vec::new();
+
+        loop {
+            let tag = self.u8()?;
+            self.bytes()?;
+
+            match tag {
+                END_COLLECTION_TAG => {
+                    self.bytes()?;
+                    return Ok(members);
+                },
+                MEMBER_ATTR_NAME_TAG => {
+                    let name = String::from_utf8_lossy(self.bytes()?).to_string();
+                    members.push(IppAttribute::with_values(&name, Vec::new()));
+                },
+                _ => {
+                    let value = self.value(tag)?;
+                    members.last_mut()
+                        .ok_or("IPP collection value without a member name")?
+                        .values.push(value);
+                },
+            }
+        }
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_encode_decode_roundtrip() {
+        let mut request = IppMessage::request(Operation::PrintJob, 7);
+        request
+            .add(GroupTag::Operation, IppAttribute::new("printer-uri", IppValue::Uri("ipp://localhost/printers/epson".to_string())))
+            .add(GroupTag::Job, IppAttribute::new("copies", IppValue::Integer(2)))
+            .add(GroupTag::Job, IppAttribute::with_values("page-ranges", vec![IppValue::RangeOfInteger(1, 3), IppValue::RangeOfInteger(5, 5)]))
+            .add(GroupTag::Job, IppAttribute::new("media-col", IppValue::Collection(vec![
+                IppAttribute::new("media-source", IppValue::Keyword("main".to_string())),
+                IppAttribute::new("media-size", IppValue::Collection(vec![
+                    IppAttribute::new("x-dimension", IppValue::Integer(21000)),
+                    IppAttribute::new("y-dimension", IppValue::Integer(29700)),
+                ])),
+            ])))
+            .add(GroupTag::Job, IppAttribute::new("job-name", IppValue::OutOfBand(0x13)));
+        request.data = b"%PDF-1.4".to_vec();
+
+        let decoded = IppMessage::decode(&request.encode()).unwrap();
+        assert_eq!(decoded, request);
+
+        let job = decoded.group(GroupTag::Job).unwrap();
+        assert_eq!(job.get_int("copies"), Some(2));
+        assert_eq!(job.get("page-ranges").unwrap().values.len(), 2);
+        assert_eq!(decoded.group(GroupTag::Operation).unwrap().get_str("printer-uri"), Some("ipp://localhost/printers/epson"));
+    }
+
+    #[test]
+    fn test_decode_rejects_truncated_message() {
+        let mut request = IppMessage::request(Operation::GetJobs, 1);
+        request.add(GroupTag::Operation, IppAttribute::new("which-jobs", IppValue::Keyword("not-completed".to_string())));
+        let bytes = request.encode();
+
+        assert!(IppMessage::decode(&bytes[..bytes.len() - 4]).is_err());
+        assert!(IppMessage::decode(&bytes[..5]).is_err());
+    }
+}
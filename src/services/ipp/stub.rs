@@ -0,0 +1,313 @@
+//! In-process stand-in for cupsd answering the IPP operations [`super::IppClient`] sends
+
+use std::convert::Infallible;
+use std::sync::{Arc, Mutex};
+use bytes::Bytes;
+use http_body_util::{BodyExt, Full};
+use hyper::body::Incoming;
+use hyper::server::conn::http1;
+use hyper::service::service_fn;
+use hyper::{Request, Response};
+use hyper_util::rt::TokioIo;
+use tokio::net::TcpListener;
+use tokio::task::JoinHandle;
+use super::protocol::{GroupTag, IppAttribute, IppAttributeGroup, IppMessage, IppValue, Operation, STATUS_NOT_FOUND};
+
+const STATUS_OK: u16 = 0x0000;
+const STATUS_BAD_REQUEST: u16 = 0x0400;
+const STATUS_NOT_POSSIBLE: u16 = 0x0404;
+const STATUS_NOT_ACCEPTING: u16 = 0x0506;
+const STATUS_OPERATION_NOT_SUPPORTED: u16 = 0x0501;
+
+#[derive(Debug, Clone)]
+pub struct StubPrinter {
+    pub name: String,
+    pub make_and_model: String,
+    pub info: String,
+    pub location: String,
+    /// printer-state, 3 idle, 4 processing, 5 stopped
+    pub state: i32,
+    pub state_message: String,
+    pub accepting_jobs: bool,
+    /// Sent along with the basic attributes, e.g. the -supported ones
+    pub extra_attributes: Vec<IppAttribute>,
+}
+
+#[derive(Debug, Clone)]
+pub struct StubJob {
+    pub id: i32,
+    pub printer: String,
+    pub user: String,
+    /// job-state, 3 pending up to 9 completed
+    pub state: i32,
+    pub state_reasons: Vec<String>,
+    pub impressions_completed: i32,
+    pub media_sheets_completed: i32,
+    pub job_attributes: IppAttributeGroup,
+    pub document: Vec<u8>,
+}
+
+#[derive(Default)]
+struct StubState {
+    printers: Vec<StubPrinter>,
+    default_printer: Option<String>,
+    jobs: Vec<StubJob>,
+}
+
+pub struct IppStub {
+    port: u16,
+    state: Arc<Mutex<StubState>>,
+    server: JoinHandle<()>,
+}
+
+impl StubPrinter {
+    pub fn new(name: &str, make_and_model: &str) -> Self {
+        Self {
+            name: name.to_string(),
+            make_and_model: make_and_model.to_string(),
+            info: name.replace('_', " "),
+            location: String::new(),
+            state: 3,
+            state_message: String::new(),
+            accepting_jobs: true,
+            extra_attributes: Vec::new(),
+        }
+    }
+
+    pub fn with_attribute(mut self, attribute: IppAttribute) -> Self {
+        self.extra_attributes.push(attribute);
+        self
+    }
+
+    fn attributes(&self, base_uri: &str) -> IppAttributeGroup {
+        let mut group = IppAttributeGroup::new(GroupTag::Printer);
+        group.attributes = vec![
+            IppAttribute::new("printer-name", IppValue::Name(self.name.clone())),
+            IppAttribute::new("printer-uri-supported", IppValue::Uri(format!("{}/printers/{}", base_uri, self.name))),
+            IppAttribute::new("printer-make-and-model", IppValue::Text(self.make_and_model.clone())),
+            IppAttribute::new("printer-info", IppValue::Text(self.info.clone())),
+            IppAttribute::new("printer-location", IppValue::Text(self.location.clone())),
+            IppAttribute::new("printer-state", IppValue::Enum(self.state)),
+            IppAttribute::new("printer-state-message", IppValue::Text(self.state_message.clone())),
+            IppAttribute::new("printer-is-accepting-jobs", IppValue::Boolean(self.accepting_jobs)),
+        ];
+        group.attributes.extend(self.extra_attributes.iter().cloned());
+        group
+    }
+}
+
+impl StubJob {
+    fn attributes(&self, base_uri: &str) -> IppAttributeGroup {
+        let reasons = self.state_reasons.iter().map(|reason| IppValue::Keyword(reason.clone())).collect();
+
+        let mut group = IppAttributeGroup::new(GroupTag::Job);
+        group.attributes = vec![
+            IppAttribute::new("job-id", IppValue::Integer(self.id)),
+            IppAttribute::new("job-uri", IppValue::Uri(format!("{}/jobs/{}", base_uri, self.id))),
+            IppAttribute::new("job-printer-uri", IppValue::Uri(format!("{}/printers/{}", base_uri, self.printer))),
+            IppAttribute::new("job-originating-user-name", IppValue::Name(self.user.clone())),
+            IppAttribute::new("job-state", IppValue::Enum(self.state)),
+            IppAttribute::with_values("job-state-reasons", reasons),
+            IppAttribute::new("job-impressions-completed", IppValue::Integer(self.impressions_completed)),
+            IppAttribute::new("job-media-sheets-completed", IppValue::Integer(self.media_sheets_completed)),
+        ];
+        group
+    }
+}
+
+impl IppStub {
+    pub async fn start(printers: Vec<StubPrinter>, default_printer: Option<&str>) -> Self {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let state = Arc::new(Mutex::new(StubState {
+            printers,
+            default_printer: default_printer.map(|name| name.to_string()),
+            jobs: Vec::new(),
+        }));
+
+        let server_state = state.clone();
+        let server = tokio::spawn(async move {
+            while let Ok((stream, _)) = listener.accept().await {
+                let state = server_state.clone();
+                tokio::spawn(async move {
+                    let service = service_fn(move |request| handle(request, state.clone(), port));
+                    let _ = http1::Builder::new().serve_connection(TokioIo::new(stream), service).await;
+                });
+            }
+        });
+
+        Self { port, state, server }
+    }
+
+    pub fn url(&self) -> String {
+        format!("http://127.0.0.1:{}", self.port)
+    }
+
+    pub fn printer(&self, name: &str) -> Option<StubPrinter> {
+        self.state.lock().unwrap().printers.iter().find(|printer| printer.name == name).cloned()
+    }
+
+    pub fn job(&self, job_id: i32) -> Option<StubJob> {
+        self.state.lock().unwrap().jobs.iter().find(|job| job.id == job_id).cloned()
+    }
+
+    /// Move a job along as cupsd would while printing
+    pub fn set_job_state(&self, job_id: i32, state: i32, reasons: &[&str], impressions_completed: i32, media_sheets_completed: i32) {
+        let mut stub_state = self.state.lock().unwrap();
+        if let Some(job) = stub_state.jobs.iter_mut().find(|job| job.id == job_id) {
+            job.state = state;
+            job.state_reasons = reasons.iter().map(|reason| reason.to_string()).collect();
+            job.impressions_completed = impressions_completed;
+            job.media_sheets_completed = media_sheets_completed;
+        }
+    }
+}
+
+impl Drop for IppStub {
+    fn drop(&mut self) {
+        self.server.abort();
+    }
+}
+
+async fn handle(request: Request<Incoming>, state: Arc<Mutex<StubState>>, port: u16) -> Result<Response<Full<Bytes>>, Infallible> {
+    let body = request.into_body().collect().await.map(|body| body.to_bytes()).unwrap_or_default();
+    let response = match IppMessage::decode(&body) {
+        Ok(ipp_request) => answer(&ipp_request, &mut state.lock().unwrap(), &format!("ipp://127.0.0.1:{}", port)),
+        Err(_) => return Ok(Response::builder().status(400).body(Full::new(Bytes::new())).unwrap()),
+    };
+
+    Ok(Response::builder()
+        .header("Content-Type", "application/ipp")
+        .body(Full::new(Bytes::from(response.encode())))
+        .unwrap())
+}
+
+fn answer(request: &IppMessage, state: &mut StubState, base_uri: &str) -> IppMessage {
+    let operation = request.group(GroupTag::Operation);
+    let target = |name: &str| operation
+        .and_then(|group| group.get_str(name))
+        .and_then(|uri| uri.rsplit('/').next())
+        .unwrap_or_default()
+        .to_string();
+    let find_printer = |state: &StubState, name: &str| state.printers.iter().find(|printer| printer.name == name).cloned();
+
+    let mut response = IppMessage::response(request, STATUS_OK);
+    let code = match request.code {
+        code if code == Operation::CupsGetDefault as u16 => {
+            match state.default_printer.as_deref().and_then(|name| find_printer(state, name)) {
+                Some(printer) => {
+                    response.groups.push(printer.attributes(base_uri));
+                    STATUS_OK
+                },
+                None => STATUS_NOT_FOUND,
+            }
+        },
+        code if code == Operation::CupsGetPrinters as u16 => {
+            response.groups.extend(state.printers.iter().map(|printer| printer.attributes(base_uri)));
+            if state.printers.is_empty() { STATUS_NOT_FOUND } else { STATUS_OK }
+        },
+        code if code == Operation::GetPrinterAttributes as u16 => {
+            match find_printer(state, &target("printer-uri")) {
+                Some(printer) => {
+                    response.groups.push(printer.attributes(base_uri));
+                    STATUS_OK
+                },
+                None => STATUS_NOT_FOUND,
+            }
+        },
+        code if code == Operation::PrintJob as u16 => {
+            match find_printer(state, &target("printer-uri")) {
+                Some(printer) if !printer.accepting_jobs => STATUS_NOT_ACCEPTING,
+                Some(printer) => {
+                    let job = StubJob {
+                        id: state.jobs.len() as i32 + 1,
+                        printer: printer.name,
+                        user: operation.and_then(|group| group.get_str("requesting-user-name")).unwrap_or_default().to_string(),
+                        state: 3,
+                        state_reasons: vec!["none".to_string()],
+                        impressions_completed: 0,
+                        media_sheets_completed: 0,
+                        job_attributes: request.group(GroupTag::Job).cloned().unwrap_or_else(|| IppAttributeGroup::new(GroupTag::Job)),
+                        document: request.data.clone(),
+                    };
+                    response.groups.push(job.attributes(base_uri));
+                    state.jobs.push(job);
+                    STATUS_OK
+                },
+                None => STATUS_NOT_FOUND,
+            }
+        },
+        code if code == Operation::GetJobAttributes as u16 || code == Operation::CancelJob as u16 => {
+            let job = target("job-uri").parse::<i32>().ok()
+                .and_then(|job_id| state.jobs.iter_mut().find(|job| job.id == job_id));
+
+            match job {
+                None => STATUS_NOT_FOUND,
+                Some(job) if code == Operation::CancelJob as u16 => {
+                    match job.state < 7 {
+                        true => {
+                            job.state = 7;
+                            job.state_reasons = vec!["job-canceled-by-user".to_string()];
+                            STATUS_OK
+                        },
+                        false => STATUS_NOT_POSSIBLE,
+                    }
+                },
+                Some(job) => {
+                    response.groups.push(job.attributes(base_uri));
+                    STATUS_OK
+                },
+            }
+        },
+        code if code == Operation::GetJobs as u16 => {
+            let completed = operation.and_then(|group| group.get_str("which-jobs")) == Some("completed");
+            response.groups.extend(state.jobs.iter()
+                .filter(|job| (job.state >= 7) == completed)
+                .map(|job| job.attributes(base_uri)));
+            STATUS_OK
+        },
+        code if [Operation::PausePrinter, Operation::ResumePrinter, Operation::PurgeJobs, Operation::CupsAcceptJobs,
+            Operation::CupsRejectJobs, Operation::CupsSetDefault].iter().any(|operation| *operation as u16 == code) => {
+            let name = target("printer-uri");
+            let message = operation.and_then(|group| group.get_str("printer-state-message")).unwrap_or_default().to_string();
+
+            match state.printers.iter_mut().find(|printer| printer.name == name) {
+                None => STATUS_NOT_FOUND,
+                Some(printer) => {
+                    match code {
+                        code if code == Operation::PausePrinter as u16 => {
+                            printer.state = 5;
+                            printer.state_message = message;
+                        },
+                        code if code == Operation::ResumePrinter as u16 => {
+                            printer.state = 3;
+                            printer.state_message = String::new();
+                        },
+                        code if code == Operation::CupsAcceptJobs as u16 => printer.accepting_jobs = true,
+                        code if code == Operation::CupsRejectJobs as u16 => {
+                            printer.accepting_jobs = false;
+                            printer.state_message = message;
+                        },
+                        code if code == Operation::CupsSetDefault as u16 => state.default_printer = Some(name),
+                        _ => {
+                            for job in state.jobs.iter_mut().filter(|job| job.printer == name && job.state < 7) {
+                                job.state = 7;
+                                job.state_reasons = vec!["job-canceled-by-operator".to_string()];
+                            }
+                        },
+                    }
+                    STATUS_OK
+                },
+            }
+        },
+        _ if operation.is_none() => STATUS_BAD_REQUEST,
+        _ => STATUS_OPERATION_NOT_SUPPORTED,
+    };
+
+    response.code = code;
+    if code != STATUS_OK {
+        response.groups.truncate(1);
+        response.add(GroupTag::Operation, IppAttribute::new("status-message", IppValue::Text("stub refused the request".to_string())));
+    }
+    response
+}
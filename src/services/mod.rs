@@ -1,4 +1,8 @@
+pub mod backend;
+pub mod convert;
 pub mod cups;
+pub mod dnssd;
+pub mod ipp;
 pub mod sane;
 
 pub mod escputil;
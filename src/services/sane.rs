@@ -1,25 +1,30 @@
+use std::path::Path;
 use tokio::process::Command;
 use crate::models::{Scanner, ScanJob};
+use crate::services::backend::ScanBackend;
 use crate::services::command_exists;
 use crate::capitalize;
 
 
 
+#[derive(Clone)]
 pub struct SaneService;
 
 impl SaneService {
     pub fn new() -> Self {
         Self
     }
+}
 
+impl ScanBackend for SaneService {
     /// Check if SANE is available
-    pub async fn is_available(&self) -> bool {
+    async fn is_available(&self) -> bool {
         command_exists("scanimage")
 
     }
 
     /// Get list of available scanners
-    pub async fn get_scanners(&self) -> Result<Vec<Scanner>, String> {
+    async fn get_scanners(&self) -> Result<Vec<Scanner>, String> {
         let output = Command::new("sane-find-scanner")
             .output()
             .await
@@ -36,30 +41,29 @@ impl SaneService {
             if line.contains("found") && line.contains("scanner") {
                 // "found possible USB scanner (vendor=0x04b8 [EPSON], product=0x1142 [L3110 Series]) at libusb:001:002"
                 let parts: Vec<&str> = line.split_whitespace().collect();
-                if parts.len() >= 2 {
-                    if let Some(at_pos) = line.find(" at ") {
-                        let device_part = &line[at_pos + 4..];
-                        let vendor = self.extract_vendor_from_line(line);
-                        let model = self.extract_model_from_line(line);
-                        let partial_name = device_part.trim();
-
-                        // let name = format!("epson2:{partial_name}");
-
-                        let name = match self.get_scanner_name_from_scanimage(partial_name).await {
-                            Ok(name) => name,
-                            Err(e) => {
-                                log::warn!("{e} - for {partial_name}");
-                                continue
-                            },
-                        };
-
-                        scanners.push(Scanner {
-                            name,
-                            vendor: vendor.unwrap_or("Unknown".to_string()),
-                            model: model.unwrap_or("Scanner".to_string()),
-                            device_type: "flatbed scanner".to_string(),
-                        });
-                    }
+                if parts.len() >= 2
+                    && let Some(at_pos) = line.find(" at ") {
+                    let device_part = &line[at_pos + 4..];
+                    let vendor = self.extract_vendor_from_line(line);
+                    let model = self.extract_model_from_line(line);
+                    let partial_name = device_part.trim();
+
+                    // let name = format!("epson2:{partial_name}");
+
+                    let name = match self.get_scanner_name_from_scanimage(partial_name).await {
+                        Ok(name) => name,
+                        Err(e) => {
+                            log::warn!("{e} - for {partial_name}");
+                            continue
+                        },
+                    };
+
+                    scanners.push(Scanner {
+                        name,
+                        vendor: vendor.unwrap_or("Unknown".to_string()),
+                        model: model.unwrap_or("Scanner".to_string()),
+                        device_type: "flatbed scanner".to_string(),
+                    });
                 }
             }
         }
@@ -67,6 +71,56 @@ impl SaneService {
         Ok(scanners)
     }
 
+    /// Start a scan job
+    async fn start_scan(&self, job: &ScanJob, scans_dir: &Path) -> Result<String, String> {
+        let output_path = job.get_file_path(scans_dir)
+            .ok_or("No output filename specified")?;
+
+        let mut cmd = Command::new("scanimage");
+
+        // Add device option
+        cmd.args(["-d", &job.scanner]);
+
+        // Add resolution
+        cmd.args(["--resolution", &job.resolution.to_string()]);
+
+        // Add format
+        let format_arg = match job.format {
+            crate::models::ScanFormat::Pdf => "pdf",
+            crate::models::ScanFormat::Jpeg => "jpeg",
+            crate::models::ScanFormat::Png => "png",
+            crate::models::ScanFormat::Tiff => "tiff",
+        };
+        cmd.args(["--format", format_arg]);
+
+        // Add color mode
+        let mode_arg = match job.color_mode {
+            crate::models::ColorMode::Color => "Color",
+            crate::models::ColorMode::Grayscale => "Gray",
+            crate::models::ColorMode::Monochrome => "Lineart",
+        };
+        cmd.args(["--mode", mode_arg]);
+
+        // Add output file
+        cmd.args(["-o", &output_path]);
+
+        // Don't leave scanimage writing to the file once the server has shut down
+        cmd.kill_on_drop(true);
+
+        let output = cmd.output()
+            .await
+            .map_err(|e| format!("Failed to execute scanimage: {}", e))?;
+
+        if !output.status.success() {
+            let stderr = String::from_utf8_lossy(&output.stderr);
+            return Err(format!("Scan failed: {}", stderr));
+        }
+
+        Ok(output_path)
+    }
+}
+
+impl SaneService {
     async fn get_scanner_name_from_scanimage(&self, pattern_name: &str) -> Result<String, String> {
         let output = Command::new("scanimage")
             .arg("-L")
@@ -116,51 +170,6 @@ impl SaneService {
 
         None
     }
-
-    /// Start a scan job
-    pub async fn start_scan(&self, job: &ScanJob) -> Result<String, String> {
-        let output_path = job.get_file_path()
-            .ok_or("No output filename specified")?;
-
-        let mut cmd = Command::new("scanimage");
-
-        // Add device option
-        cmd.args(["-d", &job.scanner]);
-
-        // Add resolution
-        cmd.args(["--resolution", &job.resolution.to_string()]);
-
-        // Add format
-        let format_arg = match job.format {
-            crate::models::ScanFormat::Pdf => "pdf",
-            crate::models::ScanFormat::Jpeg => "jpeg",
-            crate::models::ScanFormat::Png => "png",
-            crate::models::ScanFormat::Tiff => "tiff",
-        };
-        cmd.args(["--format", format_arg]);
-
-        // Add color mode
-        let mode_arg = match job.color_mode {
-            crate::models::ColorMode::Color => "Color",
-            crate::models::ColorMode::Grayscale => "Gray",
-            crate::models::ColorMode::Monochrome => "Lineart",
-        };
-        cmd.args(["--mode", mode_arg]);
-
-        // Add output file
-        cmd.args(["-o", &output_path]);
-
-        let output = cmd.output()
-            .await
-            .map_err(|e| format!("Failed to execute scanimage: {}", e))?;
-
-        if !output.status.success() {
-            let stderr = String::from_utf8_lossy(&output.stderr);
-            return Err(format!("Scan failed: {}", stderr));
-        }
-
-        Ok(output_path)
-    }
 }
 
 
@@ -0,0 +1,327 @@
+use std::net::ToSocketAddrs;
+use std::path::{Path, PathBuf};
+use config::{Config, Environment, File};
+use serde::Deserialize;
+
+const SETTINGS_FILE: &str = "config/settings.toml";
+const ENV_PREFIX: &str = "WEB_PRINTER";
+
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct Settings {
+    pub server: ServerSettings,
+    pub tls: TlsSettings,
+    pub store: StoreSettings,
+    pub auth: AuthSettings,
+    pub redis: RedisSettings,
+    pub storage: StorageSettings,
+    pub devices: DeviceSettings,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct ServerSettings {
+    pub bind_address: String,
+    pub workers: usize,
+    pub max_upload_size: usize,
+    /// How long a shutdown waits for the running job before checkpointing it
+    pub shutdown_timeout_secs: u64,
+}
+
+/// How the server is exposed: plain HTTP, HTTPS only, or HTTPS plus an HTTP listener redirecting to it
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum TlsMode {
+    Http,
+    Https,
+    Both,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct TlsSettings {
+    pub mode: TlsMode,
+    /// HTTPS listener address, `server.bind_address` stays the plain HTTP one
+    pub bind_address: String,
+    pub cert_path: PathBuf,
+    pub key_path: PathBuf,
+    /// Generate and persist a self-signed certificate when none is found at `cert_path`/`key_path`
+    pub self_signed: bool,
+    pub acme: AcmeSettings,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct AcmeSettings {
+    pub enabled: bool,
+    pub directory_url: String,
+    pub contact_email: Option<String>,
+    pub domains: Vec<String>,
+    /// Extra root certificate for the directory, e.g. the Pebble test CA
+    pub root_ca_path: Option<PathBuf>,
+    pub account_path: PathBuf,
+    pub renew_before_days: i64,
+    pub check_interval_hours: u64,
+}
+
+/// Where sessions and rate-limit counters live
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum StoreBackend {
+    /// Redis at `redis.url`, shared between processes
+    Redis,
+    /// Sessions in the SQLite database, rate limits in memory, no external service needed
+    Embedded,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct StoreSettings {
+    pub backend: StoreBackend,
+}
+
+#[derive(Clone, Deserialize)]
+pub struct AuthSettings {
+    /// Account created on first start when the users table is empty
+    pub admin_username: String,
+    /// Password for that account, a random one is generated and logged when unset
+    pub admin_password: Option<String>,
+    /// Failed logins allowed from one address before it is locked out
+    pub max_failed_logins: u32,
+    pub lockout_minutes: u64,
+}
+
+impl std::fmt::Debug for AuthSettings {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("AuthSettings")
+            .field("admin_username", &self.admin_username)
+            .field("admin_password", &self.admin_password.as_ref().map(|_| "<redacted>"))
+            .field("max_failed_logins", &self.max_failed_logins)
+            .field("lockout_minutes", &self.lockout_minutes)
+            .finish()
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct RedisSettings {
+    pub url: String,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct StorageSettings {
+    pub uploads_dir: PathBuf,
+    pub scans_dir: PathBuf,
+    pub database_path: PathBuf,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct DeviceSettings {
+    /// Seconds between looking for printers and scanners that were plugged in or removed, 0 disables it
+    pub refresh_interval_secs: u64,
+    /// Seconds between reading the ink and toner levels, 0 disables the monitoring
+    pub ink_check_interval_secs: u64,
+    pub backend: DeviceBackend,
+}
+
+/// What the printers, scanners and maintenance commands are backed by
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum DeviceBackend {
+    /// CUPS, SANE and escputil on this machine
+    System,
+    /// Made-up printers and a scanner kept in memory, for trying the app without hardware
+    Simulated,
+}
+
+impl Settings {
+    /// Load settings from config/settings.toml, overridden by WEB_PRINTER_* environment variables
+    ///
+    /// Nested keys are separated with a double underscore, e.g. `WEB_PRINTER_SERVER__BIND_ADDRESS`.
+    pub fn load() -> Result<Self, String> {
+        Self::load_from(SETTINGS_FILE)
+    }
+
+    pub fn load_from(path: &str) -> Result<Self, String> {
+        let settings: Settings = Config::builder()
+            .set_default("server.bind_address", "0.0.0.0:8080").map_err(|e| e.to_string())?
+            .set_default("server.workers", 4).map_err(|e| e.to_string())?
+            .set_default("server.max_upload_size", 50 * 1024 * 1024).map_err(|e| e.to_string())?
+            .set_default("server.shutdown_timeout_secs", 60).map_err(|e| e.to_string())?
+            .set_default("tls.mode", "http").map_err(|e| e.to_string())?
+            .set_default("tls.bind_address", "0.0.0.0:8443").map_err(|e| e.to_string())?
+            .set_default("tls.cert_path", "certs/cert.pem").map_err(|e| e.to_string())?
+            .set_default("tls.key_path", "certs/key.pem").map_err(|e| e.to_string())?
+            .set_default("tls.self_signed", true).map_err(|e| e.to_string())?
+            .set_default("tls.acme.enabled", false).map_err(|e| e.to_string())?
+            .set_default("tls.acme.directory_url", "https://acme-v02.api.letsencrypt.org/directory").map_err(|e| e.to_string())?
+            .set_default("tls.acme.domains", Vec::<String>::new()).map_err(|e| e.to_string())?
+            .set_default("tls.acme.account_path", "certs/acme_account.json").map_err(|e| e.to_string())?
+            .set_default("tls.acme.renew_before_days", 30).map_err(|e| e.to_string())?
+            .set_default("tls.acme.check_interval_hours", 12).map_err(|e| e.to_string())?
+            .set_default("store.backend", "redis").map_err(|e| e.to_string())?
+            .set_default("auth.admin_username", "admin").map_err(|e| e.to_string())?
+            .set_default("auth.max_failed_logins", 5).map_err(|e| e.to_string())?
+            .set_default("auth.lockout_minutes", 15).map_err(|e| e.to_string())?
+            .set_default("redis.url", "redis://127.0.0.1:6379").map_err(|e| e.to_string())?
+            .set_default("storage.uploads_dir", "uploads").map_err(|e| e.to_string())?
+            .set_default("storage.scans_dir", "scans").map_err(|e| e.to_string())?
+            .set_default("storage.database_path", "data/print_scan_manager.db").map_err(|e| e.to_string())?
+            .set_default("devices.refresh_interval_secs", 30).map_err(|e| e.to_string())?
+            .set_default("devices.ink_check_interval_secs", 600).map_err(|e| e.to_string())?
+            .set_default("devices.backend", "system").map_err(|e| e.to_string())?
+            .add_source(File::with_name(path).required(false))
+            .add_source(
+                Environment::with_prefix(ENV_PREFIX)
+                    .prefix_separator("_")
+                    .separator("__")
+                    .try_parsing(true)
+                    .list_separator(",")
+                    .with_list_parse_key("tls.acme.domains")
+            )
+            .build()
+            .map_err(|e| format!("Failed to read settings: {}", e))?
+            .try_deserialize()
+            .map_err(|e| format!("Invalid settings: {}", e))?;
+
+        settings.validate()?;
+
+        Ok(settings)
+    }
+
+    /// Check values which would otherwise only fail later, when the server is already running
+    pub fn validate(&self) -> Result<(), String> {
+        let mut errors = Vec::new();
+
+        if self.server.bind_address.to_socket_addrs().is_err() {
+            errors.push(format!("server.bind_address '{}' is not a valid socket address", self.server.bind_address));
+        }
+        if self.tls.mode != TlsMode::Http {
+            match self.tls.bind_address.to_socket_addrs() {
+                Err(_) => errors.push(format!("tls.bind_address '{}' is not a valid socket address", self.tls.bind_address)),
+                Ok(_) if self.tls.mode == TlsMode::Both && self.tls.bind_address == self.server.bind_address => {
+                    errors.push("tls.bind_address must differ from server.bind_address in 'both' mode".to_string());
+                },
+                Ok(_) => {}
+            }
+        }
+        if self.tls.acme.enabled {
+            if self.tls.mode != TlsMode::Both {
+                errors.push("tls.acme requires tls.mode = 'both', http-01 challenges are answered on server.bind_address".to_string());
+            }
+            if self.tls.acme.domains.is_empty() {
+                errors.push("tls.acme.domains must list at least one domain".to_string());
+            }
+            if !self.tls.acme.directory_url.starts_with("https://") {
+                errors.push(format!("tls.acme.directory_url '{}' must be an https:// URL", self.tls.acme.directory_url));
+            }
+            if self.tls.acme.check_interval_hours == 0 {
+                errors.push("tls.acme.check_interval_hours must be greater than 0".to_string());
+            }
+        }
+        if self.auth.admin_username.trim().is_empty() {
+            errors.push("auth.admin_username must not be empty".to_string());
+        }
+        if self.auth.admin_password.as_ref().is_some_and(|password| password.len() < 8) {
+            errors.push("auth.admin_password must be at least 8 characters".to_string());
+        }
+        if self.auth.max_failed_logins == 0 || self.auth.lockout_minutes == 0 {
+            errors.push("auth.max_failed_logins and auth.lockout_minutes must be greater than 0".to_string());
+        }
+        if self.server.workers == 0 {
+            errors.push("server.workers must be greater than 0".to_string());
+        }
+        if self.server.max_upload_size == 0 {
+            errors.push("server.max_upload_size must be greater than 0".to_string());
+        }
+        if self.store.backend == StoreBackend::Redis
+            && !self.redis.url.starts_with("redis://")
+            && !self.redis.url.starts_with("rediss://") {
+            errors.push(format!("redis.url '{}' must start with redis:// or rediss://", self.redis.url));
+        }
+
+        for (key, path) in [
+            ("storage.uploads_dir", &self.storage.uploads_dir),
+            ("storage.scans_dir", &self.storage.scans_dir),
+            ("storage.database_path", &self.storage.database_path),
+        ] {
+            if path.as_os_str().is_empty() {
+                errors.push(format!("{} must not be empty", key));
+            }
+        }
+        if self.storage.database_path.to_str().is_none() {
+            errors.push("storage.database_path must be valid UTF-8".to_string());
+        }
+
+        match errors.is_empty() {
+            true => Ok(()),
+            false => Err(errors.join("; ")),
+        }
+    }
+
+    pub fn uploads_dir(&self) -> &Path {
+        &self.storage.uploads_dir
+    }
+
+    pub fn scans_dir(&self) -> &Path {
+        &self.storage.scans_dir
+    }
+
+    /// Port of the HTTPS listener, used to build redirect locations
+    pub fn https_port(&self) -> Option<u16> {
+        self.tls.bind_address.to_socket_addrs().ok()?.next().map(|addr| addr.port())
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_defaults_without_settings_file() {
+        let settings = Settings::load_from("config/does_not_exist").unwrap();
+
+        assert_eq!(settings.server.bind_address, "0.0.0.0:8080");
+        assert_eq!(settings.server.workers, 4);
+        assert_eq!(settings.server.max_upload_size, 50 * 1024 * 1024);
+        assert_eq!(settings.storage.uploads_dir, PathBuf::from("uploads"));
+        assert_eq!(settings.storage.scans_dir, PathBuf::from("scans"));
+        assert_eq!(settings.tls.mode, TlsMode::Http);
+        assert_eq!(settings.store.backend, StoreBackend::Redis);
+        assert_eq!(settings.https_port(), Some(8443));
+        assert_eq!(settings.devices.refresh_interval_secs, 30);
+        assert_eq!(settings.devices.ink_check_interval_secs, 600);
+        assert_eq!(settings.devices.backend, DeviceBackend::System);
+    }
+
+    #[test]
+    fn test_validate_rejects_bad_values() {
+        let mut settings = Settings::load_from("config/does_not_exist").unwrap();
+        settings.server.workers = 0;
+        settings.redis.url = "http://localhost".to_string();
+        settings.storage.scans_dir = PathBuf::new();
+
+        let err = settings.validate().unwrap_err();
+
+        assert!(err.contains("server.workers"));
+        assert!(err.contains("redis.url"));
+        assert!(err.contains("storage.scans_dir"));
+
+        settings.store.backend = StoreBackend::Embedded;
+        assert!(!settings.validate().unwrap_err().contains("redis.url"));
+    }
+
+    #[test]
+    fn test_validate_tls_listeners() {
+        let mut settings = Settings::load_from("config/does_not_exist").unwrap();
+        settings.tls.mode = TlsMode::Both;
+        settings.tls.bind_address = settings.server.bind_address.clone();
+
+        assert!(settings.validate().unwrap_err().contains("tls.bind_address"));
+
+        settings.tls.bind_address = "0.0.0.0:8443".to_string();
+        assert!(settings.validate().is_ok());
+
+        settings.tls.acme.enabled = true;
+        assert!(settings.validate().unwrap_err().contains("tls.acme.domains"));
+
+        settings.tls.acme.domains = vec!["printer.example.com".to_string()];
+        assert!(settings.validate().is_ok());
+    }
+}
@@ -0,0 +1,71 @@
+use std::time::Duration;
+use actix_web::dev::ServerHandle;
+use actix_web::web;
+use sqlx::SqlitePool;
+use crate::models::JobQueue;
+use crate::settings::Settings;
+
+
+/// Stops the server on SIGTERM or Ctrl+C without losing jobs
+///
+/// New jobs are refused right away, the running one gets `server.shutdown_timeout_secs` to finish
+/// and is otherwise checkpointed. Queued jobs stay queued in the database for the next start.
+pub struct ShutdownCoordinator {
+    job_queue: JobQueue,
+    pool: SqlitePool,
+    settings: web::Data<Settings>,
+}
+
+impl ShutdownCoordinator {
+    pub fn new(job_queue: JobQueue, pool: SqlitePool, settings: web::Data<Settings>) -> Self {
+        Self { job_queue, pool, settings }
+    }
+
+    /// Wait for a shutdown signal, drain the job queue, then stop `servers`
+    pub async fn run(self, servers: Vec<ServerHandle>) {
+        if let Err(e) = wait_for_signal().await {
+            log::error!("Failed to listen for shutdown signals: {}", e);
+            return;
+        }
+
+        self.drain().await;
+
+        log::info!("Stopping HTTP server");
+        for server in servers {
+            server.stop(true).await;
+        }
+    }
+
+    pub async fn drain(&self) {
+        let timeout = Duration::from_secs(self.settings.server.shutdown_timeout_secs);
+        log::info!("Shutting down, waiting up to {} s for the running job", timeout.as_secs());
+
+        self.job_queue.begin_shutdown().await;
+
+        if !self.job_queue.wait_idle(timeout).await {
+            log::warn!("Running job did not finish in time");
+            if let Err(e) = self.job_queue.checkpoint_running_job(&self.pool, &self.settings).await {
+                log::error!("Failed to checkpoint the running job: {}", e);
+            }
+        }
+
+        let remaining = self.job_queue.len().await;
+        if remaining > 0 {
+            log::info!("{} queued jobs left for the next start", remaining);
+        }
+    }
+}
+
+async fn wait_for_signal() -> std::io::Result<()> {
+    let mut terminate = tokio::signal::unix::signal(tokio::signal::unix::SignalKind::terminate())?;
+
+    tokio::select! {
+        _ = terminate.recv() => log::info!("Received SIGTERM"),
+        result = tokio::signal::ctrl_c() => {
+            result?;
+            log::info!("Received Ctrl+C");
+        },
+    }
+
+    Ok(())
+}
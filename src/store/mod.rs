@@ -0,0 +1,53 @@
+use std::collections::HashMap;
+use actix_session::storage::{LoadError, RedisSessionStore, SaveError, SessionKey, SessionStore, UpdateError};
+use actix_web::cookie::time::Duration;
+
+pub mod rate_limit;
+mod sqlite_session;
+
+pub use sqlite_session::SqliteSessionStore;
+
+
+/// Session store selected by `store.backend`
+#[derive(Clone)]
+pub enum SessionBackend {
+    Redis(RedisSessionStore),
+    Sqlite(SqliteSessionStore),
+}
+
+impl SessionStore for SessionBackend {
+    async fn load(&self, session_key: &SessionKey) -> Result<Option<HashMap<String, String>>, LoadError> {
+        match self {
+            SessionBackend::Redis(store) => store.load(session_key).await,
+            SessionBackend::Sqlite(store) => store.load(session_key).await,
+        }
+    }
+
+    async fn save(&self, session_state: HashMap<String, String>, ttl: &Duration) -> Result<SessionKey, SaveError> {
+        match self {
+            SessionBackend::Redis(store) => store.save(session_state, ttl).await,
+            SessionBackend::Sqlite(store) => store.save(session_state, ttl).await,
+        }
+    }
+
+    async fn update(&self, session_key: SessionKey, session_state: HashMap<String, String>, ttl: &Duration) -> Result<SessionKey, UpdateError> {
+        match self {
+            SessionBackend::Redis(store) => store.update(session_key, session_state, ttl).await,
+            SessionBackend::Sqlite(store) => store.update(session_key, session_state, ttl).await,
+        }
+    }
+
+    async fn update_ttl(&self, session_key: &SessionKey, ttl: &Duration) -> Result<(), anyhow::Error> {
+        match self {
+            SessionBackend::Redis(store) => store.update_ttl(session_key, ttl).await,
+            SessionBackend::Sqlite(store) => store.update_ttl(session_key, ttl).await,
+        }
+    }
+
+    async fn delete(&self, session_key: &SessionKey) -> Result<(), anyhow::Error> {
+        match self {
+            SessionBackend::Redis(store) => store.delete(session_key).await,
+            SessionBackend::Sqlite(store) => store.delete(session_key).await,
+        }
+    }
+}
@@ -0,0 +1,204 @@
+use std::collections::HashMap;
+use std::future::{ready, Future, Ready};
+use std::pin::Pin;
+use std::rc::Rc;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use actix_limitation::{Error as LimitationError, Limiter};
+use actix_session::SessionExt;
+use actix_web::body::EitherBody;
+use actix_web::dev::{forward_ready, Service, ServiceRequest, ServiceResponse, Transform};
+use actix_web::{Error, HttpResponse};
+use crate::auth::bearer_token;
+use crate::models::hash_token;
+
+/// Idle buckets are dropped once this many clients have been seen
+const MAX_TRACKED_KEYS: usize = 1024;
+
+
+/// Counter storage behind the [`RateLimiter`] middleware
+pub enum RateLimitBackend {
+    Redis(Limiter),
+    Memory(MemoryLimiter),
+}
+
+impl RateLimitBackend {
+    /// Take one request from `key`'s allowance, `Ok(false)` once it is used up
+    async fn check(&self, key: &str) -> Result<bool, String> {
+        match self {
+            RateLimitBackend::Redis(limiter) => match limiter.count(key).await {
+                Ok(_) => Ok(true),
+                Err(LimitationError::LimitExceeded(_)) => Ok(false),
+                Err(e) => Err(e.to_string()),
+            },
+            RateLimitBackend::Memory(limiter) => Ok(limiter.check(key)),
+        }
+    }
+}
+
+struct Bucket {
+    tokens: f64,
+    updated: Instant,
+}
+
+/// In-process token bucket: `limit` requests per `period`, refilled continuously
+pub struct MemoryLimiter {
+    limit: f64,
+    refill_per_sec: f64,
+    buckets: Mutex<HashMap<String, Bucket>>,
+}
+
+impl MemoryLimiter {
+    pub fn new(limit: usize, period: Duration) -> Self {
+        Self {
+            limit: limit as f64,
+            refill_per_sec: limit as f64 / period.as_secs_f64(),
+            buckets: Mutex::new(HashMap::new()),
+        }
+    }
+
+    pub fn check(&self, key: &str) -> bool {
+        self.check_at(key, Instant::now())
+    }
+
+    fn check_at(&self, key: &str, now: Instant) -> bool {
+        let mut buckets = self.buckets.lock().unwrap_or_else(|e| e.into_inner());
+
+        if !buckets.contains_key(key) && buckets.len() >= MAX_TRACKED_KEYS {
+            // Buckets which would be full again carry no information
+            buckets.retain(|_, bucket| self.refill(bucket, now) < self.limit);
+        }
+
+        let bucket = buckets.entry(key.to_string()).or_insert(Bucket { tokens: self.limit, updated: now });
+        bucket.tokens = self.refill(bucket, now);
+        bucket.updated = now;
+
+        if bucket.tokens >= 1.0 {
+            bucket.tokens -= 1.0;
+            true
+        } else {
+            false
+        }
+    }
+
+    fn refill(&self, bucket: &Bucket, now: Instant) -> f64 {
+        let elapsed = now.saturating_duration_since(bucket.updated).as_secs_f64();
+        (bucket.tokens + elapsed * self.refill_per_sec).min(self.limit)
+    }
+}
+
+/// Identify the client: API token, logged in user, then the rate-api-id cookie, then the remote address
+fn rate_limit_key(req: &ServiceRequest) -> Option<String> {
+    if req.path().starts_with("/api/") && let Some(token) = bearer_token(req.headers()) {
+        return Some(format!("token:{}", hash_token(token)));
+    }
+
+    req.get_session()
+        .get::<String>("user_id")
+        .ok()
+        .flatten()
+        .or_else(|| {
+            req.cookie("rate-api-id")
+                .map(|c| c.value().to_string())
+        })
+        .or_else(|| {
+            req.connection_info()
+                .realip_remote_addr()
+                .map(|s| s.to_string())
+        })
+}
+
+/// Rate limit middleware answering 429 Too Many Requests once a client's allowance is used up
+///
+/// Must be registered before (inside) the session middleware so logged in users are keyed by id.
+#[derive(Clone)]
+pub struct RateLimiter {
+    backend: Arc<RateLimitBackend>,
+}
+
+impl RateLimiter {
+    pub fn new(backend: Arc<RateLimitBackend>) -> Self {
+        Self { backend }
+    }
+}
+
+impl<S, B> Transform<S, ServiceRequest> for RateLimiter
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error> + 'static,
+    S::Future: 'static,
+    B: 'static,
+{
+    type Response = ServiceResponse<EitherBody<B>>;
+    type Error = Error;
+    type Transform = RateLimiterMiddleware<S>;
+    type InitError = ();
+    type Future = Ready<Result<Self::Transform, Self::InitError>>;
+
+    fn new_transform(&self, service: S) -> Self::Future {
+        ready(Ok(RateLimiterMiddleware {
+            service: Rc::new(service),
+            backend: self.backend.clone(),
+        }))
+    }
+}
+
+pub struct RateLimiterMiddleware<S> {
+    service: Rc<S>,
+    backend: Arc<RateLimitBackend>,
+}
+
+impl<S, B> Service<ServiceRequest> for RateLimiterMiddleware<S>
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error> + 'static,
+    S::Future: 'static,
+    B: 'static,
+{
+    type Response = ServiceResponse<EitherBody<B>>;
+    type Error = Error;
+    type Future = Pin<Box<dyn Future<Output = Result<Self::Response, Self::Error>>>>;
+
+    forward_ready!(service);
+
+    fn call(&self, req: ServiceRequest) -> Self::Future {
+        let service = Rc::clone(&self.service);
+        let backend = self.backend.clone();
+
+        Box::pin(async move {
+            if let Some(key) = rate_limit_key(&req) {
+                match backend.check(&key).await {
+                    Ok(true) => {},
+                    Ok(false) => {
+                        log::warn!("Rate limit exceeded for {}", key);
+                        return Ok(req.into_response(HttpResponse::TooManyRequests().finish().map_into_right_body()));
+                    },
+                    Err(e) => {
+                        log::error!("Rate limit check failed: {}", e);
+                        return Ok(req.into_response(HttpResponse::InternalServerError().finish().map_into_right_body()));
+                    },
+                }
+            }
+
+            service.call(req).await.map(ServiceResponse::map_into_left_body)
+        })
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_memory_limiter_refills() {
+        let limiter = MemoryLimiter::new(2, Duration::from_secs(60));
+        let start = Instant::now();
+
+        assert!(limiter.check_at("a", start));
+        assert!(limiter.check_at("a", start));
+        assert!(!limiter.check_at("a", start));
+        assert!(limiter.check_at("b", start));
+
+        assert!(!limiter.check_at("a", start + Duration::from_secs(10)));
+        assert!(limiter.check_at("a", start + Duration::from_secs(45)));
+    }
+}
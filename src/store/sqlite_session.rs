@@ -0,0 +1,136 @@
+use std::collections::HashMap;
+use actix_session::storage::{generate_session_key, LoadError, SaveError, SessionKey, SessionStore, UpdateError};
+use actix_web::cookie::time::Duration;
+use sqlx::{Row, SqlitePool};
+use crate::query_bind;
+
+
+/// Session store keeping the session state as JSON in the `sessions` table
+#[derive(Clone)]
+pub struct SqliteSessionStore {
+    pool: SqlitePool,
+}
+
+impl SqliteSessionStore {
+    pub fn new(pool: SqlitePool) -> Self {
+        Self { pool }
+    }
+
+    async fn purge_expired(&self) -> Result<u64, sqlx::Error> {
+        let result = query_bind!(
+            "DELETE FROM sessions WHERE expires_at <= ?;",
+            chrono::Utc::now().timestamp()
+        ).execute(&self.pool).await?;
+
+        Ok(result.rows_affected())
+    }
+}
+
+fn expires_at(ttl: &Duration) -> i64 {
+    chrono::Utc::now().timestamp() + ttl.whole_seconds()
+}
+
+impl SessionStore for SqliteSessionStore {
+    async fn load(&self, session_key: &SessionKey) -> Result<Option<HashMap<String, String>>, LoadError> {
+        let row = query_bind!(
+            "SELECT state FROM sessions WHERE session_key = ? AND expires_at > ?;",
+            session_key.as_ref(),
+            chrono::Utc::now().timestamp()
+        ).fetch_optional(&self.pool).await.map_err(|e| LoadError::Other(e.into()))?;
+
+        match row {
+            Some(row) => {
+                let state: String = row.get("state");
+                serde_json::from_str(&state)
+                    .map(Some)
+                    .map_err(|e| LoadError::Deserialization(e.into()))
+            },
+            None => Ok(None),
+        }
+    }
+
+    async fn save(&self, session_state: HashMap<String, String>, ttl: &Duration) -> Result<SessionKey, SaveError> {
+        let state = serde_json::to_string(&session_state).map_err(|e| SaveError::Serialization(e.into()))?;
+        let session_key = generate_session_key();
+
+        // New sessions are rare compared to loads, a good moment to drop the stale ones
+        if let Err(e) = self.purge_expired().await {
+            log::warn!("Could not purge expired sessions: {}", e);
+        }
+
+        query_bind!(
+            "INSERT INTO sessions (session_key, state, expires_at) VALUES (?, ?, ?);",
+            session_key.as_ref(),
+            state,
+            expires_at(ttl)
+        ).execute(&self.pool).await.map_err(|e| SaveError::Other(e.into()))?;
+
+        Ok(session_key)
+    }
+
+    async fn update(&self, session_key: SessionKey, session_state: HashMap<String, String>, ttl: &Duration) -> Result<SessionKey, UpdateError> {
+        let state = serde_json::to_string(&session_state).map_err(|e| UpdateError::Serialization(e.into()))?;
+
+        let result = query_bind!(
+            "UPDATE sessions SET state = ?, expires_at = ? WHERE session_key = ?;",
+            state,
+            expires_at(ttl),
+            session_key.as_ref()
+        ).execute(&self.pool).await.map_err(|e| UpdateError::Other(e.into()))?;
+
+        // The session expired or was deleted in the meantime, store it under a fresh key
+        if result.rows_affected() == 0 {
+            return self.save(session_state, ttl).await.map_err(|e| match e {
+                SaveError::Serialization(e) => UpdateError::Serialization(e),
+                SaveError::Other(e) => UpdateError::Other(e),
+            });
+        }
+
+        Ok(session_key)
+    }
+
+    async fn update_ttl(&self, session_key: &SessionKey, ttl: &Duration) -> Result<(), anyhow::Error> {
+        query_bind!(
+            "UPDATE sessions SET expires_at = ? WHERE session_key = ?;",
+            expires_at(ttl),
+            session_key.as_ref()
+        ).execute(&self.pool).await?;
+
+        Ok(())
+    }
+
+    async fn delete(&self, session_key: &SessionKey) -> Result<(), anyhow::Error> {
+        query_bind!(
+            "DELETE FROM sessions WHERE session_key = ?;",
+            session_key.as_ref()
+        ).execute(&self.pool).await?;
+
+        Ok(())
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::test_pool;
+
+    #[tokio::test]
+    async fn test_session_roundtrip() {
+        let pool = test_pool().await;
+        let store = SqliteSessionStore::new(pool);
+
+        let state = HashMap::from([("user_id".to_string(), "\"42\"".to_string())]);
+        let key = store.save(state.clone(), &Duration::hours(1)).await.unwrap();
+        assert_eq!(store.load(&key).await.unwrap(), Some(state));
+
+        store.update_ttl(&key, &Duration::seconds(-1)).await.unwrap();
+        assert_eq!(store.load(&key).await.unwrap(), None);
+
+        let key = store.update(key, HashMap::new(), &Duration::hours(1)).await.unwrap();
+        assert_eq!(store.load(&key).await.unwrap(), Some(HashMap::new()));
+
+        store.delete(&key).await.unwrap();
+        assert_eq!(store.load(&key).await.unwrap(), None);
+    }
+}
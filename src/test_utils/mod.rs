@@ -0,0 +1,47 @@
+//! Fixtures shared by the tests
+
+use std::ops::Deref;
+use std::path::{Path, PathBuf};
+use sqlx::SqlitePool;
+use sqlx::sqlite::SqlitePoolOptions;
+use crate::database::migrations::run_migrations;
+
+
+/// In-memory database with every migration applied
+pub async fn test_pool() -> SqlitePool {
+    let pool = SqlitePoolOptions::new().max_connections(1).connect("sqlite::memory:").await.unwrap();
+    run_migrations(&pool).await.unwrap();
+    pool
+}
+
+/// Fresh directory under the system temp dir, removed with its contents when dropped,
+/// also when an assertion fails first
+pub struct TempDir(PathBuf);
+
+impl TempDir {
+    pub fn new(prefix: &str) -> Self {
+        let path = std::env::temp_dir().join(format!("web_printer_{}_{}", prefix, uuid::Uuid::new_v4()));
+        std::fs::create_dir_all(&path).unwrap();
+        Self(path)
+    }
+}
+
+impl Deref for TempDir {
+    type Target = Path;
+
+    fn deref(&self) -> &Path {
+        &self.0
+    }
+}
+
+impl AsRef<Path> for TempDir {
+    fn as_ref(&self) -> &Path {
+        &self.0
+    }
+}
+
+impl Drop for TempDir {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_dir_all(&self.0);
+    }
+}
@@ -0,0 +1,252 @@
+use std::collections::HashMap;
+use std::fs;
+use std::sync::Arc;
+use std::time::Duration;
+use actix_web::{web, HttpResponse};
+use instant_acme::{
+    Account, AccountCredentials, AuthorizationStatus, ChallengeType, Identifier, NewAccount, NewOrder,
+    Order, OrderStatus, RetryPolicy,
+};
+use tokio::sync::RwLock;
+use x509_parser::prelude::*;
+use crate::settings::{AcmeSettings, TlsSettings};
+use crate::tls::{load_certified_key, CertResolver};
+use crate::tls::self_signed::write_private_key;
+
+
+/// Pending http-01 challenge responses, token -> key authorization
+#[derive(Clone, Default)]
+pub struct AcmeChallenges {
+    tokens: Arc<RwLock<HashMap<String, String>>>,
+}
+
+impl AcmeChallenges {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    async fn insert(&self, token: String, key_authorization: String) {
+        self.tokens.write().await.insert(token, key_authorization);
+    }
+
+    async fn remove(&self, tokens: &[String]) {
+        let mut map = self.tokens.write().await;
+        for token in tokens {
+            map.remove(token);
+        }
+    }
+
+    async fn get(&self, token: &str) -> Option<String> {
+        self.tokens.read().await.get(token).cloned()
+    }
+}
+
+/// GET /.well-known/acme-challenge/{token} - Answer an http-01 challenge
+pub async fn acme_challenge(path: web::Path<String>, challenges: web::Data<AcmeChallenges>) -> HttpResponse {
+    match challenges.get(&path.into_inner()).await {
+        Some(key_authorization) => HttpResponse::Ok()
+            .content_type("application/octet-stream")
+            .body(key_authorization),
+        None => HttpResponse::NotFound().finish(),
+    }
+}
+
+/// Background task keeping the certificate issued by the ACME directory fresh
+///
+/// Checks the certificate on disk every `check_interval_hours` and orders a new one when it is
+/// self-signed, does not cover all configured domains or expires within `renew_before_days`.
+/// A renewed certificate is written to disk and swapped into the running server through `resolver`.
+pub async fn run_renewal(tls: TlsSettings, resolver: Arc<CertResolver>, challenges: AcmeChallenges) {
+    let interval = Duration::from_secs(tls.acme.check_interval_hours * 60 * 60);
+
+    loop {
+        match needs_renewal(&tls) {
+            Ok(false) => log::debug!("ACME certificate is still valid"),
+            Ok(true) => {
+                log::info!("Requesting certificate for {} from {}", tls.acme.domains.join(", "), tls.acme.directory_url);
+                match renew(&tls, &resolver, &challenges).await {
+                    Ok(_) => log::info!("ACME certificate installed"),
+                    Err(e) => log::error!("ACME certificate renewal failed: {}", e),
+                }
+            },
+            Err(e) => log::error!("Could not check certificate: {}", e),
+        }
+
+        tokio::time::sleep(interval).await;
+    }
+}
+
+fn needs_renewal(tls: &TlsSettings) -> Result<bool, String> {
+    let cert_pem = match fs::read(&tls.cert_path) {
+        Ok(pem) => pem,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(true),
+        Err(e) => return Err(e.to_string()),
+    };
+
+    let (_, pem) = parse_x509_pem(&cert_pem).map_err(|e| e.to_string())?;
+    let cert = pem.parse_x509().map_err(|e| e.to_string())?;
+
+    Ok(certificate_needs_renewal(&cert, &tls.acme, chrono::Utc::now().timestamp()))
+}
+
+fn certificate_needs_renewal(cert: &X509Certificate<'_>, acme: &AcmeSettings, now: i64) -> bool {
+    if cert.issuer() == cert.subject() {
+        log::info!("Current certificate is self-signed");
+        return true;
+    }
+
+    let days_left = (cert.validity().not_after.timestamp() - now) / (24 * 60 * 60);
+    if days_left < acme.renew_before_days {
+        log::info!("Current certificate expires in {} days", days_left);
+        return true;
+    }
+
+    let names = cert.subject_alternative_name().ok().flatten()
+        .map(|san| san.value.general_names.iter()
+            .filter_map(|name| match name {
+                GeneralName::DNSName(dns) => Some(dns.to_lowercase()),
+                _ => None,
+            })
+            .collect::<Vec<String>>())
+        .unwrap_or_default();
+
+    match acme.domains.iter().find(|domain| !names.contains(&domain.to_lowercase())) {
+        Some(domain) => {
+            log::info!("Current certificate does not cover {}", domain);
+            true
+        },
+        None => false,
+    }
+}
+
+async fn renew(tls: &TlsSettings, resolver: &CertResolver, challenges: &AcmeChallenges) -> Result<(), String> {
+    let (chain_pem, key_pem) = obtain_certificate(&tls.acme, challenges).await?;
+
+    // Make sure rustls accepts the new pair before replacing the working one on disk
+    let certified_key = load_certified_key(&mut chain_pem.as_bytes(), &mut key_pem.as_bytes())
+        .map_err(|e| format!("Issued certificate is not usable: {}", e))?;
+
+    fs::write(&tls.cert_path, &chain_pem).map_err(|e| e.to_string())?;
+    write_private_key(&tls.key_path, key_pem.as_bytes()).map_err(|e| e.to_string())?;
+
+    resolver.replace(certified_key);
+
+    Ok(())
+}
+
+async fn obtain_certificate(acme: &AcmeSettings, challenges: &AcmeChallenges) -> Result<(String, String), String> {
+    let account = get_account(acme).await?;
+
+    let identifiers = acme.domains.iter()
+        .map(|domain| Identifier::Dns(domain.clone()))
+        .collect::<Vec<Identifier>>();
+    let mut order = account.new_order(&NewOrder::new(&identifiers))
+        .await
+        .map_err(|e| format!("Failed to create order: {}", e))?;
+
+    let mut tokens = Vec::new();
+    let result = complete_challenges(&mut order, challenges, &mut tokens).await;
+    challenges.remove(&tokens).await;
+    result?;
+
+    let key_pem = order.finalize()
+        .await
+        .map_err(|e| format!("Failed to finalize order: {}", e))?;
+    let chain_pem = order.poll_certificate(&RetryPolicy::default())
+        .await
+        .map_err(|e| format!("Failed to download certificate: {}", e))?;
+
+    Ok((chain_pem, key_pem))
+}
+
+async fn complete_challenges(order: &mut Order, challenges: &AcmeChallenges, tokens: &mut Vec<String>) -> Result<(), String> {
+    let mut authorizations = order.authorizations();
+    while let Some(result) = authorizations.next().await {
+        let mut authorization = result.map_err(|e| e.to_string())?;
+        match authorization.status {
+            AuthorizationStatus::Pending => {},
+            AuthorizationStatus::Valid => continue,
+            status => return Err(format!("Authorization is {:?}", status)),
+        }
+
+        let mut challenge = authorization.challenge(ChallengeType::Http01)
+            .ok_or("ACME directory did not offer an http-01 challenge")?;
+
+        challenges.insert(challenge.token.clone(), challenge.key_authorization().as_str().to_string()).await;
+        tokens.push(challenge.token.clone());
+
+        challenge.set_ready()
+            .await
+            .map_err(|e| format!("Failed to set challenge ready: {}", e))?;
+    }
+
+    let status = order.poll_ready(&RetryPolicy::default())
+        .await
+        .map_err(|e| format!("Order did not become ready: {}", e))?;
+
+    match status {
+        OrderStatus::Ready => Ok(()),
+        status => Err(format!("Unexpected order status: {:?}", status)),
+    }
+}
+
+/// Restore the ACME account from `account_path`, registering a new one on first use
+async fn get_account(acme: &AcmeSettings) -> Result<Account, String> {
+    let builder = match &acme.root_ca_path {
+        Some(root_ca_path) => Account::builder_with_root(root_ca_path),
+        None => Account::builder(),
+    }.map_err(|e| format!("Failed to create ACME client: {}", e))?;
+
+    if let Ok(credentials) = fs::read_to_string(&acme.account_path) {
+        let credentials: AccountCredentials = serde_json::from_str(&credentials)
+            .map_err(|e| format!("Invalid ACME account file {}: {}", acme.account_path.display(), e))?;
+
+        return builder.from_credentials(credentials)
+            .await
+            .map_err(|e| format!("Failed to restore ACME account: {}", e));
+    }
+
+    let contact = acme.contact_email.as_ref().map(|email| format!("mailto:{}", email));
+    let contact = contact.iter().map(|c| c.as_str()).collect::<Vec<&str>>();
+
+    let (account, credentials) = builder.create(
+        &NewAccount {
+            contact: &contact,
+            terms_of_service_agreed: true,
+            only_return_existing: false,
+        },
+        acme.directory_url.clone(),
+        None,
+    ).await.map_err(|e| format!("Failed to register ACME account: {}", e))?;
+
+    let credentials = serde_json::to_string_pretty(&credentials).map_err(|e| e.to_string())?;
+    if let Some(parent) = acme.account_path.parent() {
+        fs::create_dir_all(parent).map_err(|e| e.to_string())?;
+    }
+    write_private_key(&acme.account_path, credentials.as_bytes()).map_err(|e| e.to_string())?;
+    log::info!("Registered ACME account {}", account.id());
+
+    Ok(account)
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::settings::Settings;
+    use crate::test_utils::TempDir;
+
+    #[test]
+    fn test_self_signed_certificate_needs_renewal() {
+        let tls_dir = TempDir::new("tls");
+        let mut tls = Settings::load_from("config/does_not_exist").unwrap().tls;
+        tls.cert_path = tls_dir.join("cert.pem");
+        tls.key_path = tls_dir.join("key.pem");
+        tls.acme.domains = vec!["localhost".to_string()];
+
+        assert!(needs_renewal(&tls).unwrap());
+
+        crate::tls::self_signed::generate(&tls.cert_path, &tls.key_path).unwrap();
+        assert!(needs_renewal(&tls).unwrap());
+    }
+}
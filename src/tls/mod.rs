@@ -0,0 +1,156 @@
+use std::fs::File;
+use std::io::{self, BufRead, BufReader};
+use std::path::Path;
+use std::sync::{Arc, RwLock};
+use actix_web::{http::header, HttpRequest, HttpResponse};
+use rustls::server::{ClientHello, ResolvesServerCert};
+use rustls::sign::CertifiedKey;
+use rustls::ServerConfig;
+use crate::settings::TlsSettings;
+
+pub mod acme;
+mod self_signed;
+
+
+/// Serves the current certificate and lets it be replaced without restarting the listener
+#[derive(Debug)]
+pub struct CertResolver {
+    current: RwLock<Arc<CertifiedKey>>,
+}
+
+impl CertResolver {
+    pub fn new(certified_key: CertifiedKey) -> Self {
+        Self { current: RwLock::new(Arc::new(certified_key)) }
+    }
+
+    pub fn replace(&self, certified_key: CertifiedKey) {
+        let mut current = self.current.write().unwrap_or_else(|e| e.into_inner());
+        *current = Arc::new(certified_key);
+    }
+}
+
+impl ResolvesServerCert for CertResolver {
+    fn resolve(&self, _client_hello: ClientHello<'_>) -> Option<Arc<CertifiedKey>> {
+        let current = self.current.read().unwrap_or_else(|e| e.into_inner());
+        Some(current.clone())
+    }
+}
+
+/// Load the certificate chain and private key configured in `tls.cert_path`/`tls.key_path`
+///
+/// The certificate file may contain the full chain (leaf first), the key may be PKCS#8, PKCS#1 (RSA) or SEC1 (EC).
+/// When either file is missing and `tls.self_signed` (or ACME) is enabled, a self-signed pair is generated first.
+/// The returned resolver is used to swap in renewed certificates.
+pub fn get_tls_config(tls: &TlsSettings) -> Result<(ServerConfig, Arc<CertResolver>), io::Error> {
+    if !tls.cert_path.exists() || !tls.key_path.exists() {
+        if !tls.self_signed && !tls.acme.enabled {
+            return Err(io::Error::new(
+                io::ErrorKind::NotFound,
+                format!("Certificate {} or key {} not found", tls.cert_path.display(), tls.key_path.display())
+            ));
+        }
+        self_signed::generate(&tls.cert_path, &tls.key_path)?;
+    }
+
+    let certified_key = load_certified_key_from(&tls.cert_path, &tls.key_path)?;
+    let resolver = Arc::new(CertResolver::new(certified_key));
+
+    let tls_config = ServerConfig::builder()
+        .with_no_client_auth()
+        .with_cert_resolver(resolver.clone());
+
+    Ok((tls_config, resolver))
+}
+
+fn load_certified_key_from(cert_path: &Path, key_path: &Path) -> Result<CertifiedKey, io::Error> {
+    let mut certs_file = BufReader::new(File::open(cert_path)?);
+    let mut keys_file = BufReader::new(File::open(key_path)?);
+
+    load_certified_key(&mut certs_file, &mut keys_file).map_err(|e| io::Error::new(
+        e.kind(),
+        format!("{} / {}: {}", cert_path.display(), key_path.display(), e)
+    ))
+}
+
+pub(crate) fn load_certified_key(certs: &mut dyn BufRead, key: &mut dyn BufRead) -> Result<CertifiedKey, io::Error> {
+    let tls_certs = rustls_pemfile::certs(certs)
+        .collect::<Result<Vec<_>, _>>()?;
+    if tls_certs.is_empty() {
+        return Err(io::Error::new(io::ErrorKind::InvalidData, "No certificates found"));
+    }
+
+    let tls_key = rustls_pemfile::private_key(key)?
+        .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "No PKCS#8, RSA or EC private key found"))?;
+
+    CertifiedKey::from_der(tls_certs, tls_key, &rustls::crypto::aws_lc_rs::default_provider())
+        .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+}
+
+/// Handler for the plain HTTP listener in `both` mode, sends every request to the HTTPS listener
+pub async fn redirect_to_https(req: HttpRequest, https_port: u16) -> HttpResponse {
+    let connection_info = req.connection_info();
+    let path_and_query = req.uri().path_and_query().map_or("/", |pq| pq.as_str());
+    let location = https_location(connection_info.host(), https_port, path_and_query);
+
+    HttpResponse::PermanentRedirect()
+        .insert_header((header::LOCATION, location))
+        .finish()
+}
+
+fn https_location(host: &str, https_port: u16, path_and_query: &str) -> String {
+    let hostname = if host.starts_with('[') {
+        // IPv6 literal, e.g. [fe80::1]:8080
+        host.split_once(']').map_or(host.to_string(), |(addr, _)| format!("{}]", addr))
+    } else {
+        host.rsplit_once(':').map_or(host, |(name, _)| name).to_string()
+    };
+
+    match https_port {
+        443 => format!("https://{}{}", hostname, path_and_query),
+        port => format!("https://{}:{}{}", hostname, port, path_and_query),
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::PathBuf;
+    use crate::settings::{Settings, TlsMode};
+    use crate::test_utils::TempDir;
+
+    #[test]
+    fn test_https_location() {
+        assert_eq!(https_location("raspberrypi:8080", 8443, "/print?x=1"), "https://raspberrypi:8443/print?x=1");
+        assert_eq!(https_location("192.168.1.10", 443, "/"), "https://192.168.1.10/");
+        assert_eq!(https_location("[fe80::1]:8080", 8443, "/scan"), "https://[fe80::1]:8443/scan");
+    }
+
+    #[test]
+    fn test_missing_certificate() {
+        let tls = TlsSettings {
+            mode: TlsMode::Https,
+            bind_address: "0.0.0.0:8443".to_string(),
+            cert_path: PathBuf::from("certs/does_not_exist.pem"),
+            key_path: PathBuf::from("certs/does_not_exist.key"),
+            self_signed: false,
+            acme: Settings::load_from("config/does_not_exist").unwrap().tls.acme,
+        };
+
+        let err = get_tls_config(&tls).unwrap_err();
+
+        assert_eq!(err.kind(), io::ErrorKind::NotFound);
+    }
+
+    #[test]
+    fn test_generates_self_signed_certificate() {
+        let _ = rustls::crypto::aws_lc_rs::default_provider().install_default();
+        let tls_dir = TempDir::new("tls");
+        let mut tls = Settings::load_from("config/does_not_exist").unwrap().tls;
+        tls.cert_path = tls_dir.join("cert.pem");
+        tls.key_path = tls_dir.join("key.pem");
+
+        assert!(get_tls_config(&tls).is_ok());
+        assert!(tls.key_path.exists());
+    }
+}
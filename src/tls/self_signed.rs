@@ -0,0 +1,188 @@
+use std::fs;
+use std::io::{self, Write};
+use std::net::IpAddr;
+use std::path::Path;
+use chrono::{Datelike, Utc};
+use rcgen::{date_time_ymd, CertificateParams, DistinguishedName, DnType, KeyPair, SanType};
+
+const VALIDITY_YEARS: i32 = 5;
+
+
+/// Generate a self-signed certificate for this host and persist it to `cert_path`/`key_path`
+///
+/// The certificate covers the hostname, `<hostname>.local`, localhost and every non-loopback
+/// interface address, so the UI can be opened by name or by LAN IP.
+/// A hostname that is not a valid DNS name is left out, localhost and the addresses are always covered.
+pub fn generate(cert_path: &Path, key_path: &Path) -> Result<(), io::Error> {
+    let hostname = get_hostname();
+    let mut names = vec![hostname.clone(), format!("{}.local", hostname), "localhost".to_string()];
+    names.sort();
+    names.dedup();
+    names.retain(|name| {
+        let valid = is_dns_name(name);
+        if !valid {
+            log::warn!("Leaving '{}' out of the self-signed certificate, it is not a valid DNS name", name);
+        }
+        valid
+    });
+
+    let mut addresses = get_lan_addresses();
+    addresses.push(IpAddr::from([127, 0, 0, 1]));
+
+    let (cert_pem, key_pem) = build_certificate(&hostname, &names, &addresses)?;
+
+    for path in [cert_path, key_path] {
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+    }
+    fs::write(cert_path, cert_pem)?;
+    write_private_key(key_path, key_pem.as_bytes())?;
+
+    log::info!("Generated self-signed certificate for {} ({})",
+        names.join(", "),
+        addresses.iter().map(|ip| ip.to_string()).collect::<Vec<String>>().join(", ")
+    );
+
+    Ok(())
+}
+
+fn build_certificate(common_name: &str, names: &[String], addresses: &[IpAddr]) -> Result<(String, String), io::Error> {
+    let mut params = CertificateParams::new(Vec::<String>::new()).map_err(io::Error::other)?;
+
+    for name in names {
+        let dns_name = name.clone().try_into().map_err(io::Error::other)?;
+        params.subject_alt_names.push(SanType::DnsName(dns_name));
+    }
+    for address in addresses {
+        params.subject_alt_names.push(SanType::IpAddress(*address));
+    }
+
+    let mut distinguished_name = DistinguishedName::new();
+    distinguished_name.push(DnType::CommonName, common_name);
+    distinguished_name.push(DnType::OrganizationName, "Print/Scan Manager");
+    params.distinguished_name = distinguished_name;
+
+    let now = Utc::now();
+    params.not_before = date_time_ymd(now.year(), now.month() as u8, now.day() as u8);
+    params.not_after = date_time_ymd(now.year() + VALIDITY_YEARS, now.month() as u8, now.day().min(28) as u8);
+
+    let key_pair = KeyPair::generate().map_err(io::Error::other)?;
+    let cert = params.self_signed(&key_pair).map_err(io::Error::other)?;
+
+    Ok((cert.pem(), key_pair.serialize_pem()))
+}
+
+/// Letters, digits and inner hyphens in dot separated labels, as accepted for certificate DNS names
+fn is_dns_name(name: &str) -> bool {
+    name.len() <= 253 && name.split('.').all(|label| {
+        !label.is_empty()
+            && label.len() <= 63
+            && !label.starts_with('-')
+            && !label.ends_with('-')
+            && label.chars().all(|c| c.is_ascii_alphanumeric() || c == '-')
+    })
+}
+
+/// Write a private key readable only by the owner
+///
+/// The file is created with mode 0600, an existing one is restricted before the key is written.
+pub(crate) fn write_private_key(path: &Path, pem: &[u8]) -> Result<(), io::Error> {
+    let mut options = fs::OpenOptions::new();
+    options.write(true).create(true).truncate(true);
+
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::OpenOptionsExt;
+        options.mode(0o600);
+    }
+
+    let mut file = options.open(path)?;
+
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        file.set_permissions(fs::Permissions::from_mode(0o600))?;
+    }
+
+    file.write_all(pem)
+}
+
+fn get_hostname() -> String {
+    fs::read_to_string("/proc/sys/kernel/hostname")
+        .or_else(|_| fs::read_to_string("/etc/hostname"))
+        .map(|s| s.trim().to_lowercase())
+        .ok()
+        .filter(|s| !s.is_empty())
+        .unwrap_or_else(|| "localhost".to_string())
+}
+
+fn get_lan_addresses() -> Vec<IpAddr> {
+    match if_addrs::get_if_addrs() {
+        Ok(interfaces) => {
+            let mut addresses = interfaces.iter()
+                .filter(|interface| !interface.is_loopback() && !interface.is_link_local())
+                .map(|interface| interface.ip())
+                .collect::<Vec<IpAddr>>();
+            addresses.sort();
+            addresses.dedup();
+            addresses
+        },
+        Err(e) => {
+            log::warn!("Could not list network interfaces: {}", e);
+            Vec::new()
+        }
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::TempDir;
+    use x509_parser::prelude::*;
+
+    #[test]
+    fn test_certificate_covers_names_and_addresses() {
+        let names = vec!["raspberrypi".to_string(), "raspberrypi.local".to_string()];
+        let addresses = vec![IpAddr::from([192, 168, 1, 20])];
+
+        let (cert_pem, key_pem) = build_certificate("raspberrypi", &names, &addresses).unwrap();
+        assert!(key_pem.contains("PRIVATE KEY"));
+
+        let (_, pem) = parse_x509_pem(cert_pem.as_bytes()).unwrap();
+        let cert = pem.parse_x509().unwrap();
+        let san = cert.subject_alternative_name().unwrap().unwrap().value;
+
+        assert!(san.general_names.contains(&GeneralName::DNSName("raspberrypi.local")));
+        assert!(san.general_names.contains(&GeneralName::IPAddress(&[192, 168, 1, 20])));
+        assert_eq!(cert.issuer(), cert.subject());
+    }
+
+    #[test]
+    fn test_is_dns_name() {
+        for name in ["raspberrypi", "print-server.local", "localhost", "pi4"] {
+            assert!(is_dns_name(name), "{}", name);
+        }
+        for name in ["print_server", "print_server.local", "-pi", "pi-.local", "pi..local", "", "drukarka-ł"] {
+            assert!(!is_dns_name(name), "{}", name);
+        }
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_write_private_key_permissions() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let dir = TempDir::new("key");
+        let path = dir.join("key.pem");
+        write_private_key(&path, b"first").unwrap();
+        assert_eq!(fs::metadata(&path).unwrap().permissions().mode() & 0o777, 0o600);
+
+        // A key left readable by an older version is restricted when it is replaced
+        fs::set_permissions(&path, fs::Permissions::from_mode(0o644)).unwrap();
+        write_private_key(&path, b"second").unwrap();
+        assert_eq!(fs::metadata(&path).unwrap().permissions().mode() & 0o777, 0o600);
+        assert_eq!(fs::read(&path).unwrap(), b"second");
+    }
+}
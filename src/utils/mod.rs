@@ -21,10 +21,9 @@ pub async fn get_disk_space() -> Option<u64> {
             let stdout = String::from_utf8_lossy(&output.stdout);
             for line in stdout.lines().skip(1) {
                 let parts: Vec<&str> = line.split_whitespace().collect();
-                if parts.len() >= 4 {
-                    if let Ok(available) = parts[3].parse::<u64>() {
-                        return Some(available);
-                    }
+                if parts.len() >= 4
+                    && let Ok(available) = parts[3].parse::<u64>() {
+                    return Some(available);
                 }
             }
             None
@@ -0,0 +1,37 @@
+// login.js - Login page, kept separate from main.js which needs a session
+
+document.addEventListener('DOMContentLoaded', () => {
+    const form = document.getElementById('login-form');
+    const error = document.getElementById('login-error');
+    const submit = document.getElementById('login-submit');
+
+    form.addEventListener('submit', async (e) => {
+        e.preventDefault();
+        error.textContent = '';
+        submit.disabled = true;
+
+        try {
+            const response = await fetch('/api/auth/login', {
+                method: 'POST',
+                headers: {'Content-Type': 'application/json'},
+                body: JSON.stringify({
+                    username: form.username.value,
+                    password: form.password.value
+                })
+            });
+            const result = await response.json().catch(() => ({}));
+
+            if (response.ok && result.success) {
+                window.location.href = '/';
+                return;
+            }
+
+            error.textContent = result.message || `Login failed (HTTP ${response.status})`;
+            form.password.value = '';
+        } catch (err) {
+            error.textContent = 'Could not reach the server';
+        } finally {
+            submit.disabled = false;
+        }
+    });
+});
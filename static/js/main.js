@@ -6,6 +6,8 @@ const AppState = {
     printers: [],
     scanners: [],
     uptime_ms: 0,
+    settings: null,
+    user: null,
 };
 
 let eventSource = null;
@@ -20,6 +22,11 @@ const API = {
         try {
             const response = await fetch(`/api${endpoint}`, options);
 
+            if (response.status === 401) {
+                window.location.href = '/login';
+                throw new Error('Login required');
+            }
+
             if (!response.ok) {
                 const errorData = await response.json().catch(() => ({}));
                 throw new Error(errorData.message || `HTTP ${response.status}: ${response.statusText}`);
@@ -217,6 +224,12 @@ const Utils = {
         };
     },
 
+    escapeHtml(text) {
+        const div = document.createElement('div');
+        div.textContent = text ?? '';
+        return div.innerHTML.replace(/"/g, '&quot;');
+    },
+
     truncateFilename(filename, maxLength = 20) {
         if (!filename || filename.length <= maxLength) return filename;
 
@@ -339,9 +352,49 @@ function updateDashboardStats(status) {
         if (element) element.textContent = value;
     });
 
+    displayInkLevels(status.ink_levels);
     startUptimeUpdates();
 }
 
+function displayInkLevels(inkLevels) {
+    const container = document.getElementById('ink-levels');
+    if (!container) return;
+
+    if (!inkLevels?.length) {
+        container.innerHTML = `
+            <div class="activity-placeholder">
+                <i class="fas fa-tint-slash"></i>
+                <p>No ink levels reported</p>
+            </div>
+        `;
+        return;
+    }
+
+    const threshold = AppState.settings?.low_ink_threshold ?? 0;
+
+    container.innerHTML = inkLevels.map(printer => `
+        <div class="ink-printer">
+            <div class="ink-printer-name">${printer.printer.replace(/_/g, ' ')}</div>
+            ${printer.markers.map(marker => {
+                const known = marker.level !== null && marker.level !== undefined;
+                const low = known && marker.level < threshold;
+                // Combined cartridges list one colour per ink, the first one is enough for the bar
+                const color = (marker.color || '').match(/#[0-9A-Fa-f]{6}/)?.[0] || 'var(--text-muted)';
+
+                return `
+                    <div class="ink-marker${low ? ' ink-low' : ''}" title="${marker.name}">
+                        <span class="ink-marker-name">${marker.name}</span>
+                        <div class="ink-bar">
+                            <div class="ink-bar-fill" style="width: ${known ? marker.level : 0}%; background: ${color};"></div>
+                        </div>
+                        <span class="ink-marker-level">${known ? `${marker.level}%` : '?'}</span>
+                    </div>
+                `;
+            }).join('')}
+        </div>
+    `).join('');
+}
+
 
 // DATA LOADING
 async function loadInitialData() {
@@ -388,7 +441,9 @@ function displayRecentActivity(jobs) {
         const isPrint = job.Print !== undefined;
         const jobData = isPrint ? job.Print : job.Scan;
         const type = isPrint ? 'Print' : 'Scan';
-        const name = Utils.truncateFilename(isPrint ? jobData.filename : (jobData.output_filename || 'Scan'), 20);
+        const name = Utils.escapeHtml(Utils.truncateFilename(isPrint ? jobData.original_filename : (jobData.output_filename || 'Scan'), 20));
+        const pages = isPrint ? jobData.pages_printed : jobData.page_count;
+        const pageInfo = pages ? ` · ${pages} ${pages === 1 ? 'page' : 'pages'}` : '';
 
         return `
             <div class="activity-item">
@@ -397,7 +452,7 @@ function displayRecentActivity(jobs) {
                 </div>
                 <div class="activity-content">
                     <div class="activity-title">${type}: ${name}</div>
-                    <div class="activity-time">${Utils.formatActivityTime(jobData.completed_at || jobData.created_at)}</div>
+                    <div class="activity-time">${Utils.formatActivityTime(jobData.completed_at || jobData.created_at)}${pageInfo}</div>
                 </div>
                 <span class="status-badge status-${jobData.status.toLowerCase()}">
                     ${jobData.status}
@@ -633,8 +688,8 @@ function displayJobQueue(jobs) {
         const isPrint = item.Print !== undefined;
         const job = isPrint ? item.Print : item.Scan;
         const type = isPrint ? 'print' : 'scan';
-        const isProcessing = ['processing', 'printing', 'scanning'].includes(job.status.toLowerCase());
-        const filename = isPrint ? job.filename : (job.output_filename || 'Scan');
+        const isProcessing = ['converting', 'processing', 'printing', 'scanning'].includes(job.status.toLowerCase());
+        const filename = Utils.escapeHtml(isPrint ? job.original_filename : (job.output_filename || 'Scan'));
 
         const waitTime = calculateWaitTime(job);
         const processTime = calculateProcessingTime(job);
@@ -704,7 +759,7 @@ function startQueueTimeUpdates() {
 
             const isPrint = jobData.Print !== undefined;
             const job = isPrint ? jobData.Print : jobData.Scan;
-            const isProcessing = ['processing', 'printing', 'scanning'].includes(job.status.toLowerCase());
+            const isProcessing = ['converting', 'processing', 'printing', 'scanning'].includes(job.status.toLowerCase());
 
             const timeValueEl = item.querySelector('.queue-time-value');
             if (timeValueEl) {
@@ -783,11 +838,72 @@ async function handleSSEMessage(data) {
 
             break;
 
+        case 'settings_update':
+            applySystemSettings(data.settings);
+            break;
+
+        case 'device_update':
+            applyDeviceUpdate(data);
+            break;
+
+        case 'ink_warning':
+            data.warnings.forEach(warning => {
+                Toast.warning(`${warning.marker} of ${warning.printer.replace(/_/g, ' ')} is low (${warning.level}%)`);
+            });
+            break;
+
         default:
             console.log('Unknown SSE message type:', data.type);
     }
 }
 
+// Printers or scanners were plugged in, removed or changed status
+function applyDeviceUpdate(data) {
+    AppState.printers = data.printers;
+    AppState.scanners = data.scanners;
+
+    if (window.location.pathname === '/print') {
+        PrintPage.printers = data.printers;
+        displayPrinters();
+        populatePrinterDropdown();
+    } else if (window.location.pathname === '/scan') {
+        ScanPage.scanners = data.scanners;
+        displayScanners();
+        populateScannerDropdown();
+    } else if (window.location.pathname === '/') {
+        updateSystemStatus();
+    }
+
+    const { printers, scanners } = data.changes;
+    [...printers.added, ...scanners.added].forEach(name => Toast.info(`${name} connected`));
+    [...printers.removed, ...scanners.removed].forEach(name => Toast.warning(`${name} disconnected`));
+}
+
+function applySystemSettings(settings) {
+    AppState.settings = settings;
+
+    const fileInput = document.getElementById('print-file');
+    if (fileInput) {
+        fileInput.accept = settings.accepted_formats
+            .flatMap(format => format.extensions.map(extension => `.${extension}`))
+            .join(',');
+    }
+
+    const fileHelp = document.getElementById('print-file-help');
+    if (fileHelp) {
+        const formats = settings.accepted_formats.map(format => format.format.toUpperCase()).join(', ');
+        fileHelp.textContent = `Supported: ${formats} (Max ${settings.max_file_size_mb}MB)`;
+    }
+
+    const resolutionSelect = document.getElementById('scan-resolution');
+    if (resolutionSelect && !resolutionSelect.dataset.touched) {
+        resolutionSelect.value = String(settings.default_resolution);
+        resolutionSelect.addEventListener('change', () => {
+            resolutionSelect.dataset.touched = 'true';
+        }, { once: true });
+    }
+}
+
 function updateStatusFromSSE(status) {
     const updates = {
         'active-prints': status.active_prints,
@@ -801,6 +917,8 @@ function updateStatusFromSSE(status) {
             if (el) el.textContent = value;
         }
     });
+
+    displayInkLevels(status.ink_levels);
 }
 
 
@@ -826,6 +944,8 @@ document.addEventListener('DOMContentLoaded', async () => {
         setupDashboardForms();
     }
 
+    await loadCurrentUser();
+
     // Initialize SSE
     initializeSSE();
 
@@ -836,6 +956,37 @@ document.addEventListener('DOMContentLoaded', async () => {
     await loadInitialData()
 });
 
+async function loadCurrentUser() {
+    try {
+        AppState.user = await API.get('/auth/me');
+        applyUserRole(AppState.user.role);
+
+        // The printer cards may have been drawn before the role was known
+        if (window.location.pathname === '/print' && PrintPage.printers.length) {
+            displayPrinters();
+        }
+    } catch (error) {
+        console.error('Failed to load current user:', error);
+    }
+}
+
+// Hide controls the server would refuse for this role anyway
+function applyUserRole(role) {
+    const ranks = { guest: 0, user: 1, admin: 2 };
+
+    document.querySelectorAll('[data-min-role]').forEach(el => {
+        el.hidden = ranks[role] < ranks[el.dataset.minRole];
+    });
+}
+
+async function logout() {
+    try {
+        await API.post('/auth/logout', {});
+    } finally {
+        window.location.href = '/login';
+    }
+}
+
 window.addEventListener('beforeunload', () => {
     if (queueTimeUpdateInterval) clearInterval(queueTimeUpdateInterval);
     if (uptimeUpdateInterval) clearInterval(uptimeUpdateInterval);
@@ -5,6 +5,7 @@
 const PrintPage = {
     jobs: [],
     printers: [],
+    discovered: [],
     jobsRefreshInterval: null,
     isSubmitting: false
 };
@@ -69,6 +70,7 @@ function displayPrinters() {
                         <span class="status-dot status-${printer.status}"></span>
                         ${printer.status}${printer.is_default ? ' (Default)' : ''}
                     </p>
+                    ${printer.state_message ? `<p class="printer-state-message">${printer.state_message}</p>` : ''}
                     <p class="printer-description">${printer.description || 'No description available'}</p>
                     <p class="printer-location">${printer.location || 'No location set'}</p>
                 </div>
@@ -79,12 +81,138 @@ function displayPrinters() {
                         <i class="fas fa-print"></i>
                         Print
                     </button>
+                    ${AppState.user?.role === 'admin' ? printerAdminActions(printer) : ''}
                 </div>
             </div>
         `;
     }).join('');
 }
 
+function printerAdminActions(printer) {
+    const stopped = printer.status === 'stopped';
+
+    return `
+        <button class="btn btn-sm btn-secondary" title="${stopped ? 'Enable' : 'Disable'}"
+                onclick="printerAdmin('${printer.name}', '${stopped ? 'enable' : 'disable'}')">
+            <i class="fas ${stopped ? 'fa-play' : 'fa-pause'}"></i>
+        </button>
+        <button class="btn btn-sm btn-secondary" title="${printer.accepting_jobs ? 'Reject new jobs' : 'Accept jobs'}"
+                onclick="printerAdmin('${printer.name}', '${printer.accepting_jobs ? 'reject' : 'accept'}')">
+            <i class="fas ${printer.accepting_jobs ? 'fa-ban' : 'fa-check'}"></i>
+        </button>
+        <button class="btn btn-sm btn-secondary" title="Purge queue" onclick="printerAdmin('${printer.name}', 'purge')">
+            <i class="fas fa-trash"></i>
+        </button>
+        ${printer.is_default ? '' : `
+            <button class="btn btn-sm btn-secondary" title="Set as default" onclick="printerAdmin('${printer.name}', 'default')">
+                <i class="fas fa-star"></i>
+            </button>
+        `}
+        <button class="btn btn-sm btn-danger" title="Delete printer" onclick="deletePrinter('${printer.name}')">
+            <i class="fas fa-times"></i>
+        </button>
+    `;
+}
+
+// Disable, reject and purge ask first, the printers list is updated over SSE afterwards
+async function printerAdmin(name, action) {
+    let body = {};
+
+    if (action === 'disable' || action === 'reject') {
+        const reason = prompt(`Reason for ${action === 'disable' ? 'disabling' : 'rejecting jobs on'} ${name} (optional):`);
+        if (reason === null) return;
+        body = { reason };
+    } else if (action === 'purge' && !confirm(`Cancel every job queued on ${name}?`)) {
+        return;
+    }
+
+    try {
+        await API.post(`/printers/${encodeURIComponent(name)}/${action}`, body);
+        Toast.success(`Printer ${name} updated`);
+    } catch (error) {
+        Toast.error(`Failed to update printer: ${error.message}`);
+    }
+}
+
+async function deletePrinter(name) {
+    if (!confirm(`Delete the printer ${name} and every job queued on it?`)) return;
+
+    try {
+        await API.delete(`/printers/${encodeURIComponent(name)}`);
+        Toast.success(`Printer ${name} deleted`);
+    } catch (error) {
+        Toast.error(`Failed to delete printer: ${error.message}`);
+    }
+}
+
+// ADD PRINTER
+async function showAddPrinterDialog() {
+    Modal.show('add-printer-modal');
+
+    const list = document.getElementById('discovered-printers');
+    list.innerHTML = `
+        <div class="loading-placeholder">
+            <i class="fas fa-spinner fa-spin"></i>
+            <p>Looking for printers...</p>
+        </div>
+    `;
+
+    try {
+        PrintPage.discovered = await API.get('/printers/discover');
+    } catch (error) {
+        PrintPage.discovered = [];
+        Toast.error(`Failed to discover printers: ${error.message}`);
+    }
+
+    list.innerHTML = PrintPage.discovered.length
+        ? PrintPage.discovered.map((device, index) => `
+            <button type="button" class="btn btn-secondary btn-sm discovered-printer" onclick="selectDiscoveredPrinter(${index})">
+                <i class="fas ${device.connection === 'direct' ? 'fa-plug' : 'fa-network-wired'}"></i>
+                ${device.make_and_model || device.info || device.uri}
+            </button>
+        `).join('')
+        : '<p class="form-help">No devices found</p>';
+}
+
+function selectDiscoveredPrinter(index) {
+    const device = PrintPage.discovered[index];
+    const label = device.info || device.make_and_model || '';
+
+    document.getElementById('add-printer-uri').value = device.uri;
+    document.getElementById('add-printer-description').value = label;
+    document.getElementById('add-printer-name').value = label.replace(/[^A-Za-z0-9_.-]+/g, '_');
+}
+
+function closeAddPrinterDialog() {
+    Modal.hide('add-printer-modal');
+    document.getElementById('add-printer-form')?.reset();
+}
+
+async function handleAddPrinterSubmit(e) {
+    e.preventDefault();
+
+    const submitBtn = document.getElementById('add-printer-submit-btn');
+    const originalText = submitBtn.innerHTML;
+    submitBtn.innerHTML = '<i class="fas fa-spinner fa-spin"></i> Adding...';
+    submitBtn.disabled = true;
+
+    const data = Object.fromEntries(new FormData(e.target));
+    Object.keys(data).forEach(key => {
+        if (!data[key].trim()) delete data[key];
+    });
+
+    try {
+        await API.post('/printers', data);
+        Toast.success(`Printer ${data.name} added`);
+        closeAddPrinterDialog();
+    } catch (error) {
+        Toast.error(`Failed to add printer: ${error.message}`);
+    } finally {
+        submitBtn.innerHTML = originalText;
+        submitBtn.disabled = false;
+    }
+}
+
 function showPrintersError() {
     const grid = document.getElementById('printers-grid');
     if (!grid) return;
@@ -125,6 +253,10 @@ async function refreshPrinters() {
         button.disabled = true;
 
         try {
+            // Look for newly connected devices instead of reloading the list known to the server, guests only reload
+            if (AppState.user?.role !== 'guest') {
+                await API.post('/system/devices/refresh', {});
+            }
             await loadPrinters();
             Toast.success('Printers refreshed');
         } catch (error) {
@@ -181,7 +313,7 @@ function displayPrintJobs() {
         return `
             <tr class="job-row job-${status}">
                 <td>
-                    <span class="filename" title="${job.filename}">${job.filename}</span>
+                    <span class="filename" title="${Utils.escapeHtml(job.original_filename)}">${Utils.escapeHtml(job.original_filename)}</span>
                 </td>
                 <td>
                     <span class="printer-name">${PrintHelpers.getPrinterDisplayName(job)}</span>
@@ -250,11 +382,21 @@ const PrintHelpers = {
         return vendor + " " + model;
     },
 
+    formatPrintedPages(job) {
+        const pages = `${job.pages_printed} ${job.pages_printed === 1 ? 'page' : 'pages'}`;
+        if (job.sheets_printed == null) return pages;
+
+        return `${pages} on ${job.sheets_printed} ${job.sheets_printed === 1 ? 'sheet' : 'sheets'}`;
+    },
+
     getStatusIcon(status) {
         const icons = {
             'queued': 'fa-clock',
+            'converting': 'fa-file-pdf',
             'processing': 'fa-spinner fa-spin',
             'printing': 'fa-print',
+            'held': 'fa-pause-circle',
+            'stopped': 'fa-exclamation-triangle',
             'completed': 'fa-check-circle',
             'failed': 'fa-exclamation-circle',
             'cancelled': 'fa-times-circle'
@@ -268,7 +410,10 @@ const PrintHelpers = {
             'failed': { width: 100, class: 'error' },
             'cancelled': { width: 100, class: 'error' },
             'printing': { width: 75, class: 'active' },
+            'stopped': { width: 50, class: 'error' },
+            'held': { width: 0, class: '' },
             'processing': { width: 25, class: 'active' },
+            'converting': { width: 10, class: 'active' },
             'queued': { width: 0, class: '' }
         };
 
@@ -288,7 +433,7 @@ const PrintHelpers = {
         `);
 
         // Cancel button for active jobs
-        if (['queued', 'processing', 'printing'].includes(status)) {
+        if (['queued', 'converting', 'processing', 'printing', 'held', 'stopped'].includes(status)) {
             actions.push(`
                 <button class="btn btn-sm btn-danger" onclick="cancelJob('${job.id}')" title="Cancel Job">
                     <i class="fas fa-times"></i>
@@ -331,7 +476,7 @@ function showJobDetailsModal(job) {
     modal.style.display = 'flex';
 
     const status = job.status.toLowerCase();
-    const isActive = ['queued', 'processing', 'printing'].includes(status);
+    const isActive = ['queued', 'converting', 'processing', 'printing', 'held', 'stopped'].includes(status);
 
     modal.innerHTML = `
         <div class="modal-content">
@@ -343,7 +488,7 @@ function showJobDetailsModal(job) {
             </div>
             <div class="job-details">
                 ${createDetailRow('Job ID', `<code>${job.id}</code>`)}
-                ${createDetailRow('Filename', job.filename)}
+                ${createDetailRow('Filename', Utils.escapeHtml(job.original_filename))}
                 ${createDetailRow('Printer', PrintHelpers.getPrinterDisplayName(job))}
                 ${createDetailRow('Status', `
                     <span class="status-badge status-${status}">
@@ -354,13 +499,19 @@ function showJobDetailsModal(job) {
                 ${createDetailRow('Created', new Date(job.created_at).toLocaleString())}
                 ${job.completed_at ? createDetailRow('Completed', new Date(job.completed_at).toLocaleString()) : ''}
                 ${job.cups_job_id ? createDetailRow('CUPS Job ID', job.cups_job_id) : ''}
-                ${job.error_message ? createDetailRow('Error', `<span class="error-message">${job.error_message}</span>`) : ''}
+                ${job.pages_printed != null ? createDetailRow('Printed', PrintHelpers.formatPrintedPages(job)) : ''}
+                ${job.error_message ? createDetailRow('Error', `<span class="error-message">${Utils.escapeHtml(job.error_message)}</span>`) : ''}
                 ${createDetailRow('Options', `
                     <ul class="job-options">
                         <li>Copies: ${job.copies || 1}</li>
                         <li>Pages: ${job.pages || 'All'}</li>
                         <li>Duplex: ${job.duplex ? 'Yes' : 'No'}</li>
                         <li>Color: ${job.color ? 'Yes' : 'No'}</li>
+                        <li>Orientation: ${job.orientation || 'portrait'}</li>
+                        <li>Pages per sheet: ${job.pages_per_sheet || 1}</li>
+                        <li>Quality: ${job.quality || 'normal'}</li>
+                        ${job.media_type ? `<li>Media type: ${job.media_type}</li>` : ''}
+                        ${job.input_tray ? `<li>Tray: ${job.input_tray}</li>` : ''}
                     </ul>
                 `)}
             </div>
@@ -445,6 +596,7 @@ function setupPrintForm() {
 
     form.addEventListener('submit', handlePrintFormSubmit);
     setupFileInputValidation();
+    document.getElementById('add-printer-form')?.addEventListener('submit', handleAddPrinterSubmit);
 }
 
 async function handlePrintFormSubmit(e) {
@@ -491,24 +643,21 @@ function setupFileInputValidation() {
         const file = e.target.files[0];
         if (!file) return;
 
-        const maxSize = 50 * 1024 * 1024; // 50MB
-        if (file.size > maxSize) {
-            Toast.error('File size must be less than 50MB');
+        const settings = AppState.settings;
+        if (!settings) return;
+
+        if (file.size > settings.max_file_size_mb * 1024 * 1024) {
+            Toast.error(`File size must be less than ${settings.max_file_size_mb}MB`);
             fileInput.value = '';
             return;
         }
 
-        const allowedTypes = [
-            'application/pdf',
-            'application/msword',
-            'application/vnd.openxmlformats-officedocument.wordprocessingml.document',
-            'text/plain',
-            'image/jpeg',
-            'image/png'
-        ];
-
-        if (!allowedTypes.includes(file.type)) {
-            Toast.error('Unsupported file type. Please use PDF, DOC, DOCX, TXT, JPG, or PNG.');
+        const extension = file.name.includes('.') ? file.name.split('.').pop().toLowerCase() : '';
+        const accepted = settings.accepted_formats.some(format => format.extensions.includes(extension));
+
+        if (!accepted) {
+            const formats = settings.accepted_formats.map(format => format.format.toUpperCase()).join(', ');
+            Toast.error(`Unsupported file type. Please use ${formats}.`);
             fileInput.value = '';
         }
     });
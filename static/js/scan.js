@@ -123,6 +123,10 @@ async function refreshScanners() {
         button.disabled = true;
 
         try {
+            // Look for newly connected devices instead of reloading the list known to the server, guests only reload
+            if (AppState.user?.role !== 'guest') {
+                await API.post('/system/devices/refresh', {});
+            }
             await loadScanners();
             Toast.success('Scanners refreshed');
         } catch (error) {